@@ -1,15 +1,13 @@
 #!bin/bash
 
-# usage: bash build_docker.sh <version - v1 or v2>
+# usage: bash build_docker.sh
 REPO_ROOT=$(git rev-parse --show-toplevel)
 WORKDIR=$REPO_ROOT/circuit
 export CARGO_HOME=$WORKDIR/.cargo
 cd $WORKDIR
 
-VERSION=${1:-v1}
-
 cargo clean
 cargo update
 cargo fetch
 
-docker build --build-arg VERSION=${VERSION} -t server .
+docker build -t server .
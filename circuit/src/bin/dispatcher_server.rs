@@ -0,0 +1,80 @@
+use std::{path::PathBuf, sync::Arc, time::Duration};
+
+use clap::Parser;
+use rocket::{fairing::AdHoc, get, launch, post, routes, serde::json::Json, Build, Rocket, State};
+use worldcoin_aggregation::{
+    dispatcher::{
+        types::{
+            DispatcherSnarkResponse, DispatcherTaskId, DispatcherTaskRequest,
+            DispatcherTaskStatusResponse,
+        },
+        Dispatcher,
+    },
+    prover::types::ProverTaskResponse,
+    types::*,
+};
+
+#[get("/")]
+fn index() -> &'static str {
+    "I'm alive!"
+}
+
+#[post("/tasks", format = "json", data = "<task>")]
+async fn create_task(
+    task: Json<DispatcherTaskRequest>,
+    dispatcher: &State<Arc<Dispatcher>>,
+) -> Result<Json<DispatcherTaskId>> {
+    let task_id = dispatcher.create_task(task.into_inner()).await?;
+    Ok(Json(task_id))
+}
+
+#[get("/tasks/<task_id>/status")]
+async fn task_status(
+    task_id: &str,
+    dispatcher: &State<Arc<Dispatcher>>,
+) -> Option<Json<DispatcherTaskStatusResponse>> {
+    dispatcher.get_status(task_id).await.map(Json)
+}
+
+#[get("/tasks/<task_id>/snark")]
+async fn task_snark(
+    task_id: &str,
+    dispatcher: &State<Arc<Dispatcher>>,
+) -> Result<Option<Json<DispatcherSnarkResponse>>> {
+    let proof = dispatcher.get_proof(task_id).await?;
+    Ok(proof.map(|payload| {
+        Json(DispatcherSnarkResponse {
+            snark: ProverTaskResponse { payload },
+        })
+    }))
+}
+
+#[derive(Parser, Clone, Debug)]
+struct Cli {
+    /// The url of a prover server. Repeat for every prover in the pool.
+    #[arg(long = "prover-url", required = true)]
+    pub prover_urls: Vec<String>,
+    /// Persist cached proofs in this directory
+    #[arg(long = "cache-dir")]
+    pub cache_dir: Option<PathBuf>,
+    /// Forget finished tasks and their proofs in memory after this time
+    #[arg(long = "task-ttl-sec", default_value_t = 3600)]
+    pub task_ttl_sec: u64,
+}
+
+#[launch]
+fn rocket() -> Rocket<Build> {
+    let cli = Cli::parse();
+    let task_ttl = Duration::from_secs(cli.task_ttl_sec);
+    let dispatcher = Dispatcher::new(&cli.prover_urls, cli.cache_dir, task_ttl)
+        .unwrap_or_else(|e| panic!("Failed to create dispatcher. {:?}", e));
+    let dispatcher = Arc::new(dispatcher);
+    let dispatcher_clone = Arc::clone(&dispatcher);
+
+    rocket::build()
+        .mount("/", routes![index, create_task, task_status, task_snark])
+        .manage(dispatcher)
+        .attach(AdHoc::on_liftoff("Start prover workers", |_| {
+            Box::pin(async move { dispatcher_clone.spawn_workers() })
+        }))
+}
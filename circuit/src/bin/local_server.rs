@@ -4,14 +4,20 @@ use anyhow::anyhow;
 use clap::Parser;
 use rocket::{http::Status, launch, post, routes, serde::json::Json, Build, Rocket, State};
 use serde::{Deserialize, Serialize};
-use std::fs;
 use uuid::Uuid;
 use worldcoin_aggregation::{
     constants::{EXTRA_ROUNDS, INITIAL_DEPTH},
-    keygen::node_params::{NodeParams, NodeType},
+    keygen::node_params::{CircuitOptions, CircuitVersion, NodeParams, NodeType},
+    native::groth16::PublicInputMode,
     prover::{types::ProverProof, ProverConfig, ProvingServerState},
-    scheduler::{local_scheduler::*, recursive_request::*, Scheduler},
+    scheduler::{
+        claim_filter::{check_claims, group_claims_by_vk, resolve_claim_roots},
+        local_scheduler::*,
+        recursive_request::*,
+        Scheduler,
+    },
     types::*,
+    vk_registry::{VkRegistry, DEFAULT_VK_PATH},
 };
 
 #[derive(Serialize, Deserialize)]
@@ -34,6 +40,9 @@ struct Request {
     for_evm: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
     rounds: Option<usize>,
+    /// Version of the circuits, V1 by default
+    #[serde(skip_serializing_if = "Option::is_none")]
+    version: Option<CircuitVersion>,
 }
 
 #[post("/reset")]
@@ -55,6 +64,7 @@ async fn serve(task: Json<Request>, scheduler: &State<LocalScheduler>) -> Result
         is_final,
         for_evm,
         rounds,
+        version,
     } = task.into_inner();
 
     let depth = depth.unwrap_or(INITIAL_DEPTH);
@@ -83,13 +93,35 @@ async fn serve(task: Json<Request>, scheduler: &State<LocalScheduler>) -> Result
     } else {
         NodeType::Intermediate
     };
-    let params = NodeParams::new(node_type, depth, initial_depth);
+    let vk_registry = scheduler.vk_registry.clone();
+    let (root, mut groups) = tokio::task::spawn_blocking(move || {
+        let claims = resolve_claim_roots(&root, claims, false)?;
+        check_claims(&vk_registry, &root, &claims, &PublicInputMode::default())?;
+        group_claims_by_vk(&vk_registry, claims).map(|groups| (root, groups))
+    })
+    .await??;
+    if groups.len() != 1 {
+        return Err(anyhow!("All claims must use the same vk")
+            .context(InvalidInputContext)
+            .into());
+    }
+    let group = groups.pop().unwrap();
+
+    let params = NodeParams::new(
+        version.unwrap_or_default(),
+        1,
+        CircuitOptions::default(),
+        node_type,
+        depth,
+        initial_depth,
+    );
     let req = RecursiveRequest {
         start,
         end,
         root,
-        claims,
+        claims: group.claims,
         params,
+        vk_hash: group.vk_hash,
     };
 
     let scheduler = LocalScheduler::clone(scheduler);
@@ -123,6 +155,9 @@ struct Cli {
     /// The path to the file with mappings between NodeParams and circuit IDs
     #[arg(long = "cids-path")]
     pub cids_path: PathBuf,
+    /// Verifying keys accepted for claims, the first one is the default
+    #[arg(long = "vk-path", default_value = DEFAULT_VK_PATH)]
+    pub vk_paths: Vec<PathBuf>,
 }
 
 #[launch]
@@ -141,10 +176,9 @@ fn rocket() -> Rocket<Build> {
 
     let state = ProvingServerState::new(cli.prover_config);
 
-    let data = fs::read_to_string("./data/vk.json").unwrap();
-    let vk: VkNative = serde_json::from_str(&data).unwrap();
+    let vk_registry = VkRegistry::from_paths(&cli.vk_paths).expect("Failed to load vks");
 
-    let scheduler: LocalScheduler = LocalScheduler::new(cids_repo, state, vk);
+    let scheduler: LocalScheduler = LocalScheduler::new(cids_repo, state, vk_registry);
     rocket::build()
         .mount("/", routes![serve, reset])
         .manage(scheduler)
@@ -47,6 +47,11 @@ async fn serve(
         RequestRouter::Intermediate(request) => return_snark(prover, circuit_id, request).await,
         RequestRouter::Leaf(request) => return_snark(prover, circuit_id, request).await,
         RequestRouter::Root(request) => return_snark(prover, circuit_id, request).await,
+        RequestRouter::LeafV2(request) => return_snark(prover, circuit_id, request).await,
+        RequestRouter::IntermediateV2(request) => return_snark(prover, circuit_id, request).await,
+        RequestRouter::RootV2(request) => return_snark(prover, circuit_id, request).await,
+        RequestRouter::Rolling(request) => return_snark(prover, circuit_id, request).await,
+        RequestRouter::RollingV2(request) => return_snark(prover, circuit_id, request).await,
     }
 }
 
@@ -82,6 +87,13 @@ async fn load_circuit_data(
             _ = prover.build_circuit(&circuit_id, request).await?
         }
         RequestRouter::Root(request) => _ = prover.build_circuit(&circuit_id, request).await?,
+        RequestRouter::LeafV2(request) => _ = prover.build_circuit(&circuit_id, request).await?,
+        RequestRouter::IntermediateV2(request) => {
+            _ = prover.build_circuit(&circuit_id, request).await?
+        }
+        RequestRouter::RootV2(request) => _ = prover.build_circuit(&circuit_id, request).await?,
+        RequestRouter::Rolling(request) => _ = prover.build_circuit(&circuit_id, request).await?,
+        RequestRouter::RollingV2(request) => _ = prover.build_circuit(&circuit_id, request).await?,
         RequestRouter::Evm(request) => _ = prover.build_circuit(&circuit_id, request).await?,
     };
 
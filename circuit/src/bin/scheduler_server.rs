@@ -1,22 +1,49 @@
 use std::{collections::HashMap, env, fs::File, io::Write, path::PathBuf};
 
-use anyhow::anyhow;
+use anyhow::{anyhow, Context};
 use clap::Parser;
-use rocket::{get, launch, post, routes, serde::json::Json, Build, Rocket, State};
-use tokio::task;
+use ethers::types::H256;
+use itertools::Itertools;
+use rocket::{
+    fairing::AdHoc, get, launch, post, routes, serde::json::Json, Build, Rocket, State,
+};
+use tokio::{task, time::Duration};
 use uuid::Uuid;
 use worldcoin_aggregation::{
     constants::{EXTRA_ROUNDS, INITIAL_DEPTH},
-    keygen::node_params::{NodeParams, NodeType},
-    prover::types::ProverProof,
+    keygen::node_params::{CircuitOptions, CircuitVersion, NodeParams, NodeType},
+    native::{
+        self,
+        claim_tree::{ClaimProof, ClaimTree},
+        groth16::PublicInputMode,
+        instances::{check_evm_proof_instances, v2_batch_roots},
+    },
+    prover::{types::ProverProof, ProverConfig, ProvingServerState},
     scheduler::{
         async_scheduler::AsyncScheduler,
-        contract_client::{ContractClient, V1ClaimParams},
+        chain::ChainState,
+        claim_filter::{
+            check_claim_amounts, check_claims, exclude_invalid_claims, group_claims_by_vk,
+            mixed_vk_group, resolve_claim_roots, sort_claims_by_nullifier, ClaimGroup,
+        },
+        contract_client::{
+            ContractClient, FulfillParams, PendingTx, V1ChainedParams, V1ClaimParams,
+        },
+        executor::{
+            dispatcher::{DispatcherExecutor, TaskTimeouts},
+            local::LocalExecutor,
+            ExecutionSummary, ProofExecutor,
+        },
         recursive_request::*,
+        store::SchedulerStore,
         task_tracker::SchedulerTaskTracker,
-        types::{SchedulerTaskRequest, SchedulerTaskResponse},
+        types::{
+            RetryPolicy, SchedulerTaskRequest, SchedulerTaskResponse, SchedulerTaskStatus,
+            SchedulerTaskStatusResponse, SchedulerTaskSummary, VkRequest,
+        },
     },
     types::*,
+    vk_registry::{VkRegistry, DEFAULT_VK_PATH},
 };
 
 use std::sync::Arc;
@@ -32,113 +59,751 @@ async fn serve(
     task: Json<SchedulerTaskRequest>,
     scheduler: &State<Arc<AsyncScheduler>>,
 ) -> Result<Json<SchedulerTaskResponse>> {
+    let task = task.into_inner();
     let SchedulerTaskRequest {
         root,
         claims,
-    } = task.into_inner();
-
+        exclude_invalid_claims: exclude,
+    } = task;
 
     let num_proofs = claims.len();
 
     let final_circuit_params = (*scheduler.final_circuit_params).clone();
-    let NodeParams{ node_type: _, depth, initial_depth} = final_circuit_params;
+    let NodeParams {
+        version,
+        num_vks,
+        options,
+        depth,
+        ..
+    } = final_circuit_params;
+    let CircuitOptions {
+        per_claim_roots,
+        unique_nullifiers,
+        claim_amounts,
+        hash_signal,
+        public_inputs,
+        ..
+    } = options;
 
     let max_proofs = 1 << depth;
 
     if num_proofs == 0 {
-         return Err(anyhow!("Zero claims!")
+        return Err(anyhow!("Zero claims!").context(InvalidInputContext).into());
+    }
+
+    // without a vk set, every verifying key has its own tree
+    let max_group_claims = if num_vks > 1 {
+        num_proofs
+    } else {
+        let default_vk_hash = scheduler.vk_registry.default_vk_hash();
+        claims
+            .iter()
+            .counts_by(|claim| claim.vk_hash.unwrap_or(default_vk_hash))
+            .into_values()
+            .max()
+            .unwrap_or_default()
+    };
+    if max_group_claims > max_proofs {
+        return Err(anyhow!("Too many claims for one verifying key!")
             .context(InvalidInputContext)
             .into());
     }
 
-    if num_proofs > max_proofs {
+    let vk_registry = Arc::clone(&scheduler.vk_registry);
+    let app_id = scheduler.app_id.clone();
+    let (root, groups, excluded_claims) = task::spawn_blocking(move || {
+        // claims with their own root are only accepted by circuits with per-claim roots
+        let claims = resolve_claim_roots(&root, claims, per_claim_roots)?;
+        check_claim_amounts(&claims, claim_amounts)?;
+        let mode = PublicInputMode {
+            layout: public_inputs,
+            hash_signal,
+            app_id: app_id.as_deref(),
+        };
+        let (claims, excluded_claims) = if exclude {
+            exclude_invalid_claims(&vk_registry, &root, claims, &mode)?
+        } else {
+            check_claims(&vk_registry, &root, &claims, &mode)?;
+            (claims, vec![])
+        };
+        let groups = if num_vks > 1 {
+            mixed_vk_group(&vk_registry, claims)?
+        } else {
+            group_claims_by_vk(&vk_registry, claims)?
+        };
+        // the circuits only accept a batch sorted by nullifier hash, so a duplicate is rejected
+        // here rather than failing the proof
+        let groups = if unique_nullifiers {
+            groups
+                .into_iter()
+                .map(|ClaimGroup { vk_hash, claims }| {
+                    let claims = sort_claims_by_nullifier(claims)?;
+                    anyhow::Ok(ClaimGroup { vk_hash, claims })
+                })
+                .collect::<anyhow::Result<_>>()?
+        } else {
+            groups
+        };
+        anyhow::Ok((root, groups, excluded_claims))
+    })
+    .await??;
+
+    if groups.is_empty() {
+        return Err(anyhow!("All claims are invalid!")
+            .context(InvalidInputContext)
+            .into());
+    }
+    if groups.iter().any(|group| group.claims.len() > max_proofs) {
         return Err(anyhow!("Too many claims!")
             .context(InvalidInputContext)
             .into());
     }
+    if !excluded_claims.is_empty() {
+        log::info!("Excluded {} claims", excluded_claims.len());
+    }
 
+    let scheduler = Arc::clone(&scheduler.inner());
 
-    let req = RecursiveRequest {
-        start: 0,
-        end: num_proofs as u32,
-        root,
-        claims,
-        params: final_circuit_params,
+    // with rolling aggregation every group is a batch, chained with the batches of earlier
+    // requests until the chain is submitted
+    let is_chained = final_circuit_params.max_batches > 1;
+    let request_params = if is_chained {
+        final_circuit_params.batch()
+    } else {
+        final_circuit_params
     };
 
-    // Actually run the thing
-    log::info!("Running task: {req:?}");
+    let mut requests = vec![];
+    for ClaimGroup { vk_hash, claims } in groups {
+        // only the remaining claims are stored, so a resumed request proves the same batch
+        let task = SchedulerTaskRequest {
+            root: root.clone(),
+            claims: claims.clone(),
+            exclude_invalid_claims: exclude,
+        };
+
+        let req = RecursiveRequest {
+            start: 0,
+            end: claims.len() as u32,
+            root: root.clone(),
+            claims,
+            params: request_params,
+            vk_hash,
+        };
+
+        // Actually run the thing
+        log::info!("Running task: {req:?}");
+
+        let claim_tree = match version {
+            CircuitVersion::V1 => None,
+            CircuitVersion::V2 => {
+                let claim_tree = ClaimTree::new(
+                    &req.root,
+                    &req.claims,
+                    depth,
+                    per_claim_roots,
+                    claim_amounts,
+                )
+                .context(InvalidInputContext)?;
+                Some(claim_tree)
+            }
+        };
+
+        let request_id = Uuid::new_v4().to_string();
+        scheduler
+            .task_tracker
+            .create_request(&request_id, &task, &vk_hash)
+            .await?;
+        if let Some(claim_tree) = claim_tree {
+            scheduler
+                .task_tracker
+                .record_claim_tree(&request_id, claim_tree)
+                .await?;
+        }
+
+        requests.push(VkRequest {
+            request_id: request_id.clone(),
+            vk_hash,
+            num_claims: req.claims.len(),
+        });
+        if is_chained {
+            task::spawn(run_chained_request(Arc::clone(&scheduler), request_id, req));
+        } else {
+            task::spawn(run_request(Arc::clone(&scheduler), request_id, req));
+        }
+    }
 
-    let scheduler = Arc::clone(&scheduler.inner());
+    log::info!("Successfully created task!");
 
-    let request_id = Uuid::new_v4().to_string();
-    let request_id_clone = request_id.clone();
+    return Ok(Json(SchedulerTaskResponse {
+        request_id: requests[0].request_id.clone(),
+        requests,
+        excluded_claims,
+    }));
+}
 
-    let contract_client = Arc::clone(&scheduler.contract_client);
+/// Generate the final proof for the request and submit it on-chain, recording progress in the
+/// task tracker.
+async fn run_request(scheduler: Arc<AsyncScheduler>, request_id: String, req: RecursiveRequest) {
+    scheduler
+        .task_tracker
+        .update_status(&request_id, SchedulerTaskStatus::Running)
+        .await;
+
+    let proof = match scheduler
+        .recursive_gen_proof(&request_id, req.clone(), true)
+        .await
+    {
+        Ok(proof) => proof,
+        Err(e) => {
+            log::error!("Failed to generate proof for request {}: {:?}", request_id, e);
+            scheduler.task_tracker.record_failure(&request_id, &e).await;
+            return;
+        }
+    };
 
-    task::spawn(async move {
-        let proof = scheduler
-            .recursive_gen_proof(&request_id, req.clone(), true)
-            .await
-            .unwrap();
+    scheduler
+        .task_tracker
+        .record_proof(&request_id, proof.clone())
+        .await;
 
-        match proof {
-            ProverProof::EvmProof(final_proof) => {
-                log::info!("Successfully generated proof! {:?}", final_proof);
+    match proof {
+        ProverProof::EvmProof(final_proof) => {
+            log::info!("Successfully generated proof! {:?}", final_proof);
 
-                let request_id_to_tasks = scheduler.task_tracker.request_id_to_tasks.lock().await;
+            if let Err(e) = write_execution_summary(&scheduler, &request_id).await {
+                log::warn!(
+                    "Failed to write execution summary for request {}: {:?}",
+                    request_id,
+                    e
+                );
+            }
 
-                let tasks: &Vec<(String, NodeParams)> =
-                    request_id_to_tasks.get(&request_id).unwrap();
-                // dump execution summary
-                let json_string =
-                    serde_json::to_string_pretty(tasks).expect("Failed to serialize data to JSON");
-                let mut file = File::create(
+            let vk_hash = match check_final_instances(&req, &final_proof) {
+                Ok(vk_hash) => vk_hash,
+                Err(e) => {
+                    log::error!("Invalid final proof for request {}: {:?}", request_id, e);
                     scheduler
-                        .execution_summary_path
-                        .join(format!("{}.json", request_id)),
-                )
-                .unwrap();
-                file.write_all(json_string.as_bytes()).unwrap();
-
-                let retry_send_threshold = 5;
-
-                // the vk_hash for the corresponding vk.json
-                const VK_HASH: &str =
-                    "0x46e72119ce99272ddff09e0780b472fdc612ca799c245eea223b27e57a5f9cec";
-
-                #[cfg(feature = "v1")]
-                let params = V1ClaimParams::new(VK_HASH, &req.root, &req.claims, final_proof);
-                #[cfg(feature = "v2")]
-                let params = final_proof;
-
-                for _i in 0..retry_send_threshold {
-                    let ret = contract_client.fulfill(params.clone()).await;
-                    match ret {
-                        Ok(tx_hash) => {
-                            println!("fulfilled query {}, tx_hash {}", request_id, tx_hash);
-                            return;
-                        }
-                        Err(_) => {
-                            println!("Failed to fulfill request {}, retrying", request_id);
-                            tokio::time::sleep(tokio::time::Duration::from_secs(3)).await;
-                        }
-                    }
+                        .task_tracker
+                        .record_error(&request_id, format!("Invalid final proof: {e:#}"))
+                        .await;
+                    return;
                 }
+            };
+
+            let params = match req.params.version {
+                CircuitVersion::V1 => FulfillParams::V1(V1ClaimParams::new(
+                    &format!("{vk_hash:?}"),
+                    &req.root,
+                    &req.claims,
+                    final_proof,
+                    req.params.depth,
+                    req.params.options.per_claim_roots,
+                )),
+                CircuitVersion::V2 => FulfillParams::V2(final_proof),
+            };
+
+            fulfill(&scheduler, &[request_id], params).await;
+        }
+        _ => unreachable!(),
+    }
+}
+
+/// Submit the proof on-chain for the given requests, recording the transaction hash, or the
+/// error once all retries failed
+async fn fulfill(scheduler: &AsyncScheduler, request_ids: &[String], params: FulfillParams) {
+    let retry_send_threshold = 5;
+    let request_ids_str = request_ids.join(", ");
+
+    for _i in 0..retry_send_threshold {
+        match send_fulfill_tx(scheduler, request_ids, &params).await {
+            Ok(true) => return,
+            Ok(false) => {
                 println!(
-                    "Failed to fulfill request {} after {} retries",
-                    request_id, retry_send_threshold
+                    "Transaction of request {} was dropped or reverted, retrying",
+                    request_ids_str
+                );
+            }
+            Err(e) => {
+                println!(
+                    "Failed to fulfill request {}, retrying: {:?}",
+                    request_ids_str, e
                 );
             }
-            _ => unreachable!(),
         }
-    });
+        tokio::time::sleep(tokio::time::Duration::from_secs(3)).await;
+    }
+    println!(
+        "Failed to fulfill request {} after {} retries",
+        request_ids_str, retry_send_threshold
+    );
+    for request_id in request_ids {
+        scheduler
+            .task_tracker
+            .record_error(
+                request_id,
+                format!(
+                    "Failed to submit proof on-chain after {} retries",
+                    retry_send_threshold
+                ),
+            )
+            .await;
+    }
+}
 
-    log::info!("Successfully created task!");
+/// Send the transaction which is pending for the requests, or sign and record a new one before
+/// sending it. Returns whether the transaction succeeded.
+async fn send_fulfill_tx(
+    scheduler: &AsyncScheduler,
+    request_ids: &[String],
+    params: &FulfillParams,
+) -> anyhow::Result<bool> {
+    let tx = match scheduler.task_tracker.get_pending_tx(&request_ids[0]).await {
+        Some(tx) => tx,
+        None => {
+            let tx = scheduler.contract_client.sign(params.clone()).await?;
+            for request_id in request_ids {
+                scheduler
+                    .task_tracker
+                    .record_pending_tx(request_id, &tx)
+                    .await?;
+            }
+            tx
+        }
+    };
+    confirm_tx(scheduler, request_ids, &tx).await
+}
 
-    return Ok(Json(SchedulerTaskResponse {
-        request_id: request_id_clone,
-    }));
+/// Broadcast the pending transaction of the requests if needed and wait for it. The requests are
+/// `DONE` once it succeeded. A dropped or reverted transaction is cleared, so the proof can be
+/// submitted with a new one. Returns whether the transaction succeeded.
+async fn confirm_tx(
+    scheduler: &AsyncScheduler,
+    request_ids: &[String],
+    tx: &PendingTx,
+) -> anyhow::Result<bool> {
+    let receipt = scheduler.contract_client.send(tx).await?;
+    let succeeded = receipt.is_some_and(|receipt| receipt.status == Some(1.into()));
+    for request_id in request_ids {
+        if succeeded {
+            scheduler
+                .task_tracker
+                .record_tx_hash(request_id, format!("{:?}", tx.tx_hash))
+                .await;
+        } else {
+            scheduler.task_tracker.clear_pending_tx(request_id).await?;
+        }
+    }
+    if succeeded {
+        println!(
+            "fulfilled query {}, tx_hash {:?}",
+            request_ids.join(", "),
+            tx.tx_hash
+        );
+    }
+    Ok(succeeded)
+}
+
+/// Prove the batch of the request and append it to the chain, submitting the chain once it is
+/// complete. The request stays `RUNNING` until its chain is submitted.
+async fn run_chained_request(
+    scheduler: Arc<AsyncScheduler>,
+    request_id: String,
+    req: RecursiveRequest,
+) {
+    scheduler
+        .task_tracker
+        .update_status(&request_id, SchedulerTaskStatus::Running)
+        .await;
+
+    match scheduler.append_batch(&request_id, req).await {
+        Ok(Some(chain)) => submit_chain(&scheduler, chain).await,
+        Ok(None) => log::info!("Chained the batch of request {}", request_id),
+        Err(e) => {
+            log::error!(
+                "Failed to chain the batch of request {}: {:?}",
+                request_id,
+                e
+            );
+            scheduler.task_tracker.record_failure(&request_id, &e).await;
+        }
+    }
+}
+
+/// Generate the final proof of the chain and submit it on-chain. The result is recorded for
+/// every batch of the chain, and the chain is removed from the store once its submission ended.
+async fn submit_chain(scheduler: &AsyncScheduler, chain: ChainState) {
+    prove_and_fulfill_chain(scheduler, &chain).await;
+    if let Err(e) = scheduler.task_tracker.clear_submitting_chain(&chain) {
+        log::error!(
+            "Failed to clear the submitted chain {:?}: {:?}",
+            chain.request_ids(),
+            e
+        );
+    }
+}
+
+async fn prove_and_fulfill_chain(scheduler: &AsyncScheduler, chain: &ChainState) {
+    let request_ids = chain.request_ids();
+    let final_proof = match scheduler.prove_chain(chain).await {
+        Ok(final_proof) => final_proof,
+        Err(e) => {
+            log::error!(
+                "Failed to generate proof for chain {:?}: {:?}",
+                request_ids,
+                e
+            );
+            for request_id in &request_ids {
+                scheduler.task_tracker.record_failure(request_id, &e).await;
+            }
+            return;
+        }
+    };
+    log::info!("Successfully generated proof! {:?}", final_proof);
+
+    for request_id in &request_ids {
+        scheduler
+            .task_tracker
+            .record_proof(request_id, ProverProof::EvmProof(final_proof.clone()))
+            .await;
+    }
+
+    let params = match check_chain_instances(&scheduler.final_circuit_params, chain, final_proof) {
+        Ok(params) => params,
+        Err(e) => {
+            log::error!("Invalid final proof for chain {:?}: {:?}", request_ids, e);
+            for request_id in &request_ids {
+                scheduler
+                    .task_tracker
+                    .record_error(request_id, format!("Invalid final proof: {e:#}"))
+                    .await;
+            }
+            return;
+        }
+    };
+
+    fulfill(scheduler, &request_ids, params).await;
+}
+
+/// Check the public instances of the final evm proof of a chain against the ones predicted
+/// natively from its batches, returning the arguments for its submission
+fn check_chain_instances(
+    params: &NodeParams,
+    chain: &ChainState,
+    evm_proof: String,
+) -> anyhow::Result<FulfillParams> {
+    let vk_hash = chain
+        .vk_hash
+        .ok_or_else(|| anyhow!("The chain has no vk hash"))?;
+
+    match params.version {
+        CircuitVersion::V1 => {
+            let output_hashes = chain
+                .batches
+                .iter()
+                .map(|batch| {
+                    let instances = native::instances::v1_instances_from_claims(
+                        &vk_hash,
+                        &batch.root,
+                        0,
+                        &batch.claims,
+                        params.depth,
+                        params.initial_depth,
+                        &params.options,
+                    )?;
+                    anyhow::Ok(native::instances::v1_output_hash(
+                        &instances,
+                        batch.claims.len() as u32,
+                    ))
+                })
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            let expected = native::instances::v1_chain_instances(&output_hashes);
+            check_evm_proof_instances(&evm_proof, &expected)?;
+
+            let batches = chain
+                .batches
+                .iter()
+                .map(|batch| (batch.root.clone(), batch.claims.clone()))
+                .collect::<Vec<_>>();
+            Ok(FulfillParams::V1Chained(V1ChainedParams::new(
+                &format!("{vk_hash:?}"),
+                &batches,
+                evm_proof,
+                params.depth,
+            )))
+        }
+        CircuitVersion::V2 => {
+            let batches = chain
+                .batches
+                .iter()
+                .map(|batch| {
+                    native::instances::v2_root_instances_from_claims(
+                        &vk_hash,
+                        &batch.root,
+                        &batch.claims,
+                        params.depth,
+                        &params.options,
+                    )
+                })
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            let expected = native::instances::v2_chain_instances(&batches)?;
+            check_evm_proof_instances(&evm_proof, &expected)?;
+
+            let (roots, claims_roots) = batches
+                .iter()
+                .map(|instances| v2_batch_roots(instances))
+                .unzip();
+            Ok(FulfillParams::V2Chained {
+                proof: evm_proof,
+                roots,
+                claims_roots,
+            })
+        }
+    }
+}
+
+/// Check the public instances of the final evm proof against the ones predicted natively from
+/// the request, returning the vk hash
+fn check_final_instances(req: &RecursiveRequest, evm_proof: &str) -> anyhow::Result<H256> {
+    // the registry is keyed by the native vk hash
+    let vk_hash = req.vk_hash;
+
+    let expected = match req.params.version {
+        CircuitVersion::V1 => {
+            let instances = native::instances::v1_instances_from_claims(
+                &vk_hash,
+                &req.root,
+                req.start,
+                &req.claims,
+                req.params.depth,
+                req.params.initial_depth,
+                &req.params.options,
+            )?;
+            let output_hash =
+                native::instances::v1_output_hash(&instances, req.claims.len() as u32);
+            native::instances::hi_lo(&output_hash).to_vec()
+        }
+        CircuitVersion::V2 => native::instances::v2_root_instances_from_claims(
+            &vk_hash,
+            &req.root,
+            &req.claims,
+            req.params.depth,
+            &req.params.options,
+        )?,
+    };
+
+    check_evm_proof_instances(evm_proof, &expected)?;
+    Ok(vk_hash)
+}
+
+/// Dump the tasks executed for the request to the execution summary directory
+async fn write_execution_summary(
+    scheduler: &AsyncScheduler,
+    request_id: &str,
+) -> anyhow::Result<()> {
+    let request_id_to_tasks = scheduler.task_tracker.request_id_to_tasks.lock().await;
+    let empty = vec![];
+    // all tasks may have been completed before a restart
+    let tasks: &Vec<(ExecutionSummary, NodeParams)> =
+        request_id_to_tasks.get(request_id).unwrap_or(&empty);
+    let json_string = serde_json::to_string_pretty(tasks)?;
+    let path = scheduler
+        .execution_summary_path
+        .join(format!("{}.json", request_id));
+    let mut file = File::create(&path)
+        .with_context(|| format!("Failed to create {}", path.display()))?;
+    file.write_all(json_string.as_bytes())?;
+    Ok(())
+}
+
+/// Resume the requests which had not finished when the scheduler was stopped. Proofs of
+/// completed subtrees are reused from the store, and a request or a chain whose proof was being
+/// submitted waits for its pending transaction first.
+async fn resume_requests(scheduler: Arc<AsyncScheduler>) {
+    let requests = match scheduler.task_tracker.unfinished_requests().await {
+        Ok(requests) => requests,
+        Err(e) => {
+            log::error!("Failed to load unfinished requests: {:?}", e);
+            return;
+        }
+    };
+    let is_chained = scheduler.final_circuit_params.max_batches > 1;
+    // batches in the chain wait for its submission
+    let mut chained: Vec<String> = scheduler.task_tracker.chain.lock().await.request_ids();
+    // chains taken for submission are submitted again from their rolling proof
+    let submitting = match scheduler.task_tracker.submitting_chains() {
+        Ok(chains) => chains,
+        Err(e) => {
+            log::error!("Failed to load the chains taken for submission: {:?}", e);
+            return;
+        }
+    };
+    for chain in submitting {
+        let request_ids = chain.request_ids();
+        chained.extend(request_ids.iter().cloned());
+        // the scheduler stopped after the submission ended, before the chain was cleared
+        if !requests
+            .iter()
+            .any(|(request_id, ..)| request_ids.contains(request_id))
+        {
+            if let Err(e) = scheduler.task_tracker.clear_submitting_chain(&chain) {
+                log::error!("Failed to clear the chain {:?}: {:?}", request_ids, e);
+            }
+            continue;
+        }
+        log::info!("Resuming chain {:?}", request_ids);
+        task::spawn(resume_chain(Arc::clone(&scheduler), chain));
+    }
+    for (request_id, SchedulerTaskRequest { root, claims, .. }, vk_hash) in requests {
+        if chained.contains(&request_id) {
+            continue;
+        }
+        log::info!("Resuming request {}", request_id);
+        // requests stored without their vk hash are derived as they were grouped: claims of a
+        // stored request share one vk, set explicitly when it was created, unless the circuits
+        // take the whole vk set
+        let vk_hash = match vk_hash {
+            Some(vk_hash) => vk_hash,
+            None if scheduler.final_circuit_params.num_vks > 1 => {
+                scheduler.vk_registry.vk_set_hash()
+            }
+            None => claims
+                .first()
+                .and_then(|claim| claim.vk_hash)
+                .unwrap_or_else(|| scheduler.vk_registry.default_vk_hash()),
+        };
+        let params = if is_chained {
+            scheduler.final_circuit_params.batch()
+        } else {
+            *scheduler.final_circuit_params
+        };
+        let req = RecursiveRequest {
+            start: 0,
+            end: claims.len() as u32,
+            root,
+            claims,
+            params,
+            vk_hash,
+        };
+        task::spawn(resume_request(Arc::clone(&scheduler), request_id, req));
+    }
+}
+
+/// Reconcile the pending transaction of the request, if any, and otherwise run it again
+async fn resume_request(scheduler: Arc<AsyncScheduler>, request_id: String, req: RecursiveRequest) {
+    if let Some(tx) = scheduler.task_tracker.get_pending_tx(&request_id).await {
+        log::info!(
+            "Reconciling transaction {:?} of request {}",
+            tx.tx_hash,
+            request_id
+        );
+        match confirm_tx(&scheduler, &[request_id.clone()], &tx).await {
+            Ok(true) => return,
+            Ok(false) => log::warn!(
+                "Transaction {:?} of request {} was dropped or reverted, submitting it again",
+                tx.tx_hash,
+                request_id
+            ),
+            // the transaction may still be mined, so it is reconciled on the next restart
+            Err(e) => {
+                log::error!("Failed to reconcile request {}: {:?}", request_id, e);
+                return;
+            }
+        }
+    }
+    if scheduler.final_circuit_params.max_batches > 1 {
+        run_chained_request(scheduler, request_id, req).await
+    } else {
+        run_request(scheduler, request_id, req).await
+    }
+}
+
+/// Reconcile the pending transaction of a chain taken for submission, if any, and otherwise
+/// submit the chain again
+async fn resume_chain(scheduler: Arc<AsyncScheduler>, chain: ChainState) {
+    let request_ids = chain.request_ids();
+    if let Some(tx) = scheduler.task_tracker.get_pending_tx(&request_ids[0]).await {
+        log::info!(
+            "Reconciling transaction {:?} of chain {:?}",
+            tx.tx_hash,
+            request_ids
+        );
+        match confirm_tx(&scheduler, &request_ids, &tx).await {
+            Ok(true) => {
+                if let Err(e) = scheduler.task_tracker.clear_submitting_chain(&chain) {
+                    log::error!("Failed to clear the chain {:?}: {:?}", request_ids, e);
+                }
+                return;
+            }
+            Ok(false) => log::warn!(
+                "Transaction {:?} of chain {:?} was dropped or reverted, submitting it again",
+                tx.tx_hash,
+                request_ids
+            ),
+            // the transaction may still be mined, so it is reconciled on the next restart
+            Err(e) => {
+                log::error!("Failed to reconcile chain {:?}: {:?}", request_ids, e);
+                return;
+            }
+        }
+    }
+    submit_chain(&scheduler, chain).await
+}
+
+/// Submit the batches chained so far without waiting for the remaining batch slots, e.g. on the
+/// operator's schedule. Returns the request ids of the submitted batches, whose proof is generated
+/// in the background.
+#[post("/chain/submit")]
+async fn submit_chain_now(scheduler: &State<Arc<AsyncScheduler>>) -> Result<Json<Vec<String>>> {
+    let chain = match scheduler.take_chain().await? {
+        Some(chain) => chain,
+        None => return Ok(Json(vec![])),
+    };
+    let request_ids = chain.request_ids();
+    let scheduler = Arc::clone(scheduler.inner());
+    task::spawn(async move { submit_chain(&scheduler, chain).await });
+    Ok(Json(request_ids))
+}
+
+/// Submit the chain every `interval`, unless it is empty
+async fn submit_chain_periodically(scheduler: Arc<AsyncScheduler>, interval: Duration) {
+    loop {
+        tokio::time::sleep(interval).await;
+        match scheduler.take_chain().await {
+            Ok(Some(chain)) => submit_chain(&scheduler, chain).await,
+            Ok(None) => {}
+            Err(e) => log::error!("Failed to take the chain for submission: {:?}", e),
+        }
+    }
+}
+
+#[get("/tasks/<request_id>")]
+async fn task_status(
+    request_id: &str,
+    scheduler: &State<Arc<AsyncScheduler>>,
+) -> Option<Json<SchedulerTaskStatusResponse>> {
+    scheduler
+        .task_tracker
+        .get_status(request_id)
+        .await
+        .map(Json)
+}
+
+/// The arguments of `WorldcoinAggregationV2.claim` for the claim with the given nullifier hash.
+/// Only V2 requests have claim trees.
+#[get("/tasks/<request_id>/claims/<nullifier_hash>")]
+async fn claim_proof(
+    request_id: &str,
+    nullifier_hash: &str,
+    scheduler: &State<Arc<AsyncScheduler>>,
+) -> Result<Option<Json<ClaimProof>>> {
+    let tree = scheduler.task_tracker.get_claim_tree(request_id).await?;
+    Ok(tree.and_then(|tree| tree.claim_proof(nullifier_hash).map(Json)))
+}
+
+#[get("/tasks")]
+async fn list_tasks(scheduler: &State<Arc<AsyncScheduler>>) -> Json<Vec<SchedulerTaskSummary>> {
+    Json(scheduler.task_tracker.list_statuses().await)
 }
 
 #[derive(Parser, Clone, Debug)]
@@ -146,10 +811,63 @@ struct Cli {
     /// The path to the file with mappings between NodeParams and circuit IDs
     #[arg(long = "cids-path")]
     pub cids_path: PathBuf,
-    #[arg(long = "executor-url")]
-    pub executor_url: String,
+    /// The url of the dispatcher. Required unless proofs are generated in-process with
+    /// `--circuit-data-dir` and `--srs-dir`.
+    #[arg(
+        long = "executor-url",
+        required_unless_present = "circuit_data_dir",
+        conflicts_with = "circuit_data_dir"
+    )]
+    pub executor_url: Option<String>,
+    /// Generate proofs in-process with the circuit data in this directory instead of using a
+    /// dispatcher
+    #[arg(long = "circuit-data-dir", requires = "srs_dir")]
+    pub circuit_data_dir: Option<PathBuf>,
+    /// The srs directory for in-process proving
+    #[arg(long = "srs-dir", requires = "circuit_data_dir")]
+    pub srs_dir: Option<PathBuf>,
+    /// Cache snarks of in-process proving
+    #[arg(long = "out-dir", requires = "circuit_data_dir")]
+    pub out_dir: Option<PathBuf>,
+    /// Maximum number of proofs generated concurrently in-process
+    #[arg(long = "proof-concurrency", default_value_t = 1)]
+    pub proof_concurrency: usize,
     #[arg(long = "execution-summary", default_value = "./execution_summary")]
     pub execution_summary_path: PathBuf,
+    /// The maximum number of retries for a failed node of the aggregation tree
+    #[arg(long = "max-node-retries", default_value_t = 0)]
+    pub max_node_retries: usize,
+    /// Time to wait before retrying a failed node
+    #[arg(long = "retry-backoff-ms", default_value_t = 10000)]
+    pub retry_backoff_ms: u64,
+    /// Deadline per claim for leaf tasks on the dispatcher. A leaf of depth `d` gets `2^d` times
+    /// this before it counts as stuck.
+    #[arg(long = "leaf-timeout-sec-per-claim", default_value_t = 60)]
+    pub leaf_timeout_sec_per_claim: u64,
+    /// Deadline for intermediate and root aggregation tasks on the dispatcher
+    #[arg(long = "aggregation-timeout-sec", default_value_t = 1800)]
+    pub aggregation_timeout_sec: u64,
+    /// Deadline for evm tasks on the dispatcher
+    #[arg(long = "evm-timeout-sec", default_value_t = 1800)]
+    pub evm_timeout_sec: u64,
+    /// How many times a stuck dispatcher task is resubmitted with `forceProve`
+    #[arg(long = "max-resubmits", default_value_t = 2)]
+    pub max_resubmits: usize,
+    /// Directory for persisting scheduler state. If set, unfinished requests are resumed on
+    /// restart.
+    #[arg(long = "state-dir")]
+    pub state_dir: Option<PathBuf>,
+    /// Verifying keys accepted for claims, the first one is the default
+    #[arg(long = "vk-path", default_value = DEFAULT_VK_PATH)]
+    pub vk_paths: Vec<PathBuf>,
+    /// The World ID app id of the external nullifiers. Required if the leaf circuits derive the
+    /// external nullifiers.
+    #[arg(long = "app-id")]
+    pub app_id: Option<String>,
+    /// With rolling aggregation, submit the batches chained so far at this interval instead of
+    /// waiting for all batch slots. The chain can also be submitted with `POST /chain/submit`.
+    #[arg(long = "chain-submit-interval-sec")]
+    pub chain_submit_interval_sec: Option<u64>,
 }
 
 #[launch]
@@ -174,7 +892,27 @@ fn rocket() -> Rocket<Build> {
         cid_to_params.insert(circuit_id, params);
     }
 
-    let task_tracker = SchedulerTaskTracker::new();
+    let vk_registry = VkRegistry::from_paths(&cli.vk_paths).expect("Failed to load vks");
+    if final_circuit_params.num_vks > 1 {
+        assert_eq!(
+            vk_registry.len(),
+            final_circuit_params.num_vks,
+            "The vk set of the circuits must have one key per --vk-path"
+        );
+    }
+    assert_eq!(
+        cli.app_id.is_some(),
+        final_circuit_params.options.external_nullifier,
+        "--app-id must be set if and only if the circuits derive the external nullifiers"
+    );
+
+    let task_tracker = match &cli.state_dir {
+        Some(state_dir) => {
+            let store = SchedulerStore::open(state_dir).expect("Failed to open scheduler store");
+            SchedulerTaskTracker::with_store(store).expect("Failed to load scheduler state")
+        }
+        None => SchedulerTaskTracker::new(),
+    };
 
     const CHAIN_ID: u64 = 11155111;
     let contract_address = env::var("CONTRACT_ADDRESS").expect("CONTRACT_ADDRESS must be set");
@@ -188,20 +926,90 @@ fn rocket() -> Rocket<Build> {
         &provider_uri,
         &contract_address,
         CHAIN_ID,
+        final_circuit_params.version,
     )
     .unwrap();
 
+    let executor: Arc<dyn ProofExecutor> = match cli.executor_url {
+        Some(executor_url) => {
+            // query task status from dispatcher every 5000 ms
+            const DISPATCHER_POLL_INTERVAL: u64 = 5000;
+            // threshold for concurrent tasks
+            const DISPATCHER_CONCURRENCY: usize = 100;
+            // whether to re-prove in case the input for the circuit already has proof from previous runs
+            const FORCE_PROVE: bool = false;
+
+            let timeouts = TaskTimeouts {
+                leaf_per_claim: Duration::from_secs(cli.leaf_timeout_sec_per_claim),
+                aggregation: Duration::from_secs(cli.aggregation_timeout_sec),
+                evm: Duration::from_secs(cli.evm_timeout_sec),
+            };
+            Arc::new(
+                DispatcherExecutor::new(
+                    &executor_url,
+                    DISPATCHER_POLL_INTERVAL,
+                    DISPATCHER_CONCURRENCY,
+                    FORCE_PROVE,
+                    timeouts,
+                    cli.max_resubmits,
+                )
+                .expect("Failed to create dispatcher executor"),
+            )
+        }
+        None => {
+            let prover_config = ProverConfig {
+                circuit_data_dir: cli.circuit_data_dir.unwrap(),
+                srs_dir: cli.srs_dir.unwrap(),
+                out_dir: cli.out_dir,
+                proof_concurrency: cli.proof_concurrency,
+            };
+            let state = ProvingServerState::new(prover_config);
+            Arc::new(LocalExecutor::new(Arc::new(state)))
+        }
+    };
+
     let scheduler: AsyncScheduler = AsyncScheduler::new(
         cids_repo,
         cid_to_params,
-        cli.executor_url,
+        executor,
         task_tracker,
         cli.execution_summary_path,
         contract_client,
-        final_circuit_params
+        final_circuit_params,
+        RetryPolicy {
+            max_retries: cli.max_node_retries,
+            backoff_ms: cli.retry_backoff_ms,
+        },
+        vk_registry,
+        cli.app_id,
     );
 
+    let scheduler = Arc::new(scheduler);
+    let scheduler_clone = Arc::clone(&scheduler);
+    let chain_scheduler = Arc::clone(&scheduler);
+    let chain_submit_interval = cli.chain_submit_interval_sec.map(Duration::from_secs);
+
     rocket::build()
-        .mount("/", routes![serve, index])
-        .manage(Arc::new(scheduler))
+        .mount(
+            "/",
+            routes![
+                serve,
+                task_status,
+                list_tasks,
+                claim_proof,
+                submit_chain_now,
+                index
+            ],
+        )
+        .manage(scheduler)
+        .attach(AdHoc::on_liftoff("Resume requests", |_| {
+            Box::pin(resume_requests(scheduler_clone))
+        }))
+        .attach(AdHoc::on_liftoff("Submit chain", move |_| {
+            Box::pin(async move {
+                if let Some(interval) = chain_submit_interval {
+                    task::spawn(submit_chain_periodically(chain_scheduler, interval));
+                }
+            })
+        }))
 }
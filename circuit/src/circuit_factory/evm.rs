@@ -2,13 +2,15 @@ use anyhow::{anyhow, Result};
 use axiom_eth::{
     halo2_base::gates::circuit::CircuitBuilderStage,
     halo2_proofs::poly::kzg::commitment::ParamsKZG,
-    halo2curves::bn256::Bn256,
+    halo2curves::bn256::{Bn256, Fr},
     snark_verifier_sdk::{halo2::aggregation::AggregationCircuit, Snark},
     utils::{merkle_aggregation::InputMerkleAggregation, snark_verifier::EnhancedSnark},
 };
 use serde::{Deserialize, Serialize};
 
-use crate::{keygen::node_params::PinningEvm, prover::ProofRequest};
+use crate::{
+    keygen::node_params::PinningEvm, native::instances::remove_accumulators, prover::ProofRequest,
+};
 
 use serde_with::serde_as;
 
@@ -59,4 +61,8 @@ impl ProofRequest for WorldcoinRequestEvm {
         }
         Ok(circuit)
     }
+
+    fn expected_instances(&self) -> Result<Vec<Fr>> {
+        Ok(remove_accumulators(std::slice::from_ref(&self.snark)).remove(0))
+    }
 }
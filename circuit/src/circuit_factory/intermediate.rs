@@ -2,16 +2,22 @@ use anyhow::{anyhow, Result};
 use axiom_eth::{
     halo2_base::gates::circuit::CircuitBuilderStage,
     halo2_proofs::poly::kzg::commitment::ParamsKZG,
-    halo2curves::bn256::Bn256,
+    halo2curves::bn256::{Bn256, Fr},
     snark_verifier_sdk::{halo2::aggregation::AggregationCircuit, Snark},
 };
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    keygen::node_params::{PinningIntermediate, PinningIntermediateV2},
+    circuits::{
+        v1::intermediate::WorldcoinIntermediateAggregationInput,
+        v2::intermediate::{
+            WorldcoinIntermediateAggregationCircuitV2, WorldcoinIntermediateAggregationInputV2,
+        },
+    },
+    keygen::node_params::{CircuitOptions, PinningIntermediate, PinningIntermediateV2},
+    native::instances::{children_instances, v1_join_instances, v2_join_instances},
     prover::ProofRequest,
 };
-use crate::{WorldcoinIntermediateAggregationCircuit, WorldcoinIntermediateAggregationInput};
 
 use axiom_eth::utils::snark_verifier::Base64Bytes;
 use serde_with::serde_as;
@@ -26,25 +32,22 @@ pub struct WorldcoinRequestIntermediate {
     pub initial_depth: usize,
     #[serde_as(as = "Vec<Base64Bytes>")]
     pub snarks: Vec<Snark>,
+    /// The modes of the aggregated leaves
+    #[serde(flatten)]
+    pub options: CircuitOptions,
 }
 
+/// Request for proofs [start, end) using the V2 intermediate circuit.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct WorldcoinRequestIntermediateV2(pub WorldcoinRequestIntermediate);
+
 impl ProofRequest for WorldcoinRequestIntermediate {
-    #[cfg(feature = "v1")]
     type Circuit = AggregationCircuit;
-    #[cfg(feature = "v1")]
     type Pinning = PinningIntermediate;
 
-    #[cfg(feature = "v2")]
-    type Circuit = WorldcoinIntermediateAggregationCircuit;
-    #[cfg(feature = "v2")]
-    type Pinning = PinningIntermediateV2;
-
     fn get_k(pinning: &Self::Pinning) -> u32 {
-        #[cfg(feature = "v1")]
-        return pinning.params.degree;
-
-        #[cfg(feature = "v2")]
-        return pinning.params.k() as u32;
+        pinning.params.degree
     }
     /// Legacy filename convention
     fn proof_id(&self) -> String {
@@ -66,37 +69,66 @@ impl ProofRequest for WorldcoinRequestIntermediate {
         let kzg_params = kzg_params.ok_or_else(|| anyhow!("kzg_params not provided"))?;
         let num_proofs = self.end - self.start;
 
-        #[cfg(feature = "v1")]
-        {
-            let input = WorldcoinIntermediateAggregationInput::new(
-                self.snarks,
-                num_proofs,
-                self.depth,
-                self.initial_depth,
-            );
-            let mut circuit = input.build(stage, pinning.params, kzg_params)?.0;
-            if stage.witness_gen_only() {
-                circuit.set_break_points(pinning.break_points);
-            }
-            Ok(circuit)
+        let input = WorldcoinIntermediateAggregationInput::new(
+            self.snarks,
+            num_proofs,
+            self.depth,
+            self.initial_depth,
+            self.options,
+        );
+        let mut circuit = input.build(stage, pinning.params, kzg_params)?.0;
+        if stage.witness_gen_only() {
+            circuit.set_break_points(pinning.break_points);
         }
+        Ok(circuit)
+    }
+
+    fn expected_instances(&self) -> Result<Vec<Fr>> {
+        let prev = children_instances(&self.snarks, 2);
+        v1_join_instances(&prev, self.end - self.start, self.depth, &self.options)
+    }
+}
+
+impl ProofRequest for WorldcoinRequestIntermediateV2 {
+    type Circuit = WorldcoinIntermediateAggregationCircuitV2;
+    type Pinning = PinningIntermediateV2;
+
+    fn get_k(pinning: &Self::Pinning) -> u32 {
+        pinning.params.k() as u32
+    }
+    fn proof_id(&self) -> String {
+        format!("{}_v2", self.0.proof_id())
+    }
+    fn build(
+        self,
+        stage: CircuitBuilderStage,
+        pinning: Self::Pinning,
+        kzg_params: Option<&ParamsKZG<Bn256>>,
+    ) -> Result<Self::Circuit> {
+        let kzg_params = kzg_params.ok_or_else(|| anyhow!("kzg_params not provided"))?;
+        let req = self.0;
+        let num_proofs = req.end - req.start;
 
-        #[cfg(feature = "v2")]
-        {
-            let input = WorldcoinIntermediateAggregationInput::new(
-                self.snarks,
-                num_proofs,
-                self.depth,
-                self.initial_depth,
-                kzg_params,
-            )?;
+        let input = WorldcoinIntermediateAggregationInputV2::new(
+            req.snarks,
+            num_proofs,
+            req.depth,
+            req.initial_depth,
+            req.options,
+            kzg_params,
+        )?;
 
-            let circuit =
-                WorldcoinIntermediateAggregationCircuit::new_impl(stage, input, pinning.params, 0);
-            if stage.witness_gen_only() {
-                circuit.set_break_points(pinning.break_points);
-            }
-            Ok(circuit)
+        let circuit =
+            WorldcoinIntermediateAggregationCircuitV2::new_impl(stage, input, pinning.params, 0);
+        if stage.witness_gen_only() {
+            circuit.set_break_points(pinning.break_points);
         }
+        Ok(circuit)
+    }
+
+    fn expected_instances(&self) -> Result<Vec<Fr>> {
+        let req = &self.0;
+        let prev = children_instances(&req.snarks, 2);
+        v2_join_instances(&prev, req.end - req.start, req.depth, &req.options)
     }
 }
@@ -1,16 +1,28 @@
-use anyhow::{bail, Result};
+use std::iter;
+
+use anyhow::{anyhow, bail, Context, Result};
 use axiom_eth::{
     halo2_base::gates::circuit::CircuitBuilderStage,
     halo2_proofs::poly::kzg::commitment::ParamsKZG,
     halo2curves::bn256::{Bn256, Fr},
 };
+use ethers::types::H256;
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    keygen::node_params::PinningLeaf,
+    circuits::{
+        v1::leaf::WorldcoinLeafCircuit,
+        v2::leaf::{WorldcoinLeafCircuitV2, WorldcoinLeafInputV2},
+    },
+    keygen::node_params::{CircuitOptions, PinningLeaf},
+    native::{
+        groth16::parse_field_element,
+        instances::{
+            get_vk_hash_native, get_vk_set_commitment_native, v1_leaf_instances, v2_leaf_instances,
+        },
+    },
     prover::ProofRequest,
     types::{ClaimNative, VkNative},
-    WorldcoinLeafCircuit,
 };
 
 /// Request for proofs [start, end).
@@ -20,8 +32,171 @@ pub struct WorldcoinRequestLeaf {
     pub end: u32,
     pub depth: usize,
     pub vk: VkNative,
+    /// The other keys of the vk set of a mixed-vk leaf, after `vk`. Every claim is verified
+    /// against the key of its `vk_hash`, or `vk` if it has none.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub extra_vks: Vec<VkNative>,
     pub root: String,
     pub claims: Vec<ClaimNative>,
+    /// The World ID app id of the claims if the leaf derives the external nullifier hashes from
+    /// it and the grant ids in circuit, required with `external_nullifier`. The app id is a
+    /// constant of the circuit, which must then have been generated with the same app id.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub app_id: Option<String>,
+    /// The modes of the leaf. With `fixed_vk`, `vk` is a constant of the circuit, and the public
+    /// input layout is part of the circuit too, so the circuit must have been generated with the
+    /// same `vk` and layout.
+    #[serde(flatten)]
+    pub options: CircuitOptions,
+}
+
+/// Request for proofs [start, end) using the V2 leaf circuit.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct WorldcoinRequestLeafV2(pub WorldcoinRequestLeaf);
+
+impl WorldcoinRequestLeaf {
+    /// The vk set of the leaf, `vk` first
+    pub fn vks(&self) -> impl Iterator<Item = &VkNative> {
+        iter::once(&self.vk).chain(&self.extra_vks)
+    }
+
+    fn vk_hashes(&self) -> Result<Vec<H256>> {
+        self.vks().map(get_vk_hash_native).collect()
+    }
+
+    /// The vk hash, or the commitment to the vk set for a mixed-vk leaf
+    pub fn vk_hash(&self) -> Result<H256> {
+        Ok(get_vk_set_commitment_native(&self.vk_hashes()?))
+    }
+
+    /// The vk hash of a fixed-vk leaf, which has no vk set
+    pub fn fixed_vk_hash(&self) -> Result<Option<H256>> {
+        if !self.options.fixed_vk {
+            return Ok(None);
+        }
+        if !self.extra_vks.is_empty() {
+            bail!("A fixed-vk leaf has a single vk");
+        }
+        get_vk_hash_native(&self.vk).map(Some)
+    }
+
+    /// Check a leaf with a batched Groth16 check has a single vk
+    fn check_batch_groth16(&self) -> Result<()> {
+        if self.options.batch_groth16 && !self.extra_vks.is_empty() {
+            bail!("A leaf with a batched Groth16 check has a single vk");
+        }
+        Ok(())
+    }
+
+    /// Check the circuit of `pinning` was generated with the same fixed vk, if any
+    fn check_fixed_vk(&self, pinning: &PinningLeaf) -> Result<()> {
+        let fixed_vk_hash = self.fixed_vk_hash()?;
+        if fixed_vk_hash != pinning.fixed_vk_hash {
+            bail!(
+                "Fixed vk hash {fixed_vk_hash:?} does not match the circuit's {:?}",
+                pinning.fixed_vk_hash
+            );
+        }
+        Ok(())
+    }
+
+    /// Check the circuit of `pinning` was generated with the same app id, if any
+    fn check_app_id(&self, pinning: &PinningLeaf) -> Result<()> {
+        if self.options.external_nullifier != self.app_id.is_some() {
+            bail!("An app id is required if and only if the leaf derives the external nullifiers");
+        }
+        if self.app_id != pinning.app_id {
+            bail!(
+                "App id {:?} does not match the circuit's {:?}",
+                self.app_id,
+                pinning.app_id
+            );
+        }
+        Ok(())
+    }
+
+    /// Check the circuit of `pinning` was generated with the same public input layout, and the
+    /// vks and claims have the public inputs of the layout
+    fn check_public_inputs(&self, pinning: &PinningLeaf) -> Result<()> {
+        let public_inputs = &self.options.public_inputs;
+        public_inputs.validate()?;
+        if *public_inputs != pinning.public_inputs {
+            bail!(
+                "Public input layout {:?} does not match the circuit's {:?}",
+                public_inputs,
+                pinning.public_inputs
+            );
+        }
+        let num_public_inputs = public_inputs.num_public_inputs;
+        for vk in self.vks() {
+            if vk.num_public_inputs() != num_public_inputs {
+                bail!(
+                    "vk has {} public inputs instead of {num_public_inputs}",
+                    vk.num_public_inputs()
+                );
+            }
+        }
+        let num_extra_public_inputs = public_inputs.num_extra_public_inputs();
+        for (i, claim) in self.claims.iter().enumerate() {
+            if claim.extra_public_inputs.len() != num_extra_public_inputs {
+                bail!(
+                    "Claim {i} has {} extra public inputs instead of {num_extra_public_inputs}",
+                    claim.extra_public_inputs.len()
+                );
+            }
+            for value in &claim.extra_public_inputs {
+                parse_field_element(value)
+                    .with_context(|| format!("Invalid extra public input of claim {i}"))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Index of the verifying key of every claim in the vk set
+    pub fn vk_indices(&self) -> Result<Vec<usize>> {
+        let vk_hashes = self.vk_hashes()?;
+        self.claims
+            .iter()
+            .map(|claim| match claim.vk_hash {
+                None => Ok(0),
+                Some(vk_hash) => vk_hashes
+                    .iter()
+                    .position(|h| *h == vk_hash)
+                    .ok_or_else(|| anyhow!("vk {vk_hash:?} is not in the vk set")),
+            })
+            .collect()
+    }
+
+    /// Check every claim has a valid amount if the leaf commits to claim amounts
+    fn check_amounts(&self) -> Result<()> {
+        if !self.options.claim_amounts {
+            return Ok(());
+        }
+        for (i, claim) in self.claims.iter().enumerate() {
+            let amount = claim
+                .amount
+                .as_ref()
+                .ok_or_else(|| anyhow!("Claim {i} has no amount"))?;
+            parse_field_element(amount).with_context(|| format!("Invalid amount of claim {i}"))?;
+        }
+        Ok(())
+    }
+
+    fn check_range(&self) -> Result<()> {
+        if self.end <= self.start {
+            bail!("Invalid index range: [{}, {}]", self.start, self.end);
+        }
+        let num_proofs = self.end - self.start;
+        if num_proofs > (1 << self.depth) {
+            bail!(
+                "Number of proofs {} is too large for depth {}",
+                num_proofs,
+                self.depth
+            );
+        }
+        Ok(())
+    }
 }
 
 impl ProofRequest for WorldcoinRequestLeaf {
@@ -47,16 +222,14 @@ impl ProofRequest for WorldcoinRequestLeaf {
         pinning: Self::Pinning,
         _: Option<&ParamsKZG<Bn256>>,
     ) -> Result<Self::Circuit> {
-        if self.end <= self.start {
-            bail!("Invalid index range: [{}, {}]", self.start, self.end);
-        }
-        let num_proofs = self.end - self.start;
-        if num_proofs > (1 << self.depth) {
-            bail!(
-                "Number of proofs {} is too large for depth {}",
-                num_proofs,
-                self.depth
-            );
+        self.check_range()?;
+        self.vk_indices()?;
+        self.check_fixed_vk(&pinning)?;
+        self.check_batch_groth16()?;
+        self.check_app_id(&pinning)?;
+        self.check_public_inputs(&pinning)?;
+        if self.options.claim_amounts {
+            bail!("Claim amounts are only committed to by the V2 leaf");
         }
 
         let input = self.into();
@@ -66,4 +239,63 @@ impl ProofRequest for WorldcoinRequestLeaf {
         }
         Ok(circuit)
     }
+
+    fn expected_instances(&self) -> Result<Vec<Fr>> {
+        let vk_hash = self.vk_hash()?;
+        v1_leaf_instances(
+            &vk_hash,
+            &self.root,
+            self.start,
+            self.depth,
+            &self.claims,
+            &self.options,
+        )
+    }
+}
+
+impl ProofRequest for WorldcoinRequestLeafV2 {
+    type Circuit = WorldcoinLeafCircuitV2<Fr>;
+    type Pinning = PinningLeaf;
+    fn get_k(pinning: &Self::Pinning) -> u32 {
+        pinning.params.k() as u32
+    }
+
+    fn proof_id(&self) -> String {
+        format!("{}_v2", self.0.proof_id())
+    }
+
+    fn build(
+        self,
+        stage: CircuitBuilderStage,
+        pinning: Self::Pinning,
+        _: Option<&ParamsKZG<Bn256>>,
+    ) -> Result<Self::Circuit> {
+        self.0.check_range()?;
+        self.0.vk_indices()?;
+        self.0.check_fixed_vk(&pinning)?;
+        self.0.check_batch_groth16()?;
+        self.0.check_app_id(&pinning)?;
+        self.0.check_public_inputs(&pinning)?;
+        self.0.check_amounts()?;
+
+        let input = WorldcoinLeafInputV2::from(self.0);
+        let circuit = WorldcoinLeafCircuitV2::new_impl(stage, input, pinning.params, 0);
+        if stage.witness_gen_only() {
+            circuit.set_break_points(pinning.break_points);
+        }
+        Ok(circuit)
+    }
+
+    fn expected_instances(&self) -> Result<Vec<Fr>> {
+        let req = &self.0;
+        let vk_hash = req.vk_hash()?;
+        v2_leaf_instances(
+            &vk_hash,
+            &req.root,
+            req.start,
+            req.depth,
+            &req.claims,
+            &req.options,
+        )
+    }
 }
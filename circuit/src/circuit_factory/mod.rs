@@ -3,4 +3,5 @@ pub mod evm;
 
 pub mod intermediate;
 pub mod leaf;
+pub mod rolling;
 pub mod root;
@@ -0,0 +1,144 @@
+use anyhow::{anyhow, bail, Result};
+use axiom_eth::{
+    halo2_base::gates::circuit::CircuitBuilderStage,
+    halo2_proofs::poly::kzg::commitment::ParamsKZG,
+    halo2curves::bn256::{Bn256, Fr},
+    snark_verifier_sdk::Snark,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    circuits::{
+        v1::rolling::{WorldcoinRollingAggregationCircuit, WorldcoinRollingAggregationInput},
+        v2::rolling::{WorldcoinRollingAggregationCircuitV2, WorldcoinRollingAggregationInputV2},
+    },
+    keygen::node_params::PinningRolling,
+    native::instances::{prev_instances, v1_rolling_instances, v2_rolling_instances},
+    prover::ProofRequest,
+};
+
+use axiom_eth::utils::snark_verifier::Base64Bytes;
+use serde_with::serde_as;
+
+/// Request to append the root proof of a batch to a rolling proof of the previous batches.
+#[serde_as]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct WorldcoinRequestRolling {
+    /// Number of batches of the new rolling proof. It equals the number of batches of the
+    /// previous proof if the new batch is a dummy.
+    pub num_batches: u32,
+    /// Number of batch slots of the rolling circuit
+    pub max_batches: usize,
+    /// The previous rolling proof, or the root proof of the first batch if `max_batches == 2`,
+    /// followed by the root proof of the new batch
+    #[serde_as(as = "Vec<Base64Bytes>")]
+    pub snarks: Vec<Snark>,
+}
+
+/// Request to append a batch to a rolling proof using the V2 rolling circuit.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct WorldcoinRequestRollingV2(pub WorldcoinRequestRolling);
+
+impl WorldcoinRequestRolling {
+    fn check_num_batches(&self) -> Result<()> {
+        if self.num_batches == 0 || self.num_batches as usize > self.max_batches {
+            bail!(
+                "Number of batches {} is invalid for {} batch slots",
+                self.num_batches,
+                self.max_batches
+            );
+        }
+        if self.snarks.len() != 2 {
+            bail!("Expected the previous proof and the batch proof");
+        }
+        Ok(())
+    }
+}
+
+impl ProofRequest for WorldcoinRequestRolling {
+    type Circuit = WorldcoinRollingAggregationCircuit;
+    type Pinning = PinningRolling;
+    fn get_k(pinning: &Self::Pinning) -> u32 {
+        pinning.params.k() as u32
+    }
+
+    fn proof_id(&self) -> String {
+        format!(
+            "worldcoin_{}_{:06x}_{}_rolling",
+            self.hash(),
+            self.num_batches,
+            self.max_batches
+        )
+    }
+    fn build(
+        self,
+        stage: CircuitBuilderStage,
+        pinning: Self::Pinning,
+        kzg_params: Option<&ParamsKZG<Bn256>>,
+    ) -> Result<Self::Circuit> {
+        let kzg_params = kzg_params.ok_or_else(|| anyhow!("kzg_params not provided"))?;
+        self.check_num_batches()?;
+
+        let input = WorldcoinRollingAggregationInput::new(
+            self.snarks,
+            self.num_batches,
+            self.max_batches,
+            kzg_params,
+        )?;
+
+        let circuit = WorldcoinRollingAggregationCircuit::new_impl(stage, input, pinning.params, 0);
+        if stage.witness_gen_only() {
+            circuit.set_break_points(pinning.break_points);
+        }
+        Ok(circuit)
+    }
+
+    fn expected_instances(&self) -> Result<Vec<Fr>> {
+        let [prev, batch] = prev_instances(&self.snarks);
+        v1_rolling_instances(&prev, &batch, self.num_batches, self.max_batches)
+    }
+}
+
+impl ProofRequest for WorldcoinRequestRollingV2 {
+    type Circuit = WorldcoinRollingAggregationCircuitV2;
+    type Pinning = PinningRolling;
+    fn get_k(pinning: &Self::Pinning) -> u32 {
+        pinning.params.k() as u32
+    }
+
+    fn proof_id(&self) -> String {
+        format!("{}_v2", self.0.proof_id())
+    }
+    fn build(
+        self,
+        stage: CircuitBuilderStage,
+        pinning: Self::Pinning,
+        kzg_params: Option<&ParamsKZG<Bn256>>,
+    ) -> Result<Self::Circuit> {
+        let kzg_params = kzg_params.ok_or_else(|| anyhow!("kzg_params not provided"))?;
+        let req = self.0;
+        req.check_num_batches()?;
+
+        let input = WorldcoinRollingAggregationInputV2::new(
+            req.snarks,
+            req.num_batches,
+            req.max_batches,
+            kzg_params,
+        )?;
+
+        let circuit =
+            WorldcoinRollingAggregationCircuitV2::new_impl(stage, input, pinning.params, 0);
+        if stage.witness_gen_only() {
+            circuit.set_break_points(pinning.break_points);
+        }
+        Ok(circuit)
+    }
+
+    fn expected_instances(&self) -> Result<Vec<Fr>> {
+        let req = &self.0;
+        let [prev, batch] = prev_instances(&req.snarks);
+        v2_rolling_instances(&prev, &batch, req.num_batches, req.max_batches)
+    }
+}
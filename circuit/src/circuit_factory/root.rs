@@ -1,15 +1,21 @@
 use anyhow::{anyhow, bail, Result};
 use axiom_eth::{
     halo2_base::gates::circuit::CircuitBuilderStage,
-    halo2_proofs::poly::kzg::commitment::ParamsKZG, halo2curves::bn256::Bn256,
+    halo2_proofs::poly::kzg::commitment::ParamsKZG,
+    halo2curves::bn256::{Bn256, Fr},
     snark_verifier_sdk::Snark,
 };
 
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    keygen::node_params::PinningRoot, prover::ProofRequest, WorldcoinRootAggregationCircuit,
-    WorldcoinRootAggregationInput,
+    circuits::{
+        v1::root::{WorldcoinRootAggregationCircuit, WorldcoinRootAggregationInput},
+        v2::root::{WorldcoinRootAggregationCircuitV2, WorldcoinRootAggregationInputV2},
+    },
+    keygen::node_params::{CircuitOptions, PinningRoot},
+    native::instances::{children_instances, v1_root_instances, v2_root_instances},
+    prover::ProofRequest,
 };
 
 use axiom_eth::utils::snark_verifier::Base64Bytes;
@@ -25,6 +31,31 @@ pub struct WorldcoinRequestRoot {
     pub initial_depth: usize,
     #[serde_as(as = "Vec<Base64Bytes>")]
     pub snarks: Vec<Snark>,
+    /// The modes of the aggregated leaves
+    #[serde(flatten)]
+    pub options: CircuitOptions,
+}
+
+/// Request for proofs [start, end) using the V2 root circuit.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct WorldcoinRequestRootV2(pub WorldcoinRequestRoot);
+
+impl WorldcoinRequestRoot {
+    fn check_range(&self) -> Result<u32> {
+        if self.end < self.start {
+            bail!("Invalid index range: [{}, {}]", self.start, self.end);
+        }
+        let num_proofs = self.end - self.start;
+        if num_proofs > (1 << self.depth) {
+            bail!(
+                "Number of proofs {} is too large for depth {}",
+                num_proofs,
+                self.depth
+            );
+        }
+        Ok(num_proofs)
+    }
 }
 
 impl ProofRequest for WorldcoinRequestRoot {
@@ -51,24 +82,14 @@ impl ProofRequest for WorldcoinRequestRoot {
         kzg_params: Option<&ParamsKZG<Bn256>>,
     ) -> Result<Self::Circuit> {
         let kzg_params = kzg_params.ok_or_else(|| anyhow!("kzg_params not provided"))?;
-
-        if self.end < self.start {
-            bail!("Invalid index range: [{}, {}]", self.start, self.end);
-        }
-        let num_proofs = self.end - self.start;
-        if num_proofs > (1 << self.depth) {
-            bail!(
-                "Number of proofs {} is too large for depth {}",
-                num_proofs,
-                self.depth
-            );
-        }
+        let num_proofs = self.check_range()?;
 
         let input = WorldcoinRootAggregationInput::new(
             self.snarks,
             num_proofs,
             self.depth,
             self.initial_depth,
+            self.options,
             kzg_params,
         )?;
 
@@ -78,4 +99,52 @@ impl ProofRequest for WorldcoinRequestRoot {
         }
         Ok(circuit)
     }
+
+    fn expected_instances(&self) -> Result<Vec<Fr>> {
+        let prev = children_instances(&self.snarks, 2);
+        v1_root_instances(&prev, self.end - self.start, self.depth, &self.options)
+    }
+}
+
+impl ProofRequest for WorldcoinRequestRootV2 {
+    type Circuit = WorldcoinRootAggregationCircuitV2;
+    type Pinning = PinningRoot;
+    fn get_k(pinning: &Self::Pinning) -> u32 {
+        pinning.params.k() as u32
+    }
+
+    fn proof_id(&self) -> String {
+        format!("{}_v2", self.0.proof_id())
+    }
+    fn build(
+        self,
+        stage: CircuitBuilderStage,
+        pinning: Self::Pinning,
+        kzg_params: Option<&ParamsKZG<Bn256>>,
+    ) -> Result<Self::Circuit> {
+        let kzg_params = kzg_params.ok_or_else(|| anyhow!("kzg_params not provided"))?;
+        let req = self.0;
+        let num_proofs = req.check_range()?;
+
+        let input = WorldcoinRootAggregationInputV2::new(
+            req.snarks,
+            num_proofs,
+            req.depth,
+            req.initial_depth,
+            req.options,
+            kzg_params,
+        )?;
+
+        let circuit = WorldcoinRootAggregationCircuitV2::new_impl(stage, input, pinning.params, 0);
+        if stage.witness_gen_only() {
+            circuit.set_break_points(pinning.break_points);
+        }
+        Ok(circuit)
+    }
+
+    fn expected_instances(&self) -> Result<Vec<Fr>> {
+        let req = &self.0;
+        let prev = children_instances(&req.snarks, 2);
+        v2_root_instances(&prev, req.end - req.start, req.depth, &req.options)
+    }
 }
@@ -0,0 +1,383 @@
+//! Batched verification of Groth16 proofs against a single verifying key, with one multi Miller
+//! loop and one final exponentiation for all proofs instead of a pairing check per proof.
+//!
+//! Proof `j` verifies if `e(A_j, B_j) = e(alpha, beta) * e(L_j, gamma) * e(C_j, delta)` with
+//! `L_j = IC_0 + sum_i pi_{j,i} IC_{i+1}`. The equations are combined with the powers `r_j = r^j`
+//! of a challenge `r`, the Poseidon hash of the vk hash, the proofs and their public inputs:
+//!
+//! `prod_j e(r_j A_j, B_j) * e(-(sum_j r_j) alpha, beta) * e(-sum_i s_i IC_i, gamma) *
+//! e(-sum_j r_j C_j, delta) = 1`, where `s_0 = sum_j r_j` and `s_{i+1} = sum_j r_j pi_{j,i}`.
+//!
+//! If some proof does not verify, this holds for at most `n` challenges out of `|Fr|`. The
+//! pairing is only bilinear on the prime order subgroups, so `A_j` and `C_j` are constrained to
+//! be on the curve, whose points over `Fq` all have prime order, and `B_j` to be in the prime
+//! order subgroup G2 of the twist. The vk is not checked, as the vk hash is exposed.
+
+use std::iter;
+
+use axiom_components::{
+    groth16::{
+        get_groth16_consts_from_max_pi,
+        types::{Groth16VerifierComponentProof, Groth16VerifierComponentVerificationKey},
+        utils::{HiLoPair, HiLoPoint},
+    },
+    utils::flatten::InputFlatten,
+};
+use axiom_eth::{
+    halo2_base::{
+        gates::{
+            flex_gate::threads::{parallelize_core, SinglePhaseCoreManager},
+            GateInstructions, RangeChip, RangeInstructions,
+        },
+        poseidon::hasher::{spec::OptimizedPoseidonSpec, PoseidonHasher},
+        utils::{biguint_to_fe, fe_to_biguint},
+        AssignedValue, Context,
+        QuantumCell::{Constant, Existing},
+    },
+    halo2curves::{
+        bn256::{Fq, Fq12, G1Affine, G2Affine, FROBENIUS_COEFF_FQ12_C1},
+        ff::Field as _,
+    },
+    snark_verifier::loader::halo2::halo2_ecc::{
+        bigint::ProperCrtUint,
+        bn254::{pairing::PairingChip, Fp12Chip, Fp2Chip, FpChip},
+        ecc::{EcPoint, EccChip},
+        fields::{vector::FieldVector, FieldChip},
+    },
+    utils::hilo::HiLo,
+    Field,
+};
+use itertools::Itertools;
+use num_bigint::BigUint;
+
+use crate::constants::{LIMB_BITS, NUM_LIMBS};
+
+type G1Point<F> = EcPoint<F, ProperCrtUint<F>>;
+type G2Point<F> = EcPoint<F, FieldVector<ProperCrtUint<F>>>;
+
+/// Window of the scalar multiplications by a variable scalar
+const WINDOW_BITS: usize = 4;
+
+/// `6x^2 = p - r` for the BN parameter `x = 4965661367192848881` of BN254
+const SIX_X_SQUARED: u128 = 147946756881789318990833708069417712966;
+
+/// Poseidon parameters of the challenge, the same as the snark-verifier transcript
+const POSEIDON_T: usize = 3;
+const POSEIDON_RATE: usize = 2;
+const POSEIDON_R_F: usize = 8;
+const POSEIDON_R_P: usize = 57;
+
+/// Constrains every proof of `proofs` to verify against `vk_bytes`, the flattened vk committed to
+/// by `vk_hash`. Every proof is given as its flattened bytes and its public inputs, and proofs
+/// may repeat.
+pub fn batch_verify_groth16<F: Field>(
+    pool: &mut SinglePhaseCoreManager<F>,
+    range: &RangeChip<F>,
+    vk_bytes: &[AssignedValue<F>],
+    vk_hash: HiLo<AssignedValue<F>>,
+    proofs: Vec<(Vec<AssignedValue<F>>, Vec<AssignedValue<F>>)>,
+    num_public_inputs: usize,
+) {
+    assert!(!proofs.is_empty());
+    assert!(proofs.iter().all(|(_, pi)| pi.len() == num_public_inputs));
+    let gate = range.gate();
+    let fp_chip = FpChip::<F>::new(range, LIMB_BITS, NUM_LIMBS);
+    let g1_chip = EccChip::new(&fp_chip);
+    let scalar_bits = F::NUM_BITS as usize;
+
+    // the challenge is derived from everything the equations depend on
+    let ctx = pool.main();
+    let spec = OptimizedPoseidonSpec::<F, POSEIDON_T, POSEIDON_RATE>::new::<
+        POSEIDON_R_F,
+        POSEIDON_R_P,
+        0,
+    >();
+    let mut hasher = PoseidonHasher::new(spec);
+    hasher.initialize_consts(ctx, gate);
+    let transcript = vk_hash
+        .hi_lo()
+        .into_iter()
+        .chain(
+            proofs
+                .iter()
+                .flat_map(|(proof, pi)| proof.iter().chain(pi).copied()),
+        )
+        .collect_vec();
+    let challenge = hasher.hash_fix_len_array(ctx, gate, &transcript);
+    let powers = iter::successors(Some(challenge), |power| {
+        Some(gate.mul(ctx, *power, challenge))
+    })
+    .take(proofs.len())
+    .collect_vec();
+
+    // s_0 = sum_j r_j, s_{i+1} = sum_j r_j pi_{j,i}
+    let ic_scalars = iter::once(gate.sum(ctx, powers.iter().copied()))
+        .chain((0..num_public_inputs).map(|i| {
+            let column = proofs.iter().map(|(_, pi)| Existing(pi[i]));
+            gate.inner_product(ctx, powers.iter().copied(), column)
+        }))
+        .collect_vec();
+
+    let points = parallelize_core(
+        pool,
+        proofs
+            .into_iter()
+            .zip_eq(powers.iter().copied())
+            .collect_vec(),
+        |ctx, ((proof, _), power)| {
+            let proof = Groth16VerifierComponentProof::unflatten(proof).unwrap();
+            let a = load_g1(ctx, range, &fp_chip, proof.a);
+            let b = load_g2(ctx, range, &fp_chip, proof.b);
+            let c = load_g1(ctx, range, &fp_chip, proof.c);
+            g1_chip.assert_is_on_curve::<G1Affine>(ctx, &a);
+            g1_chip.assert_is_on_curve::<G1Affine>(ctx, &c);
+            assert_in_g2(ctx, &fp_chip, &b);
+            let a = g1_chip.scalar_mult::<G1Affine>(ctx, a, vec![power], scalar_bits, WINDOW_BITS);
+            (a, b, c)
+        },
+    );
+    let (a, b, c): (Vec<_>, Vec<_>, Vec<_>) = points.into_iter().multiunzip();
+
+    let ctx = pool.main();
+    let constants = get_groth16_consts_from_max_pi(num_public_inputs);
+    let vk = Groth16VerifierComponentVerificationKey::unflatten(
+        vk_bytes.to_vec(),
+        constants.gamma_abc_g1_len,
+    );
+    let alpha = load_g1(ctx, range, &fp_chip, vk.alpha_g1);
+    let [beta, gamma, delta] =
+        [vk.beta_g2, vk.gamma_g2, vk.delta_g2].map(|point| load_g2(ctx, range, &fp_chip, point));
+    let ic = vk
+        .gamma_abc_g1
+        .into_iter()
+        .take(num_public_inputs + 1)
+        .map(|point| load_g1(ctx, range, &fp_chip, point))
+        .collect_vec();
+    let alpha =
+        g1_chip.scalar_mult::<G1Affine>(ctx, alpha, vec![ic_scalars[0]], scalar_bits, WINDOW_BITS);
+
+    let ic = g1_chip.variable_base_msm::<G1Affine>(
+        pool,
+        &ic,
+        ic_scalars.into_iter().map(|s| vec![s]).collect(),
+        scalar_bits,
+    );
+    let c = g1_chip.variable_base_msm::<G1Affine>(
+        pool,
+        &c,
+        powers.into_iter().map(|r| vec![r]).collect(),
+        scalar_bits,
+    );
+
+    let ctx = pool.main();
+    let [alpha, ic, c] = [alpha, ic, c].map(|point| g1_chip.negate(ctx, point));
+    let pairs = a
+        .iter()
+        .zip_eq(&b)
+        .chain([(&alpha, &beta), (&ic, &gamma), (&c, &delta)])
+        .collect_vec();
+    let f = PairingChip::new(&fp_chip).multi_miller_loop(ctx, pairs);
+    let fp12_chip = Fp12Chip::new(&fp_chip);
+    let f = fp12_chip.final_exp(ctx, f);
+    let one = fp12_chip.load_constant(ctx, Fq12::ONE);
+    fp12_chip.assert_equal(ctx, f, one);
+}
+
+/// Loads the base field element `hi * 2^128 + lo` as its limbs, constrained to recompose to `hi`
+/// and `lo`. The middle limb is split at bit 128.
+fn load_fq<F: Field>(
+    ctx: &mut Context<F>,
+    range: &RangeChip<F>,
+    fp_chip: &FpChip<F>,
+    value: HiLo<AssignedValue<F>>,
+) -> ProperCrtUint<F> {
+    assert_eq!((NUM_LIMBS, LIMB_BITS), (3, 88));
+    let gate = range.gate();
+    let [hi, lo] = value.hi_lo();
+    let big = (fe_to_biguint(hi.value()) << 128) + fe_to_biguint(lo.value());
+    let fq = fp_chip.load_private(ctx, biguint_to_fe::<Fq>(&big));
+    let &[l0, l1, l2] = fq.limbs() else {
+        unreachable!()
+    };
+    let (l1_hi, l1_lo) =
+        range.div_mod(ctx, l1, BigUint::from(1u64) << (128 - LIMB_BITS), LIMB_BITS);
+    let lo_limbs = gate.mul_add(
+        ctx,
+        l1_lo,
+        Constant(biguint_to_fe(&(BigUint::from(1u64) << LIMB_BITS))),
+        l0,
+    );
+    ctx.constrain_equal(&lo_limbs, &lo);
+    let hi_limbs = gate.mul_add(
+        ctx,
+        l2,
+        Constant(biguint_to_fe(
+            &(BigUint::from(1u64) << (2 * LIMB_BITS - 128)),
+        )),
+        l1_hi,
+    );
+    ctx.constrain_equal(&hi_limbs, &hi);
+    fq
+}
+
+fn load_g1<F: Field>(
+    ctx: &mut Context<F>,
+    range: &RangeChip<F>,
+    fp_chip: &FpChip<F>,
+    (x, y): HiLoPoint<AssignedValue<F>>,
+) -> G1Point<F> {
+    let x = load_fq(ctx, range, fp_chip, x);
+    let y = load_fq(ctx, range, fp_chip, y);
+    EcPoint::new(x, y)
+}
+
+/// Loads a point of the twist, with coordinates `(c0, c1)` as in the snarkjs json
+fn load_g2<F: Field>(
+    ctx: &mut Context<F>,
+    range: &RangeChip<F>,
+    fp_chip: &FpChip<F>,
+    ((x0, x1), (y0, y1)): HiLoPair<AssignedValue<F>>,
+) -> G2Point<F> {
+    let x = FieldVector(vec![
+        load_fq(ctx, range, fp_chip, x0),
+        load_fq(ctx, range, fp_chip, x1),
+    ]);
+    let y = FieldVector(vec![
+        load_fq(ctx, range, fp_chip, y0),
+        load_fq(ctx, range, fp_chip, y1),
+    ]);
+    EcPoint::new(x, y)
+}
+
+/// Constrains `point` to be on the twist and in G2, by `psi(Q) = [p]Q = [6x^2]Q` for the
+/// endomorphism `psi(x, y) = (conj(x) gamma^2, conj(y) gamma^3)` with `gamma = xi^((p - 1) / 6)`.
+/// This is the membership test of section 4.3 of <https://eprint.iacr.org/2022/352.pdf>.
+fn assert_in_g2<F: Field>(ctx: &mut Context<F>, fp_chip: &FpChip<F>, point: &G2Point<F>) {
+    let fp2_chip = Fp2Chip::new(fp_chip);
+    let g2_chip = EccChip::new(&fp2_chip);
+    g2_chip.assert_is_on_curve::<G2Affine>(ctx, point);
+
+    let gamma = FROBENIUS_COEFF_FQ12_C1[1];
+    let gamma_2 = gamma * gamma;
+    let gamma_3 = gamma_2 * gamma;
+    let gamma_2 = fp2_chip.load_constant(ctx, gamma_2);
+    let gamma_3 = fp2_chip.load_constant(ctx, gamma_3);
+    let x = fp2_chip.conjugate(ctx, point.x().clone());
+    let y = fp2_chip.conjugate(ctx, point.y().clone());
+    let psi = EcPoint::new(fp2_chip.mul(ctx, x, gamma_2), fp2_chip.mul(ctx, y, gamma_3));
+
+    let scalar = ctx.load_constant(biguint_to_fe(&BigUint::from(SIX_X_SQUARED)));
+    let multiple = g2_chip.scalar_mult::<G2Affine>(
+        ctx,
+        point.clone(),
+        vec![scalar],
+        u128::BITS as usize - SIX_X_SQUARED.leading_zeros() as usize,
+        WINDOW_BITS,
+    );
+    g2_chip.assert_equal(ctx, psi, multiple);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs::File, path::Path};
+
+    use axiom_eth::{
+        halo2_base::gates::circuit::builder::BaseCircuitBuilder, halo2_proofs::dev::MockProver,
+        halo2curves::bn256::Fr,
+    };
+    use serde::Deserialize;
+
+    use crate::{
+        native::{
+            groth16::{claim_public_inputs, parse_field_element, PublicInputMode},
+            instances::{get_vk_hash_native, hi_lo},
+        },
+        types::{get_pf_string, parse_proof, parse_vk, ClaimNative},
+        vk_registry::{read_vk, DEFAULT_VK_PATH},
+    };
+
+    use super::*;
+
+    const K: usize = 20;
+
+    #[derive(Deserialize)]
+    struct SampleRequest {
+        root: String,
+        claims: Vec<ClaimNative>,
+    }
+
+    /// The flattened proofs of the sample request with their public inputs
+    fn sample_proofs() -> Vec<(Vec<Fr>, Vec<Fr>)> {
+        let file = File::open("./data/generated_proofs_2.json").unwrap();
+        let SampleRequest { root, claims } = serde_json::from_reader(file).unwrap();
+        let root = parse_field_element(&root).unwrap();
+        claims
+            .iter()
+            .map(|claim| {
+                let proof = parse_proof(get_pf_string(&claim.proof));
+                let public_inputs =
+                    claim_public_inputs(root, claim, &PublicInputMode::default()).unwrap();
+                (proof, public_inputs)
+            })
+            .collect()
+    }
+
+    /// Whether the batched check of `proofs` against the default vk is satisfied
+    fn is_satisfied(proofs: Vec<(Vec<Fr>, Vec<Fr>)>) -> bool {
+        let vk = read_vk(Path::new(DEFAULT_VK_PATH)).unwrap();
+        let num_public_inputs = vk.num_public_inputs();
+        let vk_hash = get_vk_hash_native(&vk).unwrap();
+
+        let mut builder = BaseCircuitBuilder::<Fr>::new(false)
+            .use_k(K)
+            .use_lookup_bits(K - 1);
+        let range = builder.range_chip();
+        let ctx = builder.main(0);
+        let vk_bytes = ctx.assign_witnesses(parse_vk(
+            serde_json::to_string(&vk).unwrap(),
+            num_public_inputs,
+        ));
+        let [hi, lo] = hi_lo(&vk_hash).map(|value| ctx.load_witness(value));
+        let proofs = proofs
+            .into_iter()
+            .map(|(proof, pi)| (ctx.assign_witnesses(proof), ctx.assign_witnesses(pi)))
+            .collect();
+        batch_verify_groth16(
+            builder.pool(0),
+            &range,
+            &vk_bytes,
+            HiLo::from_hi_lo([hi, lo]),
+            proofs,
+            num_public_inputs,
+        );
+
+        builder.calculate_params(Some(20));
+        MockProver::run(K as u32, &builder, vec![])
+            .unwrap()
+            .verify()
+            .is_ok()
+    }
+
+    #[test]
+    fn test_batch_verify_groth16_accepts_valid_proofs() {
+        let proofs = sample_proofs();
+        // padding repeats proofs
+        let batch = vec![proofs[0].clone(), proofs[1].clone(), proofs[0].clone()];
+        assert!(is_satisfied(batch));
+    }
+
+    #[test]
+    fn test_batch_verify_groth16_rejects_a_tampered_proof() {
+        let proofs = sample_proofs();
+        // a valid proof, but for the public inputs of the other claim
+        let tampered = (proofs[1].0.clone(), proofs[0].1.clone());
+        assert!(!is_satisfied(vec![tampered, proofs[1].clone()]));
+    }
+
+    #[test]
+    fn test_batch_verify_groth16_rejects_a_tampered_public_input() {
+        let mut proofs = sample_proofs();
+        proofs[1].1[1] += Fr::from(1);
+        assert!(!is_satisfied(proofs));
+    }
+}
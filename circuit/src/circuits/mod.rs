@@ -1,2 +1,3 @@
+pub mod groth16;
 pub mod v1;
 pub mod v2;
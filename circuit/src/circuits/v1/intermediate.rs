@@ -1,16 +1,25 @@
-//! Intermediate aggregation circuits that aggregate in a binary tree topology:
+//! Intermediate aggregation circuits that aggregate in a tree topology, binary by default:
 //! The leaves of the tree are formed by [WorldcoinLeafCircuit]s, and intermediate notes
 //! of the tree are formed by [WorldcoinIntermediateAggreagtionCircuit]s.
 //!
 //! An [WorldcoinIntermediateAggregationCircuit] can aggregate either:
-//! - two [WorldcoinLeafCircuit]s or
-//! - two [WorldcoinIntermediateAggreagtionCircuit]s.
+//! - `arity` [WorldcoinLeafCircuit]s or
+//! - `arity` [WorldcoinIntermediateAggreagtionCircuit]s,
+//!
+//! where the arity of each layer is a power of two.
 //!
 //! The root of the aggregation tree will be a [WorldcoinRootAggregationCircuit].
 //! Intermediate and Root aggregation circuits have different public outputs.
 //! Intermediate Aggregation circuit public outputs: [start, end, vk_hash_hi, vk_hash_lo, root, ...grant_ids, ...receivers, ...nullifier_hashes]
 //! Root Aggregation circuit public outputs: [output_hash_hi, output_hash_lo], where output is
 //! [vk_hash_hi, vk_hash_lo, root, num_proofs, ...grant_ids, ...receivers, ...nullifier_hashes]
+//!
+//! Each block has `2^max_depth` entries, and the entries of claims past `num_proofs` are zero.
+//!
+//! With per-claim roots, `root` is zero and a `...roots` block is appended after `...nullifier_hashes`.
+//!
+//! With unique nullifiers, the nullifier hashes of each leaf are strictly increasing, and every
+//! aggregation checks the last nullifier hash of each proof is less than the first of the next.
 use anyhow::{bail, Ok, Result};
 use axiom_eth::{
     halo2_base::{
@@ -33,6 +42,8 @@ use axiom_eth::{
 };
 use itertools::Itertools;
 
+use crate::{keygen::node_params::CircuitOptions, utils::constrain_nullifier_boundary};
+
 pub struct WorldcoinIntermediateAggregationCircuit(pub AggregationCircuit);
 
 /// The input to create an intermediate [AggregationCircuit] that aggregates [WorldcoinLeafCircuit]s.
@@ -42,14 +53,17 @@ pub struct WorldcoinIntermediateAggregationCircuit(pub AggregationCircuit);
 pub struct WorldcoinIntermediateAggregationInput {
     // aggregation circuit with `instances` the accumulator (two G1 points) for delayed pairing verification
     pub num_proofs: u32,
-    /// `snarks` should be exactly two snarks of either
-    /// - `WorldcoinLeafCircuit` if `max_depth == initial_depth + 1` or
+    /// `snarks` should be exactly `arity` snarks, a power of two, of either
+    /// - `WorldcoinLeafCircuit` if `max_depth == initial_depth + log2(arity)` or
     /// - `WorldcoinIntermediateAggregationCircuit` (this circuit) otherwise
     ///
     /// Assumes `num_proofs > 0`.
     pub snarks: Vec<Snark>,
     pub max_depth: usize,
     pub initial_depth: usize,
+    /// With `per_claim_roots`, each claim carries its own root in an extra instance block. With
+    /// `unique_nullifiers`, the nullifier hashes must be strictly increasing across the proofs.
+    pub options: CircuitOptions,
 }
 
 impl WorldcoinIntermediateAggregationInput {
@@ -58,10 +72,10 @@ impl WorldcoinIntermediateAggregationInput {
         num_proofs: u32,
         max_depth: usize,
         initial_depth: usize,
+        options: CircuitOptions,
     ) -> Self {
         assert_ne!(num_proofs, 0);
-        assert_eq!(snarks.len(), 2);
-        assert!(max_depth > initial_depth);
+        assert!(Self::child_depth(max_depth, snarks.len()) >= initial_depth);
         assert!(num_proofs <= 1 << max_depth);
 
         Self {
@@ -69,14 +83,24 @@ impl WorldcoinIntermediateAggregationInput {
             num_proofs,
             max_depth,
             initial_depth,
+            options,
         }
     }
 
-    /// Takes the concatenated previous instances from two `WorldcoinIntermediateAggregationCircuit`s
-    /// of max depth `max_depth - 1` and
+    /// Max depth of the children of a node of max depth `max_depth` with `arity` children. The
+    /// arity is a power of two, so the claims of the children form the subtrees of depth
+    /// `max_depth - log2(arity)` of the node's claim tree.
+    pub fn child_depth(max_depth: usize, arity: usize) -> usize {
+        assert!(arity >= 2 && arity.is_power_of_two());
+        assert!(arity.ilog2() as usize <= max_depth);
+        max_depth - arity.ilog2() as usize
+    }
+
+    /// Takes the concatenated previous instances from `arity` `WorldcoinIntermediateAggregationCircuit`s
+    /// of max depth `max_depth - log2(arity)` and
     /// - checks that they form a chain of `max_depth`
     ///
-    /// If `max_depth - 1 == initial_depth`, then the previous instances are from two `WorldcoinLeafCircuit`s.
+    /// If `max_depth - log2(arity) == initial_depth`, then the previous instances are from `WorldcoinLeafCircuit`s.
     ///
     /// Returns the new instances for the depth `max_depth` circuit (without accumulators)
     ///
@@ -91,15 +115,26 @@ impl WorldcoinIntermediateAggregationInput {
     /// [5, 5 + 1 << max_depth) grant_ids_i
     /// [5 + 1 << max_depth, 5 + 2 * (1 << max_depth)) receivers_i
     /// [5 + 2 * (1 << max_depth), 5 + 3 * (1 << max_depth)) nullifier_hashes_i
+    /// [5 + 3 * (1 << max_depth), 5 + 4 * (1 << max_depth)) roots_i, only if `options.per_claim_roots`
+    ///
+    /// With `options.unique_nullifiers`, also checks the nullifier hashes are strictly increasing across
+    /// consecutive proofs.
     pub fn join_previous_instances<F: Field>(
         ctx: &mut Context<F>,
         range: &RangeChip<F>,
-        prev_instances: [Vec<AssignedValue<F>>; 2],
+        prev_instances: Vec<Vec<AssignedValue<F>>>,
         num_proofs: AssignedValue<F>,
         max_depth: usize,
+        options: &CircuitOptions,
     ) -> Vec<AssignedValue<F>> {
-        let num_instance_prev_depth = Self::get_num_instance(max_depth - 1);
-        let (mut instances, _) = Self::check_and_join_shared_instances(
+        let CircuitOptions {
+            per_claim_roots,
+            unique_nullifiers,
+            ..
+        } = *options;
+        let prev_depth = Self::child_depth(max_depth, prev_instances.len());
+        let num_instance_prev_depth = Self::get_num_instance(prev_depth, per_claim_roots);
+        let (mut instances, is_proof_dummy) = Self::check_and_join_shared_instances(
             ctx,
             range,
             &prev_instances,
@@ -107,101 +142,147 @@ impl WorldcoinIntermediateAggregationInput {
             max_depth,
             num_instance_prev_depth,
         );
-        let [instance0, instance1] = prev_instances;
 
-        // combine grant_ids
-        let prev_depth = max_depth - 1;
+        // combine grant_ids, receivers, nullifier_hashes (and roots) block by block. If a proof
+        // is dummy, its claims are padding, which is exposed as zero.
         let max_proofs_prev_depth = 1 << prev_depth;
-        instances.extend(&instance0[5..5 + max_proofs_prev_depth]);
-        instances.extend(&instance1[5..5 + max_proofs_prev_depth]);
 
-        // combine receivers
-        instances.extend(&instance0[5 + max_proofs_prev_depth..5 + 2 * max_proofs_prev_depth]);
-        instances.extend(&instance1[5 + max_proofs_prev_depth..5 + 2 * max_proofs_prev_depth]);
+        // if a proof is not dummy, the proof before it is full, so its last nullifier hash is the
+        // last of its block
+        if unique_nullifiers {
+            let nullifier_hashes_start = 5 + 2 * max_proofs_prev_depth;
+            for (i, is_dummy) in is_proof_dummy.iter().enumerate() {
+                constrain_nullifier_boundary(
+                    ctx,
+                    range,
+                    prev_instances[i][nullifier_hashes_start + max_proofs_prev_depth - 1],
+                    prev_instances[i + 1][nullifier_hashes_start],
+                    *is_dummy,
+                );
+            }
+        }
 
-        // combine nullifier_hashes
-        instances.extend(&instance0[5 + 2 * max_proofs_prev_depth..5 + 3 * max_proofs_prev_depth]);
-        instances.extend(&instance1[5 + 2 * max_proofs_prev_depth..5 + 3 * max_proofs_prev_depth]);
+        for block in 0..Self::num_claim_blocks(per_claim_roots) {
+            let block_range =
+                5 + block * max_proofs_prev_depth..5 + (block + 1) * max_proofs_prev_depth;
+            instances.extend(&prev_instances[0][block_range.clone()]);
+            for (instance, is_dummy) in prev_instances[1..].iter().zip_eq(&is_proof_dummy) {
+                instances.extend(
+                    instance[block_range.clone()]
+                        .iter()
+                        .map(|value| range.gate().mul_not(ctx, *is_dummy, *value)),
+                );
+            }
+        }
 
         instances
     }
 
-    /// Sanity check against the start & end indexes of two proofs, check instances[2..5] (vk_hash_hi, vk_hash_lo, root) are equal.
-    /// Return new start, end indexes for this aggregated proof and a selector indicating whether the 2nd proof is dummy.
+    /// Sanity check against the start & end indexes of the proofs, check instances[2..5] (vk_hash_hi, vk_hash_lo, root) are equal.
+    /// For mixed-vk leaves `vk_hash` is the commitment to the vk set, so all children must use the same vk set.
+    /// Return new start, end indexes for this aggregated proof and selectors indicating whether the proofs after the 1st are dummy.
     ///
-    /// If the the 2nd proof is not dummy, the proofs should link up.
-    /// If the 2nd proof is dummy, num_proofs should <= max_proofs_prev_depth
+    /// The `i`-th proof is dummy if num_proofs <= i * max_proofs_prev_depth. Every proof which is not dummy
+    /// should link up with the proof before it, which should then be full.
     ///
-    /// # Returns a tuple of (new_instances, is_2nd_proof_dummy)
+    /// # Returns a tuple of (new_instances, is_proof_dummy)
     /// - `new_instances` the first 5 instances (shared by v1/v2 intermediate and root) circuits. [start, end, vk_hash_hi, vk_hash_lo, root]
-    /// - `is_2nd_proof_dummy` a selector to indicate whether the 2nd proof is dummy
+    /// - `is_proof_dummy` a selector for each proof after the 1st to indicate whether it is dummy
     pub fn check_and_join_shared_instances<F: Field>(
         ctx: &mut Context<F>,
         range: &RangeChip<F>,
-        prev_instances: &[Vec<AssignedValue<F>>; 2],
+        prev_instances: &[Vec<AssignedValue<F>>],
         num_proofs: AssignedValue<F>,
         max_depth: usize,
         num_instance_prev_depth: usize,
-    ) -> (Vec<AssignedValue<F>>, AssignedValue<F>) {
-        let [instances0, instances1] = prev_instances;
-
-        assert_eq!(num_instance_prev_depth, instances0.len());
-        assert_eq!(num_instance_prev_depth, instances1.len());
+    ) -> (Vec<AssignedValue<F>>, Vec<AssignedValue<F>>) {
+        for instances in prev_instances {
+            assert_eq!(num_instance_prev_depth, instances.len());
+        }
 
         // join & sanitize index
-        let (start_idx, intermed_idx0) = (instances0[0], instances0[1]);
-        let (intermed_idx1, mut end_idx) = (instances1[0], instances1[1]);
-        let num_proofs0 = range.gate().sub(ctx, intermed_idx0, start_idx);
-        let num_proofs1 = range.gate().sub(ctx, end_idx, intermed_idx1);
-
-        let prev_depth = max_depth - 1;
-        let prev_max_proofs_plus_one = (1 << prev_depth) + 1;
-        range.check_less_than_safe(ctx, num_proofs0, prev_max_proofs_plus_one);
-        range.check_less_than_safe(ctx, num_proofs1, prev_max_proofs_plus_one);
-
-        // indicator of whether the 2nd proof is a dummy proof
-        let is_2nd_proof_dummy = range.is_less_than_safe(ctx, num_proofs, prev_max_proofs_plus_one);
-
-        // if the 2nd proof is dummy, the end index for the aggregation should be the end index of the
-        // first proof
-        end_idx = range
-            .gate()
-            .select(ctx, intermed_idx0, end_idx, is_2nd_proof_dummy);
+        let starts = prev_instances
+            .iter()
+            .map(|instances| instances[0])
+            .collect_vec();
+        let ends = prev_instances
+            .iter()
+            .map(|instances| instances[1])
+            .collect_vec();
+        let prev_num_proofs = starts
+            .iter()
+            .zip(&ends)
+            .map(|(start, end)| range.gate().sub(ctx, *end, *start))
+            .collect_vec();
+
+        let prev_depth = Self::child_depth(max_depth, prev_instances.len());
+        let max_proofs_prev_depth: u64 = 1 << prev_depth;
+        for num_proofs_i in &prev_num_proofs {
+            range.check_less_than_safe(ctx, *num_proofs_i, max_proofs_prev_depth + 1);
+        }
 
-        // make sure proofs link up
-        let mut eq_check = range.gate().is_equal(ctx, intermed_idx0, intermed_idx1);
-        eq_check = range.gate().or(ctx, eq_check, is_2nd_proof_dummy);
+        // indicator of whether the i-th proof is a dummy proof, for i > 0
+        let is_proof_dummy = (1..prev_instances.len() as u64)
+            .map(|i| range.is_less_than_safe(ctx, num_proofs, i * max_proofs_prev_depth + 1))
+            .collect_vec();
 
-        range.gate().assert_is_const(ctx, &eq_check, &F::ONE);
+        // the end index for the aggregation should be the end index of the last proof which is
+        // not dummy
+        let mut end_idx = ends[0];
+        for (end, is_dummy) in ends[1..].iter().zip(&is_proof_dummy) {
+            end_idx = range.gate().select(ctx, end_idx, *end, *is_dummy);
+        }
 
-        // if num_proofs > 2^prev_depth, then num_proofs0 must equal 2^prev_depth
         let prev_max_proofs = range.gate().pow_of_two()[prev_depth];
-        let is_max_depth0 = range
-            .gate()
-            .is_equal(ctx, num_proofs0, Constant(prev_max_proofs));
-        eq_check = range.gate().or(ctx, is_max_depth0, is_2nd_proof_dummy);
-        range.gate().assert_is_const(ctx, &eq_check, &F::ONE);
+        for (i, is_dummy) in is_proof_dummy.iter().enumerate() {
+            // make sure proofs link up
+            let mut eq_check = range.gate().is_equal(ctx, ends[i], starts[i + 1]);
+            eq_check = range.gate().or(ctx, eq_check, *is_dummy);
+
+            range.gate().assert_is_const(ctx, &eq_check, &F::ONE);
+
+            // if num_proofs > i * 2^prev_depth, then the proofs before the i-th must be full
+            let is_max_depth =
+                range
+                    .gate()
+                    .is_equal(ctx, prev_num_proofs[i], Constant(prev_max_proofs));
+            eq_check = range.gate().or(ctx, is_max_depth, *is_dummy);
+            range.gate().assert_is_const(ctx, &eq_check, &F::ONE);
+        }
 
         // check num_proofs is correct
+        let start_idx = starts[0];
         let boundary_num_diff = range.gate().sub(ctx, end_idx, start_idx);
 
         ctx.constrain_equal(&boundary_num_diff, &num_proofs);
 
         // constrain vkeyHash, root to be equal for the deps
         // which is idx 2..5
-        for _i in 2..5 {
-            ctx.constrain_equal(&instances0[_i], &instances1[_i]);
+        let instances0 = &prev_instances[0];
+        for instances in &prev_instances[1..] {
+            for _i in 2..5 {
+                ctx.constrain_equal(&instances0[_i], &instances[_i]);
+            }
         }
 
         let new_instances = [start_idx, end_idx]
             .into_iter()
             .chain(instances0[2..5].iter().cloned())
             .collect();
-        (new_instances, is_2nd_proof_dummy)
+        (new_instances, is_proof_dummy)
+    }
+
+    /// Number of per-claim instance blocks: grant_ids, receivers, nullifier_hashes and, if `per_claim_roots`, roots.
+    pub fn num_claim_blocks(per_claim_roots: bool) -> usize {
+        if per_claim_roots {
+            4
+        } else {
+            3
+        }
     }
 
-    pub fn get_num_instance(max_depth: usize) -> usize {
-        5 + 3 * (1 << max_depth)
+    pub fn get_num_instance(max_depth: usize, per_claim_roots: bool) -> usize {
+        5 + Self::num_claim_blocks(per_claim_roots) * (1 << max_depth)
     }
 }
 
@@ -215,16 +296,17 @@ impl WorldcoinIntermediateAggregationInput {
         let num_proofs = self.num_proofs;
         let max_depth = self.max_depth;
         let initial_depth = self.initial_depth;
+        let options = self.options;
         log::info!(
             "New WorldcoinIntermediateAggregationCircuit | num_proofs: {num_proofs} | max_depth: {max_depth} | initial_depth: {initial_depth}"
         );
         let prev_acc_indices = get_accumulator_indices(&self.snarks);
-        if self.max_depth == self.initial_depth + 1
-            && prev_acc_indices.iter().any(|indices| !indices.is_empty())
+        let prev_depth = Self::child_depth(max_depth, self.snarks.len());
+        if prev_depth == initial_depth && prev_acc_indices.iter().any(|indices| !indices.is_empty())
         {
             bail!("Snarks to be aggregated must not have accumulators: they should come from WorldcoinLeafCircuit");
         }
-        if self.max_depth > self.initial_depth + 1
+        if prev_depth > initial_depth
             && prev_acc_indices
                 .iter()
                 .any(|indices| indices.len() != NUM_FE_ACCUMULATOR)
@@ -256,9 +338,10 @@ impl WorldcoinIntermediateAggregationInput {
         let new_instances = Self::join_previous_instances::<Fr>(
             ctx,
             &range,
-            prev_instances.try_into().unwrap(),
+            prev_instances,
             num_proofs,
             max_depth,
+            &options,
         );
 
         if builder.assigned_instances.len() != 1 {
@@ -270,7 +353,8 @@ impl WorldcoinIntermediateAggregationInput {
 
         assigned_instances.extend(new_instances);
 
-        let num_instance_wo_accumulator = Self::get_num_instance(max_depth);
+        let num_instance_wo_accumulator =
+            Self::get_num_instance(max_depth, options.per_claim_roots);
 
         assert_eq!(
             assigned_instances.len(),
@@ -11,8 +11,9 @@ use axiom_eth::{
     rlc::circuit::builder::RlcCircuitBuilder,
     snark_verifier::util::arithmetic::fe_from_big,
     utils::{
-        build_utils::aggregation::CircuitMetadata, encode_addr_to_field,
-        eth_circuit::EthCircuitInstructions, hilo::HiLo, keccak::decorator::RlcKeccakCircuitImpl,
+        build_utils::aggregation::CircuitMetadata, circuit_utils::unsafe_lt_mask,
+        encode_addr_to_field, eth_circuit::EthCircuitInstructions, hilo::HiLo,
+        keccak::decorator::RlcKeccakCircuitImpl,
     },
     Field,
 };
@@ -34,8 +35,22 @@ use num_bigint::BigUint;
 
 use std::{fmt::Debug, vec};
 
-use crate::{circuit_factory::leaf::WorldcoinRequestLeaf, constants::*};
-use crate::{types::*, utils::get_vk_hash};
+use crate::{
+    circuit_factory::leaf::WorldcoinRequestLeaf,
+    circuits::{
+        groth16::batch_verify_groth16, v1::intermediate::WorldcoinIntermediateAggregationInput,
+    },
+    constants::*,
+    keygen::node_params::CircuitOptions,
+    native::instances::hi_lo,
+};
+use crate::{
+    types::*,
+    utils::{
+        constrain_claim_fields, constrain_sorted_nullifiers, get_app_id_hash_bytes,
+        get_external_nullifier_hash, get_signal_hash, get_vk_set_commitment, select_vk,
+    },
+};
 
 pub type WorldcoinLeafCircuit<F> = RlcKeccakCircuitImpl<F, WorldcoinLeafInput<F>>;
 
@@ -43,27 +58,54 @@ pub type WorldcoinLeafCircuit<F> = RlcKeccakCircuitImpl<F, WorldcoinLeafInput<F>
 pub struct WorldcoinLeafInput<T: Copy> {
     pub start: T,
     pub end: T,
-    pub vk_bytes: Vec<T>,
+    /// The flattened verifying keys the claims can be verified against
+    pub vk_set: Vec<Vec<T>>,
+    /// The shared root, zero if the claims have their own roots
     pub root: T,
     pub claims: Vec<ClaimInput<T>>,
+    /// The number of Groth16 public inputs plus one, the length of the IC of the vks
     pub num_public_inputs: T,
     pub max_depth: usize,
+    /// The modes of the circuit. The amounts of the claims are only committed to by the V2 leaf.
+    pub options: CircuitOptions,
+    /// The bytes of the World ID app id if the proofs are against the external nullifier hash of
+    /// the app id and the grant id, which is then computed in circuit, instead of the grant id
+    /// itself. The app id is a circuit constant.
+    pub app_id: Option<Vec<T>>,
+    /// The vk hash of a single vk which is a circuit constant. The vk and its hash are then
+    /// loaded as constants instead of being hashed in circuit.
+    pub fixed_vk_hash: Option<HiLo<T>>,
 }
 
 impl WorldcoinLeafInput<Fr> {
+    /// The claims have indices `[start, start + claims.len())`, and `vk_indices[i]` is the index
+    /// of the verifying key of `claims[i]` in `vk_strs`. With `per_claim_roots`, every claim is
+    /// verified against its own root, defaulting to `root`. With `unique_nullifiers`, the claims
+    /// must be sorted by nullifier hash without duplicates. With `claim_amounts`, every claim must
+    /// have an amount. With `hash_signal`, the proofs are against the signal hash of the
+    /// receiver. The public inputs of the proofs are arranged by `options.public_inputs`.
+    ///
+    /// The app id of `external_nullifier` and a fixed vk are set on the returned input, see
+    /// [Self::app_id] and [Self::fixed_vk_hash].
     pub fn new(
-        vk_str: String,
+        vk_strs: Vec<String>,
         root: String,
         start: u32,
-        end: u32,
         max_depth: usize,
         claims: Vec<ClaimNative>,
+        vk_indices: Vec<usize>,
+        options: CircuitOptions,
     ) -> Self {
-        let num_proofs = (end - start) as usize;
-        assert!(claims.len() == num_proofs);
+        let num_proofs = claims.len();
+        let end = start + num_proofs as u32;
+        assert!(vk_indices.len() == num_proofs);
         assert!(num_proofs > 0);
+        assert!(vk_indices.iter().all(|&i| i < vk_strs.len()));
         let max_proofs: usize = 1 << max_depth;
-        let vk_bytes = parse_vk(vk_str, MAX_GROTH16_PI);
+        let vk_set = vk_strs
+            .into_iter()
+            .map(|vk_str| parse_vk(vk_str, options.public_inputs.num_public_inputs))
+            .collect();
 
         let mut claims_input = Vec::new();
         for _i in 0..num_proofs {
@@ -73,45 +115,87 @@ impl WorldcoinLeafInput<Fr> {
                 fe_from_big(BigUint::from_str(&claims[_i].nullifier_hash).unwrap());
             let receiver = encode_addr_to_field(&claims[_i].receiver);
             let grant_id = fe_from_big(BigUint::from_str(&claims[_i].grant_id).unwrap());
+            let claim_root = biguint_to_fe(&BigUint::from_str(claims[_i].root_or(&root)).unwrap());
+            let amount = if options.claim_amounts {
+                let amount = claims[_i].amount.as_ref().expect("Claim without an amount");
+                biguint_to_fe(&BigUint::from_str(amount).unwrap())
+            } else {
+                Fr::from(0)
+            };
+            let extra_public_inputs = claims[_i]
+                .extra_public_inputs
+                .iter()
+                .map(|value| biguint_to_fe(&BigUint::from_str(value).unwrap()))
+                .collect();
             claims_input.push(ClaimInput {
                 receiver,
                 nullifier_hash,
                 grant_id,
                 proof_bytes,
+                vk_index: Fr::from(vk_indices[_i] as u64),
+                root: claim_root,
+                amount,
+                extra_public_inputs,
             })
         }
+        // padding repeats the first claim so that every proof verifies, and is masked to zero in
+        // the instances
         claims_input.resize(max_proofs, claims_input[0].clone());
 
         let start = Fr::from(start as u64);
         let end = Fr::from(end as u64);
 
-        let root = biguint_to_fe(&BigUint::from_str(root.as_str()).unwrap());
+        let root = if options.per_claim_roots {
+            Fr::from(0)
+        } else {
+            biguint_to_fe(&BigUint::from_str(root.as_str()).unwrap())
+        };
 
-        let num_public_inputs = Fr::from(MAX_GROTH16_PI as u64 + 1);
+        let num_public_inputs = Fr::from(options.public_inputs.num_public_inputs as u64 + 1);
         Self {
             root,
             start,
             end,
             max_depth,
             claims: claims_input,
-            vk_bytes,
+            vk_set,
             num_public_inputs,
+            options,
+            app_id: None,
+            fixed_vk_hash: None,
         }
     }
 }
 
 impl From<WorldcoinRequestLeaf> for WorldcoinLeafInput<Fr> {
     fn from(input: WorldcoinRequestLeaf) -> Self {
+        let vk_indices = input
+            .vk_indices()
+            .expect("Claim with a vk outside the vk set");
+        let fixed_vk_hash = input
+            .fixed_vk_hash()
+            .expect("Invalid vk")
+            .map(|vk_hash| HiLo::from_hi_lo(hi_lo(&vk_hash)));
+        let vk_strs = input
+            .vks()
+            .map(|vk| serde_json::to_string(vk).unwrap())
+            .collect();
         let WorldcoinRequestLeaf {
-            vk,
             root,
             start,
             end,
             depth,
             claims,
+            app_id,
+            options,
+            ..
         } = input;
-        let vk_str = serde_json::to_string(&vk).unwrap();
-        WorldcoinLeafInput::new(vk_str, root, start, end, depth, claims)
+        assert_eq!(end - start, claims.len() as u32);
+        WorldcoinLeafInput {
+            app_id: app_id.map(|app_id| app_id.bytes().map(|b| Fr::from(b as u64)).collect()),
+            fixed_vk_hash,
+            ..WorldcoinLeafInput::new(vk_strs, root, start, depth, claims, vk_indices, options)
+        }
     }
 }
 
@@ -124,19 +208,55 @@ impl<F: Field> WorldcoinLeafInput<F> {
 
         let mut claim_inputs: Vec<ClaimInput<AssignedValue<F>>> = Vec::new();
 
-        let vk_bytes = ctx.assign_witnesses(self.vk_bytes.clone());
+        let vk_set = self
+            .vk_set
+            .iter()
+            .map(|vk_bytes| match self.fixed_vk_hash {
+                Some(_) => ctx.load_constants(vk_bytes),
+                None => ctx.assign_witnesses(vk_bytes.clone()),
+            })
+            .collect_vec();
+        let app_id = self
+            .app_id
+            .as_ref()
+            .map(|app_id| ctx.load_constants(app_id));
+        let fixed_vk_hash = self
+            .fixed_vk_hash
+            .map(|vk_hash| HiLo::from_hi_lo(vk_hash.hi_lo().map(|x| ctx.load_constant(x))));
 
         for claim in self.claims.iter() {
             let proof_bytes = ctx.assign_witnesses(claim.proof_bytes.clone());
             let receiver = ctx.load_witness(claim.receiver);
             let nullifier_hash = ctx.load_witness(claim.nullifier_hash);
             let grant_id = ctx.load_witness(claim.grant_id);
+            // with a single vk the index is not used, so no cell is assigned for it
+            let vk_index = if self.vk_set.len() == 1 {
+                ctx.load_zero()
+            } else {
+                ctx.load_witness(claim.vk_index)
+            };
+            // likewise the shared root is used unless the claims have their own roots
+            let claim_root = if self.options.per_claim_roots {
+                ctx.load_witness(claim.root)
+            } else {
+                root
+            };
+            let amount = if self.options.claim_amounts {
+                ctx.load_witness(claim.amount)
+            } else {
+                ctx.load_zero()
+            };
+            let extra_public_inputs = ctx.assign_witnesses(claim.extra_public_inputs.clone());
 
             claim_inputs.push(ClaimInput {
                 proof_bytes,
                 receiver,
                 nullifier_hash,
                 grant_id,
+                vk_index,
+                root: claim_root,
+                amount,
+                extra_public_inputs,
             })
         }
 
@@ -144,10 +264,13 @@ impl<F: Field> WorldcoinLeafInput<F> {
             start,
             end,
             root,
-            vk_bytes,
+            vk_set,
             claims: claim_inputs,
             num_public_inputs,
             max_depth: self.max_depth,
+            options: self.options,
+            app_id,
+            fixed_vk_hash,
         }
     }
 }
@@ -155,7 +278,10 @@ impl<F: Field> WorldcoinLeafInput<F> {
 impl<F: Field> CircuitMetadata for WorldcoinLeafInput<F> {
     const HAS_ACCUMULATOR: bool = false;
     fn num_instance(&self) -> Vec<usize> {
-        vec![5 + 3 * (1 << self.max_depth)]
+        vec![WorldcoinIntermediateAggregationInput::get_num_instance(
+            self.max_depth,
+            self.options.per_claim_roots,
+        )]
     }
 }
 
@@ -180,17 +306,37 @@ impl<F: Field> EthCircuitInstructions<F> for WorldcoinLeafInput<F> {
             end,
             root,
             claims,
-            vk_bytes,
+            vk_set,
             num_public_inputs,
             max_depth,
+            options,
+            app_id,
+            fixed_vk_hash,
+            ..
         } = self.assign(ctx);
+        let CircuitOptions {
+            per_claim_roots,
+            unique_nullifiers,
+            check_claim_fields,
+            hash_signal,
+            batch_groth16,
+            public_inputs: public_input_layout,
+            ..
+        } = options;
 
         // ==== Constraints ====
 
-        // 0 <= start < end < 2^64
-        range.range_check(ctx, start, 64);
-        range.range_check(ctx, end, 64);
-        range.check_less_than(ctx, start, end, 64);
+        // the shared root is unused with per-claim roots
+        if per_claim_roots {
+            ctx.constrain_equal(&root, &zero);
+        }
+
+        // 0 <= start < end < 2^64, or 2^32 with checked claim fields as the requests index claims
+        // with u32
+        let index_bits = if check_claim_fields { 32 } else { 64 };
+        range.range_check(ctx, start, index_bits);
+        range.range_check(ctx, end, index_bits);
+        range.check_less_than(ctx, start, end, index_bits);
 
         let num_proofs = range.gate().sub(ctx, end, start);
         let max_proofs = ctx.load_constant(F::from(1 << max_depth));
@@ -200,25 +346,65 @@ impl<F: Field> EthCircuitInstructions<F> for WorldcoinLeafInput<F> {
         range.range_check(ctx, num_proofs, 64);
         range.check_less_than(ctx, num_proofs, max_proofs_plus_one, 64);
 
-        let constants = get_groth16_consts_from_max_pi(MAX_GROTH16_PI);
-
-        assert_eq!(self.vk_bytes.len(), constants.num_fe_hilo_vkey);
-        let vk_hash: HiLo<AssignedValue<F>> = get_vk_hash(ctx, range, keccak, vk_bytes.clone());
-        let vk = Groth16VerifierComponentVerificationKey::unflatten(
-            vk_bytes,
-            constants.gamma_abc_g1_len,
-        );
-
-        parallelize_core(builder.base.pool(0), claims.clone(), |ctx, claim| {
-            // pi[0] root
-            // pi[1] nullifier_hash
-            // pi[2] receiver
-            // pi[3] grant_id
-            let public_inputs =
-                [root, claim.nullifier_hash, claim.receiver, claim.grant_id].to_vec();
+        let constants = get_groth16_consts_from_max_pi(public_input_layout.num_public_inputs);
+
+        assert!(vk_set
+            .iter()
+            .all(|vk_bytes| vk_bytes.len() == constants.num_fe_hilo_vkey));
+        let vk_hash: HiLo<AssignedValue<F>> = match fixed_vk_hash {
+            Some(vk_hash) => vk_hash,
+            None => get_vk_set_commitment(ctx, range, keccak, &vk_set),
+        };
+        let app_id_hash_bytes =
+            app_id.map(|app_id| get_app_id_hash_bytes(ctx, range, keccak, app_id));
+
+        let proofs = parallelize_core(builder.base.pool(0), claims.clone(), |ctx, claim| {
+            // the receiver is a non-zero address, and the nullifier hash is non-zero. Padding
+            // claims are copies of the first claim, so they pass as well.
+            if check_claim_fields {
+                constrain_claim_fields(ctx, range, claim.receiver, claim.nullifier_hash);
+            }
+
+            // the receiver, or its World ID signal hash
+            let signal_hash = if hash_signal {
+                get_signal_hash(ctx, range, keccak, claim.receiver)
+            } else {
+                claim.receiver
+            };
+            // the grant id, or the external nullifier hash with the grant id as the action
+            let external_nullifier = match &app_id_hash_bytes {
+                Some(app_id_hash_bytes) => get_external_nullifier_hash(
+                    ctx,
+                    range,
+                    keccak,
+                    app_id_hash_bytes,
+                    claim.grant_id,
+                ),
+                None => claim.grant_id,
+            };
 
+            // the claim fields and the extra public inputs, arranged by the layout
+            let public_inputs = public_input_layout
+                .public_inputs(
+                    claim.root,
+                    claim.nullifier_hash,
+                    signal_hash,
+                    external_nullifier,
+                    &claim.extra_public_inputs,
+                )
+                .expect("Invalid extra public inputs");
+
+            // the proofs are verified together below with a batched Groth16 check
+            if batch_groth16 {
+                return Some((claim.proof_bytes, public_inputs));
+            }
+
+            let vk = Groth16VerifierComponentVerificationKey::unflatten(
+                select_vk(ctx, range, &vk_set, claim.vk_index),
+                constants.gamma_abc_g1_len,
+            );
             let groth16_verifier_input = Groth16VerifierInput {
-                vk: vk.clone(),
+                vk,
                 proof: Groth16VerifierComponentProof::unflatten(claim.proof_bytes).unwrap(),
                 num_public_inputs,
                 public_inputs,
@@ -231,33 +417,77 @@ impl<F: Field> EthCircuitInstructions<F> for WorldcoinLeafInput<F> {
                 groth16_verifier_input,
                 LIMB_BITS,
                 NUM_LIMBS,
-                MAX_GROTH16_PI,
+                public_input_layout.num_public_inputs,
             );
             let success = res.1.success;
             ctx.constrain_equal(&success.hi(), &zero);
             ctx.constrain_equal(&success.lo(), &one);
 
-            ()
+            None
         });
 
-        let (grant_ids, receivers, nullifier_hashes): (
+        // a single vk, see NodeParams
+        if batch_groth16 {
+            batch_verify_groth16(
+                builder.base.pool(0),
+                range,
+                &vk_set[0],
+                vk_hash,
+                proofs.into_iter().flatten().collect(),
+                public_input_layout.num_public_inputs,
+            );
+        }
+
+        let (grant_ids, receivers, nullifier_hashes, roots): (
+            Vec<AssignedValue<F>>,
             Vec<AssignedValue<F>>,
             Vec<AssignedValue<F>>,
             Vec<AssignedValue<F>>,
-        ) = multiunzip(
-            claims
+        ) = multiunzip(claims.into_iter().map(|claim| {
+            (
+                claim.grant_id,
+                claim.receiver,
+                claim.nullifier_hash,
+                claim.root,
+            )
+        }));
+
+        let ctx = builder.base.main(0);
+
+        // the claims are sorted by nullifier hash, so the nullifier hashes are pairwise distinct.
+        // The exposed nullifier hashes suffice for aggregation circuits to check the boundaries.
+        if unique_nullifiers {
+            constrain_sorted_nullifiers(ctx, range, &nullifier_hashes, num_proofs);
+        }
+
+        // claims with indices greater than num_proofs - 1 are padding, which is exposed as zero
+        let mask = unsafe_lt_mask(ctx, range.gate(), num_proofs, 1 << max_depth);
+        let mut apply_mask = |values: Vec<AssignedValue<F>>| {
+            values
                 .into_iter()
-                .map(|claim| (claim.grant_id, claim.receiver, claim.nullifier_hash)),
-        );
+                .zip_eq(&mask)
+                .map(|(value, mask)| range.gate().mul(ctx, value, *mask.as_ref()))
+                .collect_vec()
+        };
+        let grant_ids = apply_mask(grant_ids);
+        let receivers = apply_mask(receivers);
+        let nullifier_hashes = apply_mask(nullifier_hashes);
+        let roots = if per_claim_roots {
+            apply_mask(roots)
+        } else {
+            vec![]
+        };
 
         // instances:
         // [0] start
         // [1] end
-        // [2, 3] vkey_hash
+        // [2, 3] vkey_hash, or the commitment to the vk set
         // [4] root
         // [5, 5 + 1 << max_depth) grant_ids_i
         // [5 + 1 << max_depth, 5 + 2 * (1 << max_depth)) receivers_i
         // [5 + 2 * (1 << max_depth), 5 + 3 * (1 << max_depth)) nullifier_hashes_i
+        // [5 + 3 * (1 << max_depth), 5 + 4 * (1 << max_depth)) roots_i, with per-claim roots
+        // with entries at indices greater than num_proofs - 1 set to zero
         let assigned_instances = iter::empty()
             .chain([start, end])
             .chain([vk_hash.hi(), vk_hash.lo()])
@@ -265,6 +495,7 @@ impl<F: Field> EthCircuitInstructions<F> for WorldcoinLeafInput<F> {
             .chain(grant_ids)
             .chain(receivers)
             .chain(nullifier_hashes)
+            .chain(roots)
             .collect_vec();
 
         builder.base.assigned_instances[0] = assigned_instances;
@@ -1,3 +1,4 @@
 pub mod intermediate;
 pub mod leaf;
+pub mod rolling;
 pub mod root;
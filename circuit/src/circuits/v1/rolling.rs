@@ -0,0 +1,249 @@
+//! Rolling aggregation of successive batches, so claims can be proven continuously and posted
+//! on-chain in one proof. A [WorldcoinRollingAggregationCircuit] with `max_batches` batch slots
+//! aggregates:
+//! - the previous rolling proof with `max_batches - 1` slots, or the root proof of the first batch
+//!   if `max_batches == 2` and
+//! - the [WorldcoinRootAggregationCircuit] proof of the next batch.
+//!
+//! Like the claims of an aggregation tree, the batches fill the slots in order: a new batch can
+//! only follow a full previous proof, otherwise it is a dummy. A rolling proof is padded with dummy
+//! batches up to the rolling circuit which is wrapped for the contract. A dummy batch can be any
+//! root proof of the batch circuit, e.g. the last one, and its output is ignored.
+//!
+//! Rolling Aggregation circuit public outputs: [num_batches, chain_hash_hi, chain_hash_lo], where
+//! the chain hash starts at zero and each batch appends the output hash of its root circuit:
+//! `chain_hash = keccak256(abi.encodePacked(chain_hash, output_hash))`
+
+use anyhow::{bail, Result};
+use axiom_eth::{
+    halo2_base::{
+        gates::{GateInstructions, RangeChip, RangeInstructions},
+        AssignedValue, Context,
+        QuantumCell::Constant,
+    },
+    halo2_proofs::poly::{commitment::ParamsProver, kzg::commitment::ParamsKZG},
+    halo2curves::bn256::{Bn256, Fr},
+    keccak::KeccakChip,
+    mpt::MPTChip,
+    rlc::circuit::builder::RlcCircuitBuilder,
+    snark_verifier_sdk::{
+        halo2::aggregation::{aggregate_snarks, SnarkAggregationOutput, Svk, VerifierUniversality},
+        Snark, SHPLONK,
+    },
+    utils::{
+        build_utils::aggregation::CircuitMetadata,
+        eth_circuit::EthCircuitInstructions,
+        hilo::HiLo,
+        keccak::decorator::RlcKeccakCircuitImpl,
+        snark_verifier::{get_accumulator_indices, NUM_FE_ACCUMULATOR},
+    },
+    Field,
+};
+use itertools::Itertools;
+
+use crate::utils::compute_keccak_for_branch_nodes;
+
+pub type WorldcoinRollingAggregationCircuit =
+    RlcKeccakCircuitImpl<Fr, WorldcoinRollingAggregationInput>;
+
+#[derive(Clone, Debug)]
+pub struct WorldcoinRollingAggregationInput {
+    /// Number of batches of this proof, which includes the new batch unless it is a dummy
+    pub num_batches: u32,
+    /// `snarks` should be exactly two snarks: the previous rolling proof (or the root proof of
+    /// the first batch if `max_batches == 2`) and the root proof of the new batch
+    pub snarks: Vec<Snark>,
+    /// The number of batch slots of this circuit
+    pub max_batches: usize,
+    pub svk: Svk,
+    pub prev_acc_indices: Vec<Vec<usize>>,
+}
+
+impl WorldcoinRollingAggregationInput {
+    pub fn new(
+        snarks: Vec<Snark>,
+        num_batches: u32,
+        max_batches: usize,
+        kzg_params: &ParamsKZG<Bn256>,
+    ) -> Result<Self> {
+        if snarks.len() != 2 {
+            bail!("Rolling aggregation takes the previous proof and a batch");
+        }
+        if max_batches < 2 {
+            bail!("Rolling aggregation needs at least 2 batch slots");
+        }
+        if num_batches == 0 || num_batches as usize > max_batches {
+            bail!("Number of batches {num_batches} is invalid for {max_batches} batch slots");
+        }
+        let svk = kzg_params.get_g()[0].into();
+        let prev_acc_indices = get_accumulator_indices(&snarks);
+        if prev_acc_indices
+            .iter()
+            .any(|indices| indices.len() != NUM_FE_ACCUMULATOR)
+        {
+            bail!("Snarks to be aggregated must all be root or rolling aggregation circuits");
+        }
+        Ok(Self {
+            num_batches,
+            snarks,
+            max_batches,
+            svk,
+            prev_acc_indices,
+        })
+    }
+
+    /// Checks the new batch links up with the `prev_num_batches` batches of the previous proof:
+    /// `num_batches` is `prev_num_batches + 1` if the new batch is real, and `prev_num_batches` if
+    /// it is a dummy. A real batch must follow a full previous proof of `max_batches - 1` batches.
+    ///
+    /// Returns a selector indicating whether the new batch is a dummy.
+    pub fn check_and_join_batches<F: Field>(
+        ctx: &mut Context<F>,
+        range: &RangeChip<F>,
+        prev_num_batches: AssignedValue<F>,
+        num_batches: AssignedValue<F>,
+        max_batches: usize,
+    ) -> AssignedValue<F> {
+        let gate = range.gate();
+        let is_new_batch = gate.sub(ctx, num_batches, prev_num_batches);
+        gate.assert_bit(ctx, is_new_batch);
+
+        let is_prev_full = gate.is_equal(
+            ctx,
+            prev_num_batches,
+            Constant(F::from((max_batches - 1) as u64)),
+        );
+        let is_batch_out_of_order = gate.mul_not(ctx, is_prev_full, is_new_batch);
+        gate.assert_is_const(ctx, &is_batch_out_of_order, &F::ZERO);
+
+        gate.not(ctx, is_new_batch)
+    }
+
+    /// Returns the new chain hash `keccak(chain_hash | output_hash)`, or `chain_hash` if the batch
+    /// is a dummy
+    pub fn append_batch<F: Field>(
+        ctx: &mut Context<F>,
+        range: &RangeChip<F>,
+        keccak: &KeccakChip<F>,
+        chain_hash: &HiLo<AssignedValue<F>>,
+        output_hash: &HiLo<AssignedValue<F>>,
+        is_batch_dummy: AssignedValue<F>,
+    ) -> HiLo<AssignedValue<F>> {
+        let appended = compute_keccak_for_branch_nodes(ctx, range, keccak, chain_hash, output_hash);
+        let hi = range
+            .gate()
+            .select(ctx, chain_hash.hi(), appended.hi(), is_batch_dummy);
+        let lo = range
+            .gate()
+            .select(ctx, chain_hash.lo(), appended.lo(), is_batch_dummy);
+        HiLo::from_hi_lo([hi, lo])
+    }
+
+    // num_instance excluding the accumulator
+    pub fn get_num_instance() -> usize {
+        3
+    }
+}
+
+impl EthCircuitInstructions<Fr> for WorldcoinRollingAggregationInput {
+    type FirstPhasePayload = ();
+
+    fn virtual_assign_phase0(
+        &self,
+        builder: &mut RlcCircuitBuilder<Fr>,
+        mpt: &MPTChip<Fr>,
+    ) -> Self::FirstPhasePayload {
+        let keccak = mpt.keccak();
+        let range = keccak.range();
+        let pool = builder.base.pool(0);
+        let SnarkAggregationOutput {
+            mut previous_instances,
+            accumulator,
+            ..
+        } = aggregate_snarks::<SHPLONK>(
+            pool,
+            range,
+            self.svk,
+            self.snarks.clone(),
+            VerifierUniversality::None,
+        );
+
+        // remove old accumulators
+        for (prev_instance, acc_indices) in
+            previous_instances.iter_mut().zip_eq(&self.prev_acc_indices)
+        {
+            for i in acc_indices.iter().sorted().rev() {
+                prev_instance.remove(*i);
+            }
+        }
+
+        let ctx = pool.main();
+        let [prev, batch]: [Vec<AssignedValue<Fr>>; 2] = previous_instances.try_into().unwrap();
+        // root circuit: [output_hash_hi, output_hash_lo]
+        assert_eq!(batch.len(), 2);
+
+        let (prev_num_batches, prev_chain_hash) = if self.max_batches == 2 {
+            // the root proof of the first batch, appended to the empty chain
+            assert_eq!(prev.len(), 2);
+            let zero = ctx.load_zero();
+            let chain_hash = compute_keccak_for_branch_nodes(
+                ctx,
+                &range,
+                &keccak,
+                &HiLo::from_hi_lo([zero, zero]),
+                &HiLo::from_hi_lo([prev[0], prev[1]]),
+            );
+            (ctx.load_constant(Fr::from(1)), chain_hash)
+        } else {
+            // rolling circuit: [num_batches, chain_hash_hi, chain_hash_lo]
+            assert_eq!(prev.len(), Self::get_num_instance());
+            (prev[0], HiLo::from_hi_lo([prev[1], prev[2]]))
+        };
+
+        let num_batches = ctx.load_witness(Fr::from(self.num_batches as u64));
+        let is_batch_dummy = Self::check_and_join_batches(
+            ctx,
+            &range,
+            prev_num_batches,
+            num_batches,
+            self.max_batches,
+        );
+        let chain_hash = Self::append_batch(
+            ctx,
+            &range,
+            &keccak,
+            &prev_chain_hash,
+            &HiLo::from_hi_lo([batch[0], batch[1]]),
+            is_batch_dummy,
+        );
+
+        let assigned_instances: &mut Vec<AssignedValue<Fr>> =
+            &mut builder.base.assigned_instances[0];
+
+        assigned_instances.extend(accumulator);
+        assigned_instances.push(num_batches);
+        assigned_instances.extend(chain_hash.hi_lo());
+
+        assert_eq!(
+            assigned_instances.len(),
+            NUM_FE_ACCUMULATOR + Self::get_num_instance()
+        );
+    }
+
+    fn virtual_assign_phase1(
+        &self,
+        _: &mut RlcCircuitBuilder<Fr>,
+        _: &MPTChip<Fr>,
+        _: Self::FirstPhasePayload,
+    ) {
+        // do nothing
+    }
+}
+
+impl CircuitMetadata for WorldcoinRollingAggregationInput {
+    const HAS_ACCUMULATOR: bool = true;
+
+    fn num_instance(&self) -> Vec<usize> {
+        vec![NUM_FE_ACCUMULATOR + Self::get_num_instance()]
+    }
+}
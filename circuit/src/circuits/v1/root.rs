@@ -1,10 +1,11 @@
 //! The root of the aggregation tree.
 //! An [WorldcoinRootAggregationCircuit] can aggregate either:
-//! - two [WorldcoinLeafnCircuit]s (if `max_depth == initial_depth + 1`) or
-//! - two [WorldcoinIntermediateAggregationCircuit]s.
+//! - `arity` [WorldcoinLeafnCircuit]s (if `max_depth == initial_depth + log2(arity)`) or
+//! - `arity` [WorldcoinIntermediateAggregationCircuit]s.
 //!
 //! The difference between Intermediate and Root aggregation circuits is that they expose different public outputs. Root aggregation
-//! exposes the hash of the output [vk_hash_hi, vk_hash_lo, root, num_proofs, ...grant_ids, ...receivers, ...nullifier_hashes]
+//! exposes the hash of the output [vk_hash_hi, vk_hash_lo, root, num_proofs, ...grant_ids, ...receivers, ...nullifier_hashes],
+//! followed by `...roots` when each claim carries its own root.
 
 use anyhow::{bail, Result};
 use axiom_eth::{
@@ -29,6 +30,7 @@ use axiom_eth::{
 use itertools::Itertools;
 
 use super::intermediate::WorldcoinIntermediateAggregationInput;
+use crate::keygen::node_params::CircuitOptions;
 
 pub type WorldcoinRootAggregationCircuit = RlcKeccakCircuitImpl<Fr, WorldcoinRootAggregationInput>;
 
@@ -45,16 +47,21 @@ impl WorldcoinRootAggregationInput {
         num_proofs: u32,
         max_depth: usize,
         initial_depth: usize,
+        options: CircuitOptions,
         kzg_params: &ParamsKZG<Bn256>,
     ) -> Result<Self> {
         let svk = kzg_params.get_g()[0].into();
         let prev_acc_indices = get_accumulator_indices(&snarks);
-        if max_depth == initial_depth + 1
-            && prev_acc_indices.iter().any(|indices| !indices.is_empty())
+        let prev_depth =
+            WorldcoinIntermediateAggregationInput::child_depth(max_depth, snarks.len());
+        if prev_depth < initial_depth {
+            bail!("Too many snarks for max depth {max_depth} and initial depth {initial_depth}");
+        }
+        if prev_depth == initial_depth && prev_acc_indices.iter().any(|indices| !indices.is_empty())
         {
             bail!("Snarks to be aggregated must not have accumulators: they should come from WorldcoinLeafCircuit");
         }
-        if max_depth > initial_depth + 1
+        if prev_depth > initial_depth
             && prev_acc_indices
                 .iter()
                 .any(|indices| indices.len() != NUM_FE_ACCUMULATOR)
@@ -66,6 +73,7 @@ impl WorldcoinRootAggregationInput {
             num_proofs,
             max_depth,
             initial_depth,
+            options,
         );
         Ok(Self {
             inner,
@@ -88,6 +96,7 @@ impl EthCircuitInstructions<Fr> for WorldcoinRootAggregationInput {
             initial_depth: _,
             num_proofs,
             snarks,
+            options,
         } = self.inner.clone();
 
         let keccak = mpt.keccak();
@@ -115,16 +124,19 @@ impl EthCircuitInstructions<Fr> for WorldcoinRootAggregationInput {
         let new_instances = WorldcoinIntermediateAggregationInput::join_previous_instances::<Fr>(
             ctx,
             &range,
-            previous_instances.try_into().unwrap(),
+            previous_instances,
             num_proofs,
             max_depth,
+            &options,
         );
+        let num_claim_blocks =
+            WorldcoinIntermediateAggregationInput::num_claim_blocks(options.per_claim_roots);
 
-        // output:  [vk_hash_hi, vk_hash_lo, root, num_proofs, ...grant_ids, ...receivers, ...nullifier_hashes]
+        // output:  [vk_hash_hi, vk_hash_lo, root, num_proofs, ...grant_ids, ...receivers, ...nullifier_hashes(, ...roots)]
         let mut output: Vec<AssignedValue<Fr>> = Vec::new();
         output.extend(new_instances[2..5].to_vec());
         output.extend([num_proofs].to_vec());
-        output.extend(new_instances[5..5 + 3 * (1 << max_depth)].to_vec());
+        output.extend(new_instances[5..5 + num_claim_blocks * (1 << max_depth)].to_vec());
 
         // generate keccak hash for the outputs
         let output_bytes: Vec<AssignedValue<Fr>> = output
@@ -139,7 +151,10 @@ impl EthCircuitInstructions<Fr> for WorldcoinRootAggregationInput {
             .flatten()
             .collect();
 
-        assert_eq!(output_bytes.len(), 32 * (4 + 3 * (1 << max_depth)));
+        assert_eq!(
+            output_bytes.len(),
+            32 * (4 + num_claim_blocks * (1 << max_depth))
+        );
 
         let output_hash = keccak.keccak_fixed_len(ctx, output_bytes);
 
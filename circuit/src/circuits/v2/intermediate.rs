@@ -1,16 +1,22 @@
-//! Intermediate aggregation circuits that aggregate in a binary tree topology:
+//! Intermediate aggregation circuits that aggregate in a tree topology, binary by default:
 //! The leaves of the tree are formed by [WorldcoinLeafCircuitV2]s, and intermediate notes
 //! of the tree are formed by [WorldcoinIntermediateAggreagtionCircuitV2]s.
 //!
 //! An [WorldcoinIntermediateAggregationCircuitV2] can aggregate either:
-//! - two [WorldcoinLeafCircuitV2]s or
-//! - two [WorldcoinIntermediateAggreagtionCircuitV2]s.
+//! - `arity` [WorldcoinLeafCircuitV2]s or
+//! - `arity` [WorldcoinIntermediateAggreagtionCircuitV2]s,
+//!
+//! where the arity of each layer is a power of two. The claim root is the keccak merkle root of
+//! the claim roots of the children, so the claim tree is binary whatever the arity.
 //!
 //! The root of the aggregation tree will be a [WorldcoinRootAggregationCircuitV2].
 //! Intermediate and Root aggregation circuits have different public outputs.
 //! Intermediate Aggregation circuit public outputs: [start, end, vk_hash_hi, vk_hash_lo, grant_id, root, claim_root_hi, claim_root_lo]
 //! Root Aggregation circuit public outputs: [output_hash_hi, output_hash_lo], where output is
 //! [vk_hash_hi, vk_hash_lo, grant_id, root, num_proofs, claim_root_hi, claim_root_lo]
+//!
+//! With unique nullifiers, leaf and intermediate circuits also expose the first and last nullifier
+//! hash of their claims, which are strictly increasing.
 use anyhow::{bail, Ok, Result};
 use axiom_eth::{
     halo2_base::{
@@ -44,8 +50,9 @@ use crate::{
         v1::intermediate::WorldcoinIntermediateAggregationInput,
         v2::root::WorldcoinRootAggregationInputV2,
     },
-    constants::DUMMY_CLAIM_ROOTS,
-    utils::compute_keccak_for_branch_nodes,
+    constants::get_dummy_claim_roots,
+    keygen::node_params::CircuitOptions,
+    utils::{compute_keccak_merkle_tree, constrain_nullifier_boundary},
 };
 
 pub type WorldcoinIntermediateAggregationCircuitV2 =
@@ -57,6 +64,10 @@ pub struct WorldcoinIntermediateAggregationInputV2 {
     pub snarks: Vec<Snark>,
     pub max_depth: usize,
     pub initial_depth: usize,
+    /// With `per_claim_roots` and `claim_amounts`, the claim tree leaves include each claim's
+    /// own root and amount. With `unique_nullifiers`, the nullifier hashes must be strictly
+    /// increasing across the proofs.
+    pub options: CircuitOptions,
     pub svk: Svk,
     pub prev_acc_indices: Vec<Vec<usize>>,
 }
@@ -67,16 +78,21 @@ impl WorldcoinIntermediateAggregationInputV2 {
         num_proofs: u32,
         max_depth: usize,
         initial_depth: usize,
+        options: CircuitOptions,
         kzg_params: &ParamsKZG<Bn256>,
     ) -> Result<Self> {
         let svk = kzg_params.get_g()[0].into();
         let prev_acc_indices = get_accumulator_indices(&snarks);
-        if max_depth == initial_depth + 1
-            && prev_acc_indices.iter().any(|indices| !indices.is_empty())
+        let prev_depth =
+            WorldcoinIntermediateAggregationInput::child_depth(max_depth, snarks.len());
+        if prev_depth < initial_depth {
+            bail!("Too many snarks for max depth {max_depth} and initial depth {initial_depth}");
+        }
+        if prev_depth == initial_depth && prev_acc_indices.iter().any(|indices| !indices.is_empty())
         {
             bail!("Snarks to be aggregated must not have accumulators: they should come from WorldcoinLeafCircuitV2");
         }
-        if max_depth > initial_depth + 1
+        if prev_depth > initial_depth
             && prev_acc_indices
                 .iter()
                 .any(|indices| indices.len() != NUM_FE_ACCUMULATOR)
@@ -89,6 +105,7 @@ impl WorldcoinIntermediateAggregationInputV2 {
             snarks,
             max_depth,
             initial_depth,
+            options,
             svk,
             prev_acc_indices,
         })
@@ -132,9 +149,10 @@ impl WorldcoinIntermediateAggregationInputV2 {
             ctx,
             &range,
             &keccak,
-            previous_instances.try_into().unwrap(),
+            previous_instances,
             num_proofs,
             self.max_depth,
+            &self.options,
         );
 
         let assigned_instances: &mut Vec<AssignedValue<Fr>> =
@@ -154,20 +172,23 @@ impl WorldcoinIntermediateAggregationInputV2 {
             );
         } else {
             // intermediate circuit
-            // [start, end, vk_hash_hi, vk_hash_lo, root, claim_root_hi, claim_root_lo]
-            assigned_instances.extend_from_slice(&new_instances[0..7]);
+            // [start, end, vk_hash_hi, vk_hash_lo, root, claim_root_hi, claim_root_lo(, first_nullifier_hash, last_nullifier_hash)]
+            assigned_instances.extend(new_instances);
             assert_eq!(
                 assigned_instances.len(),
-                NUM_FE_ACCUMULATOR + WorldcoinIntermediateAggregationInputV2::get_num_instance()
+                NUM_FE_ACCUMULATOR
+                    + WorldcoinIntermediateAggregationInputV2::get_num_instance(
+                        self.options.unique_nullifiers
+                    )
             );
         }
     }
 
-    /// Takes the concatenated previous instances from two `WorldcoinIntermediateAggregationCircuitV2`s
-    /// of max depth `max_depth - 1` and
+    /// Takes the concatenated previous instances from `arity` `WorldcoinIntermediateAggregationCircuitV2`s
+    /// of max depth `max_depth - log2(arity)` and
     /// - checks that they form a chain of `max_depth`
     ///
-    /// If `max_depth - 1 == initial_depth`, then the previous instances are from two `WorldcoinLeafCircuitV2`s.
+    /// If `max_depth - log2(arity) == initial_depth`, then the previous instances are from `WorldcoinLeafCircuitV2`s.
     ///
     /// Returns the new instances for the depth `max_depth` circuit (without accumulators)
     ///
@@ -180,16 +201,24 @@ impl WorldcoinIntermediateAggregationInputV2 {
     /// [2, 3] vkey_hash
     /// [4] root
     /// [5, 6] claim_root
+    /// [7, 8] first and last nullifier_hash, only if `options.unique_nullifiers`
     pub fn join_previous_instances<F: Field>(
         ctx: &mut Context<F>,
         range: &RangeChip<F>,
         keccak: &KeccakChip<F>,
-        prev_instances: [Vec<AssignedValue<F>>; 2],
+        prev_instances: Vec<Vec<AssignedValue<F>>>,
         num_proofs: AssignedValue<F>,
         max_depth: usize,
+        options: &CircuitOptions,
     ) -> Vec<AssignedValue<F>> {
-        let num_instance_prev_depth = Self::get_num_instance();
-        let (mut instances, is_2nd_proof_dummy) =
+        let CircuitOptions {
+            per_claim_roots,
+            unique_nullifiers,
+            claim_amounts,
+            ..
+        } = *options;
+        let num_instance_prev_depth = Self::get_num_instance(unique_nullifiers);
+        let (mut instances, is_proof_dummy) =
             WorldcoinIntermediateAggregationInput::check_and_join_shared_instances(
                 ctx,
                 range,
@@ -198,14 +227,15 @@ impl WorldcoinIntermediateAggregationInputV2 {
                 max_depth,
                 num_instance_prev_depth,
             );
-
-        let [instances0, instances1] = prev_instances;
+        let prev_depth =
+            WorldcoinIntermediateAggregationInput::child_depth(max_depth, prev_instances.len());
 
         // generate claim root
-        // if the 2nd snark is not dummy, we simply calculate keccak(claim_root_left| claim_root_right)
-        // if the 2nd snark is dummy, we need to calculate the right child at (max_depth - 1) with keccak256(abi.encodePacked(address(0), bytes32(0))) as leaves
-        // since this is aggregation layer, max_depth - 1 >= 0
-        let dummy_claim_root = DUMMY_CLAIM_ROOTS[max_depth - 1];
+        // the claim roots of the snarks are the subtrees of depth prev_depth of the claim tree,
+        // whose root is the keccak merkle root of them
+        // if a snark is dummy, we need to use its subtree at prev_depth with keccak256(abi.encodePacked(address(0), bytes32(0))) as leaves
+        // with per-claim roots or claim amounts, the dummy leaves also contain a zero root or amount
+        let dummy_claim_root = get_dummy_claim_roots(per_claim_roots, claim_amounts)[prev_depth];
         let dummy_claim_root_hi = F::from_u128(u128::from_be_bytes(
             dummy_claim_root[..16].try_into().unwrap(),
         ));
@@ -216,43 +246,49 @@ impl WorldcoinIntermediateAggregationInputV2 {
         let dummy_claim_root_hi = ctx.load_constant(dummy_claim_root_hi);
         let dummy_claim_root_lo = ctx.load_constant(dummy_claim_root_lo);
 
-        let claim_root_left = HiLo::from_hi_lo([instances0[5], instances0[6]]);
-        let mut claim_root_right = HiLo::from_hi_lo([instances1[5], instances1[6]]);
-
-        let claim_root_right_hi = range.gate().select(
-            ctx,
-            dummy_claim_root_hi,
-            claim_root_right.hi(),
-            is_2nd_proof_dummy,
-        );
-        let claim_root_right_lo = range.gate().select(
-            ctx,
-            dummy_claim_root_lo,
-            claim_root_right.lo(),
-            is_2nd_proof_dummy,
-        );
-
-        claim_root_right = HiLo::from_hi_lo([claim_root_right_hi, claim_root_right_lo]);
+        let gate = range.gate();
+        let instances0 = &prev_instances[0];
+        let mut claim_roots = vec![HiLo::from_hi_lo([instances0[5], instances0[6]])];
+        for (instance, is_dummy) in prev_instances[1..].iter().zip_eq(&is_proof_dummy) {
+            let claim_root_hi = gate.select(ctx, dummy_claim_root_hi, instance[5], *is_dummy);
+            let claim_root_lo = gate.select(ctx, dummy_claim_root_lo, instance[6], *is_dummy);
+            claim_roots.push(HiLo::from_hi_lo([claim_root_hi, claim_root_lo]));
+        }
 
-        // keccak(claim_root_left| claim_root_right)
-        let claim_root = compute_keccak_for_branch_nodes(
-            ctx,
-            range,
-            keccak,
-            &claim_root_left,
-            &claim_root_right,
-        );
+        // keccak(claim_root_left| claim_root_right) up to the root
+        let claim_root = compute_keccak_merkle_tree(ctx, range, keccak, claim_roots)[0];
 
         // new instances for the aggregation layer
         // [start, end, vk_hash_hi, vk_hash_lo, root, claim_root_hi, claim_root_lo]
         instances.extend(claim_root.hi_lo());
 
+        // if a snark is dummy, the nullifier hashes are those of the snarks before it
+        if unique_nullifiers {
+            let mut last = instances0[8];
+            for (i, is_dummy) in is_proof_dummy.iter().enumerate() {
+                let instance = &prev_instances[i + 1];
+                constrain_nullifier_boundary(
+                    ctx,
+                    range,
+                    prev_instances[i][8],
+                    instance[7],
+                    *is_dummy,
+                );
+                last = gate.select(ctx, last, instance[8], *is_dummy);
+            }
+            instances.extend([instances0[7], last]);
+        }
+
         instances
     }
 
     // num_instance excluding the accumulator, it's the same number as leaf circuit num_instance
-    pub fn get_num_instance() -> usize {
-        7
+    pub fn get_num_instance(unique_nullifiers: bool) -> usize {
+        if unique_nullifiers {
+            9
+        } else {
+            7
+        }
     }
 }
 
@@ -282,6 +318,6 @@ impl CircuitMetadata for WorldcoinIntermediateAggregationInputV2 {
     const HAS_ACCUMULATOR: bool = true;
 
     fn num_instance(&self) -> Vec<usize> {
-        vec![NUM_FE_ACCUMULATOR + Self::get_num_instance()]
+        vec![NUM_FE_ACCUMULATOR + Self::get_num_instance(self.options.unique_nullifiers)]
     }
 }
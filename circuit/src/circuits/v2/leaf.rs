@@ -27,10 +27,22 @@ use axiom_components::groth16::{
 use axiom_components::utils::flatten::InputFlatten;
 
 use crate::{
-    circuit_factory::leaf::WorldcoinRequestLeaf, circuits::v1::leaf::WorldcoinLeafInput,
-    constants::*, utils::compute_keccak_merkle_tree,
+    circuit_factory::leaf::WorldcoinRequestLeaf,
+    circuits::{
+        groth16::batch_verify_groth16, v1::leaf::WorldcoinLeafInput,
+        v2::intermediate::WorldcoinIntermediateAggregationInputV2,
+    },
+    constants::*,
+    keygen::node_params::CircuitOptions,
+    utils::compute_keccak_merkle_tree,
+};
+use crate::{
+    types::*,
+    utils::{
+        constrain_claim_fields, constrain_sorted_nullifiers, get_app_id_hash_bytes,
+        get_external_nullifier_hash, get_signal_hash, get_vk_set_commitment, select_vk,
+    },
 };
-use crate::{types::*, utils::get_vk_hash};
 use axiom_components::groth16::types::Groth16VerifierComponentVerificationKey;
 use std::{fmt::Debug, vec};
 
@@ -49,7 +61,9 @@ impl From<WorldcoinRequestLeaf> for WorldcoinLeafInputV2<Fr> {
 impl<F: Field> CircuitMetadata for WorldcoinLeafInputV2<F> {
     const HAS_ACCUMULATOR: bool = false;
     fn num_instance(&self) -> Vec<usize> {
-        vec![7]
+        vec![WorldcoinIntermediateAggregationInputV2::get_num_instance(
+            self.0.options.unique_nullifiers,
+        )]
     }
 }
 
@@ -76,15 +90,35 @@ impl<F: Field> EthCircuitInstructions<F> for WorldcoinLeafInputV2<F> {
             root,
             num_public_inputs,
             claims,
-            vk_bytes,
+            vk_set,
             max_depth,
+            options,
+            app_id,
+            fixed_vk_hash,
         } = self.0.assign(ctx);
+        let CircuitOptions {
+            per_claim_roots,
+            unique_nullifiers,
+            check_claim_fields,
+            claim_amounts,
+            hash_signal,
+            batch_groth16,
+            public_inputs: public_input_layout,
+            ..
+        } = options;
 
         // ==== Constraints ====
-        // 0 <= start < end < 2^64
-        range.range_check(ctx, start, 64);
-        range.range_check(ctx, end, 64);
-        range.check_less_than(ctx, start, end, 64);
+        // the shared root is unused with per-claim roots
+        if per_claim_roots {
+            ctx.constrain_equal(&root, &zero);
+        }
+
+        // 0 <= start < end < 2^64, or 2^32 with checked claim fields as the requests index claims
+        // with u32
+        let index_bits = if check_claim_fields { 32 } else { 64 };
+        range.range_check(ctx, start, index_bits);
+        range.range_check(ctx, end, index_bits);
+        range.check_less_than(ctx, start, end, index_bits);
 
         let num_proofs = gate.sub(ctx, end, start);
         let max_proofs = ctx.load_constant(F::from(1 << max_depth));
@@ -94,16 +128,18 @@ impl<F: Field> EthCircuitInstructions<F> for WorldcoinLeafInputV2<F> {
         range.range_check(ctx, num_proofs, 64);
         range.check_less_than(ctx, num_proofs, max_proofs_plus_one, 64);
 
-        let constants = get_groth16_consts_from_max_pi(MAX_GROTH16_PI);
+        let constants = get_groth16_consts_from_max_pi(public_input_layout.num_public_inputs);
 
-        assert_eq!(vk_bytes.len(), constants.num_fe_hilo_vkey);
+        assert!(vk_set
+            .iter()
+            .all(|vk_bytes| vk_bytes.len() == constants.num_fe_hilo_vkey));
 
-        let vk_hash: HiLo<AssignedValue<F>> = get_vk_hash(ctx, range, keccak, vk_bytes.clone());
-
-        let vk = Groth16VerifierComponentVerificationKey::unflatten(
-            vk_bytes,
-            constants.gamma_abc_g1_len,
-        );
+        let vk_hash: HiLo<AssignedValue<F>> = match fixed_vk_hash {
+            Some(vk_hash) => vk_hash,
+            None => get_vk_set_commitment(ctx, range, keccak, &vk_set),
+        };
+        let app_id_hash_bytes =
+            app_id.map(|app_id| get_app_id_hash_bytes(ctx, range, keccak, app_id));
 
         let selector: Vec<SafeBool<F>> =
             unsafe_lt_mask(ctx, gate, num_proofs, 1 << self.0.max_depth);
@@ -114,71 +150,154 @@ impl<F: Field> EthCircuitInstructions<F> for WorldcoinLeafInputV2<F> {
             "Collections must be of the same length"
         );
 
+        let nullifier_hashes = claims
+            .iter()
+            .map(|claim| claim.nullifier_hash)
+            .collect_vec();
+
         let inputs: Vec<(ClaimInput<AssignedValue<F>>, SafeBool<F>)> =
             izip!(claims, selector).collect();
 
-        let leaves = parallelize_core(builder.base.pool(0), inputs, |ctx, input| {
+        let results = parallelize_core(builder.base.pool(0), inputs, |ctx, input| {
             let (claim, mask) = input;
 
-            // pi[0] root
-            // pi[1] nullifier_hash
-            // pi[2] signal_hash from receiver
-            // pi[3] grant_id
-            let public_inputs =
-                [root, claim.nullifier_hash, claim.receiver, claim.grant_id].to_vec();
-
-            let groth16_verifier_input = Groth16VerifierInput {
-                vk: vk.clone(),
-                proof: Groth16VerifierComponentProof::unflatten(claim.proof_bytes).unwrap(),
-                num_public_inputs,
-                public_inputs: public_inputs.clone(),
+            // the receiver is a non-zero address, and the nullifier hash is non-zero. Padding
+            // claims are copies of the first claim, so they pass as well.
+            if check_claim_fields {
+                constrain_claim_fields(ctx, range, claim.receiver, claim.nullifier_hash);
+            }
+
+            // the receiver, or its World ID signal hash
+            let signal_hash = if hash_signal {
+                get_signal_hash(ctx, range, keccak, claim.receiver)
+            } else {
+                claim.receiver
+            };
+            // the grant id, or the external nullifier hash with the grant id as the action
+            let external_nullifier = match &app_id_hash_bytes {
+                Some(app_id_hash_bytes) => get_external_nullifier_hash(
+                    ctx,
+                    range,
+                    keccak,
+                    app_id_hash_bytes,
+                    claim.grant_id,
+                ),
+                None => claim.grant_id,
             };
 
-            // constrain groth16 verify success
-            let res = handle_single_groth16verify(
-                ctx,
-                range,
-                groth16_verifier_input,
-                LIMB_BITS,
-                NUM_LIMBS,
-                MAX_GROTH16_PI,
-            );
-            let success = res.1.success;
-            ctx.constrain_equal(&success.hi(), &zero);
-            ctx.constrain_equal(&success.lo(), &one);
+            // the claim fields and the extra public inputs, arranged by the layout
+            let public_inputs = public_input_layout
+                .public_inputs(
+                    claim.root,
+                    claim.nullifier_hash,
+                    signal_hash,
+                    external_nullifier,
+                    &claim.extra_public_inputs,
+                )
+                .expect("Invalid extra public inputs");
+
+            // the proofs are verified together below with a batched Groth16 check
+            let batched_proof = if batch_groth16 {
+                Some((claim.proof_bytes, public_inputs))
+            } else {
+                let vk = Groth16VerifierComponentVerificationKey::unflatten(
+                    select_vk(ctx, range, &vk_set, claim.vk_index),
+                    constants.gamma_abc_g1_len,
+                );
+                let groth16_verifier_input = Groth16VerifierInput {
+                    vk,
+                    proof: Groth16VerifierComponentProof::unflatten(claim.proof_bytes).unwrap(),
+                    num_public_inputs,
+                    public_inputs,
+                };
+
+                // constrain groth16 verify success
+                let res = handle_single_groth16verify(
+                    ctx,
+                    range,
+                    groth16_verifier_input,
+                    LIMB_BITS,
+                    NUM_LIMBS,
+                    public_input_layout.num_public_inputs,
+                );
+                let success = res.1.success;
+                ctx.constrain_equal(&success.hi(), &zero);
+                ctx.constrain_equal(&success.lo(), &one);
+                None
+            };
 
             // use mask to calculate the correct leaf
             // Leaves: keccak256(abi.encodePacked(grant_ids_i, receivers_i, nullifierHashes_i))
             // Leaves with indices greater than num_proofs - 1 are given by keccak256(abi.encodePacked(uint256(0), address(0), bytes32(0)))
+            // With per-claim roots, the claim's root is appended: keccak256(abi.encodePacked(grant_ids_i, receivers_i, nullifierHashes_i, roots_i))
+            // With claim amounts, the claim's amount is appended after that, and the leaf starts with CLAIM_AMOUNT_LEAF_PREFIX so that it differs in length from the leaves without an amount: keccak256(abi.encodePacked(bytes1(0x01), grant_ids_i, receivers_i, nullifierHashes_i, (roots_i,) amounts_i))
             let mut bytes = Vec::new();
             let masked_grant_id = gate.mul(ctx, claim.grant_id, mask);
             let masked_receiver = gate.mul(ctx, claim.receiver, mask);
-            let masked_nullifier_hash = gate.mul(ctx, public_inputs[1], mask);
+            let masked_nullifier_hash = gate.mul(ctx, claim.nullifier_hash, mask);
             bytes.extend(uint_to_bytes_be(ctx, range, &masked_grant_id, 32));
             bytes.extend(uint_to_bytes_be(ctx, range, &masked_receiver, 20));
             bytes.extend(uint_to_bytes_be(ctx, range, &masked_nullifier_hash, 32));
-            let bytes = bytes.iter().map(|sb| *sb.as_ref()).collect();
+            if per_claim_roots {
+                let masked_root = gate.mul(ctx, claim.root, mask);
+                bytes.extend(uint_to_bytes_be(ctx, range, &masked_root, 32));
+            }
+            if claim_amounts {
+                let masked_amount = gate.mul(ctx, claim.amount, mask);
+                bytes.extend(uint_to_bytes_be(ctx, range, &masked_amount, 32));
+            }
+            let prefix =
+                claim_amounts.then(|| ctx.load_constant(F::from(CLAIM_AMOUNT_LEAF_PREFIX as u64)));
+            let bytes = prefix
+                .into_iter()
+                .chain(bytes.iter().map(|sb| *sb.as_ref()))
+                .collect();
             let keccak_hash = keccak.keccak_fixed_len(ctx, bytes);
 
-            HiLo::from_hi_lo([keccak_hash.output_hi, keccak_hash.output_lo])
+            (
+                HiLo::from_hi_lo([keccak_hash.output_hi, keccak_hash.output_lo]),
+                batched_proof,
+            )
         });
+        let (leaves, batched_proofs): (Vec<_>, Vec<_>) = results.into_iter().unzip();
+
+        // a single vk, see NodeParams
+        if batch_groth16 {
+            batch_verify_groth16(
+                builder.base.pool(0),
+                range,
+                &vk_set[0],
+                vk_hash,
+                batched_proofs.into_iter().flatten().collect(),
+                public_input_layout.num_public_inputs,
+            );
+        }
 
         let ctx = builder.base.main(0);
 
         let merkle_tree = compute_keccak_merkle_tree(ctx, range, keccak, leaves);
         let claim_root = merkle_tree[0];
 
+        // the claims are sorted by nullifier hash, so the nullifier hashes are pairwise distinct
+        let nullifier_range = if unique_nullifiers {
+            constrain_sorted_nullifiers(ctx, range, &nullifier_hashes, num_proofs).to_vec()
+        } else {
+            vec![]
+        };
+
         // instances:
         // [0] start
         // [1] end
-        // [2, 3] vkey_hash
-        // [4] root
+        // [2, 3] vkey_hash, or the commitment to the vk set
+        // [4] root, zero with per-claim roots
         // [5, 6] claim_root
+        // [7, 8] first and last nullifier_hash, with unique nullifiers
         let assigned_instances = iter::empty()
             .chain([start, end])
             .chain(vk_hash.hi_lo())
             .chain([root])
             .chain(claim_root.hi_lo())
+            .chain(nullifier_range)
             .collect_vec();
 
         builder.base.assigned_instances[0] = assigned_instances;
@@ -0,0 +1,218 @@
+//! Rolling aggregation of successive V2 batches, see [crate::circuits::v1::rolling] for how the
+//! batches are chained. A [WorldcoinRollingAggregationCircuitV2] aggregates the previous rolling
+//! proof, or the root proof of the first batch, and the [WorldcoinRootAggregationCircuitV2] proof
+//! of the next batch. All batches must have the same vk hash.
+//!
+//! Rolling Aggregation circuit public outputs: [num_batches, vk_hash_hi, vk_hash_lo, num_proofs,
+//! chain_hash_hi, chain_hash_lo], where `num_proofs` is the total over all batches and the chain
+//! hash starts at zero and each batch appends its root and claim root:
+//! `chain_hash = keccak256(abi.encodePacked(chain_hash, root, claim_root))`
+
+use anyhow::Result;
+use axiom_eth::{
+    halo2_base::{
+        gates::{GateInstructions, RangeChip, RangeInstructions},
+        AssignedValue, Context,
+    },
+    halo2_proofs::poly::kzg::commitment::ParamsKZG,
+    halo2curves::bn256::{Bn256, Fr},
+    keccak::KeccakChip,
+    mpt::MPTChip,
+    rlc::circuit::builder::RlcCircuitBuilder,
+    snark_verifier_sdk::{
+        halo2::aggregation::{aggregate_snarks, SnarkAggregationOutput, VerifierUniversality},
+        Snark, SHPLONK,
+    },
+    utils::{
+        build_utils::aggregation::CircuitMetadata, eth_circuit::EthCircuitInstructions, hilo::HiLo,
+        keccak::decorator::RlcKeccakCircuitImpl, snark_verifier::NUM_FE_ACCUMULATOR,
+        uint_to_bytes_be,
+    },
+    Field,
+};
+use itertools::Itertools;
+
+use crate::circuits::v1::rolling::WorldcoinRollingAggregationInput;
+
+use super::root::WorldcoinRootAggregationInputV2;
+
+pub type WorldcoinRollingAggregationCircuitV2 =
+    RlcKeccakCircuitImpl<Fr, WorldcoinRollingAggregationInputV2>;
+
+#[derive(Clone, Debug)]
+pub struct WorldcoinRollingAggregationInputV2(pub WorldcoinRollingAggregationInput);
+
+impl WorldcoinRollingAggregationInputV2 {
+    pub fn new(
+        snarks: Vec<Snark>,
+        num_batches: u32,
+        max_batches: usize,
+        kzg_params: &ParamsKZG<Bn256>,
+    ) -> Result<Self> {
+        WorldcoinRollingAggregationInput::new(snarks, num_batches, max_batches, kzg_params)
+            .map(Self)
+    }
+
+    /// Returns the new chain hash `keccak(chain_hash | root | claim_root)`, or `chain_hash` if the
+    /// batch is a dummy
+    pub fn append_batch<F: Field>(
+        ctx: &mut Context<F>,
+        range: &RangeChip<F>,
+        keccak: &KeccakChip<F>,
+        chain_hash: &HiLo<AssignedValue<F>>,
+        root: AssignedValue<F>,
+        claim_root: &HiLo<AssignedValue<F>>,
+        is_batch_dummy: AssignedValue<F>,
+    ) -> HiLo<AssignedValue<F>> {
+        let words = [
+            (chain_hash.hi(), 16),
+            (chain_hash.lo(), 16),
+            (root, 32),
+            (claim_root.hi(), 16),
+            (claim_root.lo(), 16),
+        ];
+        let bytes = words
+            .iter()
+            .flat_map(|(word, num_bytes)| uint_to_bytes_be(ctx, range, word, *num_bytes))
+            .map(|sb| *sb.as_ref())
+            .collect_vec();
+        let appended = keccak.keccak_fixed_len(ctx, bytes);
+
+        let hi = range
+            .gate()
+            .select(ctx, chain_hash.hi(), appended.output_hi, is_batch_dummy);
+        let lo = range
+            .gate()
+            .select(ctx, chain_hash.lo(), appended.output_lo, is_batch_dummy);
+        HiLo::from_hi_lo([hi, lo])
+    }
+
+    // num_instance excluding the accumulator
+    pub fn get_num_instance() -> usize {
+        6
+    }
+}
+
+impl EthCircuitInstructions<Fr> for WorldcoinRollingAggregationInputV2 {
+    type FirstPhasePayload = ();
+
+    fn virtual_assign_phase0(
+        &self,
+        builder: &mut RlcCircuitBuilder<Fr>,
+        mpt: &MPTChip<Fr>,
+    ) -> Self::FirstPhasePayload {
+        let input = &self.0;
+        let keccak = mpt.keccak();
+        let range = keccak.range();
+        let pool = builder.base.pool(0);
+        let SnarkAggregationOutput {
+            mut previous_instances,
+            accumulator,
+            ..
+        } = aggregate_snarks::<SHPLONK>(
+            pool,
+            range,
+            input.svk,
+            input.snarks.clone(),
+            VerifierUniversality::None,
+        );
+
+        // remove old accumulators
+        for (prev_instance, acc_indices) in previous_instances
+            .iter_mut()
+            .zip_eq(&input.prev_acc_indices)
+        {
+            for i in acc_indices.iter().sorted().rev() {
+                prev_instance.remove(*i);
+            }
+        }
+
+        let ctx = pool.main();
+        let [prev, batch]: [Vec<AssignedValue<Fr>>; 2] = previous_instances.try_into().unwrap();
+        // root circuit: [vk_hash_hi, vk_hash_lo, root, num_proofs, claim_root_hi, claim_root_lo]
+        assert_eq!(
+            batch.len(),
+            WorldcoinRootAggregationInputV2::get_num_instance()
+        );
+
+        let (prev_num_batches, vk_hash, prev_num_proofs, prev_chain_hash) =
+            if input.max_batches == 2 {
+                // the root proof of the first batch, appended to the empty chain
+                assert_eq!(
+                    prev.len(),
+                    WorldcoinRootAggregationInputV2::get_num_instance()
+                );
+                let zero = ctx.load_zero();
+                let chain_hash = Self::append_batch(
+                    ctx,
+                    &range,
+                    &keccak,
+                    &HiLo::from_hi_lo([zero, zero]),
+                    prev[2],
+                    &HiLo::from_hi_lo([prev[4], prev[5]]),
+                    zero,
+                );
+                let num_batches = ctx.load_constant(Fr::from(1));
+                (num_batches, [prev[0], prev[1]], prev[3], chain_hash)
+            } else {
+                // rolling circuit: [num_batches, vk_hash_hi, vk_hash_lo, num_proofs, chain_hash_hi, chain_hash_lo]
+                assert_eq!(prev.len(), Self::get_num_instance());
+                let chain_hash = HiLo::from_hi_lo([prev[4], prev[5]]);
+                (prev[0], [prev[1], prev[2]], prev[3], chain_hash)
+            };
+        // every batch, including a dummy, has the same vk hash
+        ctx.constrain_equal(&vk_hash[0], &batch[0]);
+        ctx.constrain_equal(&vk_hash[1], &batch[1]);
+
+        let num_batches = ctx.load_witness(Fr::from(input.num_batches as u64));
+        let is_batch_dummy = WorldcoinRollingAggregationInput::check_and_join_batches(
+            ctx,
+            &range,
+            prev_num_batches,
+            num_batches,
+            input.max_batches,
+        );
+        let batch_num_proofs = range.gate().mul_not(ctx, is_batch_dummy, batch[3]);
+        let num_proofs = range.gate().add(ctx, prev_num_proofs, batch_num_proofs);
+        let chain_hash = Self::append_batch(
+            ctx,
+            &range,
+            &keccak,
+            &prev_chain_hash,
+            batch[2],
+            &HiLo::from_hi_lo([batch[4], batch[5]]),
+            is_batch_dummy,
+        );
+
+        let assigned_instances: &mut Vec<AssignedValue<Fr>> =
+            &mut builder.base.assigned_instances[0];
+
+        assigned_instances.extend(accumulator);
+        assigned_instances.push(num_batches);
+        assigned_instances.extend(vk_hash);
+        assigned_instances.push(num_proofs);
+        assigned_instances.extend(chain_hash.hi_lo());
+
+        assert_eq!(
+            assigned_instances.len(),
+            NUM_FE_ACCUMULATOR + Self::get_num_instance()
+        );
+    }
+
+    fn virtual_assign_phase1(
+        &self,
+        _: &mut RlcCircuitBuilder<Fr>,
+        _: &MPTChip<Fr>,
+        _: Self::FirstPhasePayload,
+    ) {
+        // do nothing
+    }
+}
+
+impl CircuitMetadata for WorldcoinRollingAggregationInputV2 {
+    const HAS_ACCUMULATOR: bool = true;
+
+    fn num_instance(&self) -> Vec<usize> {
+        vec![NUM_FE_ACCUMULATOR + Self::get_num_instance()]
+    }
+}
@@ -1,7 +1,7 @@
 //! The root of the aggregation tree.
 //! An [WorldcoinRootAggregationCircuit] can aggregate either:
-//! - two [WorldcoinLeafnCircuit]s (if `max_depth == initial_depth + 1`) or
-//! - two [WorldcoinIntermediateAggregationCircuit]s.
+//! - `arity` [WorldcoinLeafnCircuit]s (if `max_depth == initial_depth + log2(arity)`) or
+//! - `arity` [WorldcoinIntermediateAggregationCircuit]s.
 //!
 //! The difference between Intermediate and Root aggregation circuits is that they expose different public outputs. Root aggregation
 //! exposes the hash of the output [vk_hash_hi, vk_hash_lo, root, num_proofs, claim_root_hi, claim_root_lo]
@@ -20,6 +20,7 @@ use axiom_eth::{
 };
 
 use super::intermediate::WorldcoinIntermediateAggregationInputV2;
+use crate::keygen::node_params::CircuitOptions;
 
 pub type WorldcoinRootAggregationCircuitV2 =
     RlcKeccakCircuitImpl<Fr, WorldcoinRootAggregationInputV2>;
@@ -33,6 +34,7 @@ impl WorldcoinRootAggregationInputV2 {
         num_proofs: u32,
         max_depth: usize,
         initial_depth: usize,
+        options: CircuitOptions,
         kzg_params: &ParamsKZG<Bn256>,
     ) -> Result<Self> {
         WorldcoinIntermediateAggregationInputV2::new(
@@ -40,6 +42,7 @@ impl WorldcoinRootAggregationInputV2 {
             num_proofs,
             max_depth,
             initial_depth,
+            options,
             kzg_params,
         )
         .map(Self)
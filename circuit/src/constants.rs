@@ -1,14 +1,8 @@
-use std::fs;
-
 use ethers::utils::keccak256;
 use lazy_static::lazy_static;
 
-use crate::types::VkNative;
-
+/// Number of public inputs of the Semaphore proofs of World ID, the default public input layout
 pub const MAX_GROTH16_PI: usize = 4;
-pub const NUM_FE_VKEY: usize = 25;
-pub const NUM_FE_GROTH16_INPUT: usize = 38;
-pub const NUM_BYTES_VK: usize = 769;
 pub const NUM_LIMBS: usize = 3;
 pub const LIMB_BITS: usize = 88;
 
@@ -17,43 +11,67 @@ pub const INITIAL_DEPTH: usize = 3;
 // extra rounds for evm proof
 pub const EXTRA_ROUNDS: usize = 1;
 
+/// First byte of the V2 claim leaves with an amount. A leaf with a root and a leaf with an amount
+/// would otherwise have the same length, so that one could be claimed as the other.
+pub const CLAIM_AMOUNT_LEAF_PREFIX: u8 = 1;
+
 lazy_static! {
-    pub static ref VK: VkNative = {
-        let file_path = "./data/vk.json";
-        let json_data = fs::read_to_string(file_path).expect("Unable to read vk");
-        serde_json::from_str(&json_data).expect("Unable to parse vk json")
-    };
-
-    pub static ref DUMMY_CLAIM_ROOTS: Vec<[u8; 32]> = {
-        let max_depth = 13; // 8192
-        // grant_id (32bytes) + receiver (20) + nullifier_hash (32)
-        let dummy_leaf = [0u8; 84];
-        let mut roots = Vec::with_capacity(max_depth + 1);
-
-        // Initialize the first level (leaf level)
-        let mut current_level = vec![keccak256(&dummy_leaf); 1];
-
-        // Store the root at depth 0
-        roots.push(current_level[0]);
+    // grant_id (32bytes) + receiver (20) + nullifier_hash (32)
+    pub static ref DUMMY_CLAIM_ROOTS: Vec<[u8; 32]> = dummy_claim_roots(&[], 84);
+
+    // grant_id (32bytes) + receiver (20) + nullifier_hash (32) + root (32)
+    pub static ref DUMMY_CLAIM_ROOTS_WITH_ROOT: Vec<[u8; 32]> = dummy_claim_roots(&[], 116);
+
+    // prefix (1 byte) + grant_id (32bytes) + receiver (20) + nullifier_hash (32) + amount (32)
+    pub static ref DUMMY_CLAIM_ROOTS_WITH_AMOUNT: Vec<[u8; 32]> =
+        dummy_claim_roots(&[CLAIM_AMOUNT_LEAF_PREFIX], 116);
+
+    // prefix (1 byte) + grant_id (32bytes) + receiver (20) + nullifier_hash (32) + root (32) +
+    // amount (32)
+    pub static ref DUMMY_CLAIM_ROOTS_WITH_ROOT_AND_AMOUNT: Vec<[u8; 32]> =
+        dummy_claim_roots(&[CLAIM_AMOUNT_LEAF_PREFIX], 148);
+}
 
-        // Iteratively compute and store roots for each depth
-        for _ in 1..=max_depth {
-            // Compute the next level up
-            current_level = current_level
-                .iter()
-                .flat_map(|hash| {
-                    let mut concatenated = [0u8; 64];
-                    concatenated[..32].copy_from_slice(hash);
-                    concatenated[32..].copy_from_slice(hash); // Duplicate the hash to simulate sibling pair
-                    [keccak256(&concatenated)].into_iter()
-                })
-                .collect();
-
-            // Store the computed root for this depth
-            roots.push(current_level[0]);
-        }
-
-        roots
-    };
+/// The roots of empty claim trees by depth, for the leaf encoding with or without per-claim roots
+/// and claim amounts
+pub fn get_dummy_claim_roots(per_claim_roots: bool, claim_amounts: bool) -> &'static [[u8; 32]] {
+    match (per_claim_roots, claim_amounts) {
+        (false, false) => &DUMMY_CLAIM_ROOTS,
+        (true, false) => &DUMMY_CLAIM_ROOTS_WITH_ROOT,
+        (false, true) => &DUMMY_CLAIM_ROOTS_WITH_AMOUNT,
+        (true, true) => &DUMMY_CLAIM_ROOTS_WITH_ROOT_AND_AMOUNT,
+    }
+}
+
+/// Roots of claim trees of depth `0..=13` whose leaves are all the hash of `prefix` followed by
+/// `leaf_len` zero bytes
+fn dummy_claim_roots(prefix: &[u8], leaf_len: usize) -> Vec<[u8; 32]> {
+    let max_depth = 13; // 8192
+    let dummy_leaf = [prefix, &vec![0u8; leaf_len]].concat();
+    let mut roots = Vec::with_capacity(max_depth + 1);
+
+    // Initialize the first level (leaf level)
+    let mut current_level = vec![keccak256(&dummy_leaf); 1];
+
+    // Store the root at depth 0
+    roots.push(current_level[0]);
+
+    // Iteratively compute and store roots for each depth
+    for _ in 1..=max_depth {
+        // Compute the next level up
+        current_level = current_level
+            .iter()
+            .flat_map(|hash| {
+                let mut concatenated = [0u8; 64];
+                concatenated[..32].copy_from_slice(hash);
+                concatenated[32..].copy_from_slice(hash); // Duplicate the hash to simulate sibling pair
+                [keccak256(&concatenated)].into_iter()
+            })
+            .collect();
+
+        // Store the computed root for this depth
+        roots.push(current_level[0]);
+    }
 
+    roots
 }
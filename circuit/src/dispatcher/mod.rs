@@ -0,0 +1,320 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{self, File},
+    path::PathBuf,
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::{anyhow, Context, Result};
+use ethers::utils::{hex, keccak256};
+use log::{debug, info, warn};
+use tokio::sync::{mpsc, Mutex};
+use uuid::Uuid;
+
+use crate::{
+    prover::types::{ProverProof, ProverTask, ProverTaskResponse},
+    scheduler::types::current_timstamp_sec,
+    CircuitId,
+};
+
+use self::types::{
+    DispatcherTaskId, DispatcherTaskRequest, DispatcherTaskStatus, DispatcherTaskStatusResponse,
+};
+
+pub mod types;
+
+/// A proof is uniquely identified by `(circuit_id, keccak(input))`
+type CacheKey = (CircuitId, String);
+
+struct DispatcherTask {
+    request: DispatcherTaskRequest,
+    cache_key: CacheKey,
+    status: DispatcherTaskStatusResponse,
+}
+
+/// Reference implementation of the dispatcher API. Tasks are queued and fanned out to a pool of
+/// proving servers, one task per prover at a time. Each prover is asked to preload the circuit
+/// data through `/internal/circuit-data` before its first task for a circuit.
+///
+/// Proofs are cached by `(circuit_id, keccak(input))`, in memory and, if `cache_dir` is set, on
+/// disk as `<cache_dir>/<circuit_id>_<input_hash>.json`.
+///
+/// `DONE` and `FAILED` tasks are evicted once they have not been updated for `task_ttl`, together
+/// with the proofs in memory which no remaining task refers to. Proofs on disk are kept.
+pub struct Dispatcher {
+    provers: Vec<reqwest::Url>,
+    cache_dir: Option<PathBuf>,
+    task_ttl: Duration,
+    tasks: Mutex<HashMap<DispatcherTaskId, DispatcherTask>>,
+    cache: Mutex<HashMap<CacheKey, ProverProof>>,
+    queue_tx: mpsc::UnboundedSender<DispatcherTaskId>,
+    queue_rx: Mutex<mpsc::UnboundedReceiver<DispatcherTaskId>>,
+}
+
+impl Dispatcher {
+    pub fn new(
+        prover_urls: &[String],
+        cache_dir: Option<PathBuf>,
+        task_ttl: Duration,
+    ) -> Result<Self> {
+        if prover_urls.is_empty() {
+            return Err(anyhow!("At least one prover url is required"));
+        }
+        let provers = prover_urls
+            .iter()
+            .map(|url| reqwest::Url::parse(url).with_context(|| format!("Invalid url {url}")))
+            .collect::<Result<Vec<_>>>()?;
+        if let Some(cache_dir) = &cache_dir {
+            fs::create_dir_all(cache_dir)
+                .with_context(|| format!("Failed to create {}", cache_dir.display()))?;
+        }
+        let (queue_tx, queue_rx) = mpsc::unbounded_channel();
+        Ok(Self {
+            provers,
+            cache_dir,
+            task_ttl,
+            tasks: Default::default(),
+            cache: Default::default(),
+            queue_tx,
+            queue_rx: Mutex::new(queue_rx),
+        })
+    }
+
+    /// Start one worker per prover and the eviction of expired tasks. Must be called from within
+    /// the tokio runtime.
+    pub fn spawn_workers(self: &Arc<Self>) {
+        for prover in self.provers.clone() {
+            let dispatcher = Arc::clone(self);
+            tokio::spawn(async move { dispatcher.run_worker(prover).await });
+        }
+        let dispatcher = Arc::clone(self);
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(dispatcher.task_ttl).await;
+                dispatcher.evict_expired_tasks().await;
+            }
+        });
+    }
+
+    /// Create a task. If a proof for `(circuit_id, input)` is cached and `force_prove` is not
+    /// set, the task is `DONE` immediately.
+    pub async fn create_task(&self, request: DispatcherTaskRequest) -> Result<DispatcherTaskId> {
+        let cache_key = cache_key(&request)?;
+        // the cached proof cannot be evicted before the task refers to it
+        let mut tasks = self.tasks.lock().await;
+        let cached = if request.force_prove {
+            None
+        } else {
+            self.get_cached_proof(&cache_key).await?
+        };
+
+        let task_id = Uuid::new_v4().to_string();
+        let now = current_timstamp_sec();
+        let status = if cached.is_some() {
+            DispatcherTaskStatus::Done
+        } else {
+            DispatcherTaskStatus::Pending
+        };
+        debug!(
+            "Created task {task_id} for circuit id {} with status {status:?}",
+            request.circuit_id
+        );
+        tasks.insert(
+            task_id.clone(),
+            DispatcherTask {
+                request,
+                cache_key,
+                status: DispatcherTaskStatusResponse {
+                    status,
+                    created_at: now,
+                    updated_at: now,
+                    error: None,
+                },
+            },
+        );
+        if cached.is_none() {
+            self.queue_tx.send(task_id.clone())?;
+        }
+        Ok(task_id)
+    }
+
+    pub async fn get_status(&self, task_id: &str) -> Option<DispatcherTaskStatusResponse> {
+        self.tasks
+            .lock()
+            .await
+            .get(task_id)
+            .map(|task| task.status.clone())
+    }
+
+    /// The proof of a `DONE` task
+    pub async fn get_proof(&self, task_id: &str) -> Result<Option<ProverProof>> {
+        let cache_key = match self.tasks.lock().await.get(task_id) {
+            Some(task) if task.status.status == DispatcherTaskStatus::Done => {
+                task.cache_key.clone()
+            }
+            _ => return Ok(None),
+        };
+        self.get_cached_proof(&cache_key).await
+    }
+
+    async fn run_worker(&self, prover: reqwest::Url) {
+        info!("Worker for prover {prover} started");
+        // circuits whose data has been loaded by this prover
+        let mut loaded_circuits = HashSet::new();
+        loop {
+            let task_id = match self.queue_rx.lock().await.recv().await {
+                Some(task_id) => task_id,
+                None => return,
+            };
+            let request = match self.tasks.lock().await.get(&task_id) {
+                Some(task) => task.request.clone(),
+                None => continue,
+            };
+            let task = ProverTask {
+                circuit_id: request.circuit_id,
+                input: request.input,
+            };
+            match self
+                .prove(&prover, &mut loaded_circuits, &task_id, task)
+                .await
+            {
+                Ok(proof) => {
+                    if let Err(e) = self.finish_task(&task_id, proof).await {
+                        warn!("Failed to store proof of task {task_id}: {e:?}");
+                        self.fail_task(&task_id, &e).await;
+                    }
+                }
+                Err(e) => {
+                    warn!("Task {task_id} failed on prover {prover}: {e:?}");
+                    self.fail_task(&task_id, &e).await;
+                    // the prover may have restarted and lost the circuit data
+                    loaded_circuits.clear();
+                }
+            }
+        }
+    }
+
+    async fn prove(
+        &self,
+        prover: &reqwest::Url,
+        loaded_circuits: &mut HashSet<CircuitId>,
+        task_id: &str,
+        task: ProverTask,
+    ) -> Result<ProverProof> {
+        // proving can take long, so no request timeout and no retries
+        let client = reqwest::Client::new();
+        if !loaded_circuits.contains(&task.circuit_id) {
+            self.set_status(task_id, DispatcherTaskStatus::Preparing)
+                .await;
+            let url = prover.join("internal/circuit-data")?;
+            client
+                .post(url.clone())
+                .json(&task)
+                .send()
+                .await
+                .and_then(|resp| resp.error_for_status())
+                .with_context(|| format!("Failed when requesting {url}"))?;
+            loaded_circuits.insert(task.circuit_id.clone());
+        }
+
+        self.set_status(task_id, DispatcherTaskStatus::Proving)
+            .await;
+        let url = prover.join("tasks")?;
+        let resp: ProverTaskResponse = client
+            .post(url.clone())
+            .json(&task)
+            .send()
+            .await
+            .and_then(|resp| resp.error_for_status())
+            .with_context(|| format!("Failed when requesting {url}"))?
+            .json()
+            .await
+            .with_context(|| format!("Failed to parse response of {url}"))?;
+        Ok(resp.payload)
+    }
+
+    async fn finish_task(&self, task_id: &str, proof: ProverProof) -> Result<()> {
+        let cache_key = match self.tasks.lock().await.get(task_id) {
+            Some(task) => task.cache_key.clone(),
+            None => return Ok(()),
+        };
+        if let Some(path) = self.cache_path(&cache_key) {
+            let tmp_path = path.with_extension("json.tmp");
+            serde_json::to_writer(File::create(&tmp_path)?, &proof)?;
+            fs::rename(&tmp_path, &path)
+                .with_context(|| format!("Failed to write {}", path.display()))?;
+        }
+        self.cache.lock().await.insert(cache_key, proof);
+        self.set_status(task_id, DispatcherTaskStatus::Done).await;
+        Ok(())
+    }
+
+    async fn fail_task(&self, task_id: &str, error: &anyhow::Error) {
+        if let Some(task) = self.tasks.lock().await.get_mut(task_id) {
+            task.status.status = DispatcherTaskStatus::Failed;
+            task.status.updated_at = current_timstamp_sec();
+            task.status.error = Some(format!("{error:#}"));
+        }
+    }
+
+    /// Evict the terminal tasks which were last updated more than `task_ttl` ago, and the proofs
+    /// in memory which no remaining task refers to
+    async fn evict_expired_tasks(&self) {
+        let now = current_timstamp_sec();
+        let mut tasks = self.tasks.lock().await;
+        let num_tasks = tasks.len();
+        tasks.retain(|_, task| {
+            let is_terminal = matches!(
+                task.status.status,
+                DispatcherTaskStatus::Done | DispatcherTaskStatus::Failed
+            );
+            !is_terminal || task.status.updated_at + self.task_ttl.as_secs() > now
+        });
+        let cache_keys: HashSet<&CacheKey> = tasks.values().map(|task| &task.cache_key).collect();
+        let mut cache = self.cache.lock().await;
+        let num_proofs = cache.len();
+        cache.retain(|cache_key, _| cache_keys.contains(cache_key));
+        debug!(
+            "Evicted {} tasks and {} proofs",
+            num_tasks - tasks.len(),
+            num_proofs - cache.len()
+        );
+    }
+
+    async fn set_status(&self, task_id: &str, status: DispatcherTaskStatus) {
+        if let Some(task) = self.tasks.lock().await.get_mut(task_id) {
+            task.status.status = status;
+            task.status.updated_at = current_timstamp_sec();
+        }
+    }
+
+    async fn get_cached_proof(&self, cache_key: &CacheKey) -> Result<Option<ProverProof>> {
+        if let Some(proof) = self.cache.lock().await.get(cache_key) {
+            return Ok(Some(proof.clone()));
+        }
+        let path = match self.cache_path(cache_key) {
+            Some(path) if path.exists() => path,
+            _ => return Ok(None),
+        };
+        let f = File::open(&path).with_context(|| format!("Failed to open {}", path.display()))?;
+        let proof: ProverProof = serde_json::from_reader(f)
+            .with_context(|| format!("Failed to parse {}", path.display()))?;
+        self.cache
+            .lock()
+            .await
+            .insert(cache_key.clone(), proof.clone());
+        Ok(Some(proof))
+    }
+
+    fn cache_path(&self, (circuit_id, input_hash): &CacheKey) -> Option<PathBuf> {
+        self.cache_dir
+            .as_ref()
+            .map(|dir| dir.join(format!("{circuit_id}_{input_hash}.json")))
+    }
+}
+
+fn cache_key(request: &DispatcherTaskRequest) -> Result<CacheKey> {
+    let input = serde_json::to_vec(&request.input)?;
+    Ok((request.circuit_id.clone(), hex::encode(keccak256(input))))
+}
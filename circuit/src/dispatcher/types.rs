@@ -0,0 +1,47 @@
+use serde::{Deserialize, Serialize};
+
+use crate::prover::types::{ProverTaskResponse, TaskInput};
+
+pub type DispatcherTaskId = String;
+
+/// Body of `POST /tasks`
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DispatcherTaskRequest {
+    pub circuit_id: String,
+    pub input: TaskInput,
+    /// Generate a new proof even if one is cached for `(circuit_id, input)`
+    #[serde(default)]
+    pub force_prove: bool,
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Debug)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum DispatcherTaskStatus {
+    /// Waiting for a free prover
+    Pending,
+    /// A prover is assigned and is loading the circuit data
+    Preparing,
+    Proving,
+    Done,
+    Failed,
+}
+
+/// Response of `GET /tasks/:taskId/status`
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DispatcherTaskStatusResponse {
+    pub status: DispatcherTaskStatus,
+    #[serde(default)]
+    pub created_at: u64,
+    #[serde(default)]
+    pub updated_at: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Response of `GET /tasks/:taskId/snark`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DispatcherSnarkResponse {
+    pub snark: ProverTaskResponse,
+}
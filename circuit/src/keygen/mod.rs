@@ -35,10 +35,25 @@ use axiom_eth::{
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    circuit_factory::leaf::*, constants::VK, types::WorldcoinRequest,
-    WorldcoinIntermediateAggregationCircuit, WorldcoinIntermediateAggregationInput,
-    WorldcoinLeafCircuit, WorldcoinLeafInput, WorldcoinRootAggregationCircuit,
-    WorldcoinRootAggregationInput,
+    circuit_factory::leaf::*,
+    circuits::{
+        v1::{
+            intermediate::WorldcoinIntermediateAggregationInput,
+            leaf::{WorldcoinLeafCircuit, WorldcoinLeafInput},
+            rolling::{WorldcoinRollingAggregationCircuit, WorldcoinRollingAggregationInput},
+            root::{WorldcoinRootAggregationCircuit, WorldcoinRootAggregationInput},
+        },
+        v2::{
+            intermediate::{
+                WorldcoinIntermediateAggregationCircuitV2, WorldcoinIntermediateAggregationInputV2,
+            },
+            leaf::{WorldcoinLeafCircuitV2, WorldcoinLeafInputV2},
+            rolling::{WorldcoinRollingAggregationCircuitV2, WorldcoinRollingAggregationInputV2},
+            root::{WorldcoinRootAggregationCircuitV2, WorldcoinRootAggregationInputV2},
+        },
+    },
+    types::{ClaimNative, WorldcoinRequest},
+    vk_registry::{read_vk, DEFAULT_VK_PATH},
 };
 
 pub mod node_params;
@@ -51,11 +66,18 @@ pub struct RecursiveIntent {
     /// layers of the tree. Our aggregation structure is such that each layer of the tree only has a single circuit type so only one `k` is needed.
     pub k_at_depth: Vec<u32>,
     pub params: NodeParams,
+    /// The World ID app id baked into the leaf circuits with `external_nullifier`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub app_id: Option<String>,
 }
 
 impl RecursiveIntent {
-    pub fn new(k_at_depth: Vec<u32>, params: NodeParams) -> Self {
-        Self { k_at_depth, params }
+    pub fn new(k_at_depth: Vec<u32>, params: NodeParams, app_id: Option<String>) -> Self {
+        Self {
+            k_at_depth,
+            params,
+            app_id,
+        }
     }
     /// Each layer of tree has a unique circuit type, so this is the child circuit type.
     pub fn child(&self) -> Option<Self> {
@@ -63,47 +85,86 @@ impl RecursiveIntent {
         self.params.child().map(|params| Self {
             k_at_depth: self.k_at_depth[1..].to_vec(),
             params,
+            app_id: self.app_id.clone(),
         })
     }
 }
 
-#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Clone, Debug, Hash, PartialEq, Eq, Serialize, Deserialize)]
 pub struct IntentLeaf {
     pub k: u32,
     /// The leaf layer of the aggregation starts with max number of proofs equal to 2<sup>depth</sup>.
     pub depth: usize,
+    /// Number of verifying keys in the vk set of the leaf
+    pub num_vks: usize,
+    /// The modes of the leaf. With `fixed_vk`, the sample vk is a constant of the leaf, and with
+    /// `external_nullifier`, [Self::app_id] is.
+    pub options: CircuitOptions,
+    /// The app id of the intent, only used with `external_nullifier`
+    pub app_id: Option<String>,
 }
 
+#[derive(Clone, Debug, Hash, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IntentLeafV2(pub IntentLeaf);
+
 #[derive(Clone, Debug)]
 pub(crate) struct IntentIntermediate {
     pub k: u32,
     // This is from bad UX; only svk = kzg_params.get_g()[0] is used
     pub kzg_params: Arc<ParamsKZG<Bn256>>,
-    // There will be exponential duplication since both children have the same circuit type, but it seems better for clarity
+    // There will be exponential duplication since all children have the same circuit type, but it seems better for clarity
+    /// One tree per child, so its length is the arity of the node
     pub to_agg: Vec<AggTreeId>,
-    /// There are always two children of the same type, so we only specify the intent for one of them
+    /// All children are of the same type, so we only specify the intent for one of them
     pub child_intent: AggregationDependencyIntentOwned,
     /// The maximum number of proofs at this level of the tree is 2<sup>depth</sup>.
     pub depth: usize,
     /// The leaf layer of the aggregation starts with max number of proofs equal to 2<sup>initial_depth</sup>.
     pub initial_depth: usize,
+    /// The modes of the leaves
+    pub options: CircuitOptions,
 }
 
+#[derive(Clone, Debug)]
+pub(crate) struct IntentIntermediateV2(pub IntentIntermediate);
+
 #[derive(Clone, Debug)]
 pub(crate) struct IntentRoot {
     pub k: u32,
     // This is from bad UX; only svk = kzg_params.get_g()[0] is used
     pub(crate) kzg_params: Arc<ParamsKZG<Bn256>>,
-    // There will be exponential duplication since both children have the same circuit type, but it seems better for clarity
+    // There will be exponential duplication since all children have the same circuit type, but it seems better for clarity
+    /// One tree per child, so its length is the arity of the node
     pub to_agg: Vec<AggTreeId>,
-    /// There are always two children of the same type, so we only specify the intent for one of them
+    /// All children are of the same type, so we only specify the intent for one of them
     pub child_intent: AggregationDependencyIntentOwned,
     /// The maximum number of proofs at this level of the tree is 2<sup>depth</sup>.
     pub depth: usize,
     /// The leaf layer of the aggregation starts with max number of proofs equal to 2<sup>initial_depth</sup>.
     pub initial_depth: usize,
+    /// The modes of the leaves
+    pub options: CircuitOptions,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct IntentRootV2(pub IntentRoot);
+
+#[derive(Clone, Debug)]
+pub(crate) struct IntentRolling {
+    pub k: u32,
+    // This is from bad UX; only svk = kzg_params.get_g()[0] is used
+    pub(crate) kzg_params: Arc<ParamsKZG<Bn256>>,
+    /// The previous rolling (or first batch root) tree, followed by the batch root tree
+    pub to_agg: Vec<AggTreeId>,
+    /// The intents of the previous proof and of the batch root proof
+    pub dep_intents: [AggregationDependencyIntentOwned; 2],
+    /// The number of batch slots of this circuit
+    pub max_batches: usize,
 }
 
+#[derive(Clone, Debug)]
+pub(crate) struct IntentRollingV2(pub IntentRolling);
+
 /// Passthrough wrapper aggregation.
 /// Internal version doesn't need any additional context.
 #[derive(Clone, Debug)]
@@ -117,29 +178,68 @@ pub(crate) struct IntentEvm {
     pub child_intent: AggregationDependencyIntentOwned,
 }
 
-impl KeygenCircuitIntent<Fr> for IntentLeaf {
-    type ConcreteCircuit = WorldcoinLeafCircuit<Fr>;
-
-    type Pinning = PinningLeaf;
-    fn get_k(&self) -> u32 {
-        self.k
-    }
-    fn build_keygen_circuit(self) -> Self::ConcreteCircuit {
+impl IntentLeaf {
+    /// The request for the keygen circuit, read from the sample claims in `./data`
+    fn keygen_request(&self) -> WorldcoinRequestLeaf {
         let max_proofs = 1 << self.depth;
         let input_path = format!("./data/generated_proofs_{}.json", max_proofs);
         let request: WorldcoinRequest =
             serde_json::from_reader(File::open(input_path).expect("Fail to open input"))
                 .expect("Fail to parse input");
-        let request_leaf: WorldcoinRequestLeaf = WorldcoinRequestLeaf {
-            vk: VK.clone(),
+        // the keys are witnesses, so the sample vk can fill the whole vk set. A fixed-vk leaf
+        // bakes in the sample vk instead.
+        let mut vk = read_vk(Path::new(DEFAULT_VK_PATH)).expect("Fail to read vk");
+        // likewise only the number of public inputs of a witness vk matters, while a fixed vk
+        // must have the public inputs of the layout
+        let options = self.options;
+        let num_public_inputs = options.public_inputs.num_public_inputs;
+        if options.fixed_vk {
+            assert_eq!(
+                vk.num_public_inputs(),
+                num_public_inputs,
+                "Invalid fixed vk"
+            );
+        } else {
+            vk.IC.resize(num_public_inputs + 1, vk.IC[0].clone());
+        }
+        // the sample claims have no amounts or extra public inputs, and any value gives the same
+        // circuit
+        let num_extra_public_inputs = options.public_inputs.num_extra_public_inputs();
+        let claims = request
+            .claims
+            .into_iter()
+            .map(|claim| ClaimNative {
+                amount: options.claim_amounts.then(|| "0".to_string()),
+                extra_public_inputs: vec!["0".to_string(); num_extra_public_inputs],
+                ..claim
+            })
+            .collect();
+        let app_id = options
+            .external_nullifier
+            .then(|| self.app_id.clone().expect("Missing app id"));
+        WorldcoinRequestLeaf {
+            extra_vks: vec![vk.clone(); self.num_vks - 1],
+            vk,
             root: request.root,
-            claims: request.claims,
+            claims,
             depth: self.depth,
             start: 0,
             end: request.num_proofs as u32,
-        };
+            app_id,
+            options,
+        }
+    }
+}
 
-        let input: WorldcoinLeafInput<Fr> = request_leaf.into();
+impl KeygenCircuitIntent<Fr> for IntentLeaf {
+    type ConcreteCircuit = WorldcoinLeafCircuit<Fr>;
+
+    type Pinning = PinningLeaf;
+    fn get_k(&self) -> u32 {
+        self.k
+    }
+    fn build_keygen_circuit(self) -> Self::ConcreteCircuit {
+        let input: WorldcoinLeafInput<Fr> = self.keygen_request().into();
 
         let circuit_params = get_dummy_rlc_keccak_params(self.k as usize, self.k as usize - 1);
 
@@ -160,84 +260,97 @@ impl KeygenCircuitIntent<Fr> for IntentLeaf {
         let break_points = circuit.break_points();
         let num_instance = circuit.num_instance();
         let dk = (kzg_params.get_g()[0], kzg_params.g2(), kzg_params.s_g2());
+        let request = self.keygen_request();
+        let fixed_vk_hash = request.fixed_vk_hash().expect("Invalid vk");
         PinningLeaf {
             params,
             break_points,
             num_instance,
             dk: dk.into(),
+            fixed_vk_hash,
+            app_id: request.app_id,
+            public_inputs: request.options.public_inputs,
         }
     }
 }
 
-impl KeygenAggregationCircuitIntent for IntentIntermediate {
-    #[cfg(feature = "v2")]
-    type AggregationCircuit = WorldcoinIntermediateAggregationCircuit;
+impl KeygenCircuitIntent<Fr> for IntentLeafV2 {
+    type ConcreteCircuit = WorldcoinLeafCircuitV2<Fr>;
+
+    type Pinning = PinningLeaf;
+    fn get_k(&self) -> u32 {
+        self.0.k
+    }
+    fn build_keygen_circuit(self) -> Self::ConcreteCircuit {
+        let input: WorldcoinLeafInputV2<Fr> = self.0.keygen_request().into();
+
+        let k = self.0.k as usize;
+        let circuit_params = get_dummy_rlc_keccak_params(k, k - 1);
+
+        let mut circuit =
+            WorldcoinLeafCircuitV2::new_impl(CircuitBuilderStage::Mock, input, circuit_params, 0);
+
+        circuit.calculate_params();
 
+        circuit
+    }
+
+    fn get_pinning_after_keygen(
+        self,
+        kzg_params: &ParamsKZG<Bn256>,
+        circuit: &Self::ConcreteCircuit,
+    ) -> Self::Pinning {
+        let params = circuit.params();
+        let break_points = circuit.break_points();
+        let num_instance = circuit.num_instance();
+        let dk = (kzg_params.get_g()[0], kzg_params.g2(), kzg_params.s_g2());
+        let request = self.0.keygen_request();
+        let fixed_vk_hash = request.fixed_vk_hash().expect("Invalid vk");
+        PinningLeaf {
+            params,
+            break_points,
+            num_instance,
+            dk: dk.into(),
+            fixed_vk_hash,
+            app_id: request.app_id,
+            public_inputs: request.options.public_inputs,
+        }
+    }
+}
+
+impl KeygenAggregationCircuitIntent for IntentIntermediate {
     fn intent_of_dependencies(&self) -> Vec<AggregationDependencyIntent> {
-        vec![(&self.child_intent).into(); 2]
+        vec![(&self.child_intent).into(); self.to_agg.len()]
     }
     fn build_keygen_circuit_from_snarks(self, snarks: Vec<Snark>) -> Self::AggregationCircuit {
-        assert_eq!(snarks.len(), 2);
+        assert_eq!(snarks.len(), self.to_agg.len());
 
-        #[cfg(feature = "v1")]
-        {
-            let circuit_params: axiom_eth::snark_verifier_sdk::halo2::aggregation::AggregationConfigParams = get_dummy_aggregation_params(self.k as usize);
-
-            let input: WorldcoinIntermediateAggregationInput =
-                WorldcoinIntermediateAggregationInput::new(
-                    snarks,
-                    1 << self.depth as u32,
-                    self.depth,
-                    self.initial_depth,
-                );
-
-            let mut circuit = input
-                .build(
-                    CircuitBuilderStage::Keygen,
-                    circuit_params,
-                    &self.kzg_params,
-                )
-                .unwrap();
-            circuit.0.calculate_params(Some(20));
-            circuit.0
-        }
-        #[cfg(feature = "v2")]
-        {
-            let input: WorldcoinIntermediateAggregationInput =
-                WorldcoinIntermediateAggregationInput::new(
-                    snarks,
-                    1 << self.depth as u32,
-                    self.depth,
-                    self.initial_depth,
-                    &self.kzg_params,
-                )
-                .unwrap();
-
-            // This is aggregation circuit, so set lookup bits to max
-            let circuit_params = get_dummy_rlc_keccak_params(self.k as usize, self.k as usize - 1);
-            // This is from bad UX; only svk = kzg_params.get_g()[0] is used
-            let mut circuit = WorldcoinIntermediateAggregationCircuit::new_impl(
+        let circuit_params: axiom_eth::snark_verifier_sdk::halo2::aggregation::AggregationConfigParams = get_dummy_aggregation_params(self.k as usize);
+
+        let input: WorldcoinIntermediateAggregationInput =
+            WorldcoinIntermediateAggregationInput::new(
+                snarks,
+                1 << self.depth as u32,
+                self.depth,
+                self.initial_depth,
+                self.options,
+            );
+
+        let mut circuit = input
+            .build(
                 CircuitBuilderStage::Keygen,
-                input,
                 circuit_params,
-                0, // note: rlc is not used
-            );
-            circuit.calculate_params();
-            circuit
-        }
+                &self.kzg_params,
+            )
+            .unwrap();
+        circuit.0.calculate_params(Some(20));
+        circuit.0
     }
 }
 
 impl KeygenCircuitIntent<Fr> for IntentIntermediate {
-    #[cfg(feature = "v1")]
     type ConcreteCircuit = AggregationCircuit;
-    #[cfg(feature = "v2")]
-    type ConcreteCircuit = WorldcoinIntermediateAggregationCircuit;
-
-    #[cfg(feature = "v1")]
     type Pinning = PinningIntermediate;
-    #[cfg(feature = "v2")]
-    type Pinning = PinningIntermediateV2;
 
     fn get_k(&self) -> u32 {
         self.k
@@ -262,20 +375,82 @@ impl KeygenCircuitIntent<Fr> for IntentIntermediate {
     }
 }
 
+impl KeygenAggregationCircuitIntent for IntentIntermediateV2 {
+    type AggregationCircuit = WorldcoinIntermediateAggregationCircuitV2;
+
+    fn intent_of_dependencies(&self) -> Vec<AggregationDependencyIntent> {
+        vec![(&self.0.child_intent).into(); self.0.to_agg.len()]
+    }
+    fn build_keygen_circuit_from_snarks(self, snarks: Vec<Snark>) -> Self::AggregationCircuit {
+        assert_eq!(snarks.len(), self.0.to_agg.len());
+        let intent = self.0;
+
+        let input = WorldcoinIntermediateAggregationInputV2::new(
+            snarks,
+            1 << intent.depth as u32,
+            intent.depth,
+            intent.initial_depth,
+            intent.options,
+            &intent.kzg_params,
+        )
+        .unwrap();
+
+        // This is aggregation circuit, so set lookup bits to max
+        let circuit_params = get_dummy_rlc_keccak_params(intent.k as usize, intent.k as usize - 1);
+        // This is from bad UX; only svk = kzg_params.get_g()[0] is used
+        let mut circuit = WorldcoinIntermediateAggregationCircuitV2::new_impl(
+            CircuitBuilderStage::Keygen,
+            input,
+            circuit_params,
+            0, // note: rlc is not used
+        );
+        circuit.calculate_params();
+        circuit
+    }
+}
+
+impl KeygenCircuitIntent<Fr> for IntentIntermediateV2 {
+    type ConcreteCircuit = WorldcoinIntermediateAggregationCircuitV2;
+    type Pinning = PinningIntermediateV2;
+
+    fn get_k(&self) -> u32 {
+        self.0.k
+    }
+    fn build_keygen_circuit(self) -> Self::ConcreteCircuit {
+        self.build_keygen_circuit_shplonk()
+    }
+    fn get_pinning_after_keygen(
+        self,
+        kzg_params: &ParamsKZG<Bn256>,
+        circuit: &Self::ConcreteCircuit,
+    ) -> Self::Pinning {
+        let to_agg = compile_agg_dep_to_protocol(kzg_params, &self.0.child_intent, false);
+        let dk = (kzg_params.get_g()[0], kzg_params.g2(), kzg_params.s_g2());
+        Self::Pinning {
+            params: circuit.params(),
+            to_agg: vec![to_agg; self.0.to_agg.len()],
+            break_points: circuit.break_points(),
+            num_instance: circuit.num_instance(),
+            dk: dk.into(),
+        }
+    }
+}
+
 impl KeygenAggregationCircuitIntent for IntentRoot {
     type AggregationCircuit = WorldcoinRootAggregationCircuit;
 
     fn intent_of_dependencies(&self) -> Vec<AggregationDependencyIntent> {
-        vec![(&self.child_intent).into(); 2]
+        vec![(&self.child_intent).into(); self.to_agg.len()]
     }
     fn build_keygen_circuit_from_snarks(self, snarks: Vec<Snark>) -> Self::AggregationCircuit {
-        assert_eq!(snarks.len(), 2);
+        assert_eq!(snarks.len(), self.to_agg.len());
 
         let input = WorldcoinRootAggregationInput::new(
             snarks,
             1,
             self.depth,
             self.initial_depth,
+            self.options,
             &self.kzg_params,
         )
         .unwrap();
@@ -321,6 +496,196 @@ impl KeygenCircuitIntent<Fr> for IntentRoot {
     }
 }
 
+impl KeygenAggregationCircuitIntent for IntentRootV2 {
+    type AggregationCircuit = WorldcoinRootAggregationCircuitV2;
+
+    fn intent_of_dependencies(&self) -> Vec<AggregationDependencyIntent> {
+        vec![(&self.0.child_intent).into(); self.0.to_agg.len()]
+    }
+    fn build_keygen_circuit_from_snarks(self, snarks: Vec<Snark>) -> Self::AggregationCircuit {
+        assert_eq!(snarks.len(), self.0.to_agg.len());
+        let intent = self.0;
+
+        let input = WorldcoinRootAggregationInputV2::new(
+            snarks,
+            1,
+            intent.depth,
+            intent.initial_depth,
+            intent.options,
+            &intent.kzg_params,
+        )
+        .unwrap();
+        // This is aggregation circuit, so set lookup bits to max
+        let circuit_params = get_dummy_rlc_keccak_params(intent.k as usize, intent.k as usize - 1);
+        // This is from bad UX; only svk = kzg_params.get_g()[0] is used
+        let mut circuit = WorldcoinRootAggregationCircuitV2::new_impl(
+            CircuitBuilderStage::Keygen,
+            input,
+            circuit_params,
+            0, // note: rlc is not used
+        );
+        circuit.calculate_params();
+        circuit
+    }
+}
+
+impl KeygenCircuitIntent<Fr> for IntentRootV2 {
+    type ConcreteCircuit = WorldcoinRootAggregationCircuitV2;
+    type Pinning = PinningRoot;
+    fn get_k(&self) -> u32 {
+        self.0.k
+    }
+    fn build_keygen_circuit(self) -> Self::ConcreteCircuit {
+        self.build_keygen_circuit_shplonk()
+    }
+    fn get_pinning_after_keygen(
+        self,
+        kzg_params: &ParamsKZG<Bn256>,
+        circuit: &Self::ConcreteCircuit,
+    ) -> Self::Pinning {
+        let params = circuit.params();
+        let break_points = circuit.break_points();
+        let to_agg = compile_agg_dep_to_protocol(kzg_params, &self.0.child_intent, false);
+        let dk = (kzg_params.get_g()[0], kzg_params.g2(), kzg_params.s_g2());
+        PinningRoot {
+            params,
+            to_agg: vec![to_agg; self.0.to_agg.len()],
+            num_instance: circuit.num_instance(),
+            break_points,
+            dk: dk.into(),
+        }
+    }
+}
+
+impl KeygenAggregationCircuitIntent for IntentRolling {
+    type AggregationCircuit = WorldcoinRollingAggregationCircuit;
+
+    fn intent_of_dependencies(&self) -> Vec<AggregationDependencyIntent> {
+        self.dep_intents
+            .iter()
+            .map(|intent| intent.into())
+            .collect()
+    }
+    fn build_keygen_circuit_from_snarks(self, snarks: Vec<Snark>) -> Self::AggregationCircuit {
+        assert_eq!(snarks.len(), 2);
+
+        let input = WorldcoinRollingAggregationInput::new(
+            snarks,
+            self.max_batches as u32,
+            self.max_batches,
+            &self.kzg_params,
+        )
+        .unwrap();
+        // This is aggregation circuit, so set lookup bits to max
+        let circuit_params = get_dummy_rlc_keccak_params(self.k as usize, self.k as usize - 1);
+        // This is from bad UX; only svk = kzg_params.get_g()[0] is used
+        let mut circuit = WorldcoinRollingAggregationCircuit::new_impl(
+            CircuitBuilderStage::Keygen,
+            input,
+            circuit_params,
+            0, // note: rlc is not used
+        );
+        circuit.calculate_params();
+        circuit
+    }
+}
+
+impl KeygenCircuitIntent<Fr> for IntentRolling {
+    type ConcreteCircuit = WorldcoinRollingAggregationCircuit;
+    type Pinning = PinningRolling;
+    fn get_k(&self) -> u32 {
+        self.k
+    }
+    fn build_keygen_circuit(self) -> Self::ConcreteCircuit {
+        self.build_keygen_circuit_shplonk()
+    }
+    fn get_pinning_after_keygen(
+        self,
+        kzg_params: &ParamsKZG<Bn256>,
+        circuit: &Self::ConcreteCircuit,
+    ) -> Self::Pinning {
+        let params = circuit.params();
+        let break_points = circuit.break_points();
+        let to_agg = self
+            .dep_intents
+            .iter()
+            .map(|intent| compile_agg_dep_to_protocol(kzg_params, intent, false))
+            .collect();
+        let dk = (kzg_params.get_g()[0], kzg_params.g2(), kzg_params.s_g2());
+        PinningRolling {
+            params,
+            to_agg,
+            num_instance: circuit.num_instance(),
+            break_points,
+            dk: dk.into(),
+        }
+    }
+}
+
+impl KeygenAggregationCircuitIntent for IntentRollingV2 {
+    type AggregationCircuit = WorldcoinRollingAggregationCircuitV2;
+
+    fn intent_of_dependencies(&self) -> Vec<AggregationDependencyIntent> {
+        self.0.intent_of_dependencies()
+    }
+    fn build_keygen_circuit_from_snarks(self, snarks: Vec<Snark>) -> Self::AggregationCircuit {
+        assert_eq!(snarks.len(), 2);
+        let intent = self.0;
+
+        let input = WorldcoinRollingAggregationInputV2::new(
+            snarks,
+            intent.max_batches as u32,
+            intent.max_batches,
+            &intent.kzg_params,
+        )
+        .unwrap();
+        // This is aggregation circuit, so set lookup bits to max
+        let circuit_params = get_dummy_rlc_keccak_params(intent.k as usize, intent.k as usize - 1);
+        // This is from bad UX; only svk = kzg_params.get_g()[0] is used
+        let mut circuit = WorldcoinRollingAggregationCircuitV2::new_impl(
+            CircuitBuilderStage::Keygen,
+            input,
+            circuit_params,
+            0, // note: rlc is not used
+        );
+        circuit.calculate_params();
+        circuit
+    }
+}
+
+impl KeygenCircuitIntent<Fr> for IntentRollingV2 {
+    type ConcreteCircuit = WorldcoinRollingAggregationCircuitV2;
+    type Pinning = PinningRolling;
+    fn get_k(&self) -> u32 {
+        self.0.k
+    }
+    fn build_keygen_circuit(self) -> Self::ConcreteCircuit {
+        self.build_keygen_circuit_shplonk()
+    }
+    fn get_pinning_after_keygen(
+        self,
+        kzg_params: &ParamsKZG<Bn256>,
+        circuit: &Self::ConcreteCircuit,
+    ) -> Self::Pinning {
+        let params = circuit.params();
+        let break_points = circuit.break_points();
+        let to_agg = self
+            .0
+            .dep_intents
+            .iter()
+            .map(|intent| compile_agg_dep_to_protocol(kzg_params, intent, false))
+            .collect();
+        let dk = (kzg_params.get_g()[0], kzg_params.g2(), kzg_params.s_g2());
+        PinningRolling {
+            params,
+            to_agg,
+            num_instance: circuit.num_instance(),
+            break_points,
+            dk: dk.into(),
+        }
+    }
+}
+
 impl From<IntentEvm> for AggIntentMerkle {
     fn from(value: IntentEvm) -> Self {
         AggIntentMerkle {
@@ -364,11 +729,32 @@ impl RecursiveIntent {
         data_dir: &Path,
         cid_repo: &mut BTreeMap<NodeParams, String>,
     ) -> anyhow::Result<(AggTreeId, ProvingKey<G1Affine>, serde_json::Value)> {
+        let (tree_id, pk, pinning, _) =
+            self.create_and_serialize_proving_key_impl(srs_dir, data_dir, cid_repo)?;
+        Ok((tree_id, pk, pinning))
+    }
+
+    /// Same as [Self::create_and_serialize_proving_key], but a `Rolling` node also returns the
+    /// dependency on the batch root circuit, which every rolling circuit below it shares.
+    #[allow(clippy::type_complexity)]
+    fn create_and_serialize_proving_key_impl(
+        self,
+        srs_dir: &Path,
+        data_dir: &Path,
+        cid_repo: &mut BTreeMap<NodeParams, String>,
+    ) -> anyhow::Result<(
+        AggTreeId,
+        ProvingKey<G1Affine>,
+        serde_json::Value,
+        Option<(AggTreeId, AggregationDependencyIntentOwned)>,
+    )> {
         // If there is child, do it first
+        let mut batch = None;
         let child = if let Some(child_intent) = self.child() {
-            let is_aggregation = !matches!(child_intent.params.node_type, NodeType::Leaf);
-            let (child_id, child_pk, child_pinning) =
-                child_intent.create_and_serialize_proving_key(srs_dir, data_dir, cid_repo)?;
+            let child_node_type = child_intent.params.node_type;
+            let is_aggregation = !matches!(child_node_type, NodeType::Leaf);
+            let (child_id, child_pk, child_pinning, child_batch) =
+                child_intent.create_and_serialize_proving_key_impl(srs_dir, data_dir, cid_repo)?;
             let num_instance: Vec<usize> =
                 serde_json::from_value(child_pinning["num_instance"].clone())?;
             // !! ** ASSERTION: all aggregation circuits have accumulator in indices 0..12 ** !!
@@ -380,6 +766,11 @@ impl RecursiveIntent {
                     .then(|| AggregationCircuit::accumulator_indices().unwrap()),
                 agg_vk_hash_data: None,
             };
+            // the batch root is the child of the first rolling circuit
+            batch = match child_node_type {
+                NodeType::Root => Some((child_id.clone(), agg_intent.clone())),
+                _ => child_batch,
+            };
             Some((child_id, agg_intent))
         } else {
             None
@@ -389,15 +780,25 @@ impl RecursiveIntent {
         let kzg_params = Arc::new(read_srs_from_dir(srs_dir, k)?);
         let ((pk, pinning), children) = match self.params.node_type {
             NodeType::Leaf => {
+                if self.params.options.external_nullifier && self.app_id.is_none() {
+                    anyhow::bail!("An intent with external_nullifier must set app_id");
+                }
                 let intent = IntentLeaf {
                     k,
                     depth: self.params.initial_depth,
+                    num_vks: self.params.num_vks,
+                    options: self.params.options,
+                    app_id: self.app_id.clone(),
+                };
+                let pk_and_pinning = match self.params.version {
+                    CircuitVersion::V1 => intent.create_pk_and_pinning(&kzg_params),
+                    CircuitVersion::V2 => IntentLeafV2(intent).create_pk_and_pinning(&kzg_params),
                 };
-                (intent.create_pk_and_pinning(&kzg_params), vec![])
+                (pk_and_pinning, vec![])
             }
             NodeType::Intermediate => {
                 let (child_id, child_intent) = child.unwrap();
-                let to_agg = vec![child_id; 2];
+                let to_agg = vec![child_id; self.params.arity.arity()];
                 let intent = IntentIntermediate {
                     k,
                     kzg_params: kzg_params.clone(),
@@ -405,12 +806,19 @@ impl RecursiveIntent {
                     child_intent,
                     depth: self.params.depth,
                     initial_depth: self.params.initial_depth,
+                    options: self.params.options,
                 };
-                (intent.create_pk_and_pinning(&kzg_params), to_agg)
+                let pk_and_pinning = match self.params.version {
+                    CircuitVersion::V1 => intent.create_pk_and_pinning(&kzg_params),
+                    CircuitVersion::V2 => {
+                        IntentIntermediateV2(intent).create_pk_and_pinning(&kzg_params)
+                    }
+                };
+                (pk_and_pinning, to_agg)
             }
             NodeType::Root => {
                 let (child_id, child_intent) = child.unwrap();
-                let to_agg = vec![child_id; 2];
+                let to_agg = vec![child_id; self.params.arity.arity()];
                 let intent = IntentRoot {
                     k,
                     kzg_params: kzg_params.clone(),
@@ -418,8 +826,32 @@ impl RecursiveIntent {
                     child_intent,
                     depth: self.params.depth,
                     initial_depth: self.params.initial_depth,
+                    options: self.params.options,
                 };
-                (intent.create_pk_and_pinning(&kzg_params), to_agg)
+                let pk_and_pinning = match self.params.version {
+                    CircuitVersion::V1 => intent.create_pk_and_pinning(&kzg_params),
+                    CircuitVersion::V2 => IntentRootV2(intent).create_pk_and_pinning(&kzg_params),
+                };
+                (pk_and_pinning, to_agg)
+            }
+            NodeType::Rolling(max_batches) => {
+                let (child_id, child_intent) = child.unwrap();
+                let (batch_id, batch_intent) = batch.clone().unwrap();
+                let to_agg = vec![child_id, batch_id];
+                let intent = IntentRolling {
+                    k,
+                    kzg_params: kzg_params.clone(),
+                    to_agg: to_agg.clone(),
+                    dep_intents: [child_intent, batch_intent],
+                    max_batches,
+                };
+                let pk_and_pinning = match self.params.version {
+                    CircuitVersion::V1 => intent.create_pk_and_pinning(&kzg_params),
+                    CircuitVersion::V2 => {
+                        IntentRollingV2(intent).create_pk_and_pinning(&kzg_params)
+                    }
+                };
+                (pk_and_pinning, to_agg)
             }
             NodeType::Evm(_) => {
                 let (child_id, child_intent) = child.unwrap();
@@ -444,6 +876,8 @@ impl RecursiveIntent {
             children,
             aggregate_vk_hash: None,
         };
-        Ok((tree_id, pk, pinning))
+        // only the rolling circuits above the batch root need its dependency
+        let batch = batch.filter(|_| matches!(self.params.node_type, NodeType::Rolling(_)));
+        Ok((tree_id, pk, pinning, batch))
     }
 }
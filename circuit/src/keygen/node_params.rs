@@ -9,12 +9,96 @@ use axiom_eth::{
         snark_verifier::{AggregationCircuitParams, Base64Bytes},
     },
 };
+use ethers::types::H256;
 use serde::{Deserialize, Serialize};
 use serde_with::serde_as;
 
+use crate::public_inputs::PublicInputLayout;
+
+/// Design of the circuits of an aggregation tree
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize, PartialOrd, Ord,
+)]
+pub enum CircuitVersion {
+    /// Leaves expose all claims, the root exposes the keccak of the claims
+    #[default]
+    V1,
+    /// Leaves and the root expose the keccak merkle root of the claims
+    V2,
+}
+
+/// Modes of the circuits of an aggregation tree, all off by default. Shared by [NodeParams], the
+/// proof requests and the circuit inputs.
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize, PartialOrd, Ord,
+)]
+pub struct CircuitOptions {
+    /// Whether every claim has its own World ID root. The shared root instance is then zero, and
+    /// the roots are exposed with the claims.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub per_claim_roots: bool,
+    /// Whether the circuits constrain the nullifier hashes of a batch to be strictly increasing,
+    /// and hence pairwise distinct.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub unique_nullifiers: bool,
+    /// Whether the leaf circuits constrain the receivers to be non-zero addresses, the nullifier
+    /// hashes to be non-zero, and `start` and `end` to fit in 32 bits like the claim indices of
+    /// the requests, instead of 64 bits. Changes the leaf circuits, hence every circuit ID.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub check_claim_fields: bool,
+    /// Whether the leaf circuits have the verifying key as a constant, instead of hashing it in
+    /// circuit. Requires a single vk.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub fixed_vk: bool,
+    /// Whether the claim leaves commit to the amount of every claim. Only for V2 circuits.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub claim_amounts: bool,
+    /// Whether the leaf circuits derive the Groth16 signal hash from the receiver in circuit, for
+    /// proofs against the World ID signal hash of the receiver address.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub hash_signal: bool,
+    /// Whether the leaf circuits derive the Groth16 external nullifier hash from the app id and
+    /// the grant id of every claim in circuit. The app id is a constant of the leaf circuits.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub external_nullifier: bool,
+    /// Whether the leaf circuits verify all Groth16 proofs of a batch with one multi Miller loop
+    /// and one final exponentiation, combining the pairing equations with random coefficients
+    /// derived from the proofs. Requires a single vk.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub batch_groth16: bool,
+    /// The Groth16 public inputs of the claims verified by the leaf circuits, the Semaphore
+    /// public inputs of World ID by default
+    #[serde(default, skip_serializing_if = "PublicInputLayout::is_default")]
+    pub public_inputs: PublicInputLayout,
+}
+
 /// Circuit parameters by node type
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize, PartialOrd, Ord)]
 pub struct NodeParams {
+    /// Version of the circuits in the aggregation tree. Defaults to V1 for circuit IDs files
+    /// written before versions were introduced.
+    #[serde(default)]
+    pub version: CircuitVersion,
+    /// Number of Groth16 verifying keys the leaf circuit selects from per claim. With more than
+    /// one key, the circuits expose a commitment to the vk set instead of a single vk hash.
+    #[serde(default = "default_num_vks", skip_serializing_if = "is_single_vk")]
+    pub num_vks: usize,
+    /// Modes of the circuits, serialized inline
+    #[serde(flatten)]
+    pub options: CircuitOptions,
+    /// Number of children of the aggregation nodes of each layer of the batch tree, binary by
+    /// default. See [LayerArity].
+    #[serde(default, skip_serializing_if = "LayerArity::is_default")]
+    pub arity: LayerArity,
+    /// Number of batches chained into one rolling proof by the rolling circuits, see
+    /// [NodeType::Rolling]. With a single batch, the Evm circuits wrap the root of the batch tree.
+    /// V1 circuits with [CircuitOptions::per_claim_roots] only support a single batch, since
+    /// `distributeGrantsChained` validates one root per batch.
+    #[serde(
+        default = "default_max_batches",
+        skip_serializing_if = "is_single_batch"
+    )]
+    pub max_batches: usize,
     /// Type of the node in the aggregation tree.
     pub node_type: NodeType,
     /// The maximum number of claims at this level of the tree is 2<sup>depth</sup>.
@@ -29,18 +113,198 @@ pub enum NodeType {
     Intermediate,
     Root,
     /// The proof number range must fit within the specified max depth. `Evm(round)` performs `round + 1`
-    /// rounds of SNARK verification on the final `Root` circuit
+    /// rounds of SNARK verification on the final `Root` circuit, or the final `Rolling` circuit
+    /// if there are several batches
     Evm(usize),
+    /// `Rolling(max_batches)` appends the `Root` proof of a batch to the previous `Rolling` proof
+    /// with `max_batches - 1` batch slots, or to the `Root` proof of the first batch
+    Rolling(usize),
 }
 
-impl NodeParams {
-    pub fn new(node_type: NodeType, depth: usize, initial_depth: usize) -> Self {
-        assert!(depth >= initial_depth);
+/// Maximum number of layers of [LayerArity]
+pub const MAX_ARITY_LAYERS: usize = 16;
+
+/// Number of children of the aggregation nodes of each layer, from the `Root` layer down to the
+/// layer above the leaves, and 2 for the layers below the given ones. Every arity is a power of
+/// two, and a node of depth `depth` with `2^b` children has children of depth `depth - b`.
+///
+/// Serialized as the list of arities, and stored as their log2 so that [NodeParams] is `Copy`.
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize, PartialOrd, Ord,
+)]
+#[serde(try_from = "Vec<usize>", into = "Vec<usize>")]
+pub struct LayerArity {
+    num_layers: usize,
+    log2_arities: [u8; MAX_ARITY_LAYERS],
+}
+
+impl LayerArity {
+    /// Whether no arity is given, so every layer is binary
+    pub fn is_default(&self) -> bool {
+        self.num_layers == 0
+    }
+
+    /// Number of children of the nodes of the top layer
+    pub fn arity(&self) -> usize {
+        1 << self.log2_arity()
+    }
+
+    fn log2_arity(&self) -> usize {
+        if self.num_layers == 0 {
+            1
+        } else {
+            self.log2_arities[0] as usize
+        }
+    }
+
+    /// The arities of the layers below the top layer
+    pub fn child(&self) -> Self {
+        if self.num_layers == 0 {
+            return *self;
+        }
+        let mut log2_arities = [0; MAX_ARITY_LAYERS];
+        log2_arities[..self.num_layers - 1].copy_from_slice(&self.log2_arities[1..self.num_layers]);
         Self {
+            num_layers: self.num_layers - 1,
+            log2_arities,
+        }
+    }
+
+    /// Depth of the children of a node of depth `depth` in the top layer
+    pub fn child_depth(&self, depth: usize) -> usize {
+        depth - self.log2_arity()
+    }
+
+    /// Sum of the log2 of the given arities, the depth the given layers span
+    fn depth(&self) -> usize {
+        self.log2_arities.iter().map(|b| *b as usize).sum()
+    }
+}
+
+impl TryFrom<Vec<usize>> for LayerArity {
+    type Error = String;
+
+    fn try_from(arities: Vec<usize>) -> Result<Self, Self::Error> {
+        if arities.len() > MAX_ARITY_LAYERS {
+            return Err(format!(
+                "At most {MAX_ARITY_LAYERS} layers can have an arity"
+            ));
+        }
+        let mut log2_arities = [0; MAX_ARITY_LAYERS];
+        for (log2_arity, arity) in log2_arities.iter_mut().zip(&arities) {
+            if *arity < 2 || !arity.is_power_of_two() {
+                return Err(format!("Arity {arity} is not a power of two of at least 2"));
+            }
+            *log2_arity = arity.ilog2() as u8;
+        }
+        Ok(Self {
+            num_layers: arities.len(),
+            log2_arities,
+        })
+    }
+}
+
+impl From<LayerArity> for Vec<usize> {
+    fn from(arity: LayerArity) -> Self {
+        arity.log2_arities[..arity.num_layers]
+            .iter()
+            .map(|b| 1 << b)
+            .collect()
+    }
+}
+
+fn default_num_vks() -> usize {
+    1
+}
+
+fn is_single_vk(num_vks: &usize) -> bool {
+    *num_vks == 1
+}
+
+fn default_max_batches() -> usize {
+    1
+}
+
+fn is_single_batch(max_batches: &usize) -> bool {
+    *max_batches == 1
+}
+
+fn is_false(value: &bool) -> bool {
+    !*value
+}
+
+impl NodeParams {
+    /// Parameters of a tree with binary aggregation nodes and a single batch. See
+    /// [Self::with_arity] and [Self::with_max_batches] for the others.
+    pub fn new(
+        version: CircuitVersion,
+        num_vks: usize,
+        options: CircuitOptions,
+        node_type: NodeType,
+        depth: usize,
+        initial_depth: usize,
+    ) -> Self {
+        let params = Self {
+            version,
+            num_vks,
+            options,
+            arity: LayerArity::default(),
+            max_batches: 1,
             node_type,
             depth,
             initial_depth,
-        }
+        };
+        params.assert_valid();
+        params
+    }
+
+    pub fn with_arity(self, arity: LayerArity) -> Self {
+        let params = Self { arity, ..self };
+        params.assert_valid();
+        params
+    }
+
+    pub fn with_max_batches(self, max_batches: usize) -> Self {
+        let params = Self {
+            max_batches,
+            ..self
+        };
+        params.assert_valid();
+        params
+    }
+
+    fn assert_valid(&self) {
+        let options = &self.options;
+        assert!(self.depth >= self.initial_depth);
+        assert!(self.num_vks > 0);
+        assert!(!options.fixed_vk || self.num_vks == 1);
+        assert!(!options.batch_groth16 || self.num_vks == 1);
+        assert!(!options.claim_amounts || self.version == CircuitVersion::V2);
+        assert!(self.max_batches > 0);
+        // `distributeGrantsChained` validates the root of every batch, there is no root for a
+        // batch of claims with their own roots
+        assert!(
+            !(self.version == CircuitVersion::V1
+                && options.per_claim_roots
+                && self.max_batches > 1)
+        );
+        assert!(self.arity.depth() <= self.depth - self.initial_depth);
+        options
+            .public_inputs
+            .validate()
+            .expect("Invalid public input layout");
+    }
+
+    /// The node of the same tree with the given type, depth and arity
+    fn node(&self, node_type: NodeType, depth: usize, arity: LayerArity) -> Self {
+        let params = Self {
+            node_type,
+            depth,
+            arity,
+            ..*self
+        };
+        params.assert_valid();
+        params
     }
 
     pub fn child(&self) -> Option<Self> {
@@ -48,38 +312,50 @@ impl NodeParams {
             NodeType::Leaf => None,
             NodeType::Intermediate | NodeType::Root => {
                 assert!(self.depth > self.initial_depth);
-                if self.depth == self.initial_depth + 1 {
-                    Some(Self::new(
-                        NodeType::Leaf,
-                        self.initial_depth,
-                        self.initial_depth,
-                    ))
+                let child_depth = self.arity.child_depth(self.depth);
+                if child_depth == self.initial_depth {
+                    Some(self.node(NodeType::Leaf, self.initial_depth, self.arity.child()))
                 } else {
-                    Some(Self::new(
-                        NodeType::Intermediate,
-                        self.depth - 1,
-                        self.initial_depth,
-                    ))
+                    Some(self.node(NodeType::Intermediate, child_depth, self.arity.child()))
+                }
+            }
+            NodeType::Rolling(max_batches) => {
+                // the batches are chained by their root proofs
+                assert!(max_batches > 1 && self.depth > self.initial_depth);
+                if max_batches == 2 {
+                    Some(self.batch())
+                } else {
+                    Some(Self {
+                        node_type: NodeType::Rolling(max_batches - 1),
+                        ..*self
+                    })
                 }
             }
             NodeType::Evm(round) => {
                 if round == 0 {
-                    let node_type = if self.depth == self.initial_depth {
+                    let node_type = if self.max_batches > 1 {
+                        NodeType::Rolling(self.max_batches)
+                    } else if self.depth == self.initial_depth {
                         NodeType::Leaf
                     } else {
                         NodeType::Root
                     };
-                    Some(Self::new(node_type, self.depth, self.initial_depth))
+                    Some(self.node(node_type, self.depth, self.arity))
                 } else {
-                    Some(Self::new(
-                        NodeType::Evm(round - 1),
-                        self.depth,
-                        self.initial_depth,
-                    ))
+                    Some(self.node(NodeType::Evm(round - 1), self.depth, self.arity))
                 }
             }
         }
     }
+
+    /// The root of the aggregation tree of a single batch, whose proofs are chained by the
+    /// rolling circuits
+    pub fn batch(&self) -> Self {
+        Self {
+            max_batches: 1,
+            ..self.node(NodeType::Root, self.depth, self.arity)
+        }
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -91,6 +367,15 @@ pub struct PinningLeaf {
     /// Together with domain size `2^k`, this commits to the trusted setup used.
     /// This is all that's needed to verify the final ecpairing check on the KZG proof.
     pub dk: KzgDecidingKey<Bn256>,
+    /// The vk hash of the vk which is a constant of a fixed-vk leaf
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fixed_vk_hash: Option<H256>,
+    /// The app id which is a constant of a leaf deriving the external nullifier hashes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub app_id: Option<String>,
+    /// The index of every claim field among the Groth16 public inputs of the claims
+    #[serde(default, skip_serializing_if = "PublicInputLayout::is_default")]
+    pub public_inputs: PublicInputLayout,
 }
 
 #[serde_as]
@@ -147,4 +432,7 @@ pub struct PinningRoot {
     pub dk: KzgDecidingKey<Bn256>,
 }
 
+/// Rolling circuits are configured like root circuits
+pub type PinningRolling = PinningRoot;
+
 pub type PinningEvm = GenericAggPinning<GenericAggParams>;
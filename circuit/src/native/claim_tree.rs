@@ -0,0 +1,345 @@
+use anyhow::{anyhow, bail, Context, Result};
+use ethers::{
+    types::{Address, H256, U256},
+    utils::keccak256,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    constants::{get_dummy_claim_roots, CLAIM_AMOUNT_LEAF_PREFIX},
+    types::ClaimNative,
+};
+
+/// The fields of a claim committed to in its leaf
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ClaimLeaf {
+    pub grant_id: String,
+    pub receiver: Address,
+    pub nullifier_hash: String,
+    /// The root of the claim, only set for trees with per-claim roots
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub root: Option<String>,
+    /// The amount of the claim, only set for trees with claim amounts
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub amount: Option<String>,
+}
+
+/// The V2 claim tree, as computed by the leaf and intermediate circuits. The leaves are
+/// `keccak256(abi.encodePacked(grant_id, receiver, nullifier_hash))`, followed by the `root` of
+/// the claim with per-claim roots and then its `amount` with claim amounts, padded with the dummy
+/// leaf of [get_dummy_claim_roots] up to `2^depth` leaves. Leaves with an amount start with
+/// [CLAIM_AMOUNT_LEAF_PREFIX].
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ClaimTree {
+    /// The World ID root the claims are proven against, unless they have their own roots
+    pub root: String,
+    pub claims: Vec<ClaimLeaf>,
+    /// `levels[0]` are the leaf hashes and `levels[depth]` is `[claim_root]`. Every other level is
+    /// padded to an even length with the root of an empty subtree.
+    pub levels: Vec<Vec<H256>>,
+}
+
+/// Arguments of `WorldcoinAggregationV2.claim` for a single claim
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClaimProof {
+    pub grant_id: String,
+    pub root: String,
+    pub receiver: Address,
+    pub nullifier_hash: String,
+    /// The amount of the claim for `claimWithAmount`, only set for trees with claim amounts
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub amount: Option<String>,
+    /// The sister nodes from the leaf up to the child of the claim root
+    pub sister_nodes: Vec<H256>,
+    /// Byte `i` is 1 if `sister_nodes[i]` is the left child
+    pub is_left_bytes: H256,
+    pub claim_root: H256,
+}
+
+impl ClaimLeaf {
+    pub fn hash(&self) -> Result<H256> {
+        let grant_id = U256::from_dec_str(&self.grant_id).context("Invalid grant_id")?;
+        let nullifier_hash =
+            U256::from_dec_str(&self.nullifier_hash).context("Invalid nullifier_hash")?;
+        let mut bytes = vec![0u8; 84];
+        grant_id.to_big_endian(&mut bytes[..32]);
+        bytes[32..52].copy_from_slice(self.receiver.as_bytes());
+        nullifier_hash.to_big_endian(&mut bytes[52..]);
+        if let Some(root) = &self.root {
+            let root = U256::from_dec_str(root).context("Invalid root")?;
+            let mut root_bytes = [0u8; 32];
+            root.to_big_endian(&mut root_bytes);
+            bytes.extend(root_bytes);
+        }
+        if let Some(amount) = &self.amount {
+            let amount = U256::from_dec_str(amount).context("Invalid amount")?;
+            let mut amount_bytes = [0u8; 32];
+            amount.to_big_endian(&mut amount_bytes);
+            bytes.insert(0, CLAIM_AMOUNT_LEAF_PREFIX);
+            bytes.extend(amount_bytes);
+        }
+        Ok(H256(keccak256(bytes)))
+    }
+}
+
+impl From<&ClaimNative> for ClaimLeaf {
+    fn from(claim: &ClaimNative) -> Self {
+        Self {
+            grant_id: claim.grant_id.clone(),
+            receiver: claim.receiver,
+            nullifier_hash: claim.nullifier_hash.clone(),
+            root: None,
+            amount: None,
+        }
+    }
+}
+
+impl ClaimTree {
+    /// With `per_claim_roots`, every leaf commits to the root of its claim, `root` by default.
+    /// With `claim_amounts`, every leaf commits to the amount of its claim, which must be set.
+    pub fn new(
+        root: &str,
+        claims: &[ClaimNative],
+        depth: usize,
+        per_claim_roots: bool,
+        claim_amounts: bool,
+    ) -> Result<Self> {
+        if claims.is_empty() || claims.len() > 1 << depth {
+            bail!(
+                "Cannot build a claim tree of depth {depth} over {} claims",
+                claims.len()
+            );
+        }
+        let dummy_roots = get_dummy_claim_roots(per_claim_roots, claim_amounts);
+        if depth >= dummy_roots.len() {
+            bail!("Claim tree depth {depth} is not supported");
+        }
+        let claims: Vec<ClaimLeaf> = claims
+            .iter()
+            .enumerate()
+            .map(|(i, claim)| {
+                let amount = if claim_amounts {
+                    let amount = claim.amount.clone();
+                    Some(amount.ok_or_else(|| anyhow!("Claim {i} has no amount"))?)
+                } else {
+                    None
+                };
+                Ok(ClaimLeaf {
+                    root: per_claim_roots.then(|| claim.root_or(root).to_string()),
+                    amount,
+                    ..claim.into()
+                })
+            })
+            .collect::<Result<_>>()?;
+        let mut level = claims
+            .iter()
+            .map(ClaimLeaf::hash)
+            .collect::<Result<Vec<_>>>()?;
+        let mut levels = Vec::with_capacity(depth + 1);
+        for dummy_root in dummy_roots.iter().take(depth) {
+            if level.len() % 2 == 1 {
+                level.push(H256(*dummy_root));
+            }
+            let next_level = level
+                .chunks(2)
+                .map(|pair| hash_branch(&pair[0], &pair[1]))
+                .collect();
+            levels.push(level);
+            level = next_level;
+        }
+        levels.push(level);
+        Ok(Self {
+            root: root.to_string(),
+            claims,
+            levels,
+        })
+    }
+
+    pub fn depth(&self) -> usize {
+        self.levels.len() - 1
+    }
+
+    pub fn claim_root(&self) -> H256 {
+        self.levels[self.depth()][0]
+    }
+
+    /// Index of the claim with the given nullifier hash
+    pub fn position(&self, nullifier_hash: &str) -> Option<usize> {
+        let nullifier_hash = U256::from_dec_str(nullifier_hash).ok()?;
+        self.claims
+            .iter()
+            .position(|c| U256::from_dec_str(&c.nullifier_hash).ok() == Some(nullifier_hash))
+    }
+
+    /// Merkle proof of the claim with the given nullifier hash
+    pub fn claim_proof(&self, nullifier_hash: &str) -> Option<ClaimProof> {
+        let index = self.position(nullifier_hash)?;
+        let mut sister_nodes = Vec::with_capacity(self.depth());
+        let mut is_left_bytes = H256::zero();
+        for (i, level) in self.levels[..self.depth()].iter().enumerate() {
+            let node_index = index >> i;
+            sister_nodes.push(level[node_index ^ 1]);
+            if node_index % 2 == 1 {
+                is_left_bytes.0[i] = 1;
+            }
+        }
+        let claim = &self.claims[index];
+        Some(ClaimProof {
+            grant_id: claim.grant_id.clone(),
+            root: claim.root.clone().unwrap_or_else(|| self.root.clone()),
+            receiver: claim.receiver,
+            nullifier_hash: claim.nullifier_hash.clone(),
+            amount: claim.amount.clone(),
+            sister_nodes,
+            is_left_bytes,
+            claim_root: self.claim_root(),
+        })
+    }
+}
+
+fn hash_branch(left: &H256, right: &H256) -> H256 {
+    let mut bytes = [0u8; 64];
+    bytes[..32].copy_from_slice(left.as_bytes());
+    bytes[32..].copy_from_slice(right.as_bytes());
+    H256(keccak256(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::constants::{
+        DUMMY_CLAIM_ROOTS, DUMMY_CLAIM_ROOTS_WITH_AMOUNT, DUMMY_CLAIM_ROOTS_WITH_ROOT,
+        DUMMY_CLAIM_ROOTS_WITH_ROOT_AND_AMOUNT,
+    };
+
+    use super::*;
+
+    fn claim(i: u64) -> ClaimNative {
+        ClaimNative {
+            receiver: Address::from_low_u64_be(i),
+            nullifier_hash: (1000 + i).to_string(),
+            grant_id: "30".to_string(),
+            proof: vec![],
+            vk_hash: None,
+            root: None,
+            amount: Some((i * 10).to_string()),
+            extra_public_inputs: vec![],
+        }
+    }
+
+    /// A claim whose leaf is the dummy leaf
+    fn zero_claim() -> ClaimNative {
+        ClaimNative {
+            receiver: Address::zero(),
+            nullifier_hash: "0".to_string(),
+            grant_id: "0".to_string(),
+            root: Some("0".to_string()),
+            amount: Some("0".to_string()),
+            ..claim(0)
+        }
+    }
+
+    #[test]
+    fn test_dummy_leaves_match_dummy_claim_roots() {
+        let cases: [(bool, bool, &[[u8; 32]]); 4] = [
+            (false, false, &DUMMY_CLAIM_ROOTS),
+            (true, false, &DUMMY_CLAIM_ROOTS_WITH_ROOT),
+            (false, true, &DUMMY_CLAIM_ROOTS_WITH_AMOUNT),
+            (true, true, &DUMMY_CLAIM_ROOTS_WITH_ROOT_AND_AMOUNT),
+        ];
+        for (per_claim_roots, claim_amounts, dummy_roots) in cases {
+            for depth in [0, 1, 4] {
+                let tree =
+                    ClaimTree::new("0", &[zero_claim()], depth, per_claim_roots, claim_amounts)
+                        .unwrap();
+                assert_eq!(
+                    tree.claim_root(),
+                    H256(dummy_roots[depth]),
+                    "per_claim_roots {per_claim_roots}, claim_amounts {claim_amounts}, depth {depth}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_claim_roots_are_padded_with_dummy_roots() {
+        let claims = (1..=3).map(claim).collect::<Vec<_>>();
+        let tree = ClaimTree::new("7", &claims, 3, false, true).unwrap();
+        let leaves = tree
+            .claims
+            .iter()
+            .map(|c| c.hash().unwrap())
+            .collect::<Vec<_>>();
+        let dummy_roots = &DUMMY_CLAIM_ROOTS_WITH_AMOUNT;
+        let expected = hash_branch(
+            &hash_branch(
+                &hash_branch(&leaves[0], &leaves[1]),
+                &hash_branch(&leaves[2], &H256(dummy_roots[0])),
+            ),
+            &H256(dummy_roots[2]),
+        );
+        assert_eq!(tree.claim_root(), expected);
+    }
+
+    #[test]
+    fn test_amount_leaves_start_with_prefix() {
+        let leaf = ClaimLeaf {
+            root: Some("5".to_string()),
+            ..(&claim(1)).into()
+        };
+        let with_root = leaf.hash().unwrap();
+        let with_amount = ClaimLeaf {
+            root: None,
+            amount: Some("5".to_string()),
+            ..leaf
+        }
+        .hash()
+        .unwrap();
+        assert_ne!(with_root, with_amount);
+
+        let mut bytes = vec![CLAIM_AMOUNT_LEAF_PREFIX];
+        bytes.extend([0u8; 31]);
+        bytes.push(30);
+        bytes.extend(Address::from_low_u64_be(1).as_bytes());
+        bytes.extend([0u8; 30]);
+        bytes.extend(1001u16.to_be_bytes());
+        bytes.extend([0u8; 31]);
+        bytes.push(5);
+        assert_eq!(with_amount, H256(keccak256(bytes)));
+    }
+
+    #[test]
+    fn test_claim_proofs_open_to_the_claim_root() {
+        let claims = (1..=5).map(claim).collect::<Vec<_>>();
+        let tree = ClaimTree::new("7", &claims, 3, true, true).unwrap();
+        for (i, leaf) in tree.claims.iter().enumerate() {
+            let proof = tree.claim_proof(&leaf.nullifier_hash).unwrap();
+            assert_eq!(proof.root, "7");
+            assert_eq!(proof.amount, Some((i as u64 * 10 + 10).to_string()));
+            let node = proof.sister_nodes.iter().enumerate().fold(
+                leaf.hash().unwrap(),
+                |node, (j, sister)| {
+                    if proof.is_left_bytes.0[j] == 1 {
+                        hash_branch(sister, &node)
+                    } else {
+                        hash_branch(&node, sister)
+                    }
+                },
+            );
+            assert_eq!(node, proof.claim_root);
+        }
+        assert!(tree.claim_proof("1").is_none());
+    }
+
+    #[test]
+    fn test_new_rejects_invalid_claims() {
+        let claims = (1..=5).map(claim).collect::<Vec<_>>();
+        assert!(ClaimTree::new("7", &claims, 2, false, false).is_err());
+        assert!(ClaimTree::new("7", &[], 2, false, false).is_err());
+        let no_amount = ClaimNative {
+            amount: None,
+            ..claim(1)
+        };
+        assert!(ClaimTree::new("7", &[no_amount], 2, false, true).is_err());
+    }
+}
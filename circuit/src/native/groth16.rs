@@ -0,0 +1,433 @@
+use std::{str::FromStr, thread};
+
+use anyhow::{anyhow, bail, Context, Result};
+use axiom_eth::{
+    halo2_base::utils::{biguint_to_fe, fe_to_biguint},
+    halo2curves::{
+        bn256::{Bn256, Fq, Fq2, Fr, G1Affine, G2Affine, G2Prepared},
+        ff::PrimeField,
+        group::{prime::PrimeCurveAffine, Curve, Group},
+        pairing::{MillerLoopResult, MultiMillerLoop},
+        CurveAffine,
+    },
+    utils::encode_addr_to_field,
+};
+use ethers::{types::Address, utils::keccak256};
+use itertools::Itertools;
+use num_bigint::BigUint;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    public_inputs::PublicInputLayout,
+    types::{get_pf_string, ClaimNative, InvalidInputContext, VkNative},
+};
+
+macro_rules! deserialize_key {
+    ($json: expr, $val: expr) => {
+        serde_json::from_value($json[$val].clone())
+            .with_context(|| format!("Failed to parse {}", $val))?
+    };
+}
+
+/// Groth16 verifying key with the points decoded from the snarkjs json format
+#[derive(Clone, Debug)]
+pub struct Groth16VerifyingKeyNative {
+    pub alpha_g1: G1Affine,
+    pub beta_g2: G2Affine,
+    pub gamma_g2: G2Affine,
+    pub delta_g2: G2Affine,
+    pub ic: Vec<G1Affine>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Groth16ProofNative {
+    pub a: G1Affine,
+    pub b: G2Affine,
+    pub c: G1Affine,
+}
+
+/// A claim which failed native verification
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct InvalidClaim {
+    /// Index of the claim in the request
+    pub index: usize,
+    pub reason: String,
+}
+
+impl TryFrom<&VkNative> for Groth16VerifyingKeyNative {
+    type Error = anyhow::Error;
+
+    /// Same json layout as [crate::types::parse_vk]
+    fn try_from(vk: &VkNative) -> Result<Self> {
+        let vk_file = serde_json::to_value(vk)?;
+        let vk_alpha_1: [String; 3] = deserialize_key!(vk_file, "vk_alpha_1");
+        let vk_beta_2: [[String; 2]; 3] = deserialize_key!(vk_file, "vk_beta_2");
+        let vk_gamma_2: [[String; 2]; 3] = deserialize_key!(vk_file, "vk_gamma_2");
+        let vk_delta_2: [[String; 2]; 3] = deserialize_key!(vk_file, "vk_delta_2");
+        let ic: Vec<[String; 3]> = deserialize_key!(vk_file, "IC");
+        Ok(Self {
+            alpha_g1: parse_g1(&vk_alpha_1).context("Invalid vk_alpha_1")?,
+            beta_g2: parse_g2(&vk_beta_2).context("Invalid vk_beta_2")?,
+            gamma_g2: parse_g2(&vk_gamma_2).context("Invalid vk_gamma_2")?,
+            delta_g2: parse_g2(&vk_delta_2).context("Invalid vk_delta_2")?,
+            ic: ic
+                .iter()
+                .map(|p| parse_g1(p).context("Invalid IC"))
+                .collect::<Result<_>>()?,
+        })
+    }
+}
+
+impl Groth16VerifyingKeyNative {
+    /// Check `e(a, b) = e(alpha, beta) * e(ic(public_inputs), gamma) * e(c, delta)`
+    pub fn verify(&self, proof: &Groth16ProofNative, public_inputs: &[Fr]) -> Result<bool> {
+        if public_inputs.len() + 1 != self.ic.len() {
+            bail!(
+                "Expected {} public inputs, got {}",
+                self.ic.len() - 1,
+                public_inputs.len()
+            );
+        }
+        let vk_x = public_inputs
+            .iter()
+            .zip(self.ic[1..].iter())
+            .fold(self.ic[0].to_curve(), |acc, (pi, ic)| acc + ic * pi)
+            .to_affine();
+        let result = Bn256::multi_miller_loop(&[
+            (&proof.a, &G2Prepared::from(proof.b)),
+            (&-self.alpha_g1, &G2Prepared::from(self.beta_g2)),
+            (&-vk_x, &G2Prepared::from(self.gamma_g2)),
+            (&-proof.c, &G2Prepared::from(self.delta_g2)),
+        ])
+        .final_exponentiation();
+        Ok(bool::from(result.is_identity()))
+    }
+}
+
+/// Parse the proof of a claim, in the order of the World ID contracts
+pub fn parse_proof_native(proof: &[String]) -> Result<Groth16ProofNative> {
+    if proof.len() != 8 {
+        bail!("Expected 8 proof elements, got {}", proof.len());
+    }
+    // goes through the snarkjs json to get the same pi_b swap as the circuit input
+    let proof_file: serde_json::Value = serde_json::from_str(&get_pf_string(proof))?;
+    let a: [String; 3] = deserialize_key!(proof_file, "pi_a");
+    let b: [[String; 2]; 3] = deserialize_key!(proof_file, "pi_b");
+    let c: [String; 3] = deserialize_key!(proof_file, "pi_c");
+    Ok(Groth16ProofNative {
+        a: parse_g1(&a).context("Invalid pi_a")?,
+        b: parse_g2(&b).context("Invalid pi_b")?,
+        c: parse_g1(&c).context("Invalid pi_c")?,
+    })
+}
+
+/// World ID's `hashToField`, `uint256(keccak256(bytes)) >> 8`
+pub fn hash_to_field_native(bytes: &[u8]) -> Fr {
+    let hash = BigUint::from_bytes_be(&keccak256(bytes));
+    biguint_to_fe(&(hash >> 8))
+}
+
+/// The World ID signal hash of an address, `hashToField(abi.encodePacked(receiver))`
+pub fn signal_hash_native(receiver: &Address) -> Fr {
+    hash_to_field_native(receiver.as_bytes())
+}
+
+/// The World ID external nullifier hash of the app `app_id` with the grant id as the action,
+/// `hashToField(abi.encodePacked(hashToField(abi.encodePacked(app_id)), grant_id))`
+pub fn external_nullifier_hash_native(app_id: &str, grant_id: Fr) -> Fr {
+    let app_id_hash = hash_to_field_native(app_id.as_bytes());
+    let bytes = [app_id_hash, grant_id]
+        .iter()
+        .flat_map(|fe| fe.to_repr().into_iter().rev())
+        .collect_vec();
+    hash_to_field_native(&bytes)
+}
+
+/// How the leaf circuit derives the Groth16 public inputs of a claim. The default is the
+/// Semaphore layout with the receiver and the grant id as they are.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PublicInputMode<'a> {
+    pub layout: PublicInputLayout,
+    /// Whether the signal hash is the World ID signal hash of the receiver
+    pub hash_signal: bool,
+    /// The app id of the external nullifier hashes, if they are derived from the grant ids
+    pub app_id: Option<&'a str>,
+}
+
+/// The fields of a claim exposed by the leaf circuit, `[root, nullifier_hash, receiver,
+/// grant_id]`. `root` is used if the claim has no root of its own.
+pub fn claim_fields(root: Fr, claim: &ClaimNative) -> Result<[Fr; 4]> {
+    let root = match &claim.root {
+        Some(claim_root) => parse_field_element(claim_root).context("Invalid root")?,
+        None => root,
+    };
+    let nullifier_hash =
+        parse_field_element(&claim.nullifier_hash).context("Invalid nullifier_hash")?;
+    let receiver = encode_addr_to_field(&claim.receiver);
+    let grant_id = parse_field_element(&claim.grant_id).context("Invalid grant_id")?;
+    Ok([root, nullifier_hash, receiver, grant_id])
+}
+
+/// The public inputs of a claim, as constrained in the leaf circuit: the claim fields and the
+/// extra public inputs of the claim arranged by the layout of `mode`, by default
+/// `[root, nullifier_hash, signal_hash, external_nullifier_hash]`. The signal hash is the
+/// receiver itself, or its World ID signal hash with `hash_signal`. The external nullifier hash
+/// is the grant id itself, or the external nullifier hash of the grant id with an `app_id`.
+pub fn claim_public_inputs(
+    root: Fr,
+    claim: &ClaimNative,
+    mode: &PublicInputMode,
+) -> Result<Vec<Fr>> {
+    let [root, nullifier_hash, receiver, grant_id] = claim_fields(root, claim)?;
+    let signal_hash = if mode.hash_signal {
+        signal_hash_native(&claim.receiver)
+    } else {
+        receiver
+    };
+    let external_nullifier = match mode.app_id {
+        Some(app_id) => external_nullifier_hash_native(app_id, grant_id),
+        None => grant_id,
+    };
+    let extra_public_inputs = claim
+        .extra_public_inputs
+        .iter()
+        .map(|value| parse_field_element(value))
+        .collect::<Result<Vec<_>>>()
+        .context("Invalid extra public input")?;
+    mode.layout.public_inputs(
+        root,
+        nullifier_hash,
+        signal_hash,
+        external_nullifier,
+        &extra_public_inputs,
+    )
+}
+
+/// Check the fields the leaf circuit constrains regardless of the proof with
+/// `check_claim_fields`: the receiver and the nullifier hash are non-zero. The scheduler rejects
+/// such claims whatever the circuits.
+pub fn check_claim_fields(claim: &ClaimNative) -> Result<()> {
+    if claim.receiver.is_zero() {
+        bail!("Zero receiver");
+    }
+    let nullifier_hash =
+        parse_field_element(&claim.nullifier_hash).context("Invalid nullifier_hash")?;
+    if nullifier_hash == Fr::from(0) {
+        bail!("Zero nullifier_hash");
+    }
+    Ok(())
+}
+
+pub fn verify_claim(
+    vk: &Groth16VerifyingKeyNative,
+    root: Fr,
+    claim: &ClaimNative,
+    mode: &PublicInputMode,
+) -> Result<()> {
+    check_claim_fields(claim)?;
+    let proof = parse_proof_native(&claim.proof)?;
+    let public_inputs = claim_public_inputs(root, claim, mode)?;
+    if !vk.verify(&proof, &public_inputs)? {
+        bail!("Groth16 proof does not verify");
+    }
+    Ok(())
+}
+
+/// Verify all claims against `vk` and their root, `root` by default, in parallel. Returns the claims which failed.
+/// The public inputs of the proofs are derived from the claims by `mode`.
+pub fn verify_claims(
+    vk: &VkNative,
+    root: &str,
+    claims: &[ClaimNative],
+    mode: &PublicInputMode,
+) -> Result<Vec<InvalidClaim>> {
+    let vk = Groth16VerifyingKeyNative::try_from(vk)?;
+    let root = parse_field_element(root).context("Invalid root")?;
+
+    let num_threads = thread::available_parallelism().map_or(1, |n| n.get());
+    let chunk_size = ((claims.len() + num_threads - 1) / num_threads).max(1);
+    let invalid_claims = thread::scope(|s| {
+        let handles = claims
+            .chunks(chunk_size)
+            .enumerate()
+            .map(|(i, chunk)| {
+                let vk = &vk;
+                s.spawn(move || {
+                    chunk
+                        .iter()
+                        .enumerate()
+                        .filter_map(|(j, claim)| {
+                            verify_claim(vk, root, claim, mode)
+                                .err()
+                                .map(|e| InvalidClaim {
+                                    index: i * chunk_size + j,
+                                    reason: format!("{e:#}"),
+                                })
+                        })
+                        .collect_vec()
+                })
+            })
+            .collect_vec();
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().unwrap())
+            .collect_vec()
+    });
+    Ok(invalid_claims)
+}
+
+/// Reject the request if any claim fails native verification
+pub fn check_claims(
+    vk: &VkNative,
+    root: &str,
+    claims: &[ClaimNative],
+    mode: &PublicInputMode,
+) -> Result<()> {
+    let invalid_claims = verify_claims(vk, root, claims, mode).context(InvalidInputContext)?;
+    ensure_no_invalid_claims(&invalid_claims)
+}
+
+/// Invalid input error listing the invalid claims, if there are any
+pub fn ensure_no_invalid_claims(invalid_claims: &[InvalidClaim]) -> Result<()> {
+    if !invalid_claims.is_empty() {
+        let reasons = invalid_claims
+            .iter()
+            .map(|c| format!("claim {}: {}", c.index, c.reason))
+            .join("; ");
+        return Err(anyhow!("Invalid claims: {reasons}").context(InvalidInputContext));
+    }
+    Ok(())
+}
+
+/// Parse a decimal string, rejecting values which are not in the scalar field
+pub fn parse_field_element(value: &str) -> Result<Fr> {
+    let big = BigUint::from_str(value).with_context(|| format!("{value} is not a number"))?;
+    let fe: Fr = biguint_to_fe(&big);
+    if fe_to_biguint(&fe) != big {
+        bail!("{value} is not in the scalar field");
+    }
+    Ok(fe)
+}
+
+fn parse_fq(value: &str) -> Result<Fq> {
+    let big = BigUint::from_str(value).with_context(|| format!("{value} is not a number"))?;
+    Fq::from_str_vartime(&big.to_string())
+        .filter(|fq| fe_to_biguint(fq) == big)
+        .ok_or_else(|| anyhow!("{value} is not in the base field"))
+}
+
+fn parse_g1(point: &[String; 3]) -> Result<G1Affine> {
+    if point[2] == "0" {
+        return Ok(G1Affine::identity());
+    }
+    let x = parse_fq(&point[0])?;
+    let y = parse_fq(&point[1])?;
+    Option::from(G1Affine::from_xy(x, y)).ok_or_else(|| anyhow!("Point is not on G1"))
+}
+
+fn parse_g2(point: &[[String; 2]; 3]) -> Result<G2Affine> {
+    if point[2][0] == "0" && point[2][1] == "0" {
+        return Ok(G2Affine::identity());
+    }
+    let x = Fq2 {
+        c0: parse_fq(&point[0][0])?,
+        c1: parse_fq(&point[0][1])?,
+    };
+    let y = Fq2 {
+        c0: parse_fq(&point[1][0])?,
+        c1: parse_fq(&point[1][1])?,
+    };
+    let point: G2Affine = Option::from(G2Affine::from_xy(x, y))
+        .ok_or_else(|| anyhow!("Point is not on the twist"))?;
+    // the twist has a cofactor, and the pairing is only bilinear on G2, the subgroup of order r.
+    // [r]Q is computed as [r - 1]Q + Q, as r is zero in Fr.
+    if !bool::from((point * -Fr::one() + point).is_identity()) {
+        bail!("Point is not on G2");
+    }
+    Ok(point)
+}
+
+#[cfg(test)]
+mod tests {
+    use axiom_eth::halo2curves::ff::Field;
+
+    use super::*;
+
+    fn g2_strings(point: &G2Affine) -> [[String; 2]; 3] {
+        let [x0, x1, y0, y1] = [point.x.c0, point.x.c1, point.y.c0, point.y.c1]
+            .map(|fq| fe_to_biguint(&fq).to_string());
+        [[x0, x1], [y0, y1], ["1".to_string(), "0".to_string()]]
+    }
+
+    /// A vk with known discrete logs, and a proof of `public_inputs` for it:
+    /// `a = alpha * beta + ic(public_inputs) * gamma + c * delta` with `b` the generator
+    fn vk_and_proof(public_inputs: &[Fr]) -> (Groth16VerifyingKeyNative, Groth16ProofNative) {
+        let [alpha, beta, gamma, delta, c] = [2, 3, 5, 7, 11].map(Fr::from);
+        let ic = (0..=public_inputs.len() as u64)
+            .map(|i| Fr::from(13 + i))
+            .collect_vec();
+        let vk_x = public_inputs
+            .iter()
+            .zip(&ic[1..])
+            .fold(ic[0], |acc, (pi, ic)| acc + pi * ic);
+        let a = alpha * beta + vk_x * gamma + c * delta;
+        let g1 = |s: Fr| (G1Affine::generator() * s).to_affine();
+        let g2 = |s: Fr| (G2Affine::generator() * s).to_affine();
+        let vk = Groth16VerifyingKeyNative {
+            alpha_g1: g1(alpha),
+            beta_g2: g2(beta),
+            gamma_g2: g2(gamma),
+            delta_g2: g2(delta),
+            ic: ic.into_iter().map(g1).collect(),
+        };
+        let proof = Groth16ProofNative {
+            a: g1(a),
+            b: G2Affine::generator(),
+            c: g1(c),
+        };
+        (vk, proof)
+    }
+
+    #[test]
+    fn test_verify_checks_the_pairing_equation() {
+        let public_inputs = [1, 2, 3, 4].map(Fr::from);
+        let (vk, proof) = vk_and_proof(&public_inputs);
+        assert!(vk.verify(&proof, &public_inputs).unwrap());
+
+        let mut wrong_inputs = public_inputs;
+        wrong_inputs[1] += Fr::one();
+        assert!(!vk.verify(&proof, &wrong_inputs).unwrap());
+
+        let wrong_proof = Groth16ProofNative {
+            c: (proof.c + G1Affine::generator()).to_affine(),
+            ..proof
+        };
+        assert!(!vk.verify(&wrong_proof, &public_inputs).unwrap());
+
+        assert!(vk.verify(&proof, &public_inputs[..3]).is_err());
+    }
+
+    #[test]
+    fn test_parse_g2_accepts_points_of_g2() {
+        let point = (G2Affine::generator() * Fr::from(7)).to_affine();
+        assert_eq!(parse_g2(&g2_strings(&point)).unwrap(), point);
+    }
+
+    #[test]
+    fn test_parse_g2_rejects_points_outside_g2() {
+        // the first point of the twist with a small real x, whose order is not r
+        let point = (1u64..)
+            .find_map(|i| {
+                let x = Fq2 {
+                    c0: Fq::from(i),
+                    c1: Fq::zero(),
+                };
+                let y: Option<Fq2> = (x.square() * x + G2Affine::b()).sqrt().into();
+                y.map(|y| G2Affine { x, y })
+            })
+            .unwrap();
+        assert!(bool::from(point.is_on_curve()));
+        let err = parse_g2(&g2_strings(&point)).unwrap_err();
+        assert_eq!(err.to_string(), "Point is not on G2");
+    }
+}
@@ -0,0 +1,772 @@
+//! Reference implementation of the public instances of the circuits. Instances are given
+//! without the KZG accumulator of aggregation circuits.
+//!
+//! - V1 leaf and intermediate: `[start, end, vk_hash_hi, vk_hash_lo, root, ...grant_ids,
+//!   ...receivers, ...nullifier_hashes]`, followed by `...roots` with per-claim roots. Claims
+//!   are padded to `2^depth` with zeros.
+//! - V1 root: `[output_hash_hi, output_hash_lo]`, see [v1_output_hash]
+//! - V2 leaf and intermediate: `[start, end, vk_hash_hi, vk_hash_lo, root, claim_root_hi,
+//!   claim_root_lo]`, followed by the first and last nullifier hash with unique nullifiers
+//! - V2 root: `[vk_hash_hi, vk_hash_lo, root, num_proofs, claim_root_hi, claim_root_lo]`
+//! - V1 rolling: `[num_batches, chain_hash_hi, chain_hash_lo]`, see [v1_append_batch]
+//! - V2 rolling: `[num_batches, vk_hash_hi, vk_hash_lo, num_proofs, chain_hash_hi,
+//!   chain_hash_lo]`, see [v2_append_batch]
+//! - Evm: the instances of the wrapped snark
+//!
+//! For circuits with a vk set, `vk_hash` is the commitment to the set, see
+//! [get_vk_set_commitment_native]. For circuits with per-claim roots, the shared `root` is zero.
+
+use anyhow::{bail, Result};
+use axiom_eth::{
+    halo2curves::{bn256::Fr, ff::PrimeField},
+    snark_verifier_sdk::Snark,
+    utils::snark_verifier::{get_accumulator_indices, NUM_FE_ACCUMULATOR},
+};
+use ethers::{
+    types::{H256, U256},
+    utils::{hex, keccak256},
+};
+use itertools::Itertools;
+
+use crate::{
+    circuits::{
+        v1::intermediate::WorldcoinIntermediateAggregationInput,
+        v2::intermediate::WorldcoinIntermediateAggregationInputV2,
+    },
+    constants::get_dummy_claim_roots,
+    keygen::node_params::CircuitOptions,
+    types::{parse_vk, ClaimNative, VkNative},
+};
+
+use super::{
+    claim_tree::ClaimTree,
+    groth16::{claim_fields, parse_field_element},
+};
+
+/// Number of instances shared by all leaf and intermediate circuits:
+/// `[start, end, vk_hash_hi, vk_hash_lo, root]`
+const NUM_SHARED_INSTANCES: usize = 5;
+
+/// Keccak of the flattened vk, as computed by [crate::utils::get_vk_hash]
+pub fn get_vk_hash_native(vk: &VkNative) -> Result<H256> {
+    let vk_bytes = parse_vk(serde_json::to_string(vk)?, vk.num_public_inputs());
+    let bytes = vk_bytes.iter().flat_map(fe_to_bytes_be).collect_vec();
+    Ok(H256(keccak256(bytes)))
+}
+
+/// Commitment to a vk set, as computed by [crate::utils::get_vk_set_commitment]: the vk hash for
+/// a single key, otherwise the keccak of the concatenated vk hashes
+pub fn get_vk_set_commitment_native(vk_hashes: &[H256]) -> H256 {
+    assert!(!vk_hashes.is_empty());
+    if vk_hashes.len() == 1 {
+        return vk_hashes[0];
+    }
+    let bytes = vk_hashes.iter().flat_map(|h| h.0).collect_vec();
+    H256(keccak256(bytes))
+}
+
+/// Split a 32 byte value into `[hi, lo]` 128 bit field elements
+pub fn hi_lo(value: &H256) -> [Fr; 2] {
+    let hi = u128::from_be_bytes(value.as_bytes()[..16].try_into().unwrap());
+    let lo = u128::from_be_bytes(value.as_bytes()[16..].try_into().unwrap());
+    [Fr::from_u128(hi), Fr::from_u128(lo)]
+}
+
+/// V1 leaf instances. The claims are padded to `2^depth` with zeros.
+pub fn v1_leaf_instances(
+    vk_hash: &H256,
+    root: &str,
+    start: u32,
+    depth: usize,
+    claims: &[ClaimNative],
+    options: &CircuitOptions,
+) -> Result<Vec<Fr>> {
+    let per_claim_roots = options.per_claim_roots;
+    check_num_claims(claims, depth)?;
+    let root = parse_field_element(root)?;
+    let mut fields = claims
+        .iter()
+        .map(|claim| claim_fields(root, claim))
+        .collect::<Result<Vec<_>>>()?;
+    fields.resize(1 << depth, [Fr::from(0); 4]);
+
+    // fields are [root, nullifier_hash, receiver, grant_id]
+    let mut instances = shared_instances(
+        vk_hash,
+        shared_root(root, per_claim_roots),
+        start,
+        claims.len() as u32,
+    );
+    instances.extend(fields.iter().map(|f| f[3]));
+    instances.extend(fields.iter().map(|f| f[2]));
+    instances.extend(fields.iter().map(|f| f[1]));
+    if per_claim_roots {
+        instances.extend(fields.iter().map(|f| f[0]));
+    }
+    Ok(instances)
+}
+
+/// V1 intermediate instances of depth `depth` from the instances of its children, a power of two
+/// of them. For a node with fewer children, the first child is passed for the missing ones, whose
+/// claims are replaced by zeros.
+pub fn v1_join_instances(
+    prev: &[Vec<Fr>],
+    num_proofs: u32,
+    depth: usize,
+    options: &CircuitOptions,
+) -> Result<Vec<Fr>> {
+    let prev_max_proofs = 1 << child_depth(depth, prev.len())?;
+    let num_claim_blocks =
+        WorldcoinIntermediateAggregationInput::num_claim_blocks(options.per_claim_roots);
+    let num_instance_prev_depth = NUM_SHARED_INSTANCES + num_claim_blocks * prev_max_proofs;
+    let (mut instances, is_proof_dummy) =
+        join_shared_instances(prev, num_proofs, depth, num_instance_prev_depth)?;
+    for i in 0..num_claim_blocks {
+        let range = NUM_SHARED_INSTANCES + i * prev_max_proofs
+            ..NUM_SHARED_INSTANCES + (i + 1) * prev_max_proofs;
+        for (child, is_dummy) in prev.iter().zip_eq(&is_proof_dummy) {
+            if *is_dummy {
+                instances.extend(vec![Fr::from(0); prev_max_proofs]);
+            } else {
+                instances.extend_from_slice(&child[range.clone()]);
+            }
+        }
+    }
+    Ok(instances)
+}
+
+/// V1 root instances from the instances of its children
+pub fn v1_root_instances(
+    prev: &[Vec<Fr>],
+    num_proofs: u32,
+    depth: usize,
+    options: &CircuitOptions,
+) -> Result<Vec<Fr>> {
+    let instances = v1_join_instances(prev, num_proofs, depth, options)?;
+    Ok(hi_lo(&v1_output_hash(&instances, num_proofs)).to_vec())
+}
+
+/// Keccak of `[vk_hash_hi, vk_hash_lo, root, num_proofs, ...grant_ids, ...receivers,
+/// ...nullifier_hashes(, ...roots)]`, each as 32 bytes, given the instances of an intermediate node
+pub fn v1_output_hash(instances: &[Fr], num_proofs: u32) -> H256 {
+    let output = instances[2..5]
+        .iter()
+        .chain([Fr::from(num_proofs as u64)].iter())
+        .chain(instances[NUM_SHARED_INSTANCES..].iter());
+    let bytes = output.flat_map(fe_to_bytes_be).collect_vec();
+    H256(keccak256(bytes))
+}
+
+/// V1 instances of the node of depth `depth` over `claims`, built bottom-up like the
+/// aggregation tree
+pub fn v1_instances_from_claims(
+    vk_hash: &H256,
+    root: &str,
+    start: u32,
+    claims: &[ClaimNative],
+    depth: usize,
+    initial_depth: usize,
+    options: &CircuitOptions,
+) -> Result<Vec<Fr>> {
+    if depth == initial_depth {
+        return v1_leaf_instances(vk_hash, root, start, depth, claims, options);
+    }
+    check_num_claims(claims, depth)?;
+    let child_max_proofs = 1 << (depth - 1);
+    let mut children = claims
+        .chunks(child_max_proofs)
+        .enumerate()
+        .map(|(i, chunk)| {
+            let child_start = start + (i * child_max_proofs) as u32;
+            v1_instances_from_claims(
+                vk_hash,
+                root,
+                child_start,
+                chunk,
+                depth - 1,
+                initial_depth,
+                options,
+            )
+        })
+        .collect::<Result<Vec<_>>>()?;
+    children.resize(2, children[0].clone());
+    v1_join_instances(&children, claims.len() as u32, depth, options)
+}
+
+/// V2 leaf instances, the claim root is over the claims padded to `2^depth` with dummy leaves
+pub fn v2_leaf_instances(
+    vk_hash: &H256,
+    root: &str,
+    start: u32,
+    depth: usize,
+    claims: &[ClaimNative],
+    options: &CircuitOptions,
+) -> Result<Vec<Fr>> {
+    let claim_root = ClaimTree::new(
+        root,
+        claims,
+        depth,
+        options.per_claim_roots,
+        options.claim_amounts,
+    )?
+    .claim_root();
+    let root = shared_root(parse_field_element(root)?, options.per_claim_roots);
+    let mut instances = shared_instances(vk_hash, root, start, claims.len() as u32);
+    instances.extend(hi_lo(&claim_root));
+    if options.unique_nullifiers {
+        let nullifier_hashes = claims
+            .iter()
+            .map(|claim| parse_field_element(&claim.nullifier_hash))
+            .collect::<Result<Vec<_>>>()?;
+        if let Some(i) = (1..nullifier_hashes.len())
+            .find(|&i| !is_less_than(&nullifier_hashes[i - 1], &nullifier_hashes[i]))
+        {
+            bail!(
+                "Nullifier hashes of claims {} and {i} are not increasing",
+                i - 1
+            );
+        }
+        instances.extend([nullifier_hashes[0], *nullifier_hashes.last().unwrap()]);
+    }
+    Ok(instances)
+}
+
+/// V2 intermediate instances of depth `depth` from the instances of its children, a power of two
+/// of them. For a node with fewer children, the first child is passed for the missing ones, whose
+/// claim roots are the root of an empty subtree.
+pub fn v2_join_instances(
+    prev: &[Vec<Fr>],
+    num_proofs: u32,
+    depth: usize,
+    options: &CircuitOptions,
+) -> Result<Vec<Fr>> {
+    let CircuitOptions {
+        per_claim_roots,
+        unique_nullifiers,
+        claim_amounts,
+        ..
+    } = *options;
+    let num_instance_prev_depth =
+        WorldcoinIntermediateAggregationInputV2::get_num_instance(unique_nullifiers);
+    let (mut instances, is_proof_dummy) =
+        join_shared_instances(prev, num_proofs, depth, num_instance_prev_depth)?;
+    let dummy_claim_root = H256(
+        get_dummy_claim_roots(per_claim_roots, claim_amounts)[child_depth(depth, prev.len())?],
+    );
+    let mut claim_roots = prev
+        .iter()
+        .zip_eq(&is_proof_dummy)
+        .map(|(child, is_dummy)| {
+            if *is_dummy {
+                dummy_claim_root
+            } else {
+                from_hi_lo(&child[5..7])
+            }
+        })
+        .collect_vec();
+    // the claim roots of the children are the subtrees of the binary claim tree
+    while claim_roots.len() > 1 {
+        claim_roots = claim_roots
+            .chunks(2)
+            .map(|pair| H256(keccak256([pair[0].as_bytes(), pair[1].as_bytes()].concat())))
+            .collect();
+    }
+    instances.extend(hi_lo(&claim_roots[0]));
+    if unique_nullifiers {
+        let mut last = prev[0][8];
+        for (child, is_dummy) in prev.iter().zip_eq(&is_proof_dummy).skip(1) {
+            if *is_dummy {
+                break;
+            }
+            if !is_less_than(&last, &child[7]) {
+                bail!("Nullifier hashes of the children are not increasing");
+            }
+            last = child[8];
+        }
+        instances.extend([prev[0][7], last]);
+    }
+    Ok(instances)
+}
+
+/// V2 root instances from the instances of its children
+pub fn v2_root_instances(
+    prev: &[Vec<Fr>],
+    num_proofs: u32,
+    depth: usize,
+    options: &CircuitOptions,
+) -> Result<Vec<Fr>> {
+    let instances = v2_join_instances(prev, num_proofs, depth, options)?;
+    Ok(v2_root_from_intermediate(&instances, num_proofs))
+}
+
+/// V2 root instances over all claims of a request
+pub fn v2_root_instances_from_claims(
+    vk_hash: &H256,
+    root: &str,
+    claims: &[ClaimNative],
+    depth: usize,
+    options: &CircuitOptions,
+) -> Result<Vec<Fr>> {
+    // the root does not expose the nullifier hashes
+    let options = CircuitOptions {
+        unique_nullifiers: false,
+        ..*options
+    };
+    let instances = v2_leaf_instances(vk_hash, root, 0, depth, claims, &options)?;
+    Ok(v2_root_from_intermediate(&instances, claims.len() as u32))
+}
+
+/// V1 rolling instances from the instances of the previous rolling proof, or of the root proof of
+/// the first batch if `max_batches == 2`, and of the root proof of the new batch
+pub fn v1_rolling_instances(
+    prev: &[Fr],
+    batch: &[Fr],
+    num_batches: u32,
+    max_batches: usize,
+) -> Result<Vec<Fr>> {
+    if batch.len() != 2 {
+        bail!("Expected 2 instances from the batch root");
+    }
+    let (prev_num_batches, prev_chain_hash) = if max_batches == 2 {
+        if prev.len() != 2 {
+            bail!("Expected 2 instances from the first batch root");
+        }
+        let chain_hash = v1_append_batch(&H256::zero(), &from_hi_lo(prev));
+        (Fr::from(1), chain_hash)
+    } else {
+        if prev.len() != 3 {
+            bail!("Expected 3 instances from the previous rolling proof");
+        }
+        (prev[0], from_hi_lo(&prev[1..3]))
+    };
+    let is_batch_dummy = join_batches(prev_num_batches, num_batches, max_batches)?;
+    let chain_hash = if is_batch_dummy {
+        prev_chain_hash
+    } else {
+        v1_append_batch(&prev_chain_hash, &from_hi_lo(batch))
+    };
+    Ok([Fr::from(num_batches as u64)]
+        .into_iter()
+        .chain(hi_lo(&chain_hash))
+        .collect())
+}
+
+/// Keccak of the 32 byte `chain_hash` and the `output_hash` of a V1 batch
+pub fn v1_append_batch(chain_hash: &H256, output_hash: &H256) -> H256 {
+    H256(keccak256(
+        [chain_hash.as_bytes(), output_hash.as_bytes()].concat(),
+    ))
+}
+
+/// V2 rolling instances from the instances of the previous rolling proof, or of the root proof of
+/// the first batch if `max_batches == 2`, and of the root proof of the new batch
+pub fn v2_rolling_instances(
+    prev: &[Fr],
+    batch: &[Fr],
+    num_batches: u32,
+    max_batches: usize,
+) -> Result<Vec<Fr>> {
+    if batch.len() != 6 {
+        bail!("Expected 6 instances from the batch root");
+    }
+    if prev.len() != 6 {
+        bail!("Expected 6 instances from the previous proof");
+    }
+    let (prev_num_batches, vk_hash, prev_num_proofs, prev_chain_hash) = if max_batches == 2 {
+        let chain_hash = v2_append_batch(&H256::zero(), prev[2], &from_hi_lo(&prev[4..6]));
+        (Fr::from(1), &prev[0..2], prev[3], chain_hash)
+    } else {
+        (prev[0], &prev[1..3], prev[3], from_hi_lo(&prev[4..6]))
+    };
+    if vk_hash != &batch[0..2] {
+        bail!("The batch has a different vk hash");
+    }
+    let is_batch_dummy = join_batches(prev_num_batches, num_batches, max_batches)?;
+    let (num_proofs, chain_hash) = if is_batch_dummy {
+        (prev_num_proofs, prev_chain_hash)
+    } else {
+        let chain_hash = v2_append_batch(&prev_chain_hash, batch[2], &from_hi_lo(&batch[4..6]));
+        (prev_num_proofs + batch[3], chain_hash)
+    };
+    Ok([Fr::from(num_batches as u64)]
+        .into_iter()
+        .chain(vk_hash.iter().copied())
+        .chain([num_proofs])
+        .chain(hi_lo(&chain_hash))
+        .collect())
+}
+
+/// Keccak of the 32 byte `chain_hash`, `root` and `claim_root` of a V2 batch
+pub fn v2_append_batch(chain_hash: &H256, root: Fr, claim_root: &H256) -> H256 {
+    H256(keccak256(
+        [
+            chain_hash.as_bytes(),
+            fe_to_bytes_be(&root).as_slice(),
+            claim_root.as_bytes(),
+        ]
+        .concat(),
+    ))
+}
+
+/// V1 rolling instances of a chain of batches with the given output hashes. Dummy batches
+/// padding the chain leave the instances unchanged.
+pub fn v1_chain_instances(output_hashes: &[H256]) -> Vec<Fr> {
+    let chain_hash = output_hashes
+        .iter()
+        .fold(H256::zero(), |chain_hash, output_hash| {
+            v1_append_batch(&chain_hash, output_hash)
+        });
+    [Fr::from(output_hashes.len() as u64)]
+        .into_iter()
+        .chain(hi_lo(&chain_hash))
+        .collect()
+}
+
+/// V2 rolling instances of a chain of batches with the given V2 root instances. Dummy batches
+/// padding the chain leave the instances unchanged.
+pub fn v2_chain_instances(batches: &[Vec<Fr>]) -> Result<Vec<Fr>> {
+    let first = match batches.first() {
+        Some(first) => first,
+        None => bail!("Expected at least one batch"),
+    };
+    if batches.iter().any(|batch| batch.len() != 6) {
+        bail!("Expected 6 instances from every batch root");
+    }
+    if batches.iter().any(|batch| batch[0..2] != first[0..2]) {
+        bail!("The batches have different vk hashes");
+    }
+    let num_proofs = batches
+        .iter()
+        .fold(Fr::from(0), |num_proofs, batch| num_proofs + batch[3]);
+    let chain_hash = batches.iter().fold(H256::zero(), |chain_hash, batch| {
+        v2_append_batch(&chain_hash, batch[2], &from_hi_lo(&batch[4..6]))
+    });
+    Ok([Fr::from(batches.len() as u64)]
+        .into_iter()
+        .chain(first[0..2].iter().copied())
+        .chain([num_proofs])
+        .chain(hi_lo(&chain_hash))
+        .collect())
+}
+
+/// The `root` and `claim_root` of a V2 batch from its root instances, as passed to
+/// `validateChainedClaimsRoots`
+pub fn v2_batch_roots(instances: &[Fr]) -> (U256, H256) {
+    (
+        U256::from_big_endian(&fe_to_bytes_be(&instances[2])),
+        from_hi_lo(&instances[4..6]),
+    )
+}
+
+/// Instances of the snarks with the accumulators removed
+pub fn remove_accumulators(snarks: &[Snark]) -> Vec<Vec<Fr>> {
+    let acc_indices = get_accumulator_indices(snarks);
+    snarks
+        .iter()
+        .zip_eq(acc_indices)
+        .map(|(snark, indices)| {
+            let mut instances = snark.instances[0].clone();
+            for i in indices.into_iter().sorted().rev() {
+                instances.remove(i);
+            }
+            instances
+        })
+        .collect()
+}
+
+/// Instances of the two children of an aggregation node with the accumulators removed. A single
+/// child is used twice, like the dummy snark of the scheduler.
+pub fn prev_instances(snarks: &[Snark]) -> [Vec<Fr>; 2] {
+    children_instances(snarks, 2).try_into().unwrap()
+}
+
+/// Instances of the `arity` children of an aggregation node with the accumulators removed. The
+/// first child is used for the missing ones, like the dummy snarks of the scheduler.
+pub fn children_instances(snarks: &[Snark], arity: usize) -> Vec<Vec<Fr>> {
+    let mut prev = remove_accumulators(snarks);
+    prev.resize(arity.max(prev.len()), prev[0].clone());
+    prev
+}
+
+/// Check that the instances encoded in an evm proof, after the accumulator, are `expected`
+pub fn check_evm_proof_instances(evm_proof: &str, expected: &[Fr]) -> Result<()> {
+    let calldata = hex::decode(evm_proof)?;
+    let offset = NUM_FE_ACCUMULATOR * 32;
+    if calldata.len() < offset + expected.len() * 32 {
+        bail!("Evm proof is too short");
+    }
+    for (i, (word, fe)) in calldata[offset..].chunks(32).zip(expected).enumerate() {
+        if word != fe_to_bytes_be(fe) {
+            bail!(
+                "Instance {} of the evm proof is 0x{}, expected {:?}",
+                NUM_FE_ACCUMULATOR + i,
+                hex::encode(word),
+                fe
+            );
+        }
+    }
+    Ok(())
+}
+
+fn v2_root_from_intermediate(instances: &[Fr], num_proofs: u32) -> Vec<Fr> {
+    instances[2..5]
+        .iter()
+        .copied()
+        .chain([Fr::from(num_proofs as u64)])
+        .chain(instances[5..7].iter().copied())
+        .collect()
+}
+
+/// The shared root instance, zero with per-claim roots
+fn shared_root(root: Fr, per_claim_roots: bool) -> Fr {
+    if per_claim_roots {
+        Fr::from(0)
+    } else {
+        root
+    }
+}
+
+fn shared_instances(vk_hash: &H256, root: Fr, start: u32, num_proofs: u32) -> Vec<Fr> {
+    let [vk_hash_hi, vk_hash_lo] = hi_lo(vk_hash);
+    vec![
+        Fr::from(start as u64),
+        Fr::from((start + num_proofs) as u64),
+        vk_hash_hi,
+        vk_hash_lo,
+        root,
+    ]
+}
+
+/// Depth of the children of a node of depth `depth` with `arity` children, see
+/// `WorldcoinIntermediateAggregationInput::child_depth`
+fn child_depth(depth: usize, arity: usize) -> Result<usize> {
+    if arity < 2 || !arity.is_power_of_two() || arity.ilog2() as usize > depth {
+        bail!("Invalid number of children {arity} for depth {depth}");
+    }
+    Ok(WorldcoinIntermediateAggregationInput::child_depth(
+        depth, arity,
+    ))
+}
+
+/// Native version of `WorldcoinIntermediateAggregationInput::check_and_join_shared_instances`.
+/// Returns `[start, end, vk_hash_hi, vk_hash_lo, root]` and whether every child is a dummy.
+fn join_shared_instances(
+    prev: &[Vec<Fr>],
+    num_proofs: u32,
+    depth: usize,
+    num_instance_prev_depth: usize,
+) -> Result<(Vec<Fr>, Vec<bool>)> {
+    let prev_max_proofs = 1u32 << child_depth(depth, prev.len())?;
+    if prev
+        .iter()
+        .any(|instances| instances.len() != num_instance_prev_depth)
+    {
+        bail!("Expected {num_instance_prev_depth} instances from each child");
+    }
+    // the i-th child is a dummy if the children before it hold all the proofs
+    let is_proof_dummy = (0..prev.len() as u32)
+        .map(|i| i > 0 && num_proofs <= i * prev_max_proofs)
+        .collect_vec();
+    let instances0 = &prev[0];
+    let start = instances0[0];
+    let mut end = instances0[1];
+    for (i, (instances, is_dummy)) in prev.iter().zip_eq(&is_proof_dummy).enumerate().skip(1) {
+        if *is_dummy {
+            break;
+        }
+        let previous = &prev[i - 1];
+        if previous[1] != instances[0] {
+            bail!("Children do not link up");
+        }
+        if previous[1] - previous[0] != Fr::from(prev_max_proofs as u64) {
+            bail!("Child {} must be full", i - 1);
+        }
+        end = instances[1];
+    }
+    if end - start != Fr::from(num_proofs as u64) {
+        bail!("Children do not cover {num_proofs} proofs");
+    }
+    if prev
+        .iter()
+        .any(|instances| instances[2..5] != instances0[2..5])
+    {
+        bail!("Children have different vk hash or root");
+    }
+    let instances = [start, end]
+        .into_iter()
+        .chain(instances0[2..5].iter().copied())
+        .collect();
+    Ok((instances, is_proof_dummy))
+}
+
+/// Native version of `WorldcoinRollingAggregationInput::check_and_join_batches`. Returns whether
+/// the new batch is a dummy.
+fn join_batches(prev_num_batches: Fr, num_batches: u32, max_batches: usize) -> Result<bool> {
+    let num_batches = Fr::from(num_batches as u64);
+    if num_batches == prev_num_batches {
+        return Ok(true);
+    }
+    if num_batches != prev_num_batches + Fr::from(1) {
+        bail!("{num_batches:?} batches do not follow the {prev_num_batches:?} previous batches");
+    }
+    if prev_num_batches != Fr::from((max_batches - 1) as u64) {
+        bail!("A batch must follow a full previous proof");
+    }
+    Ok(false)
+}
+
+fn check_num_claims(claims: &[ClaimNative], depth: usize) -> Result<()> {
+    if claims.is_empty() || claims.len() > 1 << depth {
+        bail!(
+            "Invalid number of claims {} for depth {depth}",
+            claims.len()
+        );
+    }
+    Ok(())
+}
+
+/// Whether `a < b` as integers
+fn is_less_than(a: &Fr, b: &Fr) -> bool {
+    fe_to_bytes_be(a) < fe_to_bytes_be(b)
+}
+
+fn from_hi_lo(hi_lo: &[Fr]) -> H256 {
+    let mut bytes = [0u8; 32];
+    bytes[..16].copy_from_slice(&fe_to_bytes_be(&hi_lo[0])[16..]);
+    bytes[16..].copy_from_slice(&fe_to_bytes_be(&hi_lo[1])[16..]);
+    H256(bytes)
+}
+
+fn fe_to_bytes_be(fe: &Fr) -> [u8; 32] {
+    let mut bytes = fe.to_repr();
+    bytes.reverse();
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::types::Address;
+
+    use super::*;
+
+    fn claims(n: u64) -> Vec<ClaimNative> {
+        (1..=n)
+            .map(|i| ClaimNative {
+                receiver: Address::from_low_u64_be(i),
+                nullifier_hash: (1000 + i).to_string(),
+                grant_id: "30".to_string(),
+                proof: vec![],
+                vk_hash: None,
+                root: None,
+                amount: Some((i * 10).to_string()),
+                extra_public_inputs: vec![],
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_hi_lo_splits_into_128_bit_halves() {
+        let value = H256(std::array::from_fn(|i| i as u8 + 1));
+        let [hi, lo] = hi_lo(&value);
+        assert_eq!(hi, Fr::from_u128(0x0102030405060708090a0b0c0d0e0f10));
+        assert_eq!(lo, Fr::from_u128(0x1112131415161718191a1b1c1d1e1f20));
+        assert_eq!(from_hi_lo(&[hi, lo]), value);
+        assert_eq!(fe_to_bytes_be(&Fr::from(258))[30..], [1, 2]);
+    }
+
+    #[test]
+    fn test_vk_set_commitment_of_a_single_key_is_its_hash() {
+        let vk_hashes = [H256::repeat_byte(1), H256::repeat_byte(2)];
+        assert_eq!(get_vk_set_commitment_native(&vk_hashes[..1]), vk_hashes[0]);
+        let mut bytes = [1u8; 64];
+        bytes[32..].fill(2);
+        assert_eq!(
+            get_vk_set_commitment_native(&vk_hashes),
+            H256(keccak256(bytes))
+        );
+    }
+
+    #[test]
+    fn test_v1_join_instances_match_leaf_over_all_claims() {
+        let vk_hash = H256::repeat_byte(7);
+        for options in [
+            CircuitOptions::default(),
+            CircuitOptions {
+                per_claim_roots: true,
+                ..Default::default()
+            },
+        ] {
+            for n in [3, 5, 8] {
+                let claims = claims(n);
+                let joined =
+                    v1_instances_from_claims(&vk_hash, "9", 0, &claims, 3, 2, &options).unwrap();
+                let leaf = v1_leaf_instances(&vk_hash, "9", 0, 3, &claims, &options).unwrap();
+                assert_eq!(joined, leaf, "{n} claims, {options:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_v2_join_instances_match_leaf_over_all_claims() {
+        let vk_hash = H256::repeat_byte(7);
+        let options = CircuitOptions {
+            unique_nullifiers: true,
+            claim_amounts: true,
+            ..Default::default()
+        };
+        for n in [3u32, 5, 8] {
+            let claims = claims(n as u64);
+            let mut children = claims
+                .chunks(4)
+                .enumerate()
+                .map(|(i, chunk)| {
+                    v2_leaf_instances(&vk_hash, "9", i as u32 * 4, 2, chunk, &options).unwrap()
+                })
+                .collect::<Vec<_>>();
+            children.resize(2, children[0].clone());
+            let joined = v2_join_instances(&children, n, 3, &options).unwrap();
+            let leaf = v2_leaf_instances(&vk_hash, "9", 0, 3, &claims, &options).unwrap();
+            assert_eq!(joined, leaf, "{n} claims");
+        }
+    }
+
+    #[test]
+    fn test_chain_instances_match_padded_rolling_instances() {
+        let vk_hash = H256::repeat_byte(7);
+        let options = CircuitOptions::default();
+        let batches = [claims(3), claims(4)]
+            .iter()
+            .map(|claims| v2_root_instances_from_claims(&vk_hash, "9", claims, 2, &options))
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        let output_hashes = [H256::repeat_byte(1), H256::repeat_byte(2)];
+        let v1_batches = output_hashes.iter().map(hi_lo).collect::<Vec<_>>();
+
+        // two batches chained by `Rolling(2)` and padded with dummy batches up to 4 slots
+        let mut v1 = v1_batches[0].to_vec();
+        let mut v2 = batches[0].clone();
+        for max_batches in 2..=4 {
+            v1 = v1_rolling_instances(&v1, &v1_batches[1], 2, max_batches).unwrap();
+            v2 = v2_rolling_instances(&v2, &batches[1], 2, max_batches).unwrap();
+        }
+        assert_eq!(v1, v1_chain_instances(&output_hashes));
+        assert_eq!(v2, v2_chain_instances(&batches).unwrap());
+        assert_eq!(v2[3], Fr::from(7));
+
+        let (root, claim_root) = v2_batch_roots(&batches[1]);
+        assert_eq!(root, U256::from(9));
+        assert_eq!(hi_lo(&claim_root), batches[1][4..6]);
+    }
+
+    #[test]
+    fn test_v2_leaf_instances_reject_unsorted_nullifiers() {
+        let options = CircuitOptions {
+            unique_nullifiers: true,
+            ..Default::default()
+        };
+        let mut claims = claims(3);
+        claims.swap(0, 1);
+        let vk_hash = H256::repeat_byte(7);
+        assert!(v2_leaf_instances(&vk_hash, "9", 0, 2, &claims, &options).is_err());
+    }
+}
@@ -0,0 +1,5 @@
+//! Native (off-circuit) implementations of the checks done by the circuits.
+
+pub mod claim_tree;
+pub mod groth16;
+pub mod instances;
@@ -29,7 +29,7 @@ use clap::Parser;
 use ethers::utils::hex;
 use rocket::tokio;
 use serde::{de::DeserializeOwned, Serialize};
-use tokio::sync::{Mutex, RwLock};
+use tokio::sync::{RwLock, Semaphore, SemaphorePermit};
 
 use crate::{types::InvalidInputContext, CircuitId};
 pub mod types;
@@ -61,6 +61,10 @@ pub trait ProofRequest: Clone + Debug + Send + Serialize {
         kzg_params: Option<&ParamsKZG<Bn256>>,
     ) -> Result<Self::Circuit>;
 
+    /// The public instances of the circuit without the accumulator, computed natively with
+    /// [crate::native::instances]. Checked against the circuit in debug builds.
+    fn expected_instances(&self) -> Result<Vec<Fr>>;
+
     fn prover_circuit(
         self,
         pinning: Self::Pinning,
@@ -79,11 +83,14 @@ pub struct ProverConfig {
     /// Cache snarks
     #[arg(long = "out-dir")]
     pub out_dir: Option<PathBuf>,
+    /// Maximum number of proofs generated concurrently
+    #[arg(long = "proof-concurrency", default_value_t = 1)]
+    pub proof_concurrency: usize,
 }
 
 pub struct ProvingServerState {
     pub config: ProverConfig,
-    proof_mutex: Mutex<()>,
+    proof_semaphore: Semaphore,
     /// We always keep srs in memory
     srs: RwLock<HashMap<u32, Arc<ParamsKZG<Bn256>>>>,
     pinning: RwLock<HashMap<CircuitId, Arc<serde_json::Value>>>,
@@ -92,9 +99,10 @@ pub struct ProvingServerState {
 
 impl ProvingServerState {
     pub fn new(config: ProverConfig) -> Self {
+        let proof_semaphore = Semaphore::new(config.proof_concurrency.max(1));
         Self {
             config,
-            proof_mutex: Default::default(),
+            proof_semaphore,
             srs: Default::default(),
             pinning: Default::default(),
             pk: Default::default(),
@@ -116,8 +124,9 @@ impl ProvingServerState {
         self.config.out_dir.as_deref()
     }
 
-    pub async fn acquire_proof_mutex(&self) -> tokio::sync::MutexGuard<'_, ()> {
-        self.proof_mutex.lock().await
+    /// Limits the number of concurrent proofs to `proof_concurrency`
+    pub async fn acquire_proof_permit(&self) -> Result<SemaphorePermit<'_>> {
+        Ok(self.proof_semaphore.acquire().await?)
     }
     pub async fn get_srs(&self, k: u32) -> anyhow::Result<Arc<ParamsKZG<Bn256>>> {
         if let Some(srs) = self.srs.read().await.get(&k) {
@@ -185,12 +194,16 @@ impl ProvingServerState {
         circuit_id: &str,
         req: R,
     ) -> Result<EnhancedSnark> {
-        let _mutex_guard = self.acquire_proof_mutex().await;
+        let _permit = self.acquire_proof_permit().await?;
         log::info!("get_snark:circuit_id={circuit_id}");
         let snark_path = self.snark_path(circuit_id, &req);
 
         log::debug!("build circuit for proof_id={}", req.proof_id());
+        #[cfg(debug_assertions)]
+        let expected_instances = req.expected_instances()?;
         let (kzg_params, pk, circuit) = self.build_circuit(circuit_id, req).await?;
+        #[cfg(debug_assertions)]
+        check_expected_instances(&circuit, &expected_instances)?;
         log::debug!("gen_snark start");
 
         let snark = gen_snark_shplonk(&kzg_params, &pk, circuit, snark_path);
@@ -208,7 +221,7 @@ impl ProvingServerState {
     }
 
     pub async fn get_evm_proof<R: ProofRequest>(&self, circuit_id: &str, req: R) -> Result<String> {
-        let _mutex_guard = self.acquire_proof_mutex().await;
+        let _permit = self.acquire_proof_permit().await?;
         log::info!("get_evm_proof:circuit_id={circuit_id}");
         let evm_proof_path = self.evm_proof_path(circuit_id, &req);
         // check fs cache
@@ -218,7 +231,11 @@ impl ProvingServerState {
             return Ok(evm_proof);
         }
         log::debug!("build circuit for proof_id={}", req.proof_id());
+        #[cfg(debug_assertions)]
+        let expected_instances = req.expected_instances()?;
         let (kzg_params, pk, circuit) = self.build_circuit(circuit_id, req).await?;
+        #[cfg(debug_assertions)]
+        check_expected_instances(&circuit, &expected_instances)?;
         log::debug!("gen_evm_proof start");
         let instances = circuit.instances();
         let proof = gen_evm_proof_shplonk(&kzg_params, &pk, circuit, instances.clone());
@@ -276,3 +293,15 @@ impl ProvingServerState {
         .map_err(anyhow::Error::from)
     }
 }
+
+/// Check the instances of `circuit`, without the accumulator, against the native reference
+#[cfg(debug_assertions)]
+fn check_expected_instances<C: CircuitExt<Fr>>(circuit: &C, expected: &[Fr]) -> Result<()> {
+    let num_acc = C::accumulator_indices().map_or(0, |indices| indices.len());
+    let instances = circuit.instances();
+    anyhow::ensure!(
+        instances[0][num_acc..] == *expected,
+        "Circuit instances do not match the native reference"
+    );
+    Ok(())
+}
@@ -0,0 +1,218 @@
+use anyhow::{bail, Result};
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+
+use crate::constants::MAX_GROTH16_PI;
+
+/// Layout of the Groth16 public inputs of the claims: the number of public inputs, and the index
+/// of every claim field among them. Defaults to the Semaphore layout of World ID,
+/// `[root, nullifier_hash, signal_hash, external_nullifier]`.
+///
+/// Every claim field must have an index, see [Self::validate]. The public inputs which are not
+/// claim fields are given per claim by [crate::types::ClaimNative::extra_public_inputs], in
+/// order, and are not exposed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize, PartialOrd, Ord)]
+pub struct PublicInputLayout {
+    pub num_public_inputs: usize,
+    pub root: Option<usize>,
+    pub nullifier_hash: Option<usize>,
+    /// The receiver, or its World ID signal hash
+    pub signal_hash: Option<usize>,
+    /// The grant id, or the external nullifier hash derived from it
+    pub external_nullifier: Option<usize>,
+}
+
+impl Default for PublicInputLayout {
+    fn default() -> Self {
+        Self {
+            num_public_inputs: MAX_GROTH16_PI,
+            root: Some(0),
+            nullifier_hash: Some(1),
+            signal_hash: Some(2),
+            external_nullifier: Some(3),
+        }
+    }
+}
+
+impl PublicInputLayout {
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    fn field_indices(&self) -> [Option<usize>; 4] {
+        [
+            self.root,
+            self.nullifier_hash,
+            self.signal_hash,
+            self.external_nullifier,
+        ]
+    }
+
+    /// Check every claim field has an index, and the indices are distinct and below
+    /// `num_public_inputs`. The circuits expose all claim fields, so a field which is not a public
+    /// input would be exposed without being bound to the proof, and a proof could be claimed with
+    /// any receiver, grant id, root or nullifier hash.
+    pub fn validate(&self) -> Result<()> {
+        if self.num_public_inputs == 0 {
+            bail!("At least one public input is required");
+        }
+        let names = [
+            "root",
+            "nullifier_hash",
+            "signal_hash",
+            "external_nullifier",
+        ];
+        if let Some((name, _)) = names
+            .iter()
+            .zip(self.field_indices())
+            .find(|(_, index)| index.is_none())
+        {
+            bail!("The claim field {name} must be a public input");
+        }
+        let indices = self.field_indices().into_iter().flatten().collect_vec();
+        for (i, index) in indices.iter().enumerate() {
+            if *index >= self.num_public_inputs {
+                bail!(
+                    "Public input {index} is out of range for {} public inputs",
+                    self.num_public_inputs
+                );
+            }
+            if indices[..i].contains(index) {
+                bail!("Public input {index} is mapped to two claim fields");
+            }
+        }
+        Ok(())
+    }
+
+    /// Number of public inputs which are not claim fields
+    pub fn num_extra_public_inputs(&self) -> usize {
+        let num_fields = self.field_indices().into_iter().flatten().count();
+        self.num_public_inputs - num_fields
+    }
+
+    /// Arrange the claim fields and the extra public inputs of a claim into its public inputs.
+    /// The extra public inputs fill the indices which are not claim fields, in order.
+    pub fn public_inputs<T: Copy>(
+        &self,
+        root: T,
+        nullifier_hash: T,
+        signal_hash: T,
+        external_nullifier: T,
+        extra_public_inputs: &[T],
+    ) -> Result<Vec<T>> {
+        if extra_public_inputs.len() != self.num_extra_public_inputs() {
+            bail!(
+                "Expected {} extra public inputs, got {}",
+                self.num_extra_public_inputs(),
+                extra_public_inputs.len()
+            );
+        }
+        let fields = [root, nullifier_hash, signal_hash, external_nullifier];
+        let field_indices = self.field_indices();
+        let mut extra_public_inputs = extra_public_inputs.iter();
+        let public_inputs = (0..self.num_public_inputs)
+            .map(|i| match field_indices.iter().position(|j| *j == Some(i)) {
+                Some(field) => fields[field],
+                None => *extra_public_inputs.next().unwrap(),
+            })
+            .collect();
+        Ok(public_inputs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `[nullifier_hash, receiver, extra, grant_id, root, extra]`
+    fn permuted_layout() -> PublicInputLayout {
+        PublicInputLayout {
+            num_public_inputs: 6,
+            root: Some(4),
+            nullifier_hash: Some(0),
+            signal_hash: Some(1),
+            external_nullifier: Some(3),
+        }
+    }
+
+    #[test]
+    fn test_default_layout_is_semaphore() {
+        let layout = PublicInputLayout::default();
+        layout.validate().unwrap();
+        assert_eq!(layout.num_extra_public_inputs(), 0);
+        assert_eq!(layout.public_inputs(1, 2, 3, 4, &[]).unwrap(), [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_public_inputs_fill_extra_inputs_in_order() {
+        let layout = permuted_layout();
+        layout.validate().unwrap();
+        assert_eq!(layout.num_extra_public_inputs(), 2);
+        assert_eq!(
+            layout.public_inputs(1, 2, 3, 4, &[10, 11]).unwrap(),
+            [2, 3, 10, 4, 1, 11]
+        );
+    }
+
+    #[test]
+    fn test_public_inputs_reject_wrong_number_of_extra_inputs() {
+        let layout = permuted_layout();
+        assert!(layout.public_inputs(1, 2, 3, 4, &[10]).is_err());
+        assert!(layout.public_inputs(1, 2, 3, 4, &[10, 11, 12]).is_err());
+        assert!(PublicInputLayout::default()
+            .public_inputs(1, 2, 3, 4, &[10])
+            .is_err());
+    }
+
+    #[test]
+    fn test_validate_rejects_unbound_claim_fields() {
+        let layouts = [
+            PublicInputLayout {
+                root: None,
+                ..permuted_layout()
+            },
+            PublicInputLayout {
+                nullifier_hash: None,
+                ..permuted_layout()
+            },
+            PublicInputLayout {
+                signal_hash: None,
+                ..permuted_layout()
+            },
+            PublicInputLayout {
+                external_nullifier: None,
+                ..permuted_layout()
+            },
+        ];
+        for layout in layouts {
+            assert!(layout.validate().is_err(), "{layout:?}");
+        }
+    }
+
+    #[test]
+    fn test_validate_rejects_invalid_indices() {
+        let out_of_range = PublicInputLayout {
+            root: Some(6),
+            ..permuted_layout()
+        };
+        assert!(out_of_range.validate().is_err());
+
+        let duplicate = PublicInputLayout {
+            root: Some(0),
+            ..permuted_layout()
+        };
+        assert!(duplicate.validate().is_err());
+
+        let too_few = PublicInputLayout {
+            num_public_inputs: 3,
+            ..PublicInputLayout::default()
+        };
+        assert!(too_few.validate().is_err());
+
+        let empty = PublicInputLayout {
+            num_public_inputs: 0,
+            ..PublicInputLayout::default()
+        };
+        assert!(empty.validate().is_err());
+    }
+}
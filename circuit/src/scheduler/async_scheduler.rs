@@ -9,10 +9,8 @@ use rocket::tokio::sync::RwLock;
 use crate::{
     keygen::node_params::NodeParams,
     prover::types::{ProverProof, ProverTask},
-    scheduler::{
-        executor::{dispatcher::DispatcherExecutor, ProofExecutor},
-        recursive_request::RecursiveRequest,
-    },
+    scheduler::{executor::ProofExecutor, recursive_request::RecursiveRequest, types::RetryPolicy},
+    vk_registry::VkRegistry,
 };
 
 use super::{
@@ -38,10 +36,24 @@ pub struct AsyncScheduler {
     pub contract_client: Arc<ContractClient>,
     // the node params of the final aggregation circuit
     pub final_circuit_params: Arc<NodeParams>,
+    // how to retry failed nodes of the aggregation tree
+    pub retry_policy: RetryPolicy,
+    // the verifying keys accepted for claims
+    pub vk_registry: Arc<VkRegistry>,
+    // the app id of the external nullifiers, if the leaves derive them
+    pub app_id: Option<String>,
 }
 
 #[async_trait]
 impl Scheduler for AsyncScheduler {
+    fn vk_registry(&self) -> &VkRegistry {
+        &self.vk_registry
+    }
+
+    fn app_id(&self) -> Option<&str> {
+        self.app_id.as_deref()
+    }
+
     async fn get_circuit_id(&self, req: &RecursiveRequest) -> Result<String> {
         let circuit_id = self
             .circuit_id_repo
@@ -54,6 +66,10 @@ impl Scheduler for AsyncScheduler {
         Ok(circuit_id)
     }
 
+    fn retry_policy(&self) -> RetryPolicy {
+        self.retry_policy
+    }
+
     async fn generate_proof(&self, task: ProverTask) -> Result<super::executor::ExecutionResult> {
         self.executor.execute(task).await
     }
@@ -69,10 +85,27 @@ impl Scheduler for AsyncScheduler {
         let node_params = cid_to_params.get(circuit_id).unwrap();
 
         self.task_tracker
-            .record_task(request_id, &result.task_id, node_params)
+            .record_task(request_id, &result.summary, node_params)
             .await
     }
 
+    async fn get_stored_proof(
+        &self,
+        request_id: &str,
+        req: &RecursiveRequest,
+    ) -> Result<Option<ProverProof>> {
+        self.task_tracker.get_stored_proof(request_id, req)
+    }
+
+    async fn store_proof(
+        &self,
+        request_id: &str,
+        req: &RecursiveRequest,
+        proof: &ProverProof,
+    ) -> Result<()> {
+        self.task_tracker.store_proof(request_id, req, proof)
+    }
+
     async fn get_snarks_for_deps(
         &self,
         request_id: &str,
@@ -101,38 +134,30 @@ impl Scheduler for AsyncScheduler {
 }
 
 impl AsyncScheduler {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         circuit_id_repo: HashMap<NodeParams, String>,
         circuit_id_to_params: HashMap<String, NodeParams>,
-        executor_url: String,
+        executor: Arc<dyn ProofExecutor>,
         task_tracker: SchedulerTaskTracker,
         execution_summary_path: PathBuf,
         contract_client: ContractClient,
-        final_circuit_param: NodeParams
+        final_circuit_param: NodeParams,
+        retry_policy: RetryPolicy,
+        vk_registry: VkRegistry,
+        app_id: Option<String>,
     ) -> Self {
-        // query task status from dispatcher every 5000 ms
-        const DISPATCHER_POLL_INTERVAL: u64 = 5000;
-        // threshold for concurrent tasks
-        const DISPATCHER_CONCURRENCY: usize = 100;
-        // whether to re-prove in case the input for the circuit already has proof from previous runs
-        const FORCE_PROVE: bool = false;
-
         Self {
             circuit_id_repo: Arc::new(RwLock::new(circuit_id_repo)),
             cid_to_params: Arc::new(RwLock::new(circuit_id_to_params)),
-            executor: Arc::new(
-                DispatcherExecutor::new(
-                    &executor_url,
-                    DISPATCHER_POLL_INTERVAL,
-                    DISPATCHER_CONCURRENCY,
-                    FORCE_PROVE,
-                )
-                .unwrap(),
-            ),
+            executor,
             task_tracker: Arc::new(task_tracker),
             execution_summary_path: Arc::new(execution_summary_path),
             contract_client: Arc::new(contract_client),
-            final_circuit_params: Arc::new(final_circuit_param)
+            final_circuit_params: Arc::new(final_circuit_param),
+            retry_policy,
+            vk_registry: Arc::new(vk_registry),
+            app_id,
         }
     }
 }
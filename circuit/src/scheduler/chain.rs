@@ -0,0 +1,269 @@
+use anyhow::{anyhow, bail, Result};
+use axiom_eth::snark_verifier_sdk::Snark;
+use ethers::types::H256;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    circuit_factory::{
+        evm::WorldcoinRequestEvm,
+        rolling::{WorldcoinRequestRolling, WorldcoinRequestRollingV2},
+    },
+    keygen::node_params::{CircuitVersion, NodeParams, NodeType},
+    prover::types::{ProverProof, ProverTask, TaskInput},
+    types::ClaimNative,
+};
+
+use super::{
+    async_scheduler::AsyncScheduler,
+    recursive_request::RecursiveRequest,
+    types::{FailedNode, RequestRouter},
+    Scheduler,
+};
+
+/// A batch of the chain, proven by the batch tree of one request
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ChainedBatch {
+    pub request_id: String,
+    pub root: String,
+    pub claims: Vec<ClaimNative>,
+}
+
+/// The batches chained by the rolling circuits since the last submission. Each batch extends the
+/// rolling proof of the previous ones, so the chain is carried from one request into the next.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ChainState {
+    /// Hash of the verifying key shared by all batches
+    pub vk_hash: Option<H256>,
+    pub batches: Vec<ChainedBatch>,
+    /// The rolling proof of all batches, or the root proof of the first batch
+    pub proof: Option<ProverProof>,
+    /// The root proof of the last batch, which pads the chain with dummy batches
+    pub last_batch: Option<ProverProof>,
+}
+
+impl ChainState {
+    pub fn request_ids(&self) -> Vec<String> {
+        self.batches
+            .iter()
+            .map(|batch| batch.request_id.clone())
+            .collect()
+    }
+
+    pub fn num_claims(&self) -> u32 {
+        self.batches
+            .iter()
+            .map(|batch| batch.claims.len() as u32)
+            .sum()
+    }
+}
+
+impl AsyncScheduler {
+    /// Prove the batch tree of `req` and append its root to the chain. `req` must have the params
+    /// of the batch tree, see [NodeParams::batch].
+    ///
+    /// Returns a chain which is ready for submission: the chain once all batch slots are used,
+    /// or the previous chain if `req` has a different verifying key and starts a new one.
+    pub async fn append_batch(
+        &self,
+        request_id: &str,
+        req: RecursiveRequest,
+    ) -> Result<Option<ChainState>> {
+        let vk_hash = req.vk_hash;
+        let (root, claims) = (req.root.clone(), req.claims.clone());
+        let batch = self.recursive_gen_proof(request_id, req, false).await?;
+
+        let mut chain = self.task_tracker.chain.lock().await;
+        // the rolling circuits only chain batches of one verifying key
+        let submission = if chain.vk_hash.is_some_and(|chain_vk| chain_vk != vk_hash) {
+            Some(std::mem::take(&mut *chain))
+        } else {
+            None
+        };
+
+        let num_batches = chain.batches.len() + 1;
+        let proof = match &chain.proof {
+            None => batch.clone(),
+            Some(prev) => {
+                self.prove_rolling(
+                    request_id,
+                    snark(prev),
+                    snark(&batch),
+                    num_batches as u32,
+                    num_batches,
+                )
+                .await?
+            }
+        };
+        chain.vk_hash = Some(vk_hash);
+        chain.batches.push(ChainedBatch {
+            request_id: request_id.to_string(),
+            root,
+            claims,
+        });
+        chain.proof = Some(proof);
+        chain.last_batch = Some(batch);
+
+        let submission = if num_batches == self.final_circuit_params.max_batches {
+            assert!(submission.is_none());
+            Some(std::mem::take(&mut *chain))
+        } else {
+            submission
+        };
+        // the submission is stored first, its batches are not lost if the scheduler stops
+        // in between
+        if let Some(submission) = &submission {
+            self.task_tracker.store_submitting_chain(submission)?;
+        }
+        self.task_tracker.store_chain(&chain)?;
+        Ok(submission)
+    }
+
+    /// Take the current chain for submission, e.g. on the operator's schedule before all batch
+    /// slots are used. The next batch starts a new chain.
+    ///
+    /// Like the chain returned by [Self::append_batch], the taken chain stays in the store until
+    /// its submission ended, see `SchedulerTaskTracker::store_submitting_chain`.
+    pub async fn take_chain(&self) -> Result<Option<ChainState>> {
+        let mut chain = self.task_tracker.chain.lock().await;
+        if chain.batches.is_empty() {
+            return Ok(None);
+        }
+        let submission = std::mem::take(&mut *chain);
+        self.task_tracker.store_submitting_chain(&submission)?;
+        self.task_tracker.store_chain(&chain)?;
+        Ok(Some(submission))
+    }
+
+    /// Pad the chain with dummy batches up to the batch slots of the final circuit and wrap it in
+    /// the evm rounds, returning the hex encoded evm proof. Tasks are recorded for the last batch
+    /// of the chain.
+    pub async fn prove_chain(&self, chain: &ChainState) -> Result<String> {
+        let final_params = *self.final_circuit_params;
+        let num_rounds = match final_params.node_type {
+            NodeType::Evm(round) => round,
+            node_type => bail!("The final circuit {node_type:?} is not an evm circuit"),
+        };
+        let request_id = match chain.batches.last() {
+            Some(batch) => batch.request_id.as_str(),
+            None => bail!("Cannot prove an empty chain"),
+        };
+        let (mut proof, last_batch) = match (&chain.proof, &chain.last_batch) {
+            (Some(proof), Some(last_batch)) => (proof.clone(), snark(last_batch)),
+            _ => bail!("The chain has no proof"),
+        };
+
+        // a dummy batch keeps the number of batches, see `WorldcoinRequestRolling`
+        let num_batches = chain.batches.len();
+        for max_batches in num_batches + 1..=final_params.max_batches {
+            proof = self
+                .prove_rolling(
+                    request_id,
+                    snark(&proof),
+                    last_batch.clone(),
+                    num_batches as u32,
+                    max_batches,
+                )
+                .await?;
+        }
+
+        for round in 0..=num_rounds {
+            let params = NodeParams {
+                node_type: NodeType::Evm(round),
+                ..final_params
+            };
+            let req = WorldcoinRequestEvm {
+                start: 0,
+                end: chain.num_claims(),
+                depth: params.depth,
+                initial_depth: params.initial_depth,
+                round,
+                snark: snark(&proof),
+            };
+            proof = self
+                .prove_node(
+                    request_id,
+                    &params,
+                    RequestRouter::Evm(req),
+                    round == num_rounds,
+                    (0, chain.num_claims()),
+                )
+                .await?;
+        }
+
+        match proof {
+            ProverProof::EvmProof(proof) => Ok(proof),
+            ProverProof::Snark(_) => Err(anyhow!("Expected an evm proof for the chain")),
+        }
+    }
+
+    /// Append the root proof of a batch to `prev` with the rolling circuit of `max_batches`
+    /// batch slots
+    async fn prove_rolling(
+        &self,
+        request_id: &str,
+        prev: Snark,
+        batch: Snark,
+        num_batches: u32,
+        max_batches: usize,
+    ) -> Result<ProverProof> {
+        let params = NodeParams {
+            node_type: NodeType::Rolling(max_batches),
+            ..*self.final_circuit_params
+        };
+        let req = WorldcoinRequestRolling {
+            num_batches,
+            max_batches,
+            snarks: vec![prev, batch],
+        };
+        let router = match params.version {
+            CircuitVersion::V1 => RequestRouter::Rolling(req),
+            CircuitVersion::V2 => RequestRouter::RollingV2(WorldcoinRequestRollingV2(req)),
+        };
+        // rolling nodes span the batch slots rather than claims
+        self.prove_node(request_id, &params, router, false, (0, max_batches as u32))
+            .await
+    }
+
+    async fn prove_node(
+        &self,
+        request_id: &str,
+        params: &NodeParams,
+        request: RequestRouter,
+        is_evm_proof: bool,
+        (start, end): (u32, u32),
+    ) -> Result<ProverProof> {
+        let circuit_id = self
+            .circuit_id_repo
+            .read()
+            .await
+            .get(params)
+            .ok_or_else(|| anyhow!("Circuit ID for {params:?} not found"))?
+            .to_owned();
+        let node = FailedNode {
+            node_type: params.node_type,
+            depth: params.depth,
+            initial_depth: params.initial_depth,
+            start,
+            end,
+            circuit_id: Some(circuit_id.clone()),
+        };
+        let task = ProverTask {
+            circuit_id: circuit_id.clone(),
+            input: TaskInput {
+                is_evm_proof,
+                request,
+            },
+        };
+        let result = self.generate_proof_with_retries(task, &node).await?;
+        self.post_proof_gen_processing(request_id, &circuit_id, &result)
+            .await?;
+        Ok(result.proof)
+    }
+}
+
+fn snark(proof: &ProverProof) -> Snark {
+    match proof {
+        ProverProof::Snark(snark) => snark.snark.inner.clone(),
+        ProverProof::EvmProof(_) => unreachable!(),
+    }
+}
@@ -0,0 +1,384 @@
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    str::FromStr,
+};
+
+use anyhow::{anyhow, Context, Result};
+use ethers::types::H256;
+use num_bigint::BigUint;
+
+use crate::{
+    native::groth16::{
+        ensure_no_invalid_claims, parse_field_element, verify_claims, InvalidClaim, PublicInputMode,
+    },
+    types::{ClaimNative, InvalidInputContext},
+    vk_registry::VkRegistry,
+};
+
+use super::types::{ExcludedClaim, ExclusionReason};
+
+/// Claims verified against the same verifying key, which are aggregated in their own tree
+#[derive(Clone, Debug)]
+pub struct ClaimGroup {
+    pub vk_hash: H256,
+    pub claims: Vec<ClaimNative>,
+}
+
+/// Set the root of every claim explicitly, `root` by default, for circuits with per-claim roots.
+/// Otherwise all claims must be against `root`, and a claim with a different root is invalid input.
+pub fn resolve_claim_roots(
+    root: &str,
+    claims: Vec<ClaimNative>,
+    per_claim_roots: bool,
+) -> Result<Vec<ClaimNative>> {
+    let shared_root = BigUint::from_str(root).context(InvalidInputContext)?;
+    claims
+        .into_iter()
+        .enumerate()
+        .map(|(index, mut claim)| {
+            if per_claim_roots {
+                claim.root = Some(claim.root_or(root).to_string());
+            } else if let Some(claim_root) = claim.root.take() {
+                if BigUint::from_str(&claim_root).ok() != Some(shared_root.clone()) {
+                    return Err(anyhow!(
+                        "Claim {index} is against root {claim_root}, mixed roots are not supported"
+                    )
+                    .context(InvalidInputContext));
+                }
+            }
+            Ok(claim)
+        })
+        .collect()
+}
+
+/// Check every claim has a valid amount for circuits with claim amounts. Otherwise the grant
+/// amount is paid, and a claim with an amount is invalid input.
+pub fn check_claim_amounts(claims: &[ClaimNative], claim_amounts: bool) -> Result<()> {
+    for (index, claim) in claims.iter().enumerate() {
+        match (&claim.amount, claim_amounts) {
+            (None, true) => {
+                return Err(anyhow!("Claim {index} has no amount").context(InvalidInputContext))
+            }
+            (Some(amount), true) => {
+                parse_field_element(amount)
+                    .with_context(|| format!("Invalid amount of claim {index}"))
+                    .context(InvalidInputContext)?;
+            }
+            (Some(_), false) => {
+                return Err(
+                    anyhow!("Claim {index} has an amount, claim amounts are not supported")
+                        .context(InvalidInputContext),
+                )
+            }
+            (None, false) => {}
+        }
+    }
+    Ok(())
+}
+
+/// Verify every claim against the key of its vk hash. Claims with an unknown vk hash are invalid.
+/// The public inputs of the proofs are derived from the claims by `mode`. Returns the claims which
+/// failed, ordered by index.
+pub fn invalid_claims(
+    registry: &VkRegistry,
+    root: &str,
+    claims: &[ClaimNative],
+    mode: &PublicInputMode,
+) -> Result<Vec<InvalidClaim>> {
+    let mut invalid_claims = vec![];
+    let mut indices_by_vk: HashMap<H256, Vec<usize>> = HashMap::new();
+    for (index, claim) in claims.iter().enumerate() {
+        match registry.resolve(claim.vk_hash) {
+            Ok(vk_hash) => indices_by_vk.entry(vk_hash).or_default().push(index),
+            Err(e) => invalid_claims.push(InvalidClaim {
+                index,
+                reason: e.to_string(),
+            }),
+        }
+    }
+    for (vk_hash, indices) in indices_by_vk {
+        let vk = registry.get(&vk_hash)?;
+        let group_claims: Vec<_> = indices.iter().map(|&i| claims[i].clone()).collect();
+        invalid_claims.extend(
+            verify_claims(&vk, root, &group_claims, mode)?
+                .into_iter()
+                .map(|c| InvalidClaim {
+                    index: indices[c.index],
+                    reason: c.reason,
+                }),
+        );
+    }
+    invalid_claims.sort_by_key(|c| c.index);
+    Ok(invalid_claims)
+}
+
+/// Reject the request if any claim fails native verification against the key of its vk hash
+pub fn check_claims(
+    registry: &VkRegistry,
+    root: &str,
+    claims: &[ClaimNative],
+    mode: &PublicInputMode,
+) -> Result<()> {
+    let invalid_claims =
+        invalid_claims(registry, root, claims, mode).context(InvalidInputContext)?;
+    ensure_no_invalid_claims(&invalid_claims)
+}
+
+/// Drop the claims which fail native verification, and the claims reusing the nullifier hash of
+/// an earlier valid claim. Returns the remaining claims in their original order, and the
+/// excluded claims with the reason.
+pub fn exclude_invalid_claims(
+    registry: &VkRegistry,
+    root: &str,
+    claims: Vec<ClaimNative>,
+    mode: &PublicInputMode,
+) -> Result<(Vec<ClaimNative>, Vec<ExcludedClaim>)> {
+    let mut invalid_claims: HashMap<usize, String> = invalid_claims(registry, root, &claims, mode)?
+        .into_iter()
+        .map(|c| (c.index, c.reason))
+        .collect();
+
+    let mut nullifier_to_index: HashMap<BigUint, usize> = HashMap::new();
+    let mut valid_claims = vec![];
+    let mut excluded_claims = vec![];
+    for (index, claim) in claims.into_iter().enumerate() {
+        let reason = match invalid_claims.remove(&index) {
+            Some(error) => Some(ExclusionReason::InvalidClaim { error }),
+            // the nullifier hash of a valid claim is a field element
+            None => match nullifier_to_index.entry(BigUint::from_str(&claim.nullifier_hash)?) {
+                Entry::Occupied(e) => Some(ExclusionReason::DuplicateNullifier {
+                    first_index: *e.get(),
+                }),
+                Entry::Vacant(e) => {
+                    e.insert(index);
+                    None
+                }
+            },
+        };
+        match reason {
+            Some(reason) => excluded_claims.push(ExcludedClaim {
+                index,
+                nullifier_hash: claim.nullifier_hash,
+                receiver: claim.receiver,
+                reason,
+            }),
+            None => valid_claims.push(claim),
+        }
+    }
+    Ok((valid_claims, excluded_claims))
+}
+
+/// Sort claims by nullifier hash, for circuits which constrain the nullifier hashes of a batch to
+/// be strictly increasing. A nullifier hash used by more than one claim is invalid input.
+pub fn sort_claims_by_nullifier(claims: Vec<ClaimNative>) -> Result<Vec<ClaimNative>> {
+    let mut claims = claims
+        .into_iter()
+        .map(|claim| {
+            let nullifier_hash =
+                BigUint::from_str(&claim.nullifier_hash).context(InvalidInputContext)?;
+            Ok((nullifier_hash, claim))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    claims.sort_by(|(a, _), (b, _)| a.cmp(b));
+    if let Some(pair) = claims.windows(2).find(|pair| pair[0].0 == pair[1].0) {
+        return Err(anyhow!("Duplicate nullifier hash {}", pair[0].0).context(InvalidInputContext));
+    }
+    Ok(claims.into_iter().map(|(_, claim)| claim).collect())
+}
+
+/// Split verified claims into one group per vk hash, in order of first appearance. The vk hash of
+/// every claim is set explicitly, so a stored group does not depend on the default key.
+pub fn group_claims_by_vk(
+    registry: &VkRegistry,
+    claims: Vec<ClaimNative>,
+) -> Result<Vec<ClaimGroup>> {
+    let mut groups: Vec<ClaimGroup> = vec![];
+    for mut claim in claims {
+        let vk_hash = registry.resolve(claim.vk_hash)?;
+        claim.vk_hash = Some(vk_hash);
+        match groups.iter_mut().find(|group| group.vk_hash == vk_hash) {
+            Some(group) => group.claims.push(claim),
+            None => groups.push(ClaimGroup {
+                vk_hash,
+                claims: vec![claim],
+            }),
+        }
+    }
+    Ok(groups)
+}
+
+/// Put all verified claims in a single group for a mixed-vk tree, whose vk hash is the commitment
+/// to the vk set of the registry. The vk hash of every claim is set explicitly.
+pub fn mixed_vk_group(registry: &VkRegistry, claims: Vec<ClaimNative>) -> Result<Vec<ClaimGroup>> {
+    if claims.is_empty() {
+        return Ok(vec![]);
+    }
+    let claims = claims
+        .into_iter()
+        .map(|mut claim| {
+            claim.vk_hash = Some(registry.resolve(claim.vk_hash)?);
+            Ok(claim)
+        })
+        .collect::<Result<_>>()?;
+    Ok(vec![ClaimGroup {
+        vk_hash: registry.vk_set_hash(),
+        claims,
+    }])
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs::File, path::Path};
+
+    use ethers::types::Address;
+    use serde::Deserialize;
+
+    use crate::vk_registry::{read_vk, DEFAULT_VK_PATH};
+
+    use super::*;
+
+    #[derive(Deserialize)]
+    struct SampleRequest {
+        root: String,
+        claims: Vec<ClaimNative>,
+    }
+
+    fn sample_request() -> SampleRequest {
+        let file = File::open("./data/generated_proofs_2.json").unwrap();
+        serde_json::from_reader(file).unwrap()
+    }
+
+    fn registry() -> VkRegistry {
+        VkRegistry::new(read_vk(Path::new(DEFAULT_VK_PATH)).unwrap()).unwrap()
+    }
+
+    fn claim(nullifier_hash: &str) -> ClaimNative {
+        ClaimNative {
+            receiver: Address::from_low_u64_be(1),
+            nullifier_hash: nullifier_hash.to_string(),
+            grant_id: "30".to_string(),
+            proof: vec![],
+            vk_hash: None,
+            root: None,
+            amount: None,
+            extra_public_inputs: vec![],
+        }
+    }
+
+    #[test]
+    fn test_resolve_claim_roots() {
+        let claims = vec![
+            claim("1"),
+            ClaimNative {
+                root: Some("09".to_string()),
+                ..claim("2")
+            },
+        ];
+        let resolved = resolve_claim_roots("9", claims.clone(), false).unwrap();
+        assert!(resolved.iter().all(|c| c.root.is_none()));
+        let resolved = resolve_claim_roots("8", claims.clone(), true).unwrap();
+        let roots = resolved
+            .iter()
+            .map(|c| c.root.as_deref())
+            .collect::<Vec<_>>();
+        assert_eq!(roots, [Some("8"), Some("09")]);
+        assert!(resolve_claim_roots("8", claims, false).is_err());
+    }
+
+    #[test]
+    fn test_check_claim_amounts() {
+        let with_amount = ClaimNative {
+            amount: Some("100".to_string()),
+            ..claim("1")
+        };
+        let invalid_amount = ClaimNative {
+            amount: Some("x".to_string()),
+            ..claim("1")
+        };
+        check_claim_amounts(&[with_amount.clone()], true).unwrap();
+        check_claim_amounts(&[claim("1")], false).unwrap();
+        assert!(check_claim_amounts(&[claim("1")], true).is_err());
+        assert!(check_claim_amounts(&[with_amount], false).is_err());
+        assert!(check_claim_amounts(&[invalid_amount], true).is_err());
+    }
+
+    #[test]
+    fn test_sort_claims_by_nullifier_sorts_numerically() {
+        let claims = ["10", "9", "100"].map(claim).to_vec();
+        let sorted = sort_claims_by_nullifier(claims).unwrap();
+        let nullifier_hashes = sorted
+            .iter()
+            .map(|c| c.nullifier_hash.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(nullifier_hashes, ["9", "10", "100"]);
+        assert!(sort_claims_by_nullifier(["10", "9", "010"].map(claim).to_vec()).is_err());
+    }
+
+    #[test]
+    fn test_group_claims_by_vk() {
+        let registry = registry();
+        let default_vk_hash = registry.default_vk_hash();
+        let claims = vec![
+            claim("1"),
+            ClaimNative {
+                vk_hash: Some(default_vk_hash),
+                ..claim("2")
+            },
+        ];
+        let groups = group_claims_by_vk(&registry, claims.clone()).unwrap();
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].vk_hash, default_vk_hash);
+        assert!(groups[0]
+            .claims
+            .iter()
+            .all(|c| c.vk_hash == Some(default_vk_hash)));
+
+        let groups = mixed_vk_group(&registry, claims).unwrap();
+        assert_eq!(groups[0].vk_hash, registry.vk_set_hash());
+        assert!(mixed_vk_group(&registry, vec![]).unwrap().is_empty());
+
+        let unknown = ClaimNative {
+            vk_hash: Some(H256::zero()),
+            ..claim("3")
+        };
+        assert!(group_claims_by_vk(&registry, vec![unknown]).is_err());
+    }
+
+    #[test]
+    fn test_exclude_invalid_claims() {
+        let SampleRequest { root, claims } = sample_request();
+        let mut tampered = claims[1].clone();
+        tampered.grant_id = "1".to_string();
+        let unknown_vk = ClaimNative {
+            vk_hash: Some(H256::zero()),
+            ..claims[1].clone()
+        };
+        let submitted = vec![
+            claims[0].clone(),
+            tampered,
+            claims[0].clone(),
+            unknown_vk,
+            claims[1].clone(),
+        ];
+        let mode = PublicInputMode::default();
+        let (valid, excluded) =
+            exclude_invalid_claims(&registry(), &root, submitted, &mode).unwrap();
+
+        let valid = valid.iter().map(|c| &c.nullifier_hash).collect::<Vec<_>>();
+        assert_eq!(
+            valid,
+            [&claims[0].nullifier_hash, &claims[1].nullifier_hash]
+        );
+        let indices = excluded.iter().map(|c| c.index).collect::<Vec<_>>();
+        assert_eq!(indices, [1, 2, 3]);
+        assert!(matches!(
+            excluded[1].reason,
+            ExclusionReason::DuplicateNullifier { first_index: 0 }
+        ));
+        assert!(matches!(
+            &excluded[2].reason,
+            ExclusionReason::InvalidClaim { error } if error.starts_with("Unknown vk")
+        ));
+        assert!(check_claims(&registry(), &root, &claims, &mode).is_ok());
+    }
+}
@@ -1,17 +1,23 @@
 use abi::Abi;
 use ethers::prelude::*;
+use std::iter;
 use std::str::FromStr;
 use std::sync::Arc;
 
-use crate::types::ClaimNative;
-use ethers::types::Bytes;
+use crate::{keygen::node_params::CircuitVersion, types::ClaimNative};
+use ethers::{types::Bytes, utils::keccak256};
 use hex::FromHex;
+use serde::{Deserialize, Serialize};
 
+/// Arguments of `distributeGrants`. The claim arrays are padded to `2^depth` with zeros, like the
+/// public outputs of the V1 circuits, and `num_claims` is the number of claims before padding.
 #[derive(Debug, Clone)]
 pub struct V1ClaimParams {
     vkey_hash: H256,
     num_claims: U256,
     root: U256,
+    /// The root of every claim, for circuits with per-claim roots
+    roots: Option<Vec<U256>>,
     grant_ids: Vec<U256>,
     receivers: Vec<Address>,
     nullifier_hashes: Vec<U256>,
@@ -19,25 +25,32 @@ pub struct V1ClaimParams {
 }
 
 impl V1ClaimParams {
-    pub fn new(vkey_hash: &str, root: &str, claims: &Vec<ClaimNative>, proof: String) -> Self {
+    pub fn new(
+        vkey_hash: &str,
+        root: &str,
+        claims: &Vec<ClaimNative>,
+        proof: String,
+        depth: usize,
+        per_claim_roots: bool,
+    ) -> Self {
+        let max_claims = 1 << depth;
+        assert!(claims.len() <= max_claims);
         let vkey_hash = H256::from_str(vkey_hash).expect("Invalid H256 string");
 
+        let roots = per_claim_roots.then(|| {
+            claims
+                .iter()
+                .map(|claim| {
+                    U256::from_str_radix(claim.root_or(root), 10).expect("Invalid root string")
+                })
+                .chain(iter::repeat(U256::zero()))
+                .take(max_claims)
+                .collect()
+        });
+
         let root = U256::from_str_radix(root, 10).expect("Invalid root string");
         let num_claims = U256::from(claims.len() as u64); // Example conversion for num_claims
-
-        let nullifier_hashes = claims
-            .iter()
-            .map(|claim| {
-                U256::from_str_radix(&claim.nullifier_hash, 10).expect("Invalid nullifier_hash")
-            })
-            .collect();
-
-        let receivers: Vec<Address> = claims.iter().map(|claim| claim.receiver).collect();
-
-        let grant_ids: Vec<U256> = claims
-            .iter()
-            .map(|claim| U256::from_str_radix(&claim.grant_id, 10).expect("Invalid grant_id"))
-            .collect();
+        let (grant_ids, receivers, nullifier_hashes) = padded_claims(claims, max_claims);
 
         let proof = Vec::from_hex(proof).expect("Invalid hex string");
         let proof = Bytes::from(proof);
@@ -46,6 +59,7 @@ impl V1ClaimParams {
             vkey_hash,
             num_claims,
             root,
+            roots,
             grant_ids,
             receivers,
             nullifier_hashes,
@@ -54,8 +68,118 @@ impl V1ClaimParams {
     }
 }
 
+/// Arguments of `distributeGrantsChained`. The claim arrays are the concatenated claim arrays of
+/// the batches, each padded to `2^depth` like in [V1ClaimParams].
+#[derive(Debug, Clone)]
+pub struct V1ChainedParams {
+    vkey_hash: H256,
+    num_claims: Vec<U256>,
+    roots: Vec<U256>,
+    grant_ids: Vec<U256>,
+    receivers: Vec<Address>,
+    nullifier_hashes: Vec<U256>,
+    proof: Bytes,
+}
+
+impl V1ChainedParams {
+    /// `batches` are the `(root, claims)` of the chained batches, in the order of the chain
+    pub fn new(
+        vkey_hash: &str,
+        batches: &[(String, Vec<ClaimNative>)],
+        proof: String,
+        depth: usize,
+    ) -> Self {
+        let max_claims = 1 << depth;
+        let vkey_hash = H256::from_str(vkey_hash).expect("Invalid H256 string");
+
+        let mut params = Self {
+            vkey_hash,
+            num_claims: vec![],
+            roots: vec![],
+            grant_ids: vec![],
+            receivers: vec![],
+            nullifier_hashes: vec![],
+            proof: Bytes::from(Vec::from_hex(proof).expect("Invalid hex string")),
+        };
+        for (root, claims) in batches {
+            assert!(claims.len() <= max_claims);
+            let (grant_ids, receivers, nullifier_hashes) = padded_claims(claims, max_claims);
+            params.num_claims.push(U256::from(claims.len() as u64));
+            params
+                .roots
+                .push(U256::from_str_radix(root, 10).expect("Invalid root string"));
+            params.grant_ids.extend(grant_ids);
+            params.receivers.extend(receivers);
+            params.nullifier_hashes.extend(nullifier_hashes);
+        }
+        params
+    }
+}
+
+/// The grant ids, receivers and nullifier hashes of the claims, padded to `max_claims` with zeros
+fn padded_claims(
+    claims: &[ClaimNative],
+    max_claims: usize,
+) -> (Vec<U256>, Vec<Address>, Vec<U256>) {
+    let nullifier_hashes = claims
+        .iter()
+        .map(|claim| {
+            U256::from_str_radix(&claim.nullifier_hash, 10).expect("Invalid nullifier_hash")
+        })
+        .chain(iter::repeat(U256::zero()))
+        .take(max_claims)
+        .collect();
+
+    let receivers: Vec<Address> = claims
+        .iter()
+        .map(|claim| claim.receiver)
+        .chain(iter::repeat(Address::zero()))
+        .take(max_claims)
+        .collect();
+
+    let grant_ids: Vec<U256> = claims
+        .iter()
+        .map(|claim| U256::from_str_radix(&claim.grant_id, 10).expect("Invalid grant_id"))
+        .chain(iter::repeat(U256::zero()))
+        .take(max_claims)
+        .collect();
+
+    (grant_ids, receivers, nullifier_hashes)
+}
+
+/// Arguments of the transaction submitting a final proof
+#[derive(Debug, Clone)]
+pub enum FulfillParams {
+    /// `distributeGrants` of the V1 contract, or `distributeGrantsWithRoots` with per-claim roots
+    V1(V1ClaimParams),
+    /// `validateClaimsRoot` of the V2 contract, with the hex encoded evm proof
+    V2(String),
+    /// `distributeGrantsChained` of the V1 contract, for a rolling proof of several batches
+    V1Chained(V1ChainedParams),
+    /// `validateChainedClaimsRoots` of the V2 contract, for a rolling proof of several batches
+    V2Chained {
+        /// The hex encoded evm proof
+        proof: String,
+        /// The root of every batch, zero for batches with per-claim roots
+        roots: Vec<U256>,
+        claims_roots: Vec<H256>,
+    },
+}
+
+/// A signed transaction, recorded before it is broadcast so that it can be reconciled after a
+/// restart instead of being signed again
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PendingTx {
+    pub tx_hash: H256,
+    pub nonce: Option<U256>,
+    /// The RLP encoded signed transaction
+    pub raw: Bytes,
+}
+
 pub struct ContractClient {
     contract_client: Contract<SignerMiddleware<Provider<Http>, Wallet<k256::ecdsa::SigningKey>>>,
+    /// Version of the aggregation contract at the address
+    version: CircuitVersion,
 }
 
 impl ContractClient {
@@ -65,6 +189,7 @@ impl ContractClient {
         provider_uri: &str,
         contract_address: &str,
         chain_id: u64,
+        version: CircuitVersion,
     ) -> Result<Self, Box<dyn std::error::Error>> {
         let provider: Provider<Http> = Provider::<Http>::try_from(provider_uri)?;
 
@@ -74,56 +199,138 @@ impl ContractClient {
             .with_chain_id(chain_id);
 
         let contract_address: Address = contract_address.parse()?;
-        #[cfg(feature = "v1")]
-        let abi = include_str!("../../abi/WorldcoinAggregationV1Abi.json");
-        #[cfg(feature = "v2")]
-        let abi = include_str!("../../abi/WorldcoinAggregationV2Abi.json");
+        let abi = match version {
+            CircuitVersion::V1 => include_str!("../../abi/WorldcoinAggregationV1Abi.json"),
+            CircuitVersion::V2 => include_str!("../../abi/WorldcoinAggregationV2Abi.json"),
+        };
 
         let abi: Abi = serde_json::from_str(&abi)?;
         let client = SignerMiddleware::new(provider.clone(), wallet.clone());
 
         let contract_client = Contract::new(contract_address, abi, Arc::new(client));
 
-        Ok(Self { contract_client })
+        Ok(Self {
+            contract_client,
+            version,
+        })
     }
 
-    // example tx: https://sepolia.etherscan.io/tx/0x3d7488e27ba42f02bc15a2228364fa202b50d94e9fdeffbfcd9fb0b0b950b3c1
-    #[cfg(feature = "v1")]
-    pub async fn fulfill(&self, params: V1ClaimParams) -> anyhow::Result<H256> {
-        let receipt = self
-            .contract_client
-            .method::<_, ()>(
-                "distributeGrants",
-                (
-                    params.vkey_hash,
-                    params.num_claims,
-                    params.root,
-                    params.grant_ids,
-                    params.receivers,
-                    params.nullifier_hashes,
-                    params.proof,
-                ),
-            )?
-            .send()
-            .await?
-            .await?
-            .unwrap();
-
-        Ok(receipt.transaction_hash)
+    pub fn version(&self) -> CircuitVersion {
+        self.version
     }
 
-    #[cfg(feature = "v2")]
-    pub async fn fulfill(&self, proof: String) -> anyhow::Result<H256> {
-        let proof = Vec::from_hex(proof).expect("Invalid hex string");
-        let proof = Bytes::from(proof);
-        let receipt = self
-            .contract_client
-            .method::<_, ()>("validateClaimsRoot", proof)?
-            .send()
-            .await?
-            .await?
-            .unwrap();
-
-        Ok(receipt.transaction_hash)
+    /// Sign the transaction submitting `params`, without broadcasting it. See [Self::send].
+    pub async fn sign(&self, params: FulfillParams) -> anyhow::Result<PendingTx> {
+        let call = match (self.version, params) {
+            (CircuitVersion::V1, FulfillParams::V1(params)) => {
+                let V1ClaimParams {
+                    vkey_hash,
+                    num_claims,
+                    root,
+                    roots,
+                    grant_ids,
+                    receivers,
+                    nullifier_hashes,
+                    proof,
+                } = params;
+                match roots {
+                    Some(roots) => self.contract_client.method::<_, ()>(
+                        "distributeGrantsWithRoots",
+                        (
+                            vkey_hash,
+                            num_claims,
+                            roots,
+                            grant_ids,
+                            receivers,
+                            nullifier_hashes,
+                            proof,
+                        ),
+                    )?,
+                    // example tx: https://sepolia.etherscan.io/tx/0x3d7488e27ba42f02bc15a2228364fa202b50d94e9fdeffbfcd9fb0b0b950b3c1
+                    None => self.contract_client.method::<_, ()>(
+                        "distributeGrants",
+                        (
+                            vkey_hash,
+                            num_claims,
+                            root,
+                            grant_ids,
+                            receivers,
+                            nullifier_hashes,
+                            proof,
+                        ),
+                    )?,
+                }
+            }
+            (CircuitVersion::V2, FulfillParams::V2(proof)) => {
+                let proof = Vec::from_hex(proof).expect("Invalid hex string");
+                let proof = Bytes::from(proof);
+                self.contract_client
+                    .method::<_, ()>("validateClaimsRoot", proof)?
+            }
+            (CircuitVersion::V1, FulfillParams::V1Chained(params)) => {
+                let V1ChainedParams {
+                    vkey_hash,
+                    num_claims,
+                    roots,
+                    grant_ids,
+                    receivers,
+                    nullifier_hashes,
+                    proof,
+                } = params;
+                self.contract_client.method::<_, ()>(
+                    "distributeGrantsChained",
+                    (
+                        vkey_hash,
+                        num_claims,
+                        roots,
+                        grant_ids,
+                        receivers,
+                        nullifier_hashes,
+                        proof,
+                    ),
+                )?
+            }
+            (
+                CircuitVersion::V2,
+                FulfillParams::V2Chained {
+                    proof,
+                    roots,
+                    claims_roots,
+                },
+            ) => {
+                let proof = Vec::from_hex(proof).expect("Invalid hex string");
+                let proof = Bytes::from(proof);
+                self.contract_client
+                    .method::<_, ()>("validateChainedClaimsRoots", (proof, roots, claims_roots))?
+            }
+            (version, _) => anyhow::bail!("Fulfill params do not match the {version:?} contract"),
+        };
+
+        let client = self.contract_client.client();
+        let mut tx = call.tx;
+        client.fill_transaction(&mut tx, None).await?;
+        let signature = client.signer().sign_transaction(&tx).await?;
+        let raw = tx.rlp_signed(&signature);
+        Ok(PendingTx {
+            tx_hash: H256(keccak256(&raw)),
+            nonce: tx.nonce().copied(),
+            raw,
+        })
+    }
+
+    /// Broadcast the signed transaction, unless it is already mined, and wait for its receipt.
+    /// Broadcasting is idempotent, so this also reconciles a transaction which may or may not
+    /// have been broadcast. Returns `None` if the transaction was dropped, e.g. because another
+    /// transaction took its nonce.
+    pub async fn send(&self, tx: &PendingTx) -> anyhow::Result<Option<TransactionReceipt>> {
+        let client = self.contract_client.client();
+        if let Some(receipt) = client.get_transaction_receipt(tx.tx_hash).await? {
+            return Ok(Some(receipt));
+        }
+        if let Err(e) = client.send_raw_transaction(tx.raw.clone()).await {
+            // the node rejects a transaction it already knows, or whose nonce is taken
+            log::warn!("Failed to broadcast {:?}: {e}", tx.tx_hash);
+        }
+        Ok(PendingTransaction::new(tx.tx_hash, client.provider()).await?)
     }
 }
@@ -1,46 +1,68 @@
 use anyhow::{bail, Context};
 use async_trait::async_trait;
-use log::debug;
+use log::{debug, warn};
 use reqwest_middleware::{ClientBuilder, ClientWithMiddleware};
 use reqwest_retry::{policies::ExponentialBackoff, RetryTransientMiddleware};
-use serde::{Deserialize, Serialize};
-use tokio::{sync::Semaphore, time::Duration};
+use tokio::{
+    sync::Semaphore,
+    time::{Duration, Instant},
+};
 
-use crate::prover::types::{ProverProof, ProverTask, ProverTaskResponse, TaskInput};
+use crate::{
+    circuit_factory::leaf::WorldcoinRequestLeafV2,
+    dispatcher::types::{
+        DispatcherSnarkResponse, DispatcherTaskId, DispatcherTaskRequest, DispatcherTaskStatus,
+        DispatcherTaskStatusResponse,
+    },
+    prover::types::{ProverProof, ProverTask},
+    scheduler::types::RequestRouter,
+};
 
-use super::ProofExecutor;
+use super::{ProofExecutor, TaskRetry};
 
-#[derive(Serialize, Deserialize, Debug)]
-#[serde(rename_all = "camelCase")]
-struct TasksRequest {
-    pub circuit_id: String,
-    pub input: TaskInput,
-    pub force_prove: bool,
+type TaskId = DispatcherTaskId;
+
+enum WaitOutcome {
+    Done,
+    TimedOut,
 }
 
-type TasksResponse = TaskId;
-type TaskId = String;
-
-#[derive(Serialize, Deserialize, PartialEq, Eq, Debug)]
-#[serde(rename_all = "UPPERCASE")]
-enum TaskStatus {
-    PENDING,
-    PREPARING,
-    PROVING,
-    DONE,
-    FAILED,
+/// Deadlines after which a dispatcher task without terminal status counts as stuck
+#[derive(Clone, Copy, Debug)]
+pub struct TaskTimeouts {
+    /// Deadline per claim for leaf tasks, a leaf of depth `d` gets `2^d` times this
+    pub leaf_per_claim: Duration,
+    /// Deadline for intermediate and root aggregation tasks
+    pub aggregation: Duration,
+    /// Deadline for evm tasks
+    pub evm: Duration,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
-struct TaskStatusResponse {
-    pub status: TaskStatus,
+impl Default for TaskTimeouts {
+    fn default() -> Self {
+        Self {
+            leaf_per_claim: Duration::from_secs(60),
+            aggregation: Duration::from_secs(1800),
+            evm: Duration::from_secs(1800),
+        }
+    }
 }
 
-#[derive(Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
-struct ProverProofResponse {
-    pub snark: ProverTaskResponse,
+impl TaskTimeouts {
+    pub fn timeout(&self, request: &RequestRouter) -> Duration {
+        match request {
+            RequestRouter::Leaf(req) | RequestRouter::LeafV2(WorldcoinRequestLeafV2(req)) => {
+                self.leaf_per_claim * (1 << req.depth)
+            }
+            RequestRouter::Intermediate(_)
+            | RequestRouter::Root(_)
+            | RequestRouter::IntermediateV2(_)
+            | RequestRouter::RootV2(_)
+            | RequestRouter::Rolling(_)
+            | RequestRouter::RollingV2(_) => self.aggregation,
+            RequestRouter::Evm(_) => self.evm,
+        }
+    }
 }
 
 pub struct DispatcherExecutor {
@@ -48,22 +70,56 @@ pub struct DispatcherExecutor {
     pub(crate) poll_interval: Duration,
     pub(crate) proof_concurrency_semaphore: Option<Semaphore>,
     pub(crate) force_prove: bool,
+    pub(crate) timeouts: TaskTimeouts,
+    /// How many times a stuck task is resubmitted before giving up
+    pub(crate) max_resubmits: usize,
 }
 
 #[async_trait]
 impl ProofExecutor for DispatcherExecutor {
-    async fn execute_impl(&self, proof: ProverTask) -> anyhow::Result<(TaskId, ProverProof)> {
+    async fn execute_impl(
+        &self,
+        proof: ProverTask,
+    ) -> anyhow::Result<(TaskId, ProverProof, Vec<TaskRetry>)> {
         let permit = if let Some(sem) = self.proof_concurrency_semaphore.as_ref() {
             Some(sem.acquire().await?)
         } else {
             None
         };
-        let task_id = self.create_task(proof).await?;
+        let timeout = self.timeouts.timeout(&proof.input.request);
+        let mut force_prove = self.force_prove;
+        let mut retries = vec![];
+        loop {
+            let task_id = self.create_task(proof.clone(), force_prove).await?;
 
-        self.wait_task_done(&task_id).await?;
-        drop(permit);
-        let prover_proof = self.get_prover_proof(&task_id).await?;
-        Ok((task_id, prover_proof))
+            match self.wait_task_done(&task_id, timeout).await? {
+                WaitOutcome::Done => {
+                    drop(permit);
+                    let prover_proof = self.get_prover_proof(&task_id).await?;
+                    return Ok((task_id, prover_proof, retries));
+                }
+                WaitOutcome::TimedOut => {
+                    if retries.len() >= self.max_resubmits {
+                        bail!(
+                            "Task {} did not finish within {}s after {} resubmits",
+                            task_id,
+                            timeout.as_secs(),
+                            retries.len()
+                        );
+                    }
+                    warn!(
+                        "Dispatcher task {task_id} did not finish within {}s, resubmitting",
+                        timeout.as_secs()
+                    );
+                    // the stuck task is abandoned, a cached proof must not be reused
+                    force_prove = true;
+                    retries.push(TaskRetry {
+                        task_id,
+                        reason: format!("no terminal status after {}s", timeout.as_secs()),
+                    });
+                }
+            }
+        }
     }
 }
 
@@ -73,6 +129,8 @@ impl DispatcherExecutor {
         poll_interval_ms: u64,
         proof_concurrency: usize,
         force_prove: bool,
+        timeouts: TaskTimeouts,
+        max_resubmits: usize,
     ) -> anyhow::Result<Self> {
         let proof_concurrency_semaphore = if proof_concurrency == 0 {
             None
@@ -84,17 +142,19 @@ impl DispatcherExecutor {
             poll_interval: Duration::from_millis(poll_interval_ms),
             proof_concurrency_semaphore,
             force_prove,
+            timeouts,
+            max_resubmits,
         })
     }
-    async fn create_task(&self, proof: ProverTask) -> anyhow::Result<TaskId> {
+    async fn create_task(&self, proof: ProverTask, force_prove: bool) -> anyhow::Result<TaskId> {
         let circuit_id = proof.circuit_id.clone();
         debug!("Creating dispatcher task for circuit id {}", circuit_id);
         let client = self.build_client();
         let task_url = self.url.join("tasks")?;
-        let body = TasksRequest {
+        let body = DispatcherTaskRequest {
             circuit_id: proof.circuit_id,
             input: proof.input,
-            force_prove: self.force_prove,
+            force_prove,
         };
 
         let resp = client
@@ -103,7 +163,7 @@ impl DispatcherExecutor {
             .send()
             .await
             .with_context(|| format!("Failed when requesting {task_url}"))?;
-        let task_id: TasksResponse = resp
+        let task_id: TaskId = resp
             .json()
             .await
             .with_context(|| format!("Failed to parse response of /tasks"))?;
@@ -115,11 +175,18 @@ impl DispatcherExecutor {
         Ok(task_id)
     }
 
-    async fn wait_task_done(&self, task_id: &TaskId) -> anyhow::Result<()> {
+    async fn wait_task_done(
+        &self,
+        task_id: &TaskId,
+        timeout: Duration,
+    ) -> anyhow::Result<WaitOutcome> {
         let status_url = self.url.join(&format!("tasks/{}/status", task_id))?;
         debug!("Waiting for dispatcher task {task_id}");
-        // TODO: add timeout
+        let deadline = Instant::now() + timeout;
         loop {
+            if Instant::now() >= deadline {
+                return Ok(WaitOutcome::TimedOut);
+            }
             debug!(
                 "Sleep another {}ms for next status polling of dispatcher task {task_id}",
                 self.poll_interval.as_millis()
@@ -135,7 +202,8 @@ impl DispatcherExecutor {
                 .await
                 .with_context(|| format!("Failed when requesting {status_url}"))?;
             let resp_text = resp.text().await?;
-            let task_status_result = serde_json::from_str::<TaskStatusResponse>(&resp_text);
+            let task_status_result =
+                serde_json::from_str::<DispatcherTaskStatusResponse>(&resp_text);
             if task_status_result.is_err() {
                 debug!("Failed to parse response of {status_url}: {resp_text}");
             }
@@ -147,12 +215,13 @@ impl DispatcherExecutor {
                 task_status.status
             );
             match task_status.status {
-                TaskStatus::DONE => {
-                    return Ok(());
-                }
-                TaskStatus::FAILED => {
-                    bail!("Task {} failed", task_id);
+                DispatcherTaskStatus::Done => {
+                    return Ok(WaitOutcome::Done);
                 }
+                DispatcherTaskStatus::Failed => match task_status.error {
+                    Some(error) => bail!("Task {} failed: {}", task_id, error),
+                    None => bail!("Task {} failed", task_id),
+                },
                 _ => {}
             }
         }
@@ -171,7 +240,7 @@ impl DispatcherExecutor {
             return Err(err);
         }
         let resp = resp_result.unwrap();
-        let prover_proof: ProverProofResponse = resp
+        let prover_proof: DispatcherSnarkResponse = resp
             .json()
             .await
             .with_context(|| format!("Failed to parse response of {proof_url}"))?;
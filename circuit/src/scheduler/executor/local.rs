@@ -0,0 +1,209 @@
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use uuid::Uuid;
+
+use crate::{
+    prover::{
+        types::{ProverProof, ProverSnark, ProverTask, TaskInput},
+        ProvingServerState,
+    },
+    scheduler::types::RequestRouter,
+};
+
+use super::{ProofExecutor, TaskId, TaskRetry};
+
+/// Executes tasks in-process with a [ProvingServerState], without going through a dispatcher.
+/// The number of proofs generated concurrently is bounded by the `proof_concurrency` of the
+/// prover config.
+pub struct LocalExecutor {
+    pub state: Arc<ProvingServerState>,
+}
+
+impl LocalExecutor {
+    pub fn new(state: Arc<ProvingServerState>) -> Self {
+        Self { state }
+    }
+}
+
+#[async_trait]
+impl ProofExecutor for LocalExecutor {
+    async fn execute_impl(
+        &self,
+        task: ProverTask,
+    ) -> Result<(TaskId, ProverProof, Vec<TaskRetry>)> {
+        let task_id = Uuid::new_v4().to_string();
+        log::debug!(
+            "LocalExecutor:task_id={task_id} circuit_id={}",
+            task.circuit_id
+        );
+        let proof = prove_task(&self.state, task).await?;
+        Ok((task_id, proof, vec![]))
+    }
+}
+
+/// Generate the proof for `task` with the prover `state`
+pub async fn prove_task(state: &ProvingServerState, task: ProverTask) -> Result<ProverProof> {
+    let ProverTask { circuit_id, input } = task;
+    let TaskInput {
+        is_evm_proof,
+        request,
+    } = input;
+
+    if is_evm_proof {
+        let proof = match request {
+            RequestRouter::Evm(req) => state.get_evm_proof(&circuit_id, req).await,
+            _ => Err(anyhow!(
+                "EVM proof requested for circuit {circuit_id}, which is not the final circuit"
+            )),
+        }?;
+        Ok(ProverProof::EvmProof(proof))
+    } else {
+        let snark = match request {
+            RequestRouter::Leaf(req) => state.get_snark(&circuit_id, req).await,
+            RequestRouter::Intermediate(req) => state.get_snark(&circuit_id, req).await,
+            RequestRouter::Root(req) => state.get_snark(&circuit_id, req).await,
+            RequestRouter::LeafV2(req) => state.get_snark(&circuit_id, req).await,
+            RequestRouter::IntermediateV2(req) => state.get_snark(&circuit_id, req).await,
+            RequestRouter::RootV2(req) => state.get_snark(&circuit_id, req).await,
+            RequestRouter::Rolling(req) => state.get_snark(&circuit_id, req).await,
+            RequestRouter::RollingV2(req) => state.get_snark(&circuit_id, req).await,
+            RequestRouter::Evm(req) => state.get_snark(&circuit_id, req).await,
+        }?;
+        Ok(ProverProof::Snark(ProverSnark { snark, circuit_id }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        env,
+        fs::{self, File},
+        path::{Path, PathBuf},
+    };
+
+    use ethers::{core::rand::thread_rng, signers::LocalWallet};
+    use serde::Deserialize;
+
+    use crate::{
+        keygen::node_params::{CircuitOptions, CircuitVersion, NodeParams, NodeType},
+        prover::ProverConfig,
+        scheduler::{
+            async_scheduler::AsyncScheduler,
+            contract_client::ContractClient,
+            recursive_request::RecursiveRequest,
+            task_tracker::SchedulerTaskTracker,
+            types::{FailedNode, RetryPolicy},
+            Scheduler,
+        },
+        types::ClaimNative,
+        vk_registry::{read_vk, VkRegistry, DEFAULT_VK_PATH},
+    };
+
+    use super::*;
+
+    const LEAF_CIRCUIT_ID: &str = "leaf";
+
+    #[derive(Deserialize)]
+    struct SampleRequest {
+        root: String,
+        claims: Vec<ClaimNative>,
+    }
+
+    fn leaf_params() -> NodeParams {
+        NodeParams::new(
+            CircuitVersion::V1,
+            1,
+            CircuitOptions::default(),
+            NodeType::Leaf,
+            1,
+            1,
+        )
+    }
+
+    /// A scheduler proving with a [LocalExecutor] on the circuit data of `dir`, which knows a
+    /// single leaf circuit. The contract client is never used.
+    fn local_scheduler(dir: &Path) -> AsyncScheduler {
+        let (_, keystore) =
+            LocalWallet::new_keystore(dir, &mut thread_rng(), "password", None).unwrap();
+        let contract_client = ContractClient::new(
+            dir.join(keystore).to_str().unwrap(),
+            "password",
+            "http://localhost:8545",
+            "0x0000000000000000000000000000000000000001",
+            1,
+            CircuitVersion::V1,
+        )
+        .unwrap();
+        let state = ProvingServerState::new(ProverConfig {
+            circuit_data_dir: dir.to_path_buf(),
+            srs_dir: dir.to_path_buf(),
+            out_dir: None,
+            proof_concurrency: 1,
+        });
+        let registry = VkRegistry::new(read_vk(Path::new(DEFAULT_VK_PATH)).unwrap()).unwrap();
+        AsyncScheduler::new(
+            HashMap::from([(leaf_params(), LEAF_CIRCUIT_ID.to_string())]),
+            HashMap::from([(LEAF_CIRCUIT_ID.to_string(), leaf_params())]),
+            Arc::new(LocalExecutor::new(Arc::new(state))),
+            SchedulerTaskTracker::new(),
+            dir.join("execution_summary.json"),
+            contract_client,
+            leaf_params(),
+            RetryPolicy {
+                max_retries: 1,
+                backoff_ms: 0,
+            },
+            registry,
+            None,
+        )
+    }
+
+    /// The leaf request of the sample claims
+    fn leaf_request(scheduler: &AsyncScheduler) -> RecursiveRequest {
+        let file = File::open("./data/generated_proofs_2.json").unwrap();
+        let SampleRequest { root, claims } = serde_json::from_reader(file).unwrap();
+        let vk_hash = scheduler.vk_registry().default_vk_hash();
+        RecursiveRequest::new(0, 2, root, claims, leaf_params(), vk_hash).unwrap()
+    }
+
+    fn temp_dir() -> PathBuf {
+        let dir = env::temp_dir().join(Uuid::new_v4().to_string());
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[rocket::async_test]
+    async fn test_scheduler_reports_missing_circuit_data() {
+        let dir = temp_dir();
+        let scheduler = local_scheduler(&dir);
+        let req = leaf_request(&scheduler);
+
+        let error = scheduler
+            .recursive_gen_proof("request", req, false)
+            .await
+            .unwrap_err();
+        // the executor error carries the failing node
+        let node = error.downcast_ref::<FailedNode>().unwrap();
+        assert_eq!(node.circuit_id.as_deref(), Some(LEAF_CIRCUIT_ID));
+        assert!(format!("{error:?}").contains("Failed to open"), "{error:?}");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[rocket::async_test]
+    async fn test_local_executor_rejects_evm_proofs_of_other_circuits() {
+        let dir = temp_dir();
+        let scheduler = local_scheduler(&dir);
+        let req = leaf_request(&scheduler);
+
+        let error = scheduler
+            .recursive_gen_proof("request", req, true)
+            .await
+            .unwrap_err();
+        let error = format!("{error:?}");
+        assert!(error.contains("which is not the final circuit"), "{error}");
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
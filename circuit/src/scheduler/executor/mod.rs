@@ -2,32 +2,64 @@ use async_trait::async_trait;
 
 use serde::{Deserialize, Serialize};
 
-use crate::prover::types::{ProverProof, ProverTask};
+use crate::{
+    prover::types::{ProverProof, ProverTask},
+    scheduler::types::current_timstamp_sec,
+};
 
 pub mod dispatcher;
+pub mod local;
 
 pub type TaskId = String;
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct ExecutionSummary {
-    task_id: TaskId,
-    circuit_id: String,
-    execution_started_at_sec: u64,
-    execution_finished_at_sec: u64,
+    pub task_id: TaskId,
+    pub circuit_id: String,
+    pub execution_started_at_sec: u64,
+    pub execution_finished_at_sec: u64,
+    /// Earlier attempts of this task which were abandoned and resubmitted
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub retries: Vec<TaskRetry>,
+}
+
+/// An abandoned attempt to execute a task
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct TaskRetry {
+    pub task_id: TaskId,
+    pub reason: String,
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct ExecutionResult {
     pub task_id: String,
     pub proof: ProverProof,
+    pub summary: ExecutionSummary,
 }
 
 #[async_trait]
 pub trait ProofExecutor: Send + Sync + 'static {
     async fn execute(&self, proof: ProverTask) -> anyhow::Result<ExecutionResult> {
-        let (task_id, proof) = self.execute_impl(proof).await?;
+        let circuit_id = proof.circuit_id.clone();
+        let execution_started_at_sec = current_timstamp_sec();
+        let (task_id, proof, retries) = self.execute_impl(proof).await?;
 
-        Ok(ExecutionResult { task_id, proof })
+        let summary = ExecutionSummary {
+            task_id: task_id.clone(),
+            circuit_id,
+            execution_started_at_sec,
+            execution_finished_at_sec: current_timstamp_sec(),
+            retries,
+        };
+        Ok(ExecutionResult {
+            task_id,
+            proof,
+            summary,
+        })
     }
-    async fn execute_impl(&self, proof: ProverTask) -> anyhow::Result<(TaskId, ProverProof)>;
+    /// Returns the id of the successful task, its proof and the abandoned attempts before it
+    async fn execute_impl(
+        &self,
+        proof: ProverTask,
+    ) -> anyhow::Result<(TaskId, ProverProof, Vec<TaskRetry>)>;
 }
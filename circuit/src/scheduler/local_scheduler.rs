@@ -9,41 +9,53 @@ use uuid::Uuid;
 use crate::{
     keygen::node_params::NodeParams,
     prover::{
-        types::{ProverProof, ProverSnark, ProverTask, TaskInput},
+        types::{ProverProof, ProverTask},
         ProvingServerState,
     },
-    scheduler::types::RequestRouter,
-    types::VkNative,
+    scheduler::types::current_timstamp_sec,
+    vk_registry::VkRegistry,
 };
 
 use async_trait::async_trait;
 
-use super::{executor::ExecutionResult, recursive_request::RecursiveRequest, Scheduler};
+use super::{
+    executor::{local::prove_task, ExecutionResult, ExecutionSummary},
+    recursive_request::RecursiveRequest,
+    Scheduler,
+};
 
 #[derive(Clone)]
 pub struct LocalScheduler {
-    /// Mutex just to force sequential proving
     pub state: Arc<ProvingServerState>,
     pub circuit_id_repo: Arc<RwLock<HashMap<NodeParams, String>>>,
-    pub vk: VkNative,
+    pub vk_registry: Arc<VkRegistry>,
 }
 
 impl LocalScheduler {
     pub fn new(
         circuit_id_repo: HashMap<NodeParams, String>,
         state: ProvingServerState,
-        vk: VkNative,
+        vk_registry: VkRegistry,
     ) -> Self {
         Self {
             circuit_id_repo: Arc::new(RwLock::new(circuit_id_repo)),
             state: Arc::new(state),
-            vk,
+            vk_registry: Arc::new(vk_registry),
         }
     }
 }
 
 #[async_trait]
 impl Scheduler for LocalScheduler {
+    fn vk_registry(&self) -> &VkRegistry {
+        &self.vk_registry
+    }
+
+    fn app_id(&self) -> Option<&str> {
+        // the local server only serves circuits which take the external nullifiers as inputs
+        None
+    }
+
     async fn get_circuit_id(&self, req: &RecursiveRequest) -> Result<String> {
         let circuit_id = self
             .circuit_id_repo
@@ -57,44 +69,25 @@ impl Scheduler for LocalScheduler {
     }
 
     async fn generate_proof(&self, task: ProverTask) -> Result<ExecutionResult> {
-        let ProverTask { circuit_id, input } = task;
-        let TaskInput {
-            is_evm_proof,
-            request,
-        } = input;
-        log::debug!("Router:{:?} CID", circuit_id);
+        log::debug!("Router:{:?} CID", task.circuit_id);
 
         let task_id = Uuid::new_v4().to_string();
-
-        if is_evm_proof {
-            let proof = match request {
-                RequestRouter::Leaf(_) => unreachable!(),
-                RequestRouter::Intermediate(_) => unreachable!(),
-                RequestRouter::Root(_) => unreachable!(),
-                RequestRouter::Evm(req) => self.state.get_evm_proof(&circuit_id, req).await,
-            }?;
-
-            let execution_result = ExecutionResult {
-                task_id,
-                proof: ProverProof::EvmProof(proof),
-            };
-
-            Ok(execution_result)
-        } else {
-            let snark = match request {
-                RequestRouter::Leaf(req) => self.state.get_snark(&circuit_id, req).await,
-                RequestRouter::Intermediate(req) => self.state.get_snark(&circuit_id, req).await,
-                RequestRouter::Root(req) => self.state.get_snark(&circuit_id, req).await,
-                RequestRouter::Evm(req) => self.state.get_snark(&circuit_id, req).await,
-            }?;
-
-            let execution_result = ExecutionResult {
-                task_id,
-                proof: ProverProof::Snark(ProverSnark { snark, circuit_id }),
-            };
-
-            Ok(execution_result)
-        }
+        let circuit_id = task.circuit_id.clone();
+        let execution_started_at_sec = current_timstamp_sec();
+
+        let proof = prove_task(&self.state, task).await?;
+
+        Ok(ExecutionResult {
+            summary: ExecutionSummary {
+                task_id: task_id.clone(),
+                circuit_id,
+                execution_started_at_sec,
+                execution_finished_at_sec: current_timstamp_sec(),
+                retries: vec![],
+            },
+            task_id,
+            proof,
+        })
     }
 
     async fn post_proof_gen_processing(&self, _: &str, _: &str, _: &ExecutionResult) -> Result<()> {
@@ -1,31 +1,44 @@
 use anyhow::{bail, Result};
 use axiom_eth::snark_verifier_sdk::Snark;
+use ethers::types::H256;
 use executor::ExecutionResult;
 use recursive_request::RecursiveRequest;
-use types::RequestRouter;
+use tokio::time::Duration;
+use types::{FailedNode, RequestRouter, RetryPolicy};
 
 use crate::{
     circuit_factory::{
-        evm::WorldcoinRequestEvm, intermediate::WorldcoinRequestIntermediate,
-        leaf::WorldcoinRequestLeaf, root::WorldcoinRequestRoot,
+        evm::WorldcoinRequestEvm,
+        intermediate::{WorldcoinRequestIntermediate, WorldcoinRequestIntermediateV2},
+        leaf::{WorldcoinRequestLeaf, WorldcoinRequestLeafV2},
+        root::{WorldcoinRequestRoot, WorldcoinRequestRootV2},
     },
-    constants::VK,
-    keygen::node_params::NodeType,
+    keygen::node_params::{CircuitOptions, CircuitVersion, NodeType},
     prover::types::{ProverProof, ProverTask, TaskInput},
     types::ClaimNative,
+    vk_registry::VkRegistry,
 };
 use async_trait::async_trait;
 
 pub mod async_scheduler;
+pub mod chain;
+pub mod claim_filter;
 pub mod contract_client;
 pub mod executor;
 pub mod local_scheduler;
 pub mod recursive_request;
+pub mod store;
 pub mod task_tracker;
 pub mod types;
 
 #[async_trait]
 pub trait Scheduler: Send + Sync + 'static {
+    /// The verifying keys the claims can be verified against
+    fn vk_registry(&self) -> &VkRegistry;
+
+    /// The World ID app id of the external nullifiers, for leaves deriving them in circuit
+    fn app_id(&self) -> Option<&str>;
+
     fn get_request_leaf(
         &self,
         start: u32,
@@ -33,18 +46,27 @@ pub trait Scheduler: Send + Sync + 'static {
         depth: usize,
         root: String,
         claims: Vec<ClaimNative>,
+        vk_hash: &H256,
     ) -> Result<WorldcoinRequestLeaf> {
         if end - start > (1 << depth) {
             bail!("start: {start}, end: {end}, cannot request more than 2^{depth} proofs");
         }
 
+        let mut vks = self
+            .vk_registry()
+            .vk_set(vk_hash)?
+            .into_iter()
+            .map(|vk| vk.as_ref().clone());
         Ok(WorldcoinRequestLeaf {
             start,
             end,
             depth,
             root,
             claims,
-            vk: VK.clone(),
+            vk: vks.next().unwrap(),
+            extra_vks: vks.collect(),
+            app_id: None,
+            options: CircuitOptions::default(),
         })
     }
 
@@ -63,42 +85,72 @@ pub trait Scheduler: Send + Sync + 'static {
             root,
             claims,
             params,
+            vk_hash,
         } = req;
 
         if params.depth == params.initial_depth {
-            let leaf = self.get_request_leaf(start, end, params.depth, root, claims)?;
-            Ok(RequestRouter::Leaf(leaf))
+            let app_id = match (params.options.external_nullifier, self.app_id()) {
+                (true, None) => bail!("The leaf derives the external nullifiers without an app id"),
+                (true, Some(app_id)) => Some(app_id.to_string()),
+                (false, _) => None,
+            };
+            let leaf = WorldcoinRequestLeaf {
+                app_id,
+                options: params.options,
+                ..self.get_request_leaf(start, end, params.depth, root, claims, &vk_hash)?
+            };
+            Ok(match params.version {
+                CircuitVersion::V1 => RequestRouter::Leaf(leaf),
+                CircuitVersion::V2 => RequestRouter::LeafV2(WorldcoinRequestLeafV2(leaf)),
+            })
         } else {
             assert!(!snarks.is_empty());
             Ok(match params.node_type {
                 NodeType::Leaf => unreachable!(),
                 NodeType::Intermediate => {
-                    assert!(snarks.len() <= 2, "dependencies snarks should be <= 2");
-                    if snarks.len() != 2 {
-                        snarks.resize(2, snarks[0].clone()); // dummy snark
-                    }
+                    let arity = params.arity.arity();
+                    assert!(
+                        snarks.len() <= arity,
+                        "dependencies snarks should be <= {arity}"
+                    );
+                    snarks.resize(arity, snarks[0].clone()); // dummy snarks
                     let req = WorldcoinRequestIntermediate {
                         start,
                         end,
                         snarks,
                         depth: params.depth,
                         initial_depth: params.initial_depth,
+                        options: params.options,
                     };
-                    RequestRouter::Intermediate(req)
+                    match params.version {
+                        CircuitVersion::V1 => RequestRouter::Intermediate(req),
+                        CircuitVersion::V2 => {
+                            RequestRouter::IntermediateV2(WorldcoinRequestIntermediateV2(req))
+                        }
+                    }
                 }
                 NodeType::Root => {
-                    assert!(snarks.len() <= 2, "dependencies snarks should be <= 2");
-                    if snarks.len() != 2 {
-                        snarks.resize(2, snarks[0].clone()); // dummy snark
-                    }
+                    let arity = params.arity.arity();
+                    assert!(
+                        snarks.len() <= arity,
+                        "dependencies snarks should be <= {arity}"
+                    );
+                    snarks.resize(arity, snarks[0].clone()); // dummy snarks
                     let req = WorldcoinRequestRoot {
                         start,
                         end,
                         snarks,
                         depth: params.depth,
                         initial_depth: params.initial_depth,
+                        options: params.options,
                     };
-                    RequestRouter::Root(req)
+                    match params.version {
+                        CircuitVersion::V1 => RequestRouter::Root(req),
+                        CircuitVersion::V2 => RequestRouter::RootV2(WorldcoinRequestRootV2(req)),
+                    }
+                }
+                NodeType::Rolling(_) => {
+                    bail!("rolling proofs chain separate requests, see `AsyncScheduler::append_batch`")
                 }
                 NodeType::Evm(round) => {
                     assert_eq!(snarks.len(), 1); // currently just passthrough
@@ -125,10 +177,35 @@ pub trait Scheduler: Send + Sync + 'static {
         req: RecursiveRequest,
         is_evm_proof: bool,
     ) -> Result<ProverProof> {
+        if let Some(proof) = self.get_stored_proof(request_id, &req).await? {
+            log::debug!(
+                "Reusing stored proof for {:?} [{}, {})",
+                req.params,
+                req.start,
+                req.end
+            );
+            return Ok(proof);
+        }
+
+        let mut node = FailedNode {
+            node_type: req.params.node_type,
+            depth: req.params.depth,
+            initial_depth: req.params.initial_depth,
+            start: req.start,
+            end: req.end,
+            circuit_id: None,
+        };
+
+        // errors of the dependencies already carry the failing node
         let req_router = self
             .handle_recursive_request(request_id, req.clone())
-            .await?;
-        let circuit_id = self.get_circuit_id(&req).await?;
+            .await
+            .map_err(|e| with_failed_node(e, &node))?;
+        let circuit_id = self
+            .get_circuit_id(&req)
+            .await
+            .map_err(|e| with_failed_node(e, &node))?;
+        node.circuit_id = Some(circuit_id.clone());
 
         log::debug!("Router:{:?} CID-{circuit_id}", req.params);
 
@@ -140,12 +217,63 @@ pub trait Scheduler: Send + Sync + 'static {
             },
         };
 
-        let result = self.generate_proof(task).await?;
+        let result = self.generate_proof_with_retries(task, &node).await?;
         self.post_proof_gen_processing(request_id, circuit_id.as_str(), &result)
             .await?;
+        self.store_proof(request_id, &req, &result.proof).await?;
         Ok(result.proof)
     }
 
+    /// Generate the proof of `node` following the [Self::retry_policy]. The error of the last
+    /// attempt carries the failing node.
+    async fn generate_proof_with_retries(
+        &self,
+        task: ProverTask,
+        node: &FailedNode,
+    ) -> Result<ExecutionResult> {
+        let RetryPolicy {
+            max_retries,
+            backoff_ms,
+        } = self.retry_policy();
+        let mut num_retries = 0;
+        loop {
+            match self.generate_proof(task.clone()).await {
+                Ok(result) => return Ok(result),
+                Err(e) if num_retries < max_retries => {
+                    num_retries += 1;
+                    log::warn!("{node} failed, retry {num_retries}/{max_retries}: {e:?}");
+                    tokio::time::sleep(Duration::from_millis(backoff_ms)).await;
+                }
+                Err(e) => return Err(with_failed_node(e, node)),
+            }
+        }
+    }
+
+    /// Retry policy for nodes whose proof generation failed. By default nodes are not retried.
+    fn retry_policy(&self) -> RetryPolicy {
+        RetryPolicy::default()
+    }
+
+    /// Proof for the given request which was generated earlier, e.g. before a restart of the
+    /// scheduler. By default nothing is stored.
+    async fn get_stored_proof(
+        &self,
+        _request_id: &str,
+        _req: &RecursiveRequest,
+    ) -> Result<Option<ProverProof>> {
+        Ok(None)
+    }
+
+    /// Persist the proof for the given request so it can be reused by [Self::get_stored_proof]
+    async fn store_proof(
+        &self,
+        _request_id: &str,
+        _req: &RecursiveRequest,
+        _proof: &ProverProof,
+    ) -> Result<()> {
+        Ok(())
+    }
+
     /// Find the circuit id which can handle the non-recursive part of this request
     async fn get_circuit_id(&self, req: &RecursiveRequest) -> Result<String>;
 
@@ -168,3 +296,12 @@ pub trait Scheduler: Send + Sync + 'static {
         req: &RecursiveRequest,
     ) -> Result<Vec<Snark>>;
 }
+
+/// Attach `node` as the failing node to `error`, unless a failing node is already attached
+fn with_failed_node(error: anyhow::Error, node: &FailedNode) -> anyhow::Error {
+    if error.downcast_ref::<FailedNode>().is_some() {
+        error
+    } else {
+        error.context(node.clone())
+    }
+}
@@ -1,6 +1,7 @@
 use std::cmp::min;
 
 use anyhow::{bail, Result};
+use ethers::types::H256;
 
 use crate::{keygen::node_params::NodeParams, types::ClaimNative};
 
@@ -11,6 +12,9 @@ pub struct RecursiveRequest {
     pub root: String,
     pub claims: Vec<ClaimNative>,
     pub params: NodeParams,
+    /// Hash of the verifying key of all claims, or the commitment to the vk set for mixed-vk
+    /// circuits
+    pub vk_hash: H256,
 }
 
 impl RecursiveRequest {
@@ -20,6 +24,7 @@ impl RecursiveRequest {
         root: String,
         claims: Vec<ClaimNative>,
         params: NodeParams,
+        vk_hash: H256,
     ) -> Result<Self> {
         if end <= start {
             bail!("end <= start")
@@ -33,12 +38,20 @@ impl RecursiveRequest {
         if params.depth < params.initial_depth {
             bail!("depth < initial_depth");
         }
+        // each batch of a rolling proof is a separate request, see `NodeType::Rolling`
+        if params.max_batches > 1 {
+            bail!(
+                "rolling aggregation of {} batches cannot be scheduled",
+                params.max_batches
+            );
+        }
         Ok(Self {
             start,
             end,
             root,
             claims,
             params,
+            vk_hash,
         })
     }
 
@@ -53,6 +66,7 @@ impl RecursiveRequest {
             root,
             claims,
             params,
+            vk_hash,
         } = self.clone();
         assert!(end - start <= 1 << params.depth);
         if params.depth == params.initial_depth {
@@ -73,6 +87,7 @@ impl RecursiveRequest {
                         claims: claims[(start_idx - start) as usize..(end_idx - start) as usize]
                             .to_vec(),
                         params: child_params,
+                        vk_hash,
                     }
                 })
                 .collect()
@@ -0,0 +1,371 @@
+use std::{
+    fs::{self, File},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+use ethers::types::H256;
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+    keygen::node_params::{NodeParams, NodeType},
+    native::claim_tree::ClaimTree,
+    prover::types::ProverProof,
+};
+
+use super::{
+    chain::ChainState,
+    contract_client::PendingTx,
+    executor::ExecutionSummary,
+    recursive_request::RecursiveRequest,
+    types::{SchedulerTaskRequest, SchedulerTaskStatusResponse},
+};
+
+/// State of a request as loaded from the [SchedulerStore]
+#[derive(Clone, Debug)]
+pub struct StoredRequestState {
+    pub request_id: String,
+    pub status: SchedulerTaskStatusResponse,
+    /// Executed tasks as `(execution_summary, node_params)`
+    pub tasks: Vec<(ExecutionSummary, NodeParams)>,
+    /// The transaction submitting the proof, if it was signed but not confirmed
+    pub pending_tx: Option<PendingTx>,
+}
+
+/// On-disk store for the scheduler state, so that requests survive a restart of the scheduler.
+///
+/// Each request gets its own directory under `<dir>/requests/<request_id>`:
+/// - `request.json` the original [SchedulerTaskRequest]
+/// - `vk_hash.json` the hash of the verifying key, or of the vk set, the request is proven with
+/// - `status.json` the latest [SchedulerTaskStatusResponse], including the final proof and the
+///   transaction hash once the proof is submitted on-chain
+/// - `tasks.json` the executed tasks as `(execution_summary, node_params)`
+/// - `claim_tree.json` the [ClaimTree] of a V2 request
+/// - `pending_tx.json` the [PendingTx] submitting the final proof, written before it is broadcast
+///   and removed if it was dropped
+/// - `proofs/<node>.json` the proof of every completed node of the aggregation tree
+///
+/// With rolling aggregation, `<dir>/chain.json` holds the [ChainState] of the batches which are
+/// not yet submitted. A chain taken for submission moves to `<dir>/chains/<request_id>.json`,
+/// named after its first request, until its submission ended, so it is submitted from its rolling
+/// proof after a restart.
+///
+/// Every file is written to a temporary path, synced to disk and renamed over the previous
+/// version, and the directory is synced after the rename. After a crash or a power loss, a file
+/// holds either its previous or its new content. Writes of different files are not atomic
+/// together, e.g. the status of a request may be more recent than its tasks.
+#[derive(Debug)]
+pub struct SchedulerStore {
+    dir: PathBuf,
+}
+
+impl SchedulerStore {
+    pub fn open(dir: &Path) -> Result<Self> {
+        let requests_dir = dir.join("requests");
+        fs::create_dir_all(&requests_dir)
+            .with_context(|| format!("Failed to create {}", requests_dir.display()))?;
+        Ok(Self {
+            dir: dir.to_path_buf(),
+        })
+    }
+
+    pub fn put_request(&self, request_id: &str, request: &SchedulerTaskRequest) -> Result<()> {
+        self.write_json(&self.request_dir(request_id)?.join("request.json"), request)
+    }
+
+    pub fn get_request(&self, request_id: &str) -> Result<Option<SchedulerTaskRequest>> {
+        self.read_json(&self.request_dir(request_id)?.join("request.json"))
+    }
+
+    pub fn put_vk_hash(&self, request_id: &str, vk_hash: &H256) -> Result<()> {
+        self.write_json(&self.request_dir(request_id)?.join("vk_hash.json"), vk_hash)
+    }
+
+    pub fn get_vk_hash(&self, request_id: &str) -> Result<Option<H256>> {
+        self.read_json(&self.request_dir(request_id)?.join("vk_hash.json"))
+    }
+
+    pub fn put_status(&self, request_id: &str, status: &SchedulerTaskStatusResponse) -> Result<()> {
+        self.write_json(&self.request_dir(request_id)?.join("status.json"), status)
+    }
+
+    pub fn put_tasks(
+        &self,
+        request_id: &str,
+        tasks: &Vec<(ExecutionSummary, NodeParams)>,
+    ) -> Result<()> {
+        self.write_json(&self.request_dir(request_id)?.join("tasks.json"), tasks)
+    }
+
+    pub fn put_claim_tree(&self, request_id: &str, tree: &ClaimTree) -> Result<()> {
+        self.write_json(&self.request_dir(request_id)?.join("claim_tree.json"), tree)
+    }
+
+    pub fn get_claim_tree(&self, request_id: &str) -> Result<Option<ClaimTree>> {
+        self.read_json(&self.request_dir(request_id)?.join("claim_tree.json"))
+    }
+
+    pub fn put_pending_tx(&self, request_id: &str, tx: &PendingTx) -> Result<()> {
+        self.write_json(&self.request_dir(request_id)?.join("pending_tx.json"), tx)
+    }
+
+    pub fn remove_pending_tx(&self, request_id: &str) -> Result<()> {
+        let path = self.request_dir(request_id)?.join("pending_tx.json");
+        if path.exists() {
+            fs::remove_file(&path)
+                .with_context(|| format!("Failed to remove {}", path.display()))?;
+            sync_dir(path.parent().unwrap())?;
+        }
+        Ok(())
+    }
+
+    pub fn put_proof(
+        &self,
+        request_id: &str,
+        req: &RecursiveRequest,
+        proof: &ProverProof,
+    ) -> Result<()> {
+        self.write_json(&self.proof_path(request_id, req)?, proof)
+    }
+
+    pub fn get_proof(
+        &self,
+        request_id: &str,
+        req: &RecursiveRequest,
+    ) -> Result<Option<ProverProof>> {
+        self.read_json(&self.proof_path(request_id, req)?)
+    }
+
+    pub fn put_chain(&self, chain: &ChainState) -> Result<()> {
+        self.write_json(&self.dir.join("chain.json"), chain)
+    }
+
+    pub fn get_chain(&self) -> Result<Option<ChainState>> {
+        self.read_json(&self.dir.join("chain.json"))
+    }
+
+    /// Store a chain taken for submission, keyed by its first request
+    pub fn put_submitting_chain(&self, request_id: &str, chain: &ChainState) -> Result<()> {
+        self.write_json(&self.submitting_chain_path(request_id)?, chain)
+    }
+
+    pub fn remove_submitting_chain(&self, request_id: &str) -> Result<()> {
+        let path = self.submitting_chain_path(request_id)?;
+        if path.exists() {
+            fs::remove_file(&path)
+                .with_context(|| format!("Failed to remove {}", path.display()))?;
+            sync_dir(path.parent().unwrap())?;
+        }
+        Ok(())
+    }
+
+    /// Load all chains taken for submission whose submission did not end
+    pub fn get_submitting_chains(&self) -> Result<Vec<ChainState>> {
+        let dir = self.dir.join("chains");
+        if !dir.exists() {
+            return Ok(vec![]);
+        }
+        let mut chains = vec![];
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            // skip temporary files of interrupted writes
+            if path.extension().is_some_and(|ext| ext == "json") {
+                chains.extend(self.read_json(&path)?);
+            }
+        }
+        Ok(chains)
+    }
+
+    /// Load the state of all stored requests
+    pub fn load_all(&self) -> Result<Vec<StoredRequestState>> {
+        let mut requests = vec![];
+        for entry in fs::read_dir(self.dir.join("requests"))? {
+            let entry = entry?;
+            if !entry.file_type()?.is_dir() {
+                continue;
+            }
+            let request_id = entry.file_name().to_string_lossy().to_string();
+            let status = self.read_json(&entry.path().join("status.json"))?;
+            let tasks = self.read_json(&entry.path().join("tasks.json"))?;
+            let pending_tx = self.read_json(&entry.path().join("pending_tx.json"))?;
+            // a request without status was never accepted
+            if let Some(status) = status {
+                requests.push(StoredRequestState {
+                    request_id,
+                    status,
+                    tasks: tasks.unwrap_or_default(),
+                    pending_tx,
+                });
+            }
+        }
+        Ok(requests)
+    }
+
+    /// The directory of `request_id`, see [check_request_id]
+    fn request_dir(&self, request_id: &str) -> Result<PathBuf> {
+        check_request_id(request_id)?;
+        Ok(self.dir.join("requests").join(request_id))
+    }
+
+    fn submitting_chain_path(&self, request_id: &str) -> Result<PathBuf> {
+        check_request_id(request_id)?;
+        Ok(self.dir.join("chains").join(format!("{request_id}.json")))
+    }
+
+    fn proof_path(&self, request_id: &str, req: &RecursiveRequest) -> Result<PathBuf> {
+        let NodeParams {
+            node_type,
+            depth,
+            initial_depth,
+            ..
+        } = req.params;
+        let node_type = match node_type {
+            NodeType::Leaf => "leaf".to_string(),
+            NodeType::Intermediate => "inter".to_string(),
+            NodeType::Root => "root".to_string(),
+            NodeType::Rolling(max_batches) => format!("rolling{max_batches}"),
+            NodeType::Evm(round) => format!("evm{round}"),
+        };
+        Ok(self.request_dir(request_id)?.join("proofs").join(format!(
+            "{:06x}_{:06x}_{}_{}_{}.json",
+            req.start, req.end, depth, initial_depth, node_type
+        )))
+    }
+
+    fn write_json<T: Serialize>(&self, path: &Path, value: &T) -> Result<()> {
+        let parent = path.parent().context("Store path without a parent")?;
+        create_dir(parent)?;
+        let tmp_path = path.with_extension("json.tmp");
+        let mut f = File::create(&tmp_path)
+            .with_context(|| format!("Failed to create {}", tmp_path.display()))?;
+        serde_json::to_writer(&mut f, value)?;
+        // the content is on disk before the rename makes it visible
+        f.sync_all()
+            .with_context(|| format!("Failed to sync {}", tmp_path.display()))?;
+        fs::rename(&tmp_path, path)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        sync_dir(parent)
+    }
+
+    fn read_json<T: DeserializeOwned>(&self, path: &Path) -> Result<Option<T>> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        let f = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+        let value = serde_json::from_reader(f)
+            .with_context(|| format!("Failed to parse {}", path.display()))?;
+        Ok(Some(value))
+    }
+}
+
+/// Fails unless `request_id` is a single path component, so that its files are inside the store
+fn check_request_id(request_id: &str) -> Result<()> {
+    if request_id.is_empty()
+        || request_id == "."
+        || request_id == ".."
+        || request_id.contains(['/', '\\', '\0'])
+    {
+        bail!("Invalid request id {request_id:?}");
+    }
+    Ok(())
+}
+
+/// Creates `dir` and its missing parents, syncing the parent of every created directory so that
+/// the new entries survive a crash
+fn create_dir(dir: &Path) -> Result<()> {
+    if dir.exists() {
+        return Ok(());
+    }
+    if let Some(parent) = dir.parent() {
+        create_dir(parent)?;
+    }
+    fs::create_dir(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    match dir.parent() {
+        Some(parent) => sync_dir(parent),
+        None => Ok(()),
+    }
+}
+
+/// Syncs the entries of `dir`, e.g. after a rename into it
+fn sync_dir(dir: &Path) -> Result<()> {
+    File::open(dir)
+        .and_then(|dir| dir.sync_all())
+        .with_context(|| format!("Failed to sync {}", dir.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use uuid::Uuid;
+
+    use crate::scheduler::chain::ChainedBatch;
+
+    use super::*;
+
+    fn request() -> SchedulerTaskRequest {
+        SchedulerTaskRequest {
+            root: "1".to_string(),
+            claims: vec![],
+            exclude_invalid_claims: false,
+        }
+    }
+
+    #[test]
+    fn test_requests_round_trip_without_temporary_files() {
+        let dir = env::temp_dir().join(Uuid::new_v4().to_string());
+        let store = SchedulerStore::open(&dir).unwrap();
+        store.put_request("request", &request()).unwrap();
+        store.put_request("request", &request()).unwrap();
+
+        let stored = store.get_request("request").unwrap().unwrap();
+        assert_eq!(stored.root, "1");
+        assert!(store.get_request("other").unwrap().is_none());
+        let files = fs::read_dir(dir.join("requests").join("request"))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect::<Vec<_>>();
+        assert_eq!(files, ["request.json"]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_request_ids_stay_inside_the_store() {
+        let dir = env::temp_dir().join(Uuid::new_v4().to_string());
+        let store = SchedulerStore::open(&dir).unwrap();
+        for request_id in ["", ".", "..", "../request", "a/b", "a\\b"] {
+            assert!(
+                store.put_request(request_id, &request()).is_err(),
+                "{request_id:?}"
+            );
+            assert!(store.get_request(request_id).is_err(), "{request_id:?}");
+        }
+        assert!(!dir.join("request").exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_submitting_chains_round_trip() {
+        let dir = env::temp_dir().join(Uuid::new_v4().to_string());
+        let store = SchedulerStore::open(&dir).unwrap();
+        assert!(store.get_submitting_chains().unwrap().is_empty());
+
+        let chain = ChainState {
+            batches: vec![ChainedBatch {
+                request_id: "request".to_string(),
+                root: "1".to_string(),
+                claims: vec![],
+            }],
+            ..Default::default()
+        };
+        store.put_submitting_chain("request", &chain).unwrap();
+        let chains = store.get_submitting_chains().unwrap();
+        assert_eq!(chains.len(), 1);
+        assert_eq!(chains[0].request_ids(), ["request"]);
+
+        store.remove_submitting_chain("request").unwrap();
+        assert!(store.get_submitting_chains().unwrap().is_empty());
+        assert!(store.put_submitting_chain("../chain", &chain).is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,35 +1,356 @@
-use std::collections::HashMap;
+use ethers::types::H256;
+use std::{collections::HashMap, sync::Arc};
 use tokio::sync::Mutex;
 
-use crate::keygen::node_params::NodeParams;
+use crate::{
+    keygen::node_params::NodeParams, native::claim_tree::ClaimTree, prover::types::ProverProof,
+};
+
+use super::{
+    chain::ChainState,
+    contract_client::PendingTx,
+    executor::ExecutionSummary,
+    recursive_request::RecursiveRequest,
+    store::{SchedulerStore, StoredRequestState},
+    types::{
+        current_timstamp_sec, SchedulerTaskFailure, SchedulerTaskRequest, SchedulerTaskStatus,
+        SchedulerTaskStatusResponse, SchedulerTaskSummary,
+    },
+};
 
 #[derive(Debug)]
 pub struct SchedulerTaskTracker {
     // record all task information for the request
     // map request_id -> Vec<task>
-    // task -> (task_id, node_params)
-    pub request_id_to_tasks: Mutex<HashMap<String, Vec<(String, NodeParams)>>>,
+    // task -> (execution_summary, node_params)
+    pub request_id_to_tasks: Mutex<HashMap<String, Vec<(ExecutionSummary, NodeParams)>>>,
+    // record the status of each request
+    // map request_id -> status
+    pub request_id_to_status: Mutex<HashMap<String, SchedulerTaskStatusResponse>>,
+    // the claim tree of each V2 request, loaded from the store on first access after a restart
+    // map request_id -> claim_tree
+    pub request_id_to_claim_tree: Mutex<HashMap<String, Arc<ClaimTree>>>,
+    // the signed transaction submitting the proof of each request, until it is confirmed
+    // map request_id -> pending_tx
+    pub request_id_to_pending_tx: Mutex<HashMap<String, PendingTx>>,
+    // the batches chained since the last submission, with rolling aggregation
+    pub chain: Mutex<ChainState>,
+    // if set, all updates are written through to disk
+    pub store: Option<SchedulerStore>,
 }
 
 impl SchedulerTaskTracker {
     pub fn new() -> Self {
         Self {
             request_id_to_tasks: Default::default(),
+            request_id_to_status: Default::default(),
+            request_id_to_claim_tree: Default::default(),
+            request_id_to_pending_tx: Default::default(),
+            chain: Default::default(),
+            store: None,
+        }
+    }
+
+    /// Create a tracker backed by `store`, restoring the state of all requests found in it
+    pub fn with_store(store: SchedulerStore) -> anyhow::Result<Self> {
+        let mut request_id_to_tasks = HashMap::new();
+        let mut request_id_to_status = HashMap::new();
+        let mut request_id_to_pending_tx = HashMap::new();
+        for StoredRequestState {
+            request_id,
+            status,
+            tasks,
+            pending_tx,
+        } in store.load_all()?
+        {
+            if let Some(pending_tx) = pending_tx {
+                request_id_to_pending_tx.insert(request_id.clone(), pending_tx);
+            }
+            request_id_to_tasks.insert(request_id.clone(), tasks);
+            request_id_to_status.insert(request_id, status);
+        }
+        let mut chain = store.get_chain()?.unwrap_or_default();
+        // the chain was taken for submission, but the scheduler stopped before it was reset
+        let chained = chain.request_ids();
+        let submitting = store.get_submitting_chains()?;
+        if submitting
+            .iter()
+            .flat_map(|taken| taken.request_ids())
+            .any(|request_id| chained.contains(&request_id))
+        {
+            chain = ChainState::default();
         }
+        Ok(Self {
+            request_id_to_tasks: Mutex::new(request_id_to_tasks),
+            request_id_to_status: Mutex::new(request_id_to_status),
+            request_id_to_claim_tree: Default::default(),
+            request_id_to_pending_tx: Mutex::new(request_id_to_pending_tx),
+            chain: Mutex::new(chain),
+            store: Some(store),
+        })
     }
 
     pub async fn record_task(
         &self,
         request_id: &str,
-        task_id: &str,
+        summary: &ExecutionSummary,
         params: &NodeParams,
     ) -> anyhow::Result<()> {
-        let request_id_to_task_ids = self.request_id_to_tasks.lock();
-        request_id_to_task_ids
-            .await
+        let mut request_id_to_task_ids = self.request_id_to_tasks.lock().await;
+        let tasks = request_id_to_task_ids
             .entry(request_id.to_string())
-            .or_insert_with(Vec::new)
-            .push((task_id.to_string(), params.clone()));
+            .or_insert_with(Vec::new);
+        tasks.push((summary.clone(), params.clone()));
+        if let Some(store) = &self.store {
+            store.put_tasks(request_id, tasks)?;
+        }
         Ok(())
     }
+
+    /// Start tracking a new request in `PENDING` status, proven with the verifying key, or the vk
+    /// set, of `vk_hash`
+    pub async fn create_request(
+        &self,
+        request_id: &str,
+        request: &SchedulerTaskRequest,
+        vk_hash: &H256,
+    ) -> anyhow::Result<()> {
+        let status = SchedulerTaskStatusResponse::default();
+        if let Some(store) = &self.store {
+            store.put_request(request_id, request)?;
+            store.put_vk_hash(request_id, vk_hash)?;
+            store.put_status(request_id, &status)?;
+        }
+        self.request_id_to_status
+            .lock()
+            .await
+            .insert(request_id.to_string(), status);
+        Ok(())
+    }
+
+    pub async fn record_claim_tree(&self, request_id: &str, tree: ClaimTree) -> anyhow::Result<()> {
+        if let Some(store) = &self.store {
+            store.put_claim_tree(request_id, &tree)?;
+        }
+        self.request_id_to_claim_tree
+            .lock()
+            .await
+            .insert(request_id.to_string(), Arc::new(tree));
+        Ok(())
+    }
+
+    pub async fn get_claim_tree(&self, request_id: &str) -> anyhow::Result<Option<Arc<ClaimTree>>> {
+        let mut request_id_to_claim_tree = self.request_id_to_claim_tree.lock().await;
+        if let Some(tree) = request_id_to_claim_tree.get(request_id) {
+            return Ok(Some(Arc::clone(tree)));
+        }
+        let tree = match &self.store {
+            Some(store) => store.get_claim_tree(request_id)?.map(Arc::new),
+            None => None,
+        };
+        if let Some(tree) = &tree {
+            request_id_to_claim_tree.insert(request_id.to_string(), Arc::clone(tree));
+        }
+        Ok(tree)
+    }
+
+    /// Requests which have not reached a terminal status, together with their original input and
+    /// their vk hash, which is missing for requests stored by an earlier version of the scheduler.
+    /// Only available when the tracker is backed by a store.
+    pub async fn unfinished_requests(
+        &self,
+    ) -> anyhow::Result<Vec<(String, SchedulerTaskRequest, Option<H256>)>> {
+        let store = match &self.store {
+            Some(store) => store,
+            None => return Ok(vec![]),
+        };
+        let request_id_to_status = self.request_id_to_status.lock().await;
+        let mut requests = vec![];
+        for (request_id, resp) in request_id_to_status.iter() {
+            if matches!(
+                resp.status,
+                SchedulerTaskStatus::Done | SchedulerTaskStatus::Failed
+            ) {
+                continue;
+            }
+            match store.get_request(request_id)? {
+                Some(request) => {
+                    let vk_hash = store.get_vk_hash(request_id)?;
+                    requests.push((request_id.clone(), request, vk_hash));
+                }
+                None => log::warn!("Input of request {request_id} not found, cannot resume"),
+            }
+        }
+        Ok(requests)
+    }
+
+    /// Proof of a node of the aggregation tree which was completed earlier, e.g. before a restart
+    pub fn get_stored_proof(
+        &self,
+        request_id: &str,
+        req: &RecursiveRequest,
+    ) -> anyhow::Result<Option<ProverProof>> {
+        match &self.store {
+            Some(store) => store.get_proof(request_id, req),
+            None => Ok(None),
+        }
+    }
+
+    pub fn store_proof(
+        &self,
+        request_id: &str,
+        req: &RecursiveRequest,
+        proof: &ProverProof,
+    ) -> anyhow::Result<()> {
+        match &self.store {
+            Some(store) => store.put_proof(request_id, req, proof),
+            None => Ok(()),
+        }
+    }
+
+    /// Persist the chain, which the caller holds locked
+    pub fn store_chain(&self, chain: &ChainState) -> anyhow::Result<()> {
+        match &self.store {
+            Some(store) => store.put_chain(chain),
+            None => Ok(()),
+        }
+    }
+
+    /// Persist a chain taken for submission before it is proven, so that it is submitted again
+    /// after a restart instead of being lost
+    pub fn store_submitting_chain(&self, chain: &ChainState) -> anyhow::Result<()> {
+        match (&self.store, chain.batches.first()) {
+            (Some(store), Some(batch)) => store.put_submitting_chain(&batch.request_id, chain),
+            _ => Ok(()),
+        }
+    }
+
+    /// Forget a chain taken for submission once its submission ended
+    pub fn clear_submitting_chain(&self, chain: &ChainState) -> anyhow::Result<()> {
+        match (&self.store, chain.batches.first()) {
+            (Some(store), Some(batch)) => store.remove_submitting_chain(&batch.request_id),
+            _ => Ok(()),
+        }
+    }
+
+    /// Chains taken for submission whose submission did not end before a restart
+    pub fn submitting_chains(&self) -> anyhow::Result<Vec<ChainState>> {
+        match &self.store {
+            Some(store) => store.get_submitting_chains(),
+            None => Ok(vec![]),
+        }
+    }
+
+    pub async fn update_status(&self, request_id: &str, status: SchedulerTaskStatus) {
+        self.update(request_id, |resp| resp.status = status).await;
+    }
+
+    /// Record the final proof of the request. The request stays `RUNNING` until the proof is
+    /// submitted on-chain.
+    pub async fn record_proof(&self, request_id: &str, proof: ProverProof) {
+        self.update(request_id, |resp| resp.snark = Some(proof))
+            .await;
+    }
+
+    /// Record the signed transaction submitting the proof of the request. It must be recorded
+    /// before it is broadcast, so a restarted scheduler waits for it instead of submitting the
+    /// proof again.
+    pub async fn record_pending_tx(&self, request_id: &str, tx: &PendingTx) -> anyhow::Result<()> {
+        if let Some(store) = &self.store {
+            store.put_pending_tx(request_id, tx)?;
+        }
+        self.request_id_to_pending_tx
+            .lock()
+            .await
+            .insert(request_id.to_string(), tx.clone());
+        Ok(())
+    }
+
+    pub async fn get_pending_tx(&self, request_id: &str) -> Option<PendingTx> {
+        self.request_id_to_pending_tx
+            .lock()
+            .await
+            .get(request_id)
+            .cloned()
+    }
+
+    /// Forget the pending transaction of the request once it was dropped, so the proof can be
+    /// submitted again
+    pub async fn clear_pending_tx(&self, request_id: &str) -> anyhow::Result<()> {
+        if let Some(store) = &self.store {
+            store.remove_pending_tx(request_id)?;
+        }
+        self.request_id_to_pending_tx
+            .lock()
+            .await
+            .remove(request_id);
+        Ok(())
+    }
+
+    /// Mark the request as `DONE` once the proof is accepted on-chain
+    pub async fn record_tx_hash(&self, request_id: &str, tx_hash: String) {
+        self.update(request_id, |resp| {
+            resp.status = SchedulerTaskStatus::Done;
+            resp.tx_hash = Some(tx_hash);
+        })
+        .await;
+    }
+
+    /// Mark the request as `FAILED`, recording the failing node and the error chain
+    pub async fn record_failure(&self, request_id: &str, error: &anyhow::Error) {
+        let failure = SchedulerTaskFailure::from(error);
+        self.update(request_id, |resp| {
+            resp.status = SchedulerTaskStatus::Failed;
+            resp.error = Some(error.to_string());
+            resp.failure = Some(failure);
+        })
+        .await;
+    }
+
+    /// Mark the request as `FAILED` with the given error message
+    pub async fn record_error(&self, request_id: &str, error: String) {
+        self.update(request_id, |resp| {
+            resp.status = SchedulerTaskStatus::Failed;
+            resp.error = Some(error);
+        })
+        .await;
+    }
+
+    pub async fn get_status(&self, request_id: &str) -> Option<SchedulerTaskStatusResponse> {
+        self.request_id_to_status
+            .lock()
+            .await
+            .get(request_id)
+            .cloned()
+    }
+
+    /// Summaries of all tracked requests, most recently created first
+    pub async fn list_statuses(&self) -> Vec<SchedulerTaskSummary> {
+        let request_id_to_status = self.request_id_to_status.lock().await;
+        let mut summaries: Vec<SchedulerTaskSummary> = request_id_to_status
+            .iter()
+            .map(|(request_id, resp)| SchedulerTaskSummary {
+                request_id: request_id.clone(),
+                status: resp.status,
+                created_at_sec: resp.created_at_sec,
+                updated_at_sec: resp.updated_at_sec,
+            })
+            .collect();
+        summaries.sort_by(|a, b| b.created_at_sec.cmp(&a.created_at_sec));
+        summaries
+    }
+
+    async fn update<U: FnOnce(&mut SchedulerTaskStatusResponse)>(&self, request_id: &str, f: U) {
+        let mut request_id_to_status = self.request_id_to_status.lock().await;
+        let resp = request_id_to_status
+            .entry(request_id.to_string())
+            .or_insert_with(SchedulerTaskStatusResponse::default);
+        f(resp);
+        resp.updated_at_sec = current_timstamp_sec();
+        if let Some(store) = &self.store {
+            if let Err(e) = store.put_status(request_id, resp) {
+                log::error!("Failed to persist status of request {request_id}: {e:?}");
+            }
+        }
+    }
 }
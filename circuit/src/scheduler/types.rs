@@ -1,24 +1,37 @@
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::{
+    fmt::Display,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
+use ethers::types::{Address, H256};
 use serde::{Deserialize, Serialize};
 
 use crate::{
     circuit_factory::{
         evm::WorldcoinRequestEvm,
-        {
-            intermediate::WorldcoinRequestIntermediate, leaf::WorldcoinRequestLeaf,
-            root::WorldcoinRequestRoot,
-        },
+        intermediate::{WorldcoinRequestIntermediate, WorldcoinRequestIntermediateV2},
+        leaf::{WorldcoinRequestLeaf, WorldcoinRequestLeafV2},
+        rolling::{WorldcoinRequestRolling, WorldcoinRequestRollingV2},
+        root::{WorldcoinRequestRoot, WorldcoinRequestRootV2},
     },
+    keygen::node_params::NodeType,
     prover::types::ProverProof,
     types::ClaimNative,
 };
 
+/// Request for a single node of the aggregation tree. The variant determines the circuit, the
+/// `V2` variants use the V2 circuits. The evm passthrough is shared by both versions.
+/// Rolling requests chain the roots of separate aggregation trees.
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum RequestRouter {
     Leaf(WorldcoinRequestLeaf),
     Intermediate(WorldcoinRequestIntermediate),
     Root(WorldcoinRequestRoot),
+    LeafV2(WorldcoinRequestLeafV2),
+    IntermediateV2(WorldcoinRequestIntermediateV2),
+    RootV2(WorldcoinRequestRootV2),
+    Rolling(WorldcoinRequestRolling),
+    RollingV2(WorldcoinRequestRollingV2),
     Evm(WorldcoinRequestEvm),
 }
 
@@ -27,12 +40,52 @@ pub struct SchedulerTaskRequest {
     pub root: String,
     // the claims vector has [start, end) claims
     pub claims: Vec<ClaimNative>,
+    /// Drop invalid claims and duplicate nullifiers instead of rejecting the request
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub exclude_invalid_claims: bool,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct SchedulerTaskResponse {
+    /// Deprecated: the request of the first verifying key, kept for clients of requests with a
+    /// single key. Use [Self::requests] instead.
     pub request_id: String,
+    /// Claims are aggregated in one tree per verifying key. Each tree is a separate request which
+    /// is tracked and submitted on-chain on its own.
+    pub requests: Vec<VkRequest>,
+    /// Claims dropped from the request, only with `exclude_invalid_claims`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub excluded_claims: Vec<ExcludedClaim>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct VkRequest {
+    pub request_id: String,
+    pub vk_hash: H256,
+    pub num_claims: usize,
+}
+
+/// Why a claim was dropped from a request
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum ExclusionReason {
+    /// The claim failed native verification
+    InvalidClaim { error: String },
+    /// The nullifier hash is already used by the claim at `first_index`
+    #[serde(rename_all = "camelCase")]
+    DuplicateNullifier { first_index: usize },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ExcludedClaim {
+    /// Index of the claim in the submitted request
+    pub index: usize,
+    pub nullifier_hash: String,
+    pub receiver: Address,
+    pub reason: ExclusionReason,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -43,6 +96,12 @@ pub struct SchedulerTaskStatusResponse {
     pub snark: Option<ProverProof>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub error: Option<String>,
+    /// The node of the aggregation tree which caused the request to fail
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub failure: Option<SchedulerTaskFailure>,
+    /// Hash of the transaction submitting the final proof on-chain
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tx_hash: Option<String>,
     pub created_at_sec: u64,
     pub updated_at_sec: u64,
 }
@@ -55,12 +114,80 @@ impl Default for SchedulerTaskStatusResponse {
             snark: None,
             // execution_summary: None,
             error: None,
+            failure: None,
+            tx_hash: None,
             created_at_sec: now,
             updated_at_sec: now,
         }
     }
 }
 
+/// Brief status of a request, used when listing all requests known to the scheduler
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SchedulerTaskSummary {
+    pub request_id: String,
+    pub status: SchedulerTaskStatus,
+    pub created_at_sec: u64,
+    pub updated_at_sec: u64,
+}
+
+/// A node of the aggregation tree, attached as context to the error of a failed node
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct FailedNode {
+    pub node_type: NodeType,
+    pub depth: usize,
+    pub initial_depth: usize,
+    pub start: u32,
+    pub end: u32,
+    /// Not set if the failure happened before the circuit id was resolved
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub circuit_id: Option<String>,
+}
+
+impl Display for FailedNode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Node {:?} depth {} [{}, {}) circuit id {}",
+            self.node_type,
+            self.depth,
+            self.start,
+            self.end,
+            self.circuit_id.as_deref().unwrap_or("unknown")
+        )
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SchedulerTaskFailure {
+    /// The failing node, if the failure happened while proving a node of the aggregation tree
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub node: Option<FailedNode>,
+    /// The error chain, outermost first
+    pub errors: Vec<String>,
+}
+
+impl From<&anyhow::Error> for SchedulerTaskFailure {
+    fn from(error: &anyhow::Error) -> Self {
+        Self {
+            node: error.downcast_ref::<FailedNode>().cloned(),
+            errors: error.chain().map(|e| e.to_string()).collect(),
+        }
+    }
+}
+
+/// How often a failed node of the aggregation tree is retried before the whole request fails
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default)]
+pub struct RetryPolicy {
+    /// The maximum number of retries per node. 0 means no retry.
+    pub max_retries: usize,
+    /// Time to wait before each retry
+    pub backoff_ms: u64,
+}
+
 pub fn current_timstamp_sec() -> u64 {
     SystemTime::now()
         .duration_since(UNIX_EPOCH)
@@ -20,7 +20,7 @@ use axiom_components::groth16::get_groth16_consts_from_max_pi;
 
 use axiom_eth::halo2curves::bn256::Fr;
 
-use ethers::abi::Address;
+use ethers::{abi::Address, types::H256};
 use itertools::Itertools;
 use serde::Serialize;
 use serde_json::json;
@@ -63,7 +63,8 @@ impl<'r, 'o: 'r> Responder<'r, 'o> for Error {
         } else {
             response.status(rocket::http::Status::InternalServerError);
         }
-        let msg = self.0.to_string();
+        // include the cause, e.g. which claims are invalid
+        let msg = format!("{:#}", self.0);
         response
             .header(rocket::http::ContentType::Plain)
             .sized_body(msg.len(), Cursor::new(msg))
@@ -83,6 +84,28 @@ pub struct ClaimNative {
     pub nullifier_hash: String,
     pub grant_id: String,
     pub proof: Vec<String>,
+    /// Hash of the verifying key of the proof, see [crate::vk_registry::VkRegistry]. The default
+    /// key is used if not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vk_hash: Option<H256>,
+    /// The World ID root the proof is against, for circuits with per-claim roots. The root of
+    /// the request is used if not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub root: Option<String>,
+    /// The grant amount of the claim, for V2 circuits which commit to claim amounts
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub amount: Option<String>,
+    /// The Groth16 public inputs of the proof which are not claim fields, for circuits with a
+    /// public input layout other than Semaphore's, see [crate::public_inputs::PublicInputLayout]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub extra_public_inputs: Vec<String>,
+}
+
+impl ClaimNative {
+    /// The root of the claim, or `default_root` if it has none
+    pub fn root_or<'a>(&'a self, default_root: &'a str) -> &'a str {
+        self.root.as_deref().unwrap_or(default_root)
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
@@ -91,6 +114,15 @@ pub struct ClaimInput<T: Copy> {
     pub nullifier_hash: T,
     pub grant_id: T,
     pub proof_bytes: Vec<T>,
+    /// Index of the verifying key of the claim in the vk set of the leaf
+    pub vk_index: T,
+    /// The World ID root of the claim. Equal to the shared root unless the leaf has per-claim
+    /// roots.
+    pub root: T,
+    /// The grant amount of the claim, zero unless the leaf commits to claim amounts
+    pub amount: T,
+    /// The public inputs of the proof which are not claim fields
+    pub extra_public_inputs: Vec<T>,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -99,14 +131,22 @@ pub struct VkNative {
     vk_beta_2: [[String; 2]; 3],
     vk_gamma_2: [[String; 2]; 3],
     vk_delta_2: [[String; 2]; 3],
-    IC: [[String; 3]; 5],
+    IC: Vec<[String; 3]>,
+}
+
+impl VkNative {
+    /// Number of public inputs of the proofs of this vk
+    pub fn num_public_inputs(&self) -> usize {
+        self.IC.len().saturating_sub(1)
+    }
 }
 
 // https://optimistic.etherscan.io/tx/0x857068d4fbc4434b11e49bcbeb3663ba2b3b89770a5d20203bf206ff0645f104
 // https://optimistic.etherscan.io/tx/0xe5ae2511577a857b34efa8a1795f47b875d2885b1b8855775c4d409ae52a9a2b
 // NOTE: ethers Address is case in-sensitive and the checksummed address string
 // will be parsed into lowercase. So the signal_hash is always from lowercase
-// address, make sure the proof public input is also from lowercased address
+// address, make sure the proof public input is also from lowercased address.
+// Leaves with `hash_signal` instead take the keccak of the address bytes, which is case-insensitive
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct WorldcoinNativeInput {
     pub vk: VkNative,
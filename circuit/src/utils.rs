@@ -29,10 +29,14 @@ use crate::constants::*;
 
 use axiom_eth::{
     keccak::KeccakChip,
-    utils::{hilo::HiLo, uint_to_bytes_be},
+    utils::{circuit_utils::unsafe_lt_mask, hilo::HiLo, uint_to_bytes_be},
 };
 
-use axiom_eth::halo2_base::{gates::RangeChip, AssignedValue, Context};
+use axiom_eth::halo2_base::{
+    gates::{GateInstructions, RangeChip, RangeInstructions},
+    AssignedValue, Context,
+    QuantumCell::Constant,
+};
 
 #[derive(Clone, Default, Debug, Serialize, Deserialize)]
 pub struct RlcKeccakCircuitPinning {
@@ -129,12 +133,236 @@ pub fn get_vk_hash<F: Field>(
         })
         .flatten()
         .collect();
-    assert_eq!(vk_bytes.len(), (NUM_BYTES_VK - 1) * 2);
 
     let vk_hash = keccak.keccak_fixed_len(ctx, vk_bytes);
     HiLo::from_hi_lo([vk_hash.output_hi, vk_hash.output_lo])
 }
 
+/// Commitment to the vk set of a leaf. A single vk is committed to by its vk hash, so single-vk
+/// circuits are unchanged. Otherwise it is the keccak of the concatenated vk hashes.
+pub fn get_vk_set_commitment<F: Field>(
+    ctx: &mut Context<F>,
+    range: &RangeChip<F>,
+    keccak: &KeccakChip<F>,
+    vk_set: &[Vec<AssignedValue<F>>],
+) -> HiLo<AssignedValue<F>> {
+    assert!(!vk_set.is_empty());
+    if vk_set.len() == 1 {
+        return get_vk_hash(ctx, range, keccak, vk_set[0].clone());
+    }
+    let bytes = vk_set
+        .iter()
+        .flat_map(|vk_bytes| {
+            let vk_hash = get_vk_hash(ctx, range, keccak, vk_bytes.clone());
+            vk_hash
+                .hi_lo()
+                .into_iter()
+                .flat_map(|x| uint_to_bytes_be(ctx, range, &x, 16))
+                .map(|sb| *sb.as_ref())
+                .collect::<Vec<_>>()
+        })
+        .collect();
+    let commitment = keccak.keccak_fixed_len(ctx, bytes);
+    HiLo::from_hi_lo([commitment.output_hi, commitment.output_lo])
+}
+
+/// The flattened vk at `vk_index` in the vk set. Constrains `vk_index < vk_set.len()`.
+pub fn select_vk<F: Field>(
+    ctx: &mut Context<F>,
+    range: &RangeChip<F>,
+    vk_set: &[Vec<AssignedValue<F>>],
+    vk_index: AssignedValue<F>,
+) -> Vec<AssignedValue<F>> {
+    assert!(!vk_set.is_empty());
+    if vk_set.len() == 1 {
+        return vk_set[0].clone();
+    }
+    range.check_less_than_safe(ctx, vk_index, vk_set.len() as u64);
+    (0..vk_set[0].len())
+        .map(|i| {
+            let values = vk_set.iter().map(|vk_bytes| vk_bytes[i]);
+            range.gate().select_from_idx(ctx, values, vk_index)
+        })
+        .collect()
+}
+
+/// Constrains the receiver of a claim to be a non-zero address, and its nullifier hash to be
+/// non-zero
+pub fn constrain_claim_fields<F: Field>(
+    ctx: &mut Context<F>,
+    range: &RangeChip<F>,
+    receiver: AssignedValue<F>,
+    nullifier_hash: AssignedValue<F>,
+) {
+    let gate = range.gate();
+    range.range_check(ctx, receiver, 160);
+    for value in [receiver, nullifier_hash] {
+        let is_zero = gate.is_zero(ctx, value);
+        gate.assert_is_const(ctx, &is_zero, &F::ZERO);
+    }
+}
+
+/// World ID's `hashToField`, `uint256(keccak256(bytes)) >> 8`
+pub fn hash_to_field<F: Field>(
+    ctx: &mut Context<F>,
+    range: &RangeChip<F>,
+    keccak: &KeccakChip<F>,
+    bytes: Vec<AssignedValue<F>>,
+) -> AssignedValue<F> {
+    let hash = keccak.keccak_fixed_len(ctx, bytes);
+    // drop the last byte of the hash: (hi * 2^128 + lo) >> 8 = hi * 2^120 + lo >> 8
+    let (lo_shifted, _) = range.div_mod(ctx, hash.output_lo, 256u64, 128);
+    range.gate().mul_add(
+        ctx,
+        hash.output_hi,
+        Constant(F::from_u128(1 << 120)),
+        lo_shifted,
+    )
+}
+
+/// The World ID signal hash of an address, `hashToField(abi.encodePacked(receiver))`.
+/// Constrains `receiver` to be 20 bytes.
+pub fn get_signal_hash<F: Field>(
+    ctx: &mut Context<F>,
+    range: &RangeChip<F>,
+    keccak: &KeccakChip<F>,
+    receiver: AssignedValue<F>,
+) -> AssignedValue<F> {
+    let bytes = uint_to_bytes_be(ctx, range, &receiver, 20)
+        .iter()
+        .map(|sb| *sb.as_ref())
+        .collect();
+    hash_to_field(ctx, range, keccak, bytes)
+}
+
+/// The 32 bytes of `hashToField(abi.encodePacked(app_id))`, which prefix the actions of the app
+/// in its external nullifier hashes
+pub fn get_app_id_hash_bytes<F: Field>(
+    ctx: &mut Context<F>,
+    range: &RangeChip<F>,
+    keccak: &KeccakChip<F>,
+    app_id: Vec<AssignedValue<F>>,
+) -> Vec<AssignedValue<F>> {
+    let app_id_hash = hash_to_field(ctx, range, keccak, app_id);
+    uint_to_bytes_be(ctx, range, &app_id_hash, 32)
+        .iter()
+        .map(|sb| *sb.as_ref())
+        .collect()
+}
+
+/// The World ID external nullifier hash with the grant id as the action,
+/// `hashToField(abi.encodePacked(app_id_hash, grant_id))`, see [get_app_id_hash_bytes]. The
+/// action is the `uint256` grant id, so the exposed grant id is the action the proof is for.
+pub fn get_external_nullifier_hash<F: Field>(
+    ctx: &mut Context<F>,
+    range: &RangeChip<F>,
+    keccak: &KeccakChip<F>,
+    app_id_hash_bytes: &[AssignedValue<F>],
+    grant_id: AssignedValue<F>,
+) -> AssignedValue<F> {
+    let mut bytes = app_id_hash_bytes.to_vec();
+    bytes.extend(
+        uint_to_bytes_be(ctx, range, &grant_id, 32)
+            .iter()
+            .map(|sb| *sb.as_ref()),
+    );
+    hash_to_field(ctx, range, keccak, bytes)
+}
+
+/// Splits a field element into its big-endian 128 bit limbs.
+fn fe_to_hi_lo<F: Field>(fe: &F) -> [u128; 2] {
+    let limbs = fe.to_u64_limbs(4, 64);
+    [
+        limbs[2] as u128 | (limbs[3] as u128) << 64,
+        limbs[0] as u128 | (limbs[1] as u128) << 64,
+    ]
+}
+
+/// Decomposes `a` into `[hi, lo]` with `a = hi * 2^128 + lo`. The decomposition is constrained
+/// to be canonical, i.e. `hi * 2^128 + lo` is less than the modulus as an integer, so comparing
+/// the limbs compares the field elements as integers.
+pub fn decompose_canonical_hi_lo<F: Field>(
+    ctx: &mut Context<F>,
+    range: &RangeChip<F>,
+    a: AssignedValue<F>,
+) -> HiLo<AssignedValue<F>> {
+    let gate = range.gate();
+    let [hi, lo] = fe_to_hi_lo(a.value()).map(|limb| ctx.load_witness(F::from_u128(limb)));
+    range.range_check(ctx, hi, 128);
+    range.range_check(ctx, lo, 128);
+    let recomposed = gate.mul_add(ctx, hi, Constant(gate.pow_of_two()[128]), lo);
+    ctx.constrain_equal(&recomposed, &a);
+
+    // hi * 2^128 + lo <= modulus - 1
+    let [max_hi, max_lo] = fe_to_hi_lo(&-F::ONE);
+    let hi_lt = range.is_less_than(ctx, hi, Constant(F::from_u128(max_hi)), 128);
+    let hi_eq = gate.is_equal(ctx, hi, Constant(F::from_u128(max_hi)));
+    let lo_le = range.is_less_than(ctx, lo, Constant(F::from_u128(max_lo) + F::ONE), 129);
+    let is_canonical = gate.or_and(ctx, hi_lt, hi_eq, lo_le);
+    gate.assert_is_const(ctx, &is_canonical, &F::ONE);
+
+    HiLo::from_hi_lo([hi, lo])
+}
+
+/// Returns whether `a < b` as integers, given their canonical decompositions.
+pub fn is_less_than_hi_lo<F: Field>(
+    ctx: &mut Context<F>,
+    range: &RangeChip<F>,
+    a: &HiLo<AssignedValue<F>>,
+    b: &HiLo<AssignedValue<F>>,
+) -> AssignedValue<F> {
+    let gate = range.gate();
+    let hi_lt = range.is_less_than(ctx, a.hi(), b.hi(), 128);
+    let hi_eq = gate.is_equal(ctx, a.hi(), b.hi());
+    let lo_lt = range.is_less_than(ctx, a.lo(), b.lo(), 128);
+    gate.or_and(ctx, hi_lt, hi_eq, lo_lt)
+}
+
+/// Constrains the first `num_proofs` of `nullifier_hashes` to be strictly increasing, which makes
+/// them pairwise distinct. Assumes `0 < num_proofs <= nullifier_hashes.len()`.
+///
+/// Returns the first and last of them, for aggregation circuits to check the ordering across
+/// batches.
+pub fn constrain_sorted_nullifiers<F: Field>(
+    ctx: &mut Context<F>,
+    range: &RangeChip<F>,
+    nullifier_hashes: &[AssignedValue<F>],
+    num_proofs: AssignedValue<F>,
+) -> [AssignedValue<F>; 2] {
+    let gate = range.gate();
+    let mask = unsafe_lt_mask(ctx, gate, num_proofs, nullifier_hashes.len());
+    let hi_los = nullifier_hashes
+        .iter()
+        .map(|nullifier_hash| decompose_canonical_hi_lo(ctx, range, *nullifier_hash))
+        .collect::<Vec<_>>();
+    for (i, pair) in hi_los.windows(2).enumerate() {
+        let is_sorted = is_less_than_hi_lo(ctx, range, &pair[0], &pair[1]);
+        // padding claims past num_proofs are not checked
+        let is_unsorted = gate.mul_not(ctx, is_sorted, *mask[i + 1].as_ref());
+        gate.assert_is_const(ctx, &is_unsorted, &F::ZERO);
+    }
+
+    let last_idx = gate.sub(ctx, num_proofs, Constant(F::ONE));
+    let last = gate.select_from_idx(ctx, nullifier_hashes.iter().copied(), last_idx);
+    [nullifier_hashes[0], last]
+}
+
+/// Constrains the last nullifier hash of the left batch to be less than the first nullifier hash
+/// of the right batch, unless the right batch is a dummy.
+pub fn constrain_nullifier_boundary<F: Field>(
+    ctx: &mut Context<F>,
+    range: &RangeChip<F>,
+    left_last: AssignedValue<F>,
+    right_first: AssignedValue<F>,
+    is_2nd_proof_dummy: AssignedValue<F>,
+) {
+    let left_last = decompose_canonical_hi_lo(ctx, range, left_last);
+    let right_first = decompose_canonical_hi_lo(ctx, range, right_first);
+    let is_sorted = is_less_than_hi_lo(ctx, range, &left_last, &right_first);
+    let is_sorted = range.gate().or(ctx, is_sorted, is_2nd_proof_dummy);
+    range.gate().assert_is_const(ctx, &is_sorted, &F::ONE);
+}
+
 // construct a merkle tree from leaves
 // return vec is [root, ...[depth 1 nodes], ...[depth 2 nodes], ..., ...[leaves]]
 pub fn compute_keccak_merkle_tree<F: Field>(
@@ -0,0 +1,175 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use ethers::types::H256;
+
+use crate::{
+    native::instances::{get_vk_hash_native, get_vk_set_commitment_native},
+    types::VkNative,
+};
+
+/// The verifying key used when no other key is configured
+pub const DEFAULT_VK_PATH: &str = "./data/vk.json";
+
+pub fn read_vk(path: &Path) -> Result<VkNative> {
+    let json = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read vk {}", path.display()))?;
+    serde_json::from_str(&json).with_context(|| format!("Failed to parse vk {}", path.display()))
+}
+
+/// The Groth16 verifying keys accepted by a deployment, keyed by the vk hash computed like
+/// [crate::utils::get_vk_hash]. Claims which don't specify a vk hash use the default key.
+///
+/// All keys, in the order they were added, form the vk set of mixed-vk leaves.
+#[derive(Clone, Debug)]
+pub struct VkRegistry {
+    vks: HashMap<H256, Arc<VkNative>>,
+    vk_hashes: Vec<H256>,
+    default_vk_hash: H256,
+}
+
+impl VkRegistry {
+    pub fn new(default_vk: VkNative) -> Result<Self> {
+        let default_vk_hash = get_vk_hash_native(&default_vk)?;
+        Ok(Self {
+            vks: HashMap::from([(default_vk_hash, Arc::new(default_vk))]),
+            vk_hashes: vec![default_vk_hash],
+            default_vk_hash,
+        })
+    }
+
+    /// Registry of the keys in `paths`. The first key is the default.
+    pub fn from_paths(paths: &[PathBuf]) -> Result<Self> {
+        let (default_path, paths) = paths
+            .split_first()
+            .ok_or_else(|| anyhow!("At least one vk is required"))?;
+        let mut registry = Self::new(read_vk(default_path)?)?;
+        for path in paths {
+            registry.insert(read_vk(path)?)?;
+        }
+        Ok(registry)
+    }
+
+    /// Add `vk` to the registry, returning its hash
+    pub fn insert(&mut self, vk: VkNative) -> Result<H256> {
+        let vk_hash = get_vk_hash_native(&vk)?;
+        if self.vks.insert(vk_hash, Arc::new(vk)).is_some() {
+            bail!("Duplicate vk {vk_hash:?}");
+        }
+        self.vk_hashes.push(vk_hash);
+        Ok(vk_hash)
+    }
+
+    pub fn default_vk_hash(&self) -> H256 {
+        self.default_vk_hash
+    }
+
+    pub fn get(&self, vk_hash: &H256) -> Result<Arc<VkNative>> {
+        self.vks
+            .get(vk_hash)
+            .cloned()
+            .ok_or_else(|| anyhow!("Unknown vk {vk_hash:?}"))
+    }
+
+    /// Commitment to the vk set of all keys, the default key first
+    pub fn vk_set_hash(&self) -> H256 {
+        get_vk_set_commitment_native(&self.vk_hashes)
+    }
+
+    /// The keys committed to by `vk_hash`: either a single key, or the vk set of all keys
+    pub fn vk_set(&self, vk_hash: &H256) -> Result<Vec<Arc<VkNative>>> {
+        if self.vks.contains_key(vk_hash) {
+            return Ok(vec![self.get(vk_hash)?]);
+        }
+        if *vk_hash != self.vk_set_hash() {
+            bail!("Unknown vk {vk_hash:?}");
+        }
+        self.vk_hashes.iter().map(|h| self.get(h)).collect()
+    }
+
+    /// The hash of the key to use for a claim with the given vk hash
+    pub fn resolve(&self, vk_hash: Option<H256>) -> Result<H256> {
+        match vk_hash {
+            Some(vk_hash) if !self.vks.contains_key(&vk_hash) => bail!("Unknown vk {vk_hash:?}"),
+            Some(vk_hash) => Ok(vk_hash),
+            None => Ok(self.default_vk_hash),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.vks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vks.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn default_vk() -> VkNative {
+        read_vk(Path::new(DEFAULT_VK_PATH)).unwrap()
+    }
+
+    /// The default vk with an extra public input
+    fn other_vk() -> VkNative {
+        let mut vk = serde_json::to_value(default_vk()).unwrap();
+        let ic0 = vk["IC"][0].clone();
+        vk["IC"].as_array_mut().unwrap().push(ic0);
+        serde_json::from_value(vk).unwrap()
+    }
+
+    #[test]
+    fn test_claims_without_vk_hash_use_the_default_key() {
+        let registry = VkRegistry::new(default_vk()).unwrap();
+        let vk_hash = get_vk_hash_native(&default_vk()).unwrap();
+        assert_eq!(registry.default_vk_hash(), vk_hash);
+        assert_eq!(registry.resolve(None).unwrap(), vk_hash);
+        assert_eq!(registry.resolve(Some(vk_hash)).unwrap(), vk_hash);
+        assert!(registry.resolve(Some(H256::zero())).is_err());
+        assert!(registry.get(&H256::zero()).is_err());
+    }
+
+    #[test]
+    fn test_vk_set_holds_all_keys_in_order() {
+        let mut registry = VkRegistry::new(default_vk()).unwrap();
+        assert_eq!(registry.vk_set_hash(), registry.default_vk_hash());
+        let other_hash = registry.insert(other_vk()).unwrap();
+        assert_eq!(registry.len(), 2);
+
+        let vk_hashes = [registry.default_vk_hash(), other_hash];
+        assert_eq!(
+            registry.vk_set_hash(),
+            get_vk_set_commitment_native(&vk_hashes)
+        );
+        let vk_set = registry.vk_set(&registry.vk_set_hash()).unwrap();
+        let num_public_inputs = vk_set
+            .iter()
+            .map(|vk| vk.num_public_inputs())
+            .collect::<Vec<_>>();
+        assert_eq!(num_public_inputs, [4, 5]);
+        assert_eq!(registry.vk_set(&other_hash).unwrap().len(), 1);
+        assert!(registry.vk_set(&H256::zero()).is_err());
+    }
+
+    #[test]
+    fn test_insert_rejects_duplicate_keys() {
+        let mut registry = VkRegistry::new(default_vk()).unwrap();
+        assert!(registry.insert(default_vk()).is_err());
+        assert_eq!(registry.len(), 1);
+    }
+
+    #[test]
+    fn test_from_paths_requires_a_key() {
+        assert!(VkRegistry::from_paths(&[]).is_err());
+        let registry = VkRegistry::from_paths(&[PathBuf::from(DEFAULT_VK_PATH)]).unwrap();
+        assert_eq!(registry.len(), 1);
+    }
+}
@@ -23,6 +23,21 @@ contract WorldcoinAggregationV1 {
     /// @dev The offset of the lower 128 bits of the output hash in the proof
     uint256 public constant OUTPUT_HASH_LO_OFFSET = 13 * 32;
 
+    /// @dev The minimum length of a valid rolling SNARK proof over several
+    /// batches. The first 15 words here encode the public inputs.
+    uint256 public constant MINIMUM_ROLLING_SNARK_LENGTH = 15 * 32;
+
+    /// @dev The offset of the number of batches in a rolling proof
+    uint256 public constant NUM_BATCHES_OFFSET = 12 * 32;
+
+    /// @dev The offset of the upper 128 bits of the chain hash in a rolling
+    /// proof
+    uint256 public constant CHAIN_HASH_HI_OFFSET = 13 * 32;
+
+    /// @dev The offset of the lower 128 bits of the chain hash in a rolling
+    /// proof
+    uint256 public constant CHAIN_HASH_LO_OFFSET = 14 * 32;
+
     /// @dev The verification key hash of the Groth16 circuit.
     bytes32 public immutable VKEY_HASH;
 
@@ -104,6 +119,8 @@ contract WorldcoinAggregationV1 {
     }
 
     /// @notice Distribute grants to the receivers
+    /// @dev The claim arrays have the length of the circuit's output, and the
+    /// entries past `numClaims` are zero padding.
     /// @param vkeyHash The verification key hash of the Groth16 circuit.
     /// @param numClaims The number of claims to distribute
     /// @param root The World ID root
@@ -119,30 +136,12 @@ contract WorldcoinAggregationV1 {
         uint256[] calldata _nullifierHashes,
         bytes calldata proof
     ) external onlyProver {
-        uint256 grantAmount;
         {
             if (receivers.length != _nullifierHashes.length) revert InvalidProof();
-            if (numClaims != receivers.length) revert InvalidProof();
-            if (numClaims != grantIds.length) revert InvalidProof();
+            if (grantIds.length != receivers.length) revert InvalidProof();
+            if (numClaims > receivers.length) revert InvalidProof();
 
-            // Proof must have minimum 14 words.
-            // We expect the proof to be structured as such:
-            //
-            // proof[0..12 * 32]: reserved for proof verification data used with the
-            // pairing precompile
-            //
-            // proof[12 * 32..13 * 32]: outputHash Hi
-            // proof[13 * 32..14 * 32]: outputHash Lo
-            //
-            // if (proof.length < 14 * 32) revert InvalidProof();
-            //
-            // proof[14 * 32..]: Proof used in SNARK verification
-
-            if (proof.length < MINIMUM_SNARK_LENGTH) revert InvalidProof();
-
-            // No need to clean the upper bits on `vkeyLow`, `outputHash` or SNARK
-            // verification would fail.
-            if (vkeyHash != VKEY_HASH) revert InvalidVkeyHash();
+            _checkProofAndVkeyHash(vkeyHash, proof);
 
             ROOT_VALIDATOR.requireValidRoot(root);
 
@@ -160,16 +159,243 @@ contract WorldcoinAggregationV1 {
                 )
             );
 
-            bytes32 outputHash = _unsafeCalldataBytesAccess(proof, OUTPUT_HASH_HI_OFFSET) << 128
-                | _unsafeCalldataBytesAccess(proof, OUTPUT_HASH_LO_OFFSET);
+            _verifySnark(derivedOutputHash, proof);
+        }
+
+        _distribute(numClaims, grantIds, receivers, _nullifierHashes);
+    }
 
-            if (outputHash != derivedOutputHash) revert InvalidProof();
+    /// @notice Distribute grants to the receivers of claims which are each
+    /// proven against their own World ID root. The circuit exposes a zero
+    /// shared root and appends the roots to its output.
+    /// @dev The claim arrays have the length of the circuit's output, and the
+    /// entries past `numClaims` are zero padding.
+    /// @param vkeyHash The verification key hash of the Groth16 circuit.
+    /// @param numClaims The number of claims to distribute
+    /// @param roots The World ID root of each claim
+    /// @param grantIds The grant IDs
+    /// @param receivers The receivers of the grants
+    /// @param _nullifierHashes The nullifier hashes
+    function distributeGrantsWithRoots(
+        bytes32 vkeyHash,
+        uint256 numClaims,
+        uint256[] calldata roots,
+        uint256[] calldata grantIds,
+        address[] calldata receivers,
+        uint256[] calldata _nullifierHashes,
+        bytes calldata proof
+    ) external onlyProver {
+        {
+            if (receivers.length != _nullifierHashes.length) revert InvalidProof();
+            if (grantIds.length != receivers.length) revert InvalidProof();
+            if (numClaims > receivers.length) revert InvalidProof();
+            if (roots.length != receivers.length) revert InvalidProof();
+
+            _checkProofAndVkeyHash(vkeyHash, proof);
+
+            _requireValidRoots(numClaims, roots);
+
+            if (numClaims > MAX_NUM_CLAIMS) revert TooManyClaims();
+
+            bytes32 derivedOutputHash = keccak256(
+                abi.encodePacked(
+                    vkeyHash >> 128,
+                    vkeyHash & bytes32(0x00000000000000000000000000000000FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF),
+                    uint256(0),
+                    numClaims,
+                    grantIds,
+                    receivers,
+                    _nullifierHashes,
+                    roots
+                )
+            );
+
+            _verifySnark(derivedOutputHash, proof);
         }
 
+        _distribute(numClaims, grantIds, receivers, _nullifierHashes);
+    }
+
+    /// @notice Distribute grants to the receivers of several batches proven by
+    /// one rolling proof. The output hash of each batch is derived as in
+    /// `distributeGrants` and appended to a chain hash starting at zero.
+    /// @dev The claim arrays are the concatenated claim arrays of the batches,
+    /// each with the length of the circuit's output.
+    /// @param vkeyHash The verification key hash of the Groth16 circuit.
+    /// @param numClaims The number of claims of each batch
+    /// @param roots The World ID root of each batch
+    /// @param grantIds The grant IDs
+    /// @param receivers The receivers of the grants
+    /// @param _nullifierHashes The nullifier hashes
+    function distributeGrantsChained(
+        bytes32 vkeyHash,
+        uint256[] calldata numClaims,
+        uint256[] calldata roots,
+        uint256[] calldata grantIds,
+        address[] calldata receivers,
+        uint256[] calldata _nullifierHashes,
+        bytes calldata proof
+    ) external onlyProver {
+        uint256 numBatches = numClaims.length;
+        if (roots.length != numBatches) revert InvalidProof();
+        if (receivers.length != _nullifierHashes.length) revert InvalidProof();
+        if (grantIds.length != receivers.length) revert InvalidProof();
+        if (numBatches == 0 || receivers.length % numBatches != 0) revert InvalidProof();
+
+        // Proof must have minimum 15 words.
+        // We expect the proof to be structured as such:
+        //
+        // proof[0..12 * 32]: reserved for proof verification data used with the
+        // pairing precompile
+        //
+        // proof[12 * 32..13 * 32]: numBatches
+        // proof[13 * 32..14 * 32]: chainHash Hi
+        // proof[14 * 32..15 * 32]: chainHash Lo
+        //
+        // proof[15 * 32..]: Proof used in SNARK verification
+
+        if (proof.length < MINIMUM_ROLLING_SNARK_LENGTH) revert InvalidProof();
+        if (vkeyHash != VKEY_HASH) revert InvalidVkeyHash();
+        if (uint256(_unsafeCalldataBytesAccess(proof, NUM_BATCHES_OFFSET)) != numBatches) revert InvalidProof();
+
+        uint256 batchSize = receivers.length / numBatches;
+        bytes32 chainHash;
+        for (uint256 i = 0; i != numBatches;) {
+            uint256 start = i * batchSize;
+            uint256 end = start + batchSize;
+            bytes32 outputHash = _batchOutputHash(
+                vkeyHash,
+                numClaims[i],
+                roots[i],
+                grantIds[start:end],
+                receivers[start:end],
+                _nullifierHashes[start:end]
+            );
+            chainHash = keccak256(abi.encodePacked(chainHash, outputHash));
+
+            // forgefmt: disable-next-line
+            unchecked { ++i; }
+        }
+
+        bytes32 provenChainHash = _unsafeCalldataBytesAccess(proof, CHAIN_HASH_HI_OFFSET) << 128
+            | _unsafeCalldataBytesAccess(proof, CHAIN_HASH_LO_OFFSET);
+        if (chainHash != provenChainHash) revert InvalidProof();
+
+        // Verify SNARK
+        (bool success,) = VERIFIER_ADDRESS.call(proof);
+        if (!success) revert InvalidProof();
+
+        for (uint256 i = 0; i != numBatches;) {
+            uint256 start = i * batchSize;
+            uint256 end = start + batchSize;
+            _distribute(numClaims[i], grantIds[start:end], receivers[start:end], _nullifierHashes[start:end]);
+
+            // forgefmt: disable-next-line
+            unchecked { ++i; }
+        }
+    }
+
+    /// @dev Validate a batch of a rolling proof and derive its output hash
+    /// @param vkeyHash The verification key hash of the Groth16 circuit.
+    /// @param numClaims The number of claims of the batch
+    /// @param root The World ID root of the batch
+    /// @param grantIds The grant IDs of the batch
+    /// @param receivers The receivers of the batch
+    /// @param _nullifierHashes The nullifier hashes of the batch
+    /// @return The output hash of the batch's root proof
+    function _batchOutputHash(
+        bytes32 vkeyHash,
+        uint256 numClaims,
+        uint256 root,
+        uint256[] calldata grantIds,
+        address[] calldata receivers,
+        uint256[] calldata _nullifierHashes
+    ) internal view returns (bytes32) {
+        if (numClaims > receivers.length) revert InvalidProof();
+        if (numClaims > MAX_NUM_CLAIMS) revert TooManyClaims();
+
+        ROOT_VALIDATOR.requireValidRoot(root);
+
+        return keccak256(
+            abi.encodePacked(
+                vkeyHash >> 128,
+                vkeyHash & bytes32(0x00000000000000000000000000000000FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF),
+                root,
+                numClaims,
+                grantIds,
+                receivers,
+                _nullifierHashes
+            )
+        );
+    }
+
+    /// @dev Check the proof length and that the vkey hash matches the
+    /// contract's
+    /// @param vkeyHash The verification key hash of the Groth16 circuit.
+    /// @param proof The SNARK proof
+    function _checkProofAndVkeyHash(bytes32 vkeyHash, bytes calldata proof) internal view {
+        // Proof must have minimum 14 words.
+        // We expect the proof to be structured as such:
+        //
+        // proof[0..12 * 32]: reserved for proof verification data used with the
+        // pairing precompile
+        //
+        // proof[12 * 32..13 * 32]: outputHash Hi
+        // proof[13 * 32..14 * 32]: outputHash Lo
+        //
+        // if (proof.length < 14 * 32) revert InvalidProof();
+        //
+        // proof[14 * 32..]: Proof used in SNARK verification
+
+        if (proof.length < MINIMUM_SNARK_LENGTH) revert InvalidProof();
+
+        // No need to clean the upper bits on `vkeyLow`, `outputHash` or SNARK
+        // verification would fail.
+        if (vkeyHash != VKEY_HASH) revert InvalidVkeyHash();
+    }
+
+    /// @dev Validate every distinct root of the claims. Consecutive equal
+    /// roots are only validated once, so batches grouped by root are cheaper.
+    /// @param numClaims The number of claims, the roots past it are padding
+    /// @param roots The World ID roots
+    function _requireValidRoots(uint256 numClaims, uint256[] calldata roots) internal view {
+        for (uint256 i = 0; i != numClaims;) {
+            uint256 root = uint256(_unsafeCalldataArrayAccess(roots, i));
+            if (i == 0 || root != uint256(_unsafeCalldataArrayAccess(roots, i - 1))) {
+                ROOT_VALIDATOR.requireValidRoot(root);
+            }
+
+            // forgefmt: disable-next-line
+            unchecked { ++i; }
+        }
+    }
+
+    /// @dev Check the output hash of the proof and verify the SNARK
+    /// @param derivedOutputHash The output hash derived from the calldata
+    /// @param proof The SNARK proof
+    function _verifySnark(bytes32 derivedOutputHash, bytes calldata proof) internal {
+        bytes32 outputHash = _unsafeCalldataBytesAccess(proof, OUTPUT_HASH_HI_OFFSET) << 128
+            | _unsafeCalldataBytesAccess(proof, OUTPUT_HASH_LO_OFFSET);
+
+        if (outputHash != derivedOutputHash) revert InvalidProof();
+
         // Verify SNARK
         (bool success,) = VERIFIER_ADDRESS.call(proof);
         if (!success) revert InvalidProof();
+    }
 
+    /// @dev Transfer the grants of verified claims
+    /// @param numClaims The number of claims to distribute
+    /// @param grantIds The grant IDs
+    /// @param receivers The receivers of the grants
+    /// @param _nullifierHashes The nullifier hashes
+    function _distribute(
+        uint256 numClaims,
+        uint256[] calldata grantIds,
+        address[] calldata receivers,
+        uint256[] calldata _nullifierHashes
+    ) internal {
+        uint256 grantAmount;
         uint256[] calldata _receivers = _toUint256Array(receivers);
         for (uint256 i = 0; i != numClaims;) {
             uint256 grantId = uint256(_unsafeCalldataArrayAccess(grantIds, i));
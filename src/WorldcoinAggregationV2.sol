@@ -18,6 +18,19 @@ contract WorldcoinAggregationV2 {
     uint256 public constant CLAIMS_ROOT_HI_OFFSET = 16 * 32;
     uint256 public constant CLAIMS_ROOT_LO_OFFSET = 17 * 32;
 
+    /// @dev Offsets of the public outputs of a rolling proof over several
+    /// batches
+    uint256 public constant ROLLING_NUM_BATCHES_OFFSET = 12 * 32;
+    uint256 public constant ROLLING_VKEY_HASH_HI_OFFSET = 13 * 32;
+    uint256 public constant ROLLING_VKEY_HASH_LO_OFFSET = 14 * 32;
+    uint256 public constant ROLLING_NUM_CLAIMS_OFFSET = 15 * 32;
+    uint256 public constant ROLLING_CHAIN_HASH_HI_OFFSET = 16 * 32;
+    uint256 public constant ROLLING_CHAIN_HASH_LO_OFFSET = 17 * 32;
+
+    /// @dev First byte of the leaves with an amount, which separates them from
+    /// the leaves with a root of the same length
+    bytes1 public constant CLAIM_AMOUNT_LEAF_PREFIX = 0x01;
+
     /// @dev The verification key hash of the Groth16 circuit.
     bytes32 immutable VKEY_HASH;
 
@@ -67,6 +80,9 @@ contract WorldcoinAggregationV2 {
     /// @dev Receiver cannot be the zero address
     error InvalidReceiver();
 
+    /// @dev The amount of a claim cannot exceed the amount of its grant
+    error InvalidAmount();
+
     /// @dev Merkle proofs length must match `LOG_MAX_NUM_CLAIMS`
     error InvalidMerkleProofLength();
 
@@ -79,6 +95,12 @@ contract WorldcoinAggregationV2 {
     /// @dev The verification key of the query must match the contract's
     error InvalidVkeyHash();
 
+    /// @dev There must be one World ID root and one claims root per batch
+    error InvalidNumberOfBatches();
+
+    /// @dev The batches do not hash to the chain hash of the proof
+    error InvalidChainHash();
+
     modifier onlyProver() {
         if (PROVER != address(0) && msg.sender != PROVER) revert OnlyProver();
         _;
@@ -145,35 +167,108 @@ contract WorldcoinAggregationV2 {
         if (receiver == address(0)) revert InvalidReceiver();
         GRANT.checkValidity(grantId);
 
-        uint256 length = sisterNodes.length;
-        if (length != LOG_MAX_NUM_CLAIMS) revert InvalidMerkleProofLength();
+        bytes32 leaf = keccak256(abi.encodePacked(grantId, receiver, nullifierHash));
+        bytes32 claimsRoot = _computeClaimsRoot(leaf, sisterNodes, isLeftBytes);
 
-        bytes32 runningHash = keccak256(abi.encodePacked(grantId, receiver, nullifierHash));
-        for (uint256 i = 0; i != length;) {
-            // Unsafe access OK here since we know i is bounded by the length
-            bytes32 node = _unsafeCalldataArrayAccess(sisterNodes, i);
+        if (!validClaimsRoots[root][claimsRoot]) revert InvalidMerkleProof();
 
-            // Access the byte without an overflow check (safe because length is
-            // bounded by logMaxNumClaims which is bounded by 32 in the
-            // constructor). Then coerce to bool.
-            bool isLeft = _toBool(_unsafeByteAccess(isLeftBytes, i));
+        _transferGrant(grantId, receiver, nullifierHash);
+    }
 
-            if (isLeft) runningHash = _efficientHash(node, runningHash);
-            else runningHash = _efficientHash(runningHash, node);
+    /// @notice Claim a grant from a claims root with per-claim World ID
+    /// roots. Such claims roots are validated with a zero root, and `root` is
+    /// part of the leaf and validated here instead.
+    /// @param grantId The grant ID to claim
+    /// @param root The World ID root the claim is proven against
+    /// @param receiver The address that will receive the tokens
+    /// @param nullifierHash The nullifier for the proof
+    /// @param sisterNodes The Merkle proof of the claim
+    /// @param isLeftBytes The isLeft bytes of the Merkle proof, see `claim`
+    function claimWithRoot(
+        uint256 grantId,
+        uint256 root,
+        address receiver,
+        uint256 nullifierHash,
+        bytes32[] calldata sisterNodes,
+        bytes32 isLeftBytes
+    ) external {
+        if (nullifierHashes[nullifierHash]) revert NullifierHashAlreadyUsed();
+        if (receiver == address(0)) revert InvalidReceiver();
+        GRANT.checkValidity(grantId);
+        ROOT_VALIDATOR.requireValidRoot(root);
 
-            // forgefmt: disable-next-line
-            unchecked { ++i; }
-        }
+        bytes32 leaf = keccak256(abi.encodePacked(grantId, receiver, nullifierHash, root));
+        bytes32 claimsRoot = _computeClaimsRoot(leaf, sisterNodes, isLeftBytes);
 
-        if (!validClaimsRoots[root][runningHash]) revert InvalidMerkleProof();
+        if (!validClaimsRoots[0][claimsRoot]) revert InvalidMerkleProof();
 
-        nullifierHashes[nullifierHash] = true;
+        _transferGrant(grantId, receiver, nullifierHash);
+    }
 
-        uint256 grantAmount = GRANT.getAmount(grantId);
+    /// @notice Claim a grant from a claims root whose leaves commit to the
+    /// amount of every claim, and start with `CLAIM_AMOUNT_LEAF_PREFIX`. The
+    /// amount is paid instead of the grant's, and cannot exceed it.
+    /// @param grantId The grant ID to claim
+    /// @param root The root of the Merkle tree the claims root is validated
+    /// with
+    /// @param receiver The address that will receive the tokens
+    /// @param nullifierHash The nullifier for the proof
+    /// @param amount The amount of the claim
+    /// @param sisterNodes The Merkle proof of the claim
+    /// @param isLeftBytes The isLeft bytes of the Merkle proof, see `claim`
+    function claimWithAmount(
+        uint256 grantId,
+        uint256 root,
+        address receiver,
+        uint256 nullifierHash,
+        uint256 amount,
+        bytes32[] calldata sisterNodes,
+        bytes32 isLeftBytes
+    ) external {
+        if (nullifierHashes[nullifierHash]) revert NullifierHashAlreadyUsed();
+        if (receiver == address(0)) revert InvalidReceiver();
+        GRANT.checkValidity(grantId);
+        if (amount > GRANT.getAmount(grantId)) revert InvalidAmount();
 
-        WLD.transfer(receiver, grantAmount);
+        bytes32 leaf = keccak256(abi.encodePacked(CLAIM_AMOUNT_LEAF_PREFIX, grantId, receiver, nullifierHash, amount));
+        bytes32 claimsRoot = _computeClaimsRoot(leaf, sisterNodes, isLeftBytes);
 
-        emit GrantClaimed(grantId, receiver);
+        if (!validClaimsRoots[root][claimsRoot]) revert InvalidMerkleProof();
+
+        _transferAmount(grantId, receiver, nullifierHash, amount);
+    }
+
+    /// @notice Claim a grant from a claims root with per-claim World ID roots
+    /// and claim amounts, see `claimWithRoot` and `claimWithAmount`
+    /// @param grantId The grant ID to claim
+    /// @param root The World ID root the claim is proven against
+    /// @param receiver The address that will receive the tokens
+    /// @param nullifierHash The nullifier for the proof
+    /// @param amount The amount of the claim
+    /// @param sisterNodes The Merkle proof of the claim
+    /// @param isLeftBytes The isLeft bytes of the Merkle proof, see `claim`
+    function claimWithRootAndAmount(
+        uint256 grantId,
+        uint256 root,
+        address receiver,
+        uint256 nullifierHash,
+        uint256 amount,
+        bytes32[] calldata sisterNodes,
+        bytes32 isLeftBytes
+    ) external {
+        if (nullifierHashes[nullifierHash]) revert NullifierHashAlreadyUsed();
+        if (receiver == address(0)) revert InvalidReceiver();
+        GRANT.checkValidity(grantId);
+        if (amount > GRANT.getAmount(grantId)) revert InvalidAmount();
+        ROOT_VALIDATOR.requireValidRoot(root);
+
+        bytes32 leaf =
+            keccak256(abi.encodePacked(CLAIM_AMOUNT_LEAF_PREFIX, grantId, receiver, nullifierHash, root, amount));
+        bytes32 claimsRoot = _computeClaimsRoot(leaf, sisterNodes, isLeftBytes);
+
+        if (!validClaimsRoots[0][claimsRoot]) revert InvalidMerkleProof();
+
+        _transferAmount(grantId, receiver, nullifierHash, amount);
     }
 
     /// @notice Validate a claims root
@@ -203,8 +298,10 @@ contract WorldcoinAggregationV2 {
 
         if (vkeyHash != VKEY_HASH) revert InvalidVkeyHash();
 
+        // A zero root means every claim has its own root, which is validated
+        // when the grant is claimed with `claimWithRoot`
         uint256 root = uint256(_unsafeCalldataBytesAccess(proof, ROOT_OFFSET));
-        ROOT_VALIDATOR.requireValidRoot(root);
+        if (root != 0) ROOT_VALIDATOR.requireValidRoot(root);
 
         uint256 numClaims = uint256(_unsafeCalldataBytesAccess(proof, NUM_CLAIMS_OFFSET));
         if (numClaims > 1 << LOG_MAX_NUM_CLAIMS) revert InvalidNumberOfClaims();
@@ -219,6 +316,128 @@ contract WorldcoinAggregationV2 {
         validClaimsRoots[root][claimsRoot] = true;
     }
 
+    /// @notice Validate the claims roots of several batches proven by one
+    /// rolling proof
+    /// @param proof The SNARK proof
+    /// @param roots The World ID root of each batch, in the order of the
+    /// batches in the proof
+    /// @param claimsRoots The claims root of each batch
+    function validateChainedClaimsRoots(
+        bytes calldata proof,
+        uint256[] calldata roots,
+        bytes32[] calldata claimsRoots
+    ) external onlyProver {
+        // Proof must have minimuim 18 words.
+        // We expect the proof to be structured as such:
+        //
+        // proof[0..12 * 32]: reserved for proof verification data used with the
+        // pairing precompile
+        //
+        // proof[12 * 32..13 * 32]: numBatches
+        // proof[13 * 32..14 * 32]: vkeyHash Hi
+        // proof[14 * 32..15 * 32]: vkeyHash Lo
+        // proof[15 * 32..16 * 32]: numClaims, summed over all batches
+        // proof[16 * 32..17 * 32]: chainHash Hi
+        // proof[17 * 32..18 * 32]: chainHash Lo
+        //
+        // proof[18 * 32..]: Proof used in SNARK verification
+
+        if (proof.length < 18 * 32) revert InvalidProof();
+
+        bytes32 vkeyHash = _unsafeCalldataBytesAccess(proof, ROLLING_VKEY_HASH_HI_OFFSET) << 128
+            | _unsafeCalldataBytesAccess(proof, ROLLING_VKEY_HASH_LO_OFFSET);
+
+        if (vkeyHash != VKEY_HASH) revert InvalidVkeyHash();
+
+        uint256 numBatches = uint256(_unsafeCalldataBytesAccess(proof, ROLLING_NUM_BATCHES_OFFSET));
+        if (roots.length != numBatches || claimsRoots.length != numBatches) revert InvalidNumberOfBatches();
+
+        uint256 numClaims = uint256(_unsafeCalldataBytesAccess(proof, ROLLING_NUM_CLAIMS_OFFSET));
+        if (numClaims > numBatches << LOG_MAX_NUM_CLAIMS) revert InvalidNumberOfClaims();
+
+        // Each batch is appended to the chain, which starts at zero
+        bytes32 chainHash;
+        for (uint256 i = 0; i != numBatches;) {
+            // A zero root means every claim has its own root, see
+            // `validateClaimsRoot`
+            uint256 root = roots[i];
+            if (root != 0) ROOT_VALIDATOR.requireValidRoot(root);
+
+            chainHash = keccak256(abi.encodePacked(chainHash, root, claimsRoots[i]));
+
+            // forgefmt: disable-next-line
+            unchecked { ++i; }
+        }
+
+        bytes32 provenChainHash = _unsafeCalldataBytesAccess(proof, ROLLING_CHAIN_HASH_HI_OFFSET) << 128
+            | _unsafeCalldataBytesAccess(proof, ROLLING_CHAIN_HASH_LO_OFFSET);
+        if (chainHash != provenChainHash) revert InvalidChainHash();
+
+        // Verify SNARK
+        (bool success,) = VERIFIER_ADDRESS.call(proof);
+        if (!success) revert InvalidProof();
+
+        for (uint256 i = 0; i != numBatches;) {
+            validClaimsRoots[roots[i]][claimsRoots[i]] = true;
+
+            // forgefmt: disable-next-line
+            unchecked { ++i; }
+        }
+    }
+
+    /// @dev Compute the claims root from a leaf and its Merkle proof
+    /// @param leaf The hash of the claim
+    /// @param sisterNodes The Merkle proof of the claim
+    /// @param isLeftBytes The isLeft bytes of the Merkle proof
+    /// @return runningHash The claims root
+    function _computeClaimsRoot(
+        bytes32 leaf,
+        bytes32[] calldata sisterNodes,
+        bytes32 isLeftBytes
+    ) internal view returns (bytes32 runningHash) {
+        uint256 length = sisterNodes.length;
+        if (length != LOG_MAX_NUM_CLAIMS) revert InvalidMerkleProofLength();
+
+        runningHash = leaf;
+        for (uint256 i = 0; i != length;) {
+            // Unsafe access OK here since we know i is bounded by the length
+            bytes32 node = _unsafeCalldataArrayAccess(sisterNodes, i);
+
+            // Access the byte without an overflow check (safe because length is
+            // bounded by logMaxNumClaims which is bounded by 32 in the
+            // constructor). Then coerce to bool.
+            bool isLeft = _toBool(_unsafeByteAccess(isLeftBytes, i));
+
+            if (isLeft) runningHash = _efficientHash(node, runningHash);
+            else runningHash = _efficientHash(runningHash, node);
+
+            // forgefmt: disable-next-line
+            unchecked { ++i; }
+        }
+    }
+
+    /// @dev Mark the nullifier hash as used and transfer the grant
+    /// @param grantId The grant ID to claim
+    /// @param receiver The address that will receive the tokens
+    /// @param nullifierHash The nullifier for the proof
+    function _transferGrant(uint256 grantId, address receiver, uint256 nullifierHash) internal {
+        _transferAmount(grantId, receiver, nullifierHash, GRANT.getAmount(grantId));
+    }
+
+    /// @dev Mark the nullifier hash as used and transfer the amount of the
+    /// claim
+    /// @param grantId The grant ID to claim
+    /// @param receiver The address that will receive the tokens
+    /// @param nullifierHash The nullifier for the proof
+    /// @param amount The amount to transfer
+    function _transferAmount(uint256 grantId, address receiver, uint256 nullifierHash, uint256 amount) internal {
+        nullifierHashes[nullifierHash] = true;
+
+        WLD.transfer(receiver, amount);
+
+        emit GrantClaimed(grantId, receiver);
+    }
+
     /// @dev Hashes two bytes32 words without triggering memory expansion
     /// @param a The first word
     /// @param b The second word
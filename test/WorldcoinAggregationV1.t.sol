@@ -50,6 +50,85 @@ contract WorldcoinAggregationV1_Test is WorldcoinAggregationV1Helper {
         assertEq(_numClaims, numClaims, "numClaims mismatch");
     }
 
+    function test_paddedBatch() public {
+        // One claim, padded with zeros to the output length of two claims
+        uint256[] memory paddedGrantIds = new uint256[](2);
+        paddedGrantIds[0] = grantIds[0];
+        address[] memory paddedReceivers = new address[](2);
+        paddedReceivers[0] = _receivers[0];
+        uint256[] memory paddedNullifierHashes = new uint256[](2);
+        paddedNullifierHashes[0] = _nullifierHashes[0];
+
+        bytes32 outputHash = keccak256(
+            abi.encodePacked(
+                vkeyHigh, vkeyLow, root, uint256(1), paddedGrantIds, paddedReceivers, paddedNullifierHashes
+            )
+        );
+        // The fixture proof is of a full batch, so the SNARK verification is
+        // mocked and only the handling of the padding is tested
+        vm.mockCall(verifier, bytes(""), bytes(""));
+
+        vm.recordLogs();
+        aggregation.distributeGrants({
+            proof: _withOutputHash(PROOF, outputHash),
+            vkeyHash: vkeyHash,
+            numClaims: 1,
+            root: root,
+            grantIds: paddedGrantIds,
+            receivers: paddedReceivers,
+            _nullifierHashes: paddedNullifierHashes
+        });
+
+        Vm.Log[] memory logs = vm.getRecordedLogs();
+        uint256 _numClaims = 0;
+        for (uint256 i = 0; i != logs.length; ++i) {
+            if (logs[i].topics[0] != keccak256("GrantClaimed(uint256,address)")) continue;
+            assertEq(_toAddress(logs[i].topics[2]), _receivers[0], "receiver mismatch");
+            ++_numClaims;
+        }
+
+        assertEq(_numClaims, 1, "numClaims mismatch");
+        assertEq(aggregation.nullifierHashes(_nullifierHashes[0]), true, "nullifierHash should be claimed");
+        assertEq(aggregation.nullifierHashes(_nullifierHashes[1]), false, "nullifierHash should not be claimed");
+        assertEq(aggregation.nullifierHashes(0), false, "padding should not be claimed");
+    }
+
+    function test_distributeGrantsWithRoots() public {
+        uint256[] memory roots = new uint256[](numClaims);
+        for (uint256 i = 0; i != numClaims; ++i) {
+            roots[i] = root;
+        }
+
+        bytes32 outputHash = keccak256(
+            abi.encodePacked(vkeyHigh, vkeyLow, uint256(0), numClaims, grantIds, _receivers, _nullifierHashes, roots)
+        );
+        // The fixture proof is of a shared root, so the SNARK verification is
+        // mocked and only the per-claim roots are tested
+        vm.mockCall(verifier, bytes(""), bytes(""));
+
+        vm.recordLogs();
+        aggregation.distributeGrantsWithRoots({
+            proof: _withOutputHash(PROOF, outputHash),
+            vkeyHash: vkeyHash,
+            numClaims: numClaims,
+            roots: roots,
+            grantIds: grantIds,
+            receivers: _receivers,
+            _nullifierHashes: _nullifierHashes
+        });
+
+        Vm.Log[] memory logs = vm.getRecordedLogs();
+        uint256 _numClaims = 0;
+        for (uint256 i = 0; i != logs.length; ++i) {
+            if (logs[i].topics[0] != keccak256("GrantClaimed(uint256,address)")) continue;
+            assertEq(_toAddress(logs[i].topics[2]), _receivers[_numClaims], "receiver mismatch");
+            assertEq(aggregation.nullifierHashes(_nullifierHashes[_numClaims]), true, "nullifierHash should be claimed");
+            ++_numClaims;
+        }
+
+        assertEq(_numClaims, numClaims, "numClaims mismatch");
+    }
+
     function testFuzz_toAddress(bytes32 input) public view {
         address expected = address(uint160(uint256(input)));
         assertEq(aggregation.toAddress(input), expected, "toAddress failed");
@@ -140,7 +219,9 @@ contract WorldcoinAggregationV1_RevertTest is WorldcoinAggregationV1Helper {
         });
     }
 
-    function test_RevertWhen_NumClaimsLengthMismatch() public {
+    function test_RevertWhen_numClaimsMismatchesOutputHash() public {
+        // numClaims is part of the output hash, so a smaller numClaims fails
+        // the output hash check
         vm.expectRevert(WorldcoinAggregationV1.InvalidProof.selector);
         aggregation.distributeGrants({
             proof: PROOF,
@@ -153,6 +234,19 @@ contract WorldcoinAggregationV1_RevertTest is WorldcoinAggregationV1Helper {
         });
     }
 
+    function test_RevertWhen_numClaimsExceedsPaddedLength() public {
+        vm.expectRevert(WorldcoinAggregationV1.InvalidProof.selector);
+        aggregation.distributeGrants({
+            proof: PROOF,
+            vkeyHash: vkeyHash,
+            numClaims: numClaims + 1,
+            grantIds: grantIds,
+            root: root,
+            receivers: _receivers,
+            _nullifierHashes: _nullifierHashes
+        });
+    }
+
     function test_RevertWhen_proofTooShort() public {
         bytes memory invalidProof = new bytes(1);
 
@@ -181,6 +275,40 @@ contract WorldcoinAggregationV1_RevertTest is WorldcoinAggregationV1Helper {
         });
     }
 
+    function test_RevertWhen_chainedRootsLengthMismatch() public {
+        uint256[] memory batchNumClaims = new uint256[](1);
+        batchNumClaims[0] = numClaims;
+
+        vm.expectRevert(WorldcoinAggregationV1.InvalidProof.selector);
+        aggregation.distributeGrantsChained({
+            proof: PROOF,
+            vkeyHash: vkeyHash,
+            numClaims: batchNumClaims,
+            roots: new uint256[](2),
+            grantIds: grantIds,
+            receivers: _receivers,
+            _nullifierHashes: _nullifierHashes
+        });
+    }
+
+    function test_RevertWhen_chainedProofTooShort() public {
+        uint256[] memory batchNumClaims = new uint256[](1);
+        batchNumClaims[0] = numClaims;
+        uint256[] memory roots = new uint256[](1);
+        roots[0] = root;
+
+        vm.expectRevert(WorldcoinAggregationV1.InvalidProof.selector);
+        aggregation.distributeGrantsChained({
+            proof: new bytes(1),
+            vkeyHash: vkeyHash,
+            numClaims: batchNumClaims,
+            roots: roots,
+            grantIds: grantIds,
+            receivers: _receivers,
+            _nullifierHashes: _nullifierHashes
+        });
+    }
+
     function test_RevertWhen_invalidGrantId() public {
         uint256[] memory invalidGrantIds = new uint256[](2);
         invalidGrantIds[0] =
@@ -225,6 +353,41 @@ contract WorldcoinAggregationV1_RevertTest is WorldcoinAggregationV1Helper {
         });
     }
 
+    function test_RevertWhen_rootsLengthMismatch() public {
+        uint256[] memory roots = new uint256[](1);
+        roots[0] = root;
+
+        vm.expectRevert(WorldcoinAggregationV1.InvalidProof.selector);
+        aggregation.distributeGrantsWithRoots({
+            proof: PROOF,
+            vkeyHash: vkeyHash,
+            numClaims: numClaims,
+            roots: roots,
+            grantIds: grantIds,
+            receivers: _receivers,
+            _nullifierHashes: _nullifierHashes
+        });
+    }
+
+    function test_RevertWhen_invalidRootWithRoots() public {
+        uint256[] memory roots = new uint256[](numClaims);
+        for (uint256 i = 0; i != numClaims; ++i) {
+            roots[i] = root;
+        }
+        roots[numClaims - 1] = 0x00;
+
+        vm.expectRevert();
+        aggregation.distributeGrantsWithRoots({
+            proof: PROOF,
+            vkeyHash: vkeyHash,
+            numClaims: numClaims,
+            roots: roots,
+            grantIds: grantIds,
+            receivers: _receivers,
+            _nullifierHashes: _nullifierHashes
+        });
+    }
+
     function test_RevertWhen_insufficientBalance() public {
         vm.stopPrank();
         IERC20 _wldToken = IERC20(wldToken);
@@ -29,6 +29,26 @@ contract WorldcoinAggregationV2_Test is WorldcoinAggregationV2Helper {
         }
     }
 
+    function test_claimWithAmount() public {
+        uint256 amount = 1 ether;
+        bytes32[] memory sisterNodes = _validateLeaf(root, _amountLeaf(amount));
+
+        aggregation.claimWithAmount(grantIds[0], root, _receivers[0], nullifierHashes[0], amount, sisterNodes, 0x00);
+
+        assertEq(IERC20(wldToken).balanceOf(_receivers[0]), amount, "Unexpected balance");
+    }
+
+    function test_claimWithRoot() public {
+        // claims roots with per-claim roots are validated with a zero root
+        bytes32 leaf = keccak256(abi.encodePacked(grantIds[0], _receivers[0], nullifierHashes[0], root));
+        bytes32[] memory sisterNodes = _validateLeaf(0x00, leaf);
+
+        aggregation.claimWithRoot(grantIds[0], root, _receivers[0], nullifierHashes[0], sisterNodes, 0x00);
+
+        assertEq(IERC20(wldToken).balanceOf(_receivers[0]), mockGrant.getAmount(grantIds[0]), "Unexpected balance");
+        assertEq(aggregation.nullifierHashes(nullifierHashes[0]), true, "nullifierHash should be claimed");
+    }
+
     function testFuzz_efficientHash(bytes32 a, bytes32 b) public view {
         bytes32 result = aggregation.efficientHash(a, b);
         bytes32 expected = keccak256(abi.encodePacked(a, b));
@@ -109,6 +129,11 @@ contract WorldcoinAggregationV2_RevertTest is WorldcoinAggregationV2Helper {
         aggregation.validateClaimsRoot(invalidProof);
     }
 
+    function test_RevertWhen_chainedProofTooShort() public {
+        vm.expectRevert(WorldcoinAggregationV2.InvalidProof.selector);
+        aggregation.validateChainedClaimsRoots(new bytes(0), new uint256[](0), new bytes32[](0));
+    }
+
     function test_RevertWhen_invalidVkeyHash() public {
         bytes memory invalidProof = PROOF;
         invalidProof[aggregation.VKEY_HASH_LO_OFFSET() - 1] = 0x00;
@@ -203,6 +228,70 @@ contract WorldcoinAggregationV2_RevertTest is WorldcoinAggregationV2Helper {
         );
     }
 
+    function test_RevertWhen_claimingWithInvalidRoot() public {
+        aggregation.validateClaimsRoot(PROOF);
+
+        vm.expectRevert();
+        aggregation.claimWithRoot(
+            grantIds[0],
+            0x00,
+            _receivers[0],
+            nullifierHashes[0],
+            receiverProofs[0].sisterNodes,
+            receiverProofs[0].isLeftBytes
+        );
+    }
+
+    function test_RevertWhen_claimingWithRootFromSharedRoot() public {
+        aggregation.validateClaimsRoot(PROOF);
+
+        // the claims root of `PROOF` is only valid for its shared root
+        vm.expectRevert(WorldcoinAggregationV2.InvalidMerkleProof.selector);
+        aggregation.claimWithRoot(
+            grantIds[0],
+            root,
+            _receivers[0],
+            nullifierHashes[0],
+            receiverProofs[0].sisterNodes,
+            receiverProofs[0].isLeftBytes
+        );
+    }
+
+    function test_RevertWhen_claimingWithAmountFromSharedRoot() public {
+        aggregation.validateClaimsRoot(PROOF);
+
+        // the leaves of `PROOF` do not commit to an amount
+        vm.expectRevert(WorldcoinAggregationV2.InvalidMerkleProof.selector);
+        aggregation.claimWithAmount(
+            grantIds[0],
+            root,
+            _receivers[0],
+            nullifierHashes[0],
+            1 ether,
+            receiverProofs[0].sisterNodes,
+            receiverProofs[0].isLeftBytes
+        );
+    }
+
+    function test_RevertWhen_claimingWithAmountAboveGrantAmount() public {
+        uint256 amount = mockGrant.getAmount(grantIds[0]) + 1;
+        bytes32[] memory sisterNodes = _validateLeaf(root, _amountLeaf(amount));
+
+        vm.expectRevert(WorldcoinAggregationV2.InvalidAmount.selector);
+        aggregation.claimWithAmount(grantIds[0], root, _receivers[0], nullifierHashes[0], amount, sisterNodes, 0x00);
+    }
+
+    function test_RevertWhen_claimingWithAmountFromPerClaimRoots() public {
+        // a leaf with the root 1 ether has the fields of a leaf with the
+        // amount 1 ether, without the prefix
+        uint256 amount = 1 ether;
+        bytes32 leaf = keccak256(abi.encodePacked(grantIds[0], _receivers[0], nullifierHashes[0], amount));
+        bytes32[] memory sisterNodes = _validateLeaf(0x00, leaf);
+
+        vm.expectRevert(WorldcoinAggregationV2.InvalidMerkleProof.selector);
+        aggregation.claimWithAmount(grantIds[0], 0x00, _receivers[0], nullifierHashes[0], amount, sisterNodes, 0x00);
+    }
+
     function test_RevertWhen_invalidMerkleProof() public {
         aggregation.validateClaimsRoot(PROOF);
 
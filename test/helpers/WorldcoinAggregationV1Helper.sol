@@ -101,4 +101,16 @@ contract WorldcoinAggregationV1Helper is Test {
             out := input
         }
     }
+
+    /// @dev Replace the output hash in the public inputs of `proof`
+    function _withOutputHash(bytes memory proof, bytes32 outputHash) internal pure returns (bytes memory) {
+        bytes32 hi = outputHash >> 128;
+        bytes32 lo = outputHash & bytes32(uint256(type(uint128).max));
+        /// @solidity memory-safe-assembly
+        assembly {
+            mstore(add(proof, 0x1a0), hi) // 32 + 12 * 32
+            mstore(add(proof, 0x1c0), lo) // 32 + 13 * 32
+        }
+        return proof;
+    }
 }
@@ -39,6 +39,10 @@ contract WorldcoinAggregationV2Exposed is WorldcoinAggregationV2 {
     function toBool(bytes32 input) external pure returns (bool out) {
         return _toBool(input);
     }
+
+    function setValidClaimsRoot(uint256 root, bytes32 claimsRoot) external {
+        validClaimsRoots[root][claimsRoot] = true;
+    }
 }
 
 contract WorldcoinAggregationV2Helper is Test {
@@ -128,6 +132,23 @@ contract WorldcoinAggregationV2Helper is Test {
         vm.store(rootValidator, 0x000000000000000000000000000000000000000000000000000000000000012e, bytes32(root));
     }
 
+    /// @dev The leaf with an amount of the first claim
+    function _amountLeaf(uint256 amount) internal view returns (bytes32) {
+        return keccak256(
+            abi.encodePacked(
+                aggregation.CLAIM_AMOUNT_LEAF_PREFIX(), grantIds[0], _receivers[0], nullifierHashes[0], amount
+            )
+        );
+    }
+
+    /// @dev Validate the claims root with `leaf` as its left leaf under
+    /// `validationRoot`, and return the Merkle proof of the leaf
+    function _validateLeaf(uint256 validationRoot, bytes32 leaf) internal returns (bytes32[] memory sisterNodes) {
+        sisterNodes = new bytes32[](1);
+        sisterNodes[0] = keccak256("sister");
+        aggregation.setValidClaimsRoot(validationRoot, keccak256(abi.encodePacked(leaf, sisterNodes[0])));
+    }
+
     function _toAddress(bytes32 input) private pure returns (address out) {
         /// @solidity memory-safe-assembly
         assembly {